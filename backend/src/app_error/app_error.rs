@@ -1,17 +1,45 @@
 // use thiserror::Error;
 use std::fmt;
 
-use axum::response::{IntoResponse, Response};
-use hyper::http::StatusCode;
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::http::{header, StatusCode};
+use serde::Serialize;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::services::pool_monitor;
 // use std::io;
 
 
+/// A single invalid input field, addressed by its path (e.g. `items[0].amount`)
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
 #[derive(Debug)]
 pub enum AppError {
     ConfigError(String),
     DatabaseError(String),
     ServerError(String),
     SignalError(String),
+    ValidationError(String),
+    InvalidFieldsError(Vec<FieldError>),
+    RateLimitError(String, u64),
+    NotFoundError(String),
+    MethodNotAllowedError(String),
+    UnauthorizedError(String),
+    ForbiddenError(String),
+    ConflictError(String),
+    PreconditionFailedError(String),
+    GoneError(String),
+    QuotaExceededError(String),
+    ServiceUnavailableError(String),
+    /// Too busy to serve the request now, retry after the given seconds
+    OverloadedError(String, u64),
     OtherError(String),
 }
 
@@ -22,6 +50,22 @@ impl fmt::Display for AppError {
             AppError::DatabaseError(msg) => write!(f, "Database Error: {}", msg),
             AppError::ServerError(msg) => write!(f, "Server Error: {}", msg),
             AppError::SignalError(msg) => write!(f, "Signal Error: {}", msg),
+            AppError::ValidationError(msg) => write!(f, "Validation Error: {}", msg),
+            AppError::InvalidFieldsError(fields) => {
+                let names: Vec<&str> = fields.iter().map(|field| field.field.as_str()).collect();
+                write!(f, "Validation Error: invalid fields {}", names.join(", "))
+            }
+            AppError::RateLimitError(msg, _) => write!(f, "Rate Limit Error: {}", msg),
+            AppError::NotFoundError(msg) => write!(f, "Not Found Error: {}", msg),
+            AppError::MethodNotAllowedError(msg) => write!(f, "Method Not Allowed Error: {}", msg),
+            AppError::UnauthorizedError(msg) => write!(f, "Unauthorized Error: {}", msg),
+            AppError::ForbiddenError(msg) => write!(f, "Forbidden Error: {}", msg),
+            AppError::ConflictError(msg) => write!(f, "Conflict Error: {}", msg),
+            AppError::PreconditionFailedError(msg) => write!(f, "Precondition Failed Error: {}", msg),
+            AppError::GoneError(msg) => write!(f, "Gone Error: {}", msg),
+            AppError::QuotaExceededError(msg) => write!(f, "Quota Exceeded Error: {}", msg),
+            AppError::ServiceUnavailableError(msg) => write!(f, "Service Unavailable Error: {}", msg),
+            AppError::OverloadedError(msg, _) => write!(f, "Overloaded Error: {}", msg),
             AppError::OtherError(msg) => write!(f, "Other Error: {}", msg),
         }
     }
@@ -34,6 +78,19 @@ impl std::error::Error for AppError {
             AppError::DatabaseError(_) => None,
             AppError::ServerError(_) => None,
             AppError::SignalError(_) => None,
+            AppError::ValidationError(_) => None,
+            AppError::InvalidFieldsError(_) => None,
+            AppError::RateLimitError(_, _) => None,
+            AppError::NotFoundError(_) => None,
+            AppError::MethodNotAllowedError(_) => None,
+            AppError::UnauthorizedError(_) => None,
+            AppError::ForbiddenError(_) => None,
+            AppError::ConflictError(_) => None,
+            AppError::PreconditionFailedError(_) => None,
+            AppError::GoneError(_) => None,
+            AppError::QuotaExceededError(_) => None,
+            AppError::ServiceUnavailableError(_) => None,
+            AppError::OverloadedError(_, _) => None,
             AppError::OtherError(_) => None,
         }
     }
@@ -41,18 +98,281 @@ impl std::error::Error for AppError {
 
 impl From<sqlx::Error> for AppError {
     fn from(error: sqlx::Error) -> Self {
-        AppError::DatabaseError(error.to_string())
+        match error {
+            // Every connection stayed busy for the whole acquire timeout
+            sqlx::Error::PoolTimedOut => {
+                pool_monitor::record_acquire_timeout();
+                AppError::OverloadedError(
+                    "Database is busy, please retry later".to_string(),
+                    pool_monitor::retry_after_secs(),
+                )
+            }
+            // e.g. an enum label added to the database by a newer release
+            sqlx::Error::ColumnDecode { index, source } => AppError::DatabaseError(format!(
+                "Stored value in column {} is not understood by this server, \
+                 the database schema may be newer than the application: {}",
+                index, source
+            )),
+            error => AppError::DatabaseError(error.to_string()),
+        }
+    }
+}
+
+impl AppError {
+    /// Stable, machine readable code sent in the JSON error body
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::ConfigError(_) => "CONFIG",
+            AppError::DatabaseError(_) => "DATABASE",
+            AppError::ServerError(_) => "INTERNAL",
+            AppError::SignalError(_) => "UNAVAILABLE",
+            AppError::ValidationError(_) => "VALIDATION",
+            AppError::InvalidFieldsError(_) => "VALIDATION",
+            AppError::RateLimitError(_, _) => "RATE_LIMITED",
+            AppError::NotFoundError(_) => "NOT_FOUND",
+            AppError::MethodNotAllowedError(_) => "METHOD_NOT_ALLOWED",
+            AppError::UnauthorizedError(_) => "UNAUTHORIZED",
+            AppError::ForbiddenError(_) => "FORBIDDEN",
+            AppError::ConflictError(_) => "CONFLICT",
+            AppError::PreconditionFailedError(_) => "PRECONDITION_FAILED",
+            AppError::GoneError(_) => "GONE",
+            AppError::QuotaExceededError(_) => "QUOTA_EXCEEDED",
+            AppError::ServiceUnavailableError(_) => "UNAVAILABLE",
+            AppError::OverloadedError(_, _) => "OVERLOADED",
+            AppError::OtherError(_) => "INTERNAL",
+        }
+    }
+
+    /// Message shown to the client, "Invalid Input" for invalid fields
+    pub fn message(&self) -> String {
+        match self {
+            AppError::InvalidFieldsError(_) => "Invalid Input".to_string(),
+            AppError::RateLimitError(msg, _) | AppError::OverloadedError(msg, _) => msg.clone(),
+            AppError::ConfigError(msg)
+            | AppError::DatabaseError(msg)
+            | AppError::ServerError(msg)
+            | AppError::SignalError(msg)
+            | AppError::ValidationError(msg)
+            | AppError::NotFoundError(msg)
+            | AppError::MethodNotAllowedError(msg)
+            | AppError::UnauthorizedError(msg)
+            | AppError::ForbiddenError(msg)
+            | AppError::ConflictError(msg)
+            | AppError::PreconditionFailedError(msg)
+            | AppError::GoneError(msg)
+            | AppError::QuotaExceededError(msg)
+            | AppError::ServiceUnavailableError(msg)
+            | AppError::OtherError(msg) => msg.clone(),
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::ConfigError(_) => StatusCode::BAD_REQUEST,
+            AppError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::ServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::SignalError(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidFieldsError(_) => StatusCode::BAD_REQUEST,
+            AppError::RateLimitError(_, _) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::NotFoundError(_) => StatusCode::NOT_FOUND,
+            AppError::MethodNotAllowedError(_) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::UnauthorizedError(_) => StatusCode::UNAUTHORIZED,
+            AppError::ForbiddenError(_) => StatusCode::FORBIDDEN,
+            AppError::ConflictError(_) => StatusCode::CONFLICT,
+            AppError::PreconditionFailedError(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::GoneError(_) => StatusCode::GONE,
+            AppError::QuotaExceededError(_) => StatusCode::FORBIDDEN,
+            AppError::ServiceUnavailableError(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::OverloadedError(_, _) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::OtherError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut fields = Vec::new();
+        collect_field_errors(&errors, "", &mut fields);
+        AppError::InvalidFieldsError(fields)
     }
 }
 
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, fields: &mut Vec<FieldError>) {
+    for (name, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                fields.extend(errors.iter().map(|error| FieldError {
+                    field: path.clone(),
+                    message: error.message
+                        .as_ref()
+                        .map(|message| message.to_string())
+                        .unwrap_or_else(|| format!("failed the {} check", error.code)),
+                }));
+            }
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(errors, &path, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect_field_errors(errors, &format!("{}[{}]", path, index), fields);
+                }
+            }
+        }
+    }
+}
+
+/// Renders every error as `{ "error": { "code": ..., "message": ... } }`,
+/// with an additional `fields` list for invalid input fields
+///
+/// The error is also attached to the response as an `ErrorDetails`
+/// extension, from which `problem_details::render_problem_details` rebuilds
+/// the body as RFC 7807 Problem Details.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let details = ErrorDetails {
+            code: self.code(),
+            message: self.message(),
+            fields: match &self {
+                AppError::InvalidFieldsError(fields) => fields.clone(),
+                _ => Vec::new(),
+            },
+        };
+
+        let mut response = self.render_json();
+        response.extensions_mut().insert(details);
+        response
+    }
+}
+
+/// What an `AppError` response was rendered from
+#[derive(Debug, Clone)]
+pub struct ErrorDetails {
+    pub code: &'static str,
+    pub message: String,
+    pub fields: Vec<FieldError>,
+}
+
+impl AppError {
+    fn render_json(self) -> Response {
+        let status = self.status_code();
+        let code = self.code();
+
         match self {
-            AppError::ConfigError(msg) => (StatusCode::BAD_REQUEST, msg).into_response(),
-            AppError::DatabaseError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response(),
-            AppError::ServerError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response(),
-            AppError::SignalError(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg).into_response(),
-            AppError::OtherError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response(),
+            AppError::RateLimitError(msg, retry_after)
+            | AppError::OverloadedError(msg, retry_after) => (
+                status,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                error_body(code, msg),
+            ).into_response(),
+            AppError::InvalidFieldsError(fields) => (
+                status,
+                Json(serde_json::json!({
+                    "error": {
+                        "code": code,
+                        "message": "Invalid Input",
+                        "fields": fields,
+                    }
+                })),
+            ).into_response(),
+            AppError::ConfigError(msg)
+            | AppError::DatabaseError(msg)
+            | AppError::ServerError(msg)
+            | AppError::SignalError(msg)
+            | AppError::ValidationError(msg)
+            | AppError::NotFoundError(msg)
+            | AppError::MethodNotAllowedError(msg)
+            | AppError::UnauthorizedError(msg)
+            | AppError::ForbiddenError(msg)
+            | AppError::ConflictError(msg)
+            | AppError::PreconditionFailedError(msg)
+            | AppError::GoneError(msg)
+            | AppError::QuotaExceededError(msg)
+            | AppError::ServiceUnavailableError(msg)
+            | AppError::OtherError(msg) => (status, error_body(code, msg)).into_response(),
         }
     }
-}
\ No newline at end of file
+}
+
+fn error_body(code: &str, message: String) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "error": {
+            "code": code,
+            "message": message,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::auth::LoginRequest;
+    use validator::Validate;
+
+    #[derive(Validate)]
+    struct Item {
+        #[validate(range(min = 1, message = "amount must be positive"))]
+        amount: u64,
+    }
+
+    #[derive(Validate)]
+    struct Order {
+        #[validate(length(min = 1))]
+        reference: String,
+        #[validate(nested)]
+        items: Vec<Item>,
+    }
+
+    async fn render(error: AppError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn nested_errors_are_flattened_into_paths() {
+        let order = Order {
+            reference: String::new(),
+            items: vec![Item { amount: 3 }, Item { amount: 0 }],
+        };
+        let AppError::InvalidFieldsError(mut fields) = AppError::from(order.validate().unwrap_err()) else {
+            panic!("expected invalid fields");
+        };
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].field, "items[1].amount");
+        assert_eq!(fields[0].message, "amount must be positive");
+        // Without a message, the failed check is named
+        assert_eq!(fields[1].field, "reference");
+        assert_eq!(fields[1].message, "failed the length check");
+    }
+
+    #[tokio::test]
+    async fn invalid_login_fields_are_listed_in_the_body() {
+        let payload: LoginRequest = serde_json::from_value(serde_json::json!({
+            "address": "2c7536e3605d9c16a7a3d7b1898e529396a65c23zz",
+            "challenge_id": "00000000-0000-0000-0000-000000000000",
+            "signature": "",
+        })).unwrap();
+
+        let (status, body) = render(payload.validate().unwrap_err().into()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "VALIDATION");
+        assert_eq!(body["error"]["message"], "Invalid Input");
+
+        let mut fields: Vec<&str> = body["error"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["field"].as_str().unwrap())
+            .collect();
+        fields.sort();
+        assert_eq!(fields, ["address", "signature"]);
+    }
+}
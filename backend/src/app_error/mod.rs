@@ -1 +1,2 @@
-pub mod app_error;
\ No newline at end of file
+pub mod app_error;
+pub mod problem_details;
@@ -0,0 +1,218 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+
+use crate::app_error::app_error::{ErrorDetails, FieldError};
+use crate::config::app_config::ErrorFormat;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// RFC 7807 Problem Details document
+///
+/// `code` and `fields` are extension members carrying what the default
+/// JSON shape has, so clients can switch formats without losing anything.
+#[derive(Debug, Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: &'static str,
+    pub status: u16,
+    pub detail: String,
+    pub instance: String,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+impl ProblemDetails {
+    pub fn new(details: ErrorDetails, status: u16, instance: &str) -> Self {
+        ProblemDetails {
+            type_uri: problem_type(details.code),
+            title: problem_title(details.code),
+            status,
+            detail: details.message,
+            instance: instance.to_string(),
+            code: details.code,
+            fields: details.fields,
+        }
+    }
+}
+
+/// Stable `type` URI of an `AppError` code, e.g. `urn:crypto-invoice:problem:not-found`
+pub fn problem_type(code: &str) -> String {
+    format!("urn:crypto-invoice:problem:{}", code.to_ascii_lowercase().replace('_', "-"))
+}
+
+fn problem_title(code: &str) -> &'static str {
+    match code {
+        "CONFIG" => "Invalid configuration",
+        "DATABASE" => "Database error",
+        "UNAVAILABLE" => "Service unavailable",
+        "VALIDATION" => "Invalid input",
+        "RATE_LIMITED" => "Too many requests",
+        "NOT_FOUND" => "Resource not found",
+        "METHOD_NOT_ALLOWED" => "Method not allowed",
+        "UNAUTHORIZED" => "Authentication required",
+        "FORBIDDEN" => "Access denied",
+        "CONFLICT" => "Conflicting state",
+        "PRECONDITION_FAILED" => "Precondition failed",
+        "GONE" => "No longer available",
+        "QUOTA_EXCEEDED" => "Quota exceeded",
+        "OVERLOADED" => "Server overloaded",
+        _ => "Internal error",
+    }
+}
+
+/// Whether `Accept` lists `application/problem+json` with a non-zero quality
+fn accepts_problem_json(headers: &HeaderMap) -> bool {
+    headers.get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            media_type.eq_ignore_ascii_case(PROBLEM_JSON)
+                && !params.any(|param| {
+                    param.strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q <= 0.0)
+                })
+        })
+}
+
+/// Rewrites `AppError` responses as Problem Details when the request accepts
+/// `application/problem+json` or `errors.format` is `problem_details`
+///
+/// Status and headers, `Retry-After` included, are kept; only the body and
+/// its content type change. Other responses pass through untouched.
+pub async fn render_problem_details(
+    State(format): State<ErrorFormat>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let wants_problem = format == ErrorFormat::ProblemDetails || accepts_problem_json(request.headers());
+    let instance = request.uri().path().to_string();
+
+    let response = next.run(request).await;
+    if !wants_problem {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Some(details) = parts.extensions.remove::<ErrorDetails>() else {
+        return Response::from_parts(parts, body);
+    };
+
+    let problem = ProblemDetails::new(details, parts.status.as_u16(), &instance);
+    let Ok(bytes) = serde_json::to_vec(&problem) else {
+        return Response::from_parts(parts, body);
+    };
+
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_error::app_error::AppError;
+    use axum::{http::StatusCode, middleware::from_fn_with_state, routing::get, Router};
+    use serde_json::Value as JsonValue;
+    use tower::ServiceExt;
+
+    fn app(format: ErrorFormat) -> Router {
+        Router::new()
+            .route("/api/invoices/{id}", get(|| async { AppError::NotFoundError("Invoice not found".to_string()) }))
+            .route("/api/busy", get(|| async { AppError::OverloadedError("Server busy".to_string(), 30) }))
+            .route("/api/fields", get(|| async {
+                AppError::InvalidFieldsError(vec![FieldError {
+                    field: "items[0].amount".to_string(),
+                    message: "must be positive".to_string(),
+                }])
+            }))
+            .route("/api/ok", get(|| async { "ok" }))
+            .layer(from_fn_with_state(format, render_problem_details))
+    }
+
+    async fn get_json(app: Router, uri: &str, accept: Option<&str>) -> (StatusCode, HeaderMap, JsonValue) {
+        let mut request = Request::get(uri);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn errors_are_problem_details_when_accepted() {
+        let (status, headers, body) = get_json(
+            app(ErrorFormat::Json), "/api/invoices/42", Some("application/json, application/problem+json;q=0.9"),
+        ).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers[header::CONTENT_TYPE], PROBLEM_JSON);
+        assert_eq!(body["type"], "urn:crypto-invoice:problem:not-found");
+        assert_eq!(body["title"], "Resource not found");
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["detail"], "Invoice not found");
+        assert_eq!(body["instance"], "/api/invoices/42");
+        assert_eq!(body["code"], "NOT_FOUND");
+        assert!(body.get("fields").is_none());
+    }
+
+    #[tokio::test]
+    async fn errors_keep_the_default_shape_otherwise() {
+        for accept in [None, Some("application/json"), Some("application/problem+json;q=0")] {
+            let (status, headers, body) = get_json(app(ErrorFormat::Json), "/api/invoices/42", accept).await;
+
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(headers[header::CONTENT_TYPE], "application/json", "{accept:?}");
+            assert_eq!(body["error"]["code"], "NOT_FOUND");
+            assert!(body.get("type").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn problem_details_format_applies_to_every_request() {
+        let (status, headers, body) = get_json(app(ErrorFormat::ProblemDetails), "/api/fields", None).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(headers[header::CONTENT_TYPE], PROBLEM_JSON);
+        assert_eq!(body["type"], "urn:crypto-invoice:problem:validation");
+        assert_eq!(body["fields"][0]["field"], "items[0].amount");
+        assert_eq!(body["fields"][0]["message"], "must be positive");
+    }
+
+    #[tokio::test]
+    async fn status_and_retry_after_are_kept() {
+        let (status, headers, body) = get_json(app(ErrorFormat::ProblemDetails), "/api/busy", None).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(headers[header::RETRY_AFTER], "30");
+        assert_eq!(headers[header::CONTENT_TYPE], PROBLEM_JSON);
+        assert_eq!(body["status"], 503);
+        assert_eq!(body["code"], "OVERLOADED");
+    }
+
+    #[tokio::test]
+    async fn other_responses_pass_through() {
+        let response = app(ErrorFormat::ProblemDetails)
+            .oneshot(Request::get("/api/ok").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/plain"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"ok");
+    }
+}
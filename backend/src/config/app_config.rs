@@ -1,16 +1,82 @@
 use config:: {Config, ConfigError, Environment, File};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use bigdecimal::{num_bigint::BigInt, BigDecimal};
+use chrono::{Datelike, FixedOffset, NaiveDate, NaiveDateTime, Weekday};
 use std::env;
+use std::path::{Component, Path};
+use axum::http::HeaderName;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
+use sqlx::types::ipnetwork::IpNetwork;
 use std::time::Duration;
 use crate::app_error::app_error::AppError; // Ensure app_error.rs exists and is correctly defined
+use crate::models::security_events::EventType;
+use crate::services::tokens::RESERVED_CLAIMS;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Database {
     pub url: String,
     pub max_connections: u32,
     pub timeout: u64,
+    pub saturation: PoolSaturation,
+    pub retry_after_secs: u64,
+    #[serde(default)]
+    pub isolation: TransactionIsolation,
+}
+
+/// Isolation of the rate-limit and challenge transactions, see `models::isolation`
+#[derive(Debug, Deserialize, Clone)]
+pub struct TransactionIsolation {
+    #[serde(default)]
+    pub level: IsolationLevel,
+    /// Times a transaction failing to serialize is run again before giving up
+    #[serde(default = "default_isolation_retries")]
+    pub max_retries: u32,
+}
+
+fn default_isolation_retries() -> u32 {
+    3
+}
+
+impl Default for TransactionIsolation {
+    fn default() -> Self {
+        TransactionIsolation {
+            level: IsolationLevel::default(),
+            max_retries: default_isolation_retries(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IsolationLevel {
+    /// PostgreSQL's default, relying on atomic upserts and locks alone
+    #[default]
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl IsolationLevel {
+    /// Statement opening a transaction at this level, `None` for the default
+    pub fn set_statement(self) -> Option<&'static str> {
+        match self {
+            IsolationLevel::ReadCommitted => None,
+            IsolationLevel::RepeatableRead => Some("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ"),
+            IsolationLevel::Serializable => Some("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE"),
+        }
+    }
+}
+
+/// What happens to requests while every pool connection is in use
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolSaturation {
+    /// Wait up to `timeout` seconds for a connection, then fail with 503
+    Queue,
+    /// Reject new API requests with 503 without waiting
+    Shed,
 }
 
 impl Database {
@@ -24,6 +90,14 @@ impl Database {
         if self.timeout == 0 {
             return Err(AppError::DatabaseError("Timeout must be greater than 0".to_string()));
         }
+        if self.retry_after_secs == 0 {
+            return Err(AppError::DatabaseError("Retry-After must be greater than 0".to_string()));
+        }
+        if self.isolation.max_retries > 10 {
+            return Err(AppError::ConfigError(
+                "database.isolation.max_retries cannot exceed 10".to_string()
+            ));
+        }
         Ok(())
     }
 }
@@ -32,6 +106,11 @@ impl Database {
 pub struct Server {
     pub host: String,
     pub port: u16,
+    pub degraded_start: bool,
+    pub db_retry_interval_secs: u64,
+    pub dev_mode: bool,
+    pub trust_proxy_tls: bool,
+    pub trusted_proxies: Vec<String>,
 }
 
 impl Server {
@@ -44,6 +123,16 @@ impl Server {
         }
         Ok(())
     }
+
+    /// Parses `trusted_proxies`, each entry being an IP address or a CIDR range
+    pub fn trusted_proxy_networks(&self) -> Result<Vec<IpNetwork>, AppError> {
+        self.trusted_proxies
+            .iter()
+            .map(|proxy| proxy.trim().parse::<IpNetwork>().map_err(|e| {
+                AppError::ConfigError(format!("Invalid trusted proxy {}: {}", proxy, e))
+            }))
+            .collect()
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -52,12 +141,237 @@ pub struct Ethereum {
     pub private_key: Option<String>,
     pub contract_address: String,
     pub chain_id: u32,
+    /// Seconds the head may go without advancing before the node is
+    /// considered stuck, 0 to never consider it so
+    pub max_head_stall_secs: u64,
+    /// RPC requests to this chain's endpoint allowed in flight at once
+    #[serde(default = "default_max_concurrent_rpc")]
+    pub max_concurrent_rpc: usize,
+    #[serde(default)]
+    pub rpc_saturation: RpcSaturation,
+    /// Seconds a queued RPC request waits for a free slot before failing
+    #[serde(default = "default_rpc_queue_timeout_secs")]
+    pub rpc_queue_timeout_secs: u64,
+    pub tokens: Vec<TokenConfig>,
+}
+
+fn default_max_concurrent_rpc() -> usize {
+    16
+}
+
+fn default_rpc_queue_timeout_secs() -> u64 {
+    5
+}
+
+/// What happens to RPC requests while `max_concurrent_rpc` are in flight
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RpcSaturation {
+    /// Wait up to `rpc_queue_timeout_secs` for a slot, then fail with 503
+    #[default]
+    Queue,
+    /// Fail with 503 without waiting
+    Shed,
+}
+
+impl Ethereum {
+    pub fn validate_rpc_concurrency(&self) -> Result<(), AppError> {
+        if self.max_concurrent_rpc == 0 {
+            return Err(AppError::ConfigError(
+                "ethereum.max_concurrent_rpc must be greater than 0".to_string()
+            ));
+        }
+        if self.rpc_saturation == RpcSaturation::Queue && self.rpc_queue_timeout_secs == 0 {
+            return Err(AppError::ConfigError(
+                "ethereum.rpc_queue_timeout_secs must be greater than 0 when queueing".to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Rejects token lists declaring the same contract twice on a chain
+    pub fn validate_tokens(&self) -> Result<(), AppError> {
+        let mut seen = HashSet::new();
+        for token in &self.tokens {
+            if !seen.insert((token.chain_id, token.contract_address.to_lowercase())) {
+                return Err(AppError::ConfigError(format!(
+                    "Token {} is declared twice on chain {}",
+                    token.contract_address, token.chain_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up a supported token by chain and contract address
+    pub fn find_token(&self, chain_id: u32, contract_address: &str) -> Option<&TokenConfig> {
+        self.tokens.iter().find(|token| {
+            token.chain_id == chain_id && token.contract_address.eq_ignore_ascii_case(contract_address)
+        })
+    }
+}
+
+/// An ERC-20 token accepted for payments
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TokenConfig {
+    pub contract_address: String,
+    pub symbol: String,
+    pub decimals: u32,
+    pub chain_id: u32,
+}
+
+impl TokenConfig {
+    /// Interprets a raw on-chain amount, e.g. a `Transfer` value, in token units
+    pub fn amount_from_base_units(&self, raw_amount: BigInt) -> BigDecimal {
+        BigDecimal::new(raw_amount, i64::from(self.decimals))
+    }
+
+    /// Converts an amount in token units to the raw on-chain amount
+    ///
+    /// Fails when the amount has more fractional digits than the token supports.
+    pub fn amount_to_base_units(&self, amount: &BigDecimal) -> Result<BigInt, AppError> {
+        let (raw_amount, scale) = amount.with_scale(i64::from(self.decimals)).into_bigint_and_exponent();
+        if &BigDecimal::new(raw_amount.clone(), scale) != amount {
+            return Err(AppError::ValidationError(format!(
+                "Amount has more than {} decimals for {}",
+                self.decimals, self.symbol
+            )));
+        }
+        Ok(raw_amount)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Approvals {
+    pub signers: Vec<String>,
+    pub threshold: usize,
+}
+
+impl Approvals {
+    /// An empty signer list leaves approvals disabled, no batch can meet the threshold
+    pub fn validate_approvals(&self) -> Result<(), AppError> {
+        if self.threshold == 0 {
+            return Err(AppError::ConfigError("Approval threshold must be greater than 0".to_string()));
+        }
+        if !self.signers.is_empty() && self.threshold > self.signers.len() {
+            return Err(AppError::ConfigError("Approval threshold exceeds the number of signers".to_string()));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Auth {
+    pub domain: String,
+    pub uri: String,
     pub jwt_secret: String,
+    pub access_token_expires_in: u64,
     pub token_expires_in: u64,
+    pub max_token_iat_skew_secs: u64,
+    pub login_cooldown_secs: u64,
+    pub max_active_challenges: u32,
+    pub notify_on_lockout: bool,
+    pub share_token_expires_in: u64,
+    pub default_locale: String,
+    pub statements: HashMap<String, String>,
+    pub expiry_time_format: String,
+    pub expiry_utc_offset: String,
+    pub max_timestamp_skew_secs: u64,
+    /// Seconds a just-expired challenge is told apart from an unknown one
+    pub expired_challenge_grace_secs: u64,
+    /// Active sessions a user may hold at once, 0 for no limit
+    pub max_sessions_per_user: u32,
+    pub session_limit_policy: SessionLimitPolicy,
+    /// Check the `0x` prefix and hex digits of addresses at request validation
+    pub strict_address_validation: bool,
+    pub purpose_tags: PurposeTags,
+    /// Claims added to every access and refresh token, e.g. a tenant id
+    #[serde(default)]
+    pub extra_claims: serde_json::Map<String, serde_json::Value>,
+}
+
+/// What a sign-in does when the user already holds `max_sessions_per_user`
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionLimitPolicy {
+    /// Revoke the oldest sessions to make room for the new one
+    EvictOldest,
+    /// Refuse the sign-in until a session ends
+    Reject,
+}
+
+/// Tags opening the statement of each kind of signed challenge, so that a
+/// signature given for one purpose is refused for any other
+#[derive(Debug, Deserialize, Clone)]
+pub struct PurposeTags {
+    pub login: String,
+    pub accept_invoice: String,
+}
+
+impl PurposeTags {
+    pub fn validate_tags(&self) -> Result<(), AppError> {
+        for tag in [&self.login, &self.accept_invoice] {
+            if tag.trim().is_empty() || tag.contains(char::is_whitespace) {
+                return Err(AppError::ConfigError(format!("Invalid purpose tag \"{}\": it must be a single word", tag)));
+            }
+        }
+        if self.login == self.accept_invoice {
+            return Err(AppError::ConfigError("Purpose tags must differ from one another".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl Auth {
+    pub fn validate_auth(&self) -> Result<(), AppError> {
+        if self.max_active_challenges == 0 {
+            return Err(AppError::ConfigError("Max active challenges must be greater than 0".to_string()));
+        }
+        if !self.statements.contains_key(&self.default_locale) {
+            return Err(AppError::ConfigError(format!("No sign-in statement for default locale {}", self.default_locale)));
+        }
+        self.expiry_offset()?;
+        Ok(())
+    }
+
+    /// Extra claims may not override a registered one, see `RESERVED_CLAIMS`
+    pub fn validate_extra_claims(&self) -> Result<(), AppError> {
+        for name in self.extra_claims.keys() {
+            if name.trim().is_empty() {
+                return Err(AppError::ConfigError("Extra claim names cannot be empty".to_string()));
+            }
+            if RESERVED_CLAIMS.contains(&name.as_str()) {
+                return Err(AppError::ConfigError(format!(
+                    "Extra claim \"{}\" collides with a registered claim", name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Offset from UTC the challenge expiry is displayed in, e.g. `+02:00`
+    pub fn expiry_offset(&self) -> Result<FixedOffset, AppError> {
+        self.expiry_utc_offset.parse::<FixedOffset>()
+            .map_err(|e| AppError::ConfigError(format!("Invalid expiry_utc_offset {}: {}", self.expiry_utc_offset, e)))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ErrorsConfig {
+    #[serde(default)]
+    pub format: ErrorFormat,
+}
+
+/// Body error responses are rendered with
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorFormat {
+    /// `{ "error": { "code", "message" } }`, or Problem Details for
+    /// requests accepting `application/problem+json`
+    #[default]
+    Json,
+    /// Always RFC 7807 `application/problem+json`
+    ProblemDetails,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -66,6 +380,661 @@ pub struct FrontendConfig {
     pub dev_server_port : u16,
     pub assets_path: String,
     pub debug: bool,
+    #[serde(default = "default_exposed_keys")]
+    pub exposed_keys: Vec<String>,
+    #[serde(default)]
+    pub api_url_source: ApiUrlSource,
+    /// Origins a request-derived `api_url` may take, e.g. "https://app.example.com"
+    #[serde(default)]
+    pub allowed_api_origins: Vec<String>,
+    /// Alternative index templates by name, see `services::index_templates`
+    #[serde(default)]
+    pub index_variants: HashMap<String, IndexVariant>,
+    /// Request header naming the variant to serve, unset to select by `RUN_ENV` only
+    #[serde(default)]
+    pub index_variant_header: Option<String>,
+    #[serde(default)]
+    pub early_hints: EarlyHints,
+}
+
+/// Assets the home page announces in a `Link` preload header, see
+/// `services::early_hints`
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct EarlyHints {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Paths relative to the frontend dist directory, `*` allowed in file names
+    #[serde(default)]
+    pub preload: Vec<String>,
+}
+
+/// An index.html template served instead of the default one
+#[derive(Debug, Deserialize, Clone)]
+pub struct IndexVariant {
+    /// Template file, relative to the frontend dist directory
+    pub template: String,
+    /// Replacements applied to the template, e.g. a banner or meta tags
+    #[serde(default)]
+    pub placeholders: HashMap<String, String>,
+}
+
+/// Where the `api_url` injected into the page comes from
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiUrlSource {
+    /// Always `frontend.api_url`
+    #[default]
+    Static,
+    /// The origin the page was requested on, when listed in
+    /// `frontend.allowed_api_origins`, `frontend.api_url` otherwise
+    RequestHost,
+}
+
+impl FrontendConfig {
+    pub fn validate_frontend(&self) -> Result<(), AppError> {
+        if self.api_url_source == ApiUrlSource::RequestHost && self.allowed_api_origins.is_empty() {
+            return Err(AppError::ConfigError(
+                "frontend.allowed_api_origins cannot be empty with api_url_source = \"request_host\"".to_string()
+            ));
+        }
+        for (name, variant) in &self.index_variants {
+            let path = Path::new(&variant.template);
+            let inside_dist = path.components().all(|component| matches!(component, Component::Normal(_)));
+            if variant.template.is_empty() || !inside_dist {
+                return Err(AppError::ConfigError(format!(
+                    "frontend.index_variants.{} template must be a path inside the dist directory, got '{}'",
+                    name, variant.template
+                )));
+            }
+        }
+        if self.early_hints.enabled && self.early_hints.preload.is_empty() {
+            return Err(AppError::ConfigError(
+                "frontend.early_hints.preload cannot be empty when early hints are enabled".to_string()
+            ));
+        }
+        for entry in &self.early_hints.preload {
+            let inside_dist = Path::new(entry).components().all(|component| matches!(component, Component::Normal(_)));
+            if entry.is_empty() || !inside_dist {
+                return Err(AppError::ConfigError(format!(
+                    "frontend.early_hints.preload entries must be paths inside the dist directory, got '{}'", entry
+                )));
+            }
+        }
+        if let Some(header) = &self.index_variant_header
+            && HeaderName::from_bytes(header.as_bytes()).is_err() {
+            return Err(AppError::ConfigError(format!(
+                "frontend.index_variant_header is not a valid header name: '{}'", header
+            )));
+        }
+        for origin in &self.allowed_api_origins {
+            let valid = origin.strip_prefix("https://")
+                .or_else(|| origin.strip_prefix("http://"))
+                .is_some_and(|host| !host.is_empty() && !host.contains(['/', '?', '#', '@']));
+            if !valid {
+                return Err(AppError::ConfigError(format!(
+                    "frontend.allowed_api_origins entries must be scheme://host[:port], got '{}'", origin
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// `api_url` to inject for a page requested on `request_origin`
+    ///
+    /// A derived origin is only used when it is allowlisted, so a forged
+    /// `Host` header cannot point the page at another server.
+    pub fn resolve_api_url(&self, request_origin: Option<&str>) -> String {
+        if self.api_url_source == ApiUrlSource::RequestHost
+            && let Some(origin) = request_origin
+            && let Some(allowed) = self.allowed_api_origins.iter().find(|allowed| allowed.eq_ignore_ascii_case(origin)) {
+            return allowed.clone();
+        }
+        self.api_url.clone()
+    }
+}
+
+fn default_exposed_keys() -> Vec<String> {
+    ["api_url", "dev_server_port", "assets_path", "debug"]
+        .iter()
+        .map(|key| key.to_string())
+        .collect()
+}
+
+/// Longest retention accepted, one hundred years
+const MAX_RETENTION_DAYS: i64 = 36_500;
+
+/// Longest pause between two pruning runs, one day
+const MAX_PURGE_INTERVAL_SECS: u64 = 24 * 3600;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Retention {
+    pub retention_days: i64,
+    pub purge_interval_secs: u64,
+    pub preserved_event_types: Vec<EventType>,
+    /// Wallet diagnostics reports older than this many days are pruned
+    pub diagnostics_retention_days: i64,
+}
+
+impl Retention {
+    pub fn validate_retention(&self) -> Result<(), AppError> {
+        if self.retention_days <= 0 {
+            return Err(AppError::ConfigError("Retention days must be greater than 0".to_string()));
+        }
+        if self.retention_days > MAX_RETENTION_DAYS {
+            return Err(AppError::ConfigError(format!(
+                "Retention days cannot exceed {}", MAX_RETENTION_DAYS
+            )));
+        }
+        if self.purge_interval_secs == 0 {
+            return Err(AppError::ConfigError("Purge interval must be greater than 0".to_string()));
+        }
+        if self.purge_interval_secs > MAX_PURGE_INTERVAL_SECS {
+            return Err(AppError::ConfigError(format!(
+                "Purge interval cannot exceed {} seconds", MAX_PURGE_INTERVAL_SECS
+            )));
+        }
+        if self.diagnostics_retention_days <= 0 {
+            return Err(AppError::ConfigError("Diagnostics retention days must be greater than 0".to_string()));
+        }
+        if self.diagnostics_retention_days > MAX_RETENTION_DAYS {
+            return Err(AppError::ConfigError(format!(
+                "Diagnostics retention days cannot exceed {}", MAX_RETENTION_DAYS
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// At most `max_attempts` per identifier within `window_secs`
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateLimitRule {
+    pub max_attempts: u32,
+    pub window_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateLimits {
+    pub challenge: RateLimitRule,
+    pub login: RateLimitRule,
+    /// Applied per address, refreshes count against `challenge` per client IP
+    pub refresh_challenge: RateLimitRule,
+    pub verify_signature: RateLimitRule,
+    pub wallet_telemetry: RateLimitRule,
+    pub challenge_preview: RateLimitRule,
+    pub offenders: RateLimitOffenders,
+}
+
+/// What happens to identifiers that keep exceeding their rate limits
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateLimitOffenders {
+    /// Deny identifiers with `block_after_violations` violations within `window_secs`
+    pub auto_block: bool,
+    pub block_after_violations: i64,
+    pub window_secs: u64,
+    /// How long an identifier stays denied, 0 until an admin clears it
+    pub block_duration_secs: u64,
+}
+
+impl RateLimitOffenders {
+    pub fn validate_offenders(&self) -> Result<(), AppError> {
+        if self.auto_block && (self.block_after_violations <= 0 || self.window_secs == 0) {
+            return Err(AppError::ConfigError(
+                "rate_limits.offenders block_after_violations and window_secs must be greater than 0".to_string()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BotFilter {
+    pub enabled: bool,
+    pub blocked_user_agents: Vec<String>,
+    pub block_missing_user_agent: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Outbox {
+    pub enabled: bool,
+    pub webhook_url: String,
+    /// Endpoints receiving the messages of an aggregate type instead of `webhook_url`
+    #[serde(default)]
+    pub aggregate_webhooks: HashMap<String, String>,
+    pub poll_interval_secs: u64,
+    pub batch_size: i64,
+    pub max_attempts: u32,
+    pub base_backoff_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Outbox {
+    /// Endpoint the messages of `aggregate_type` are delivered to
+    pub fn webhook_url_for(&self, aggregate_type: &str) -> &str {
+        self.aggregate_webhooks.get(aggregate_type).unwrap_or(&self.webhook_url)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Tarpit {
+    pub enabled: bool,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub window_secs: u64,
+}
+
+impl Tarpit {
+    pub fn validate_tarpit(&self) -> Result<(), AppError> {
+        if self.base_delay_ms > self.max_delay_ms {
+            return Err(AppError::ConfigError("Tarpit base delay cannot exceed the max delay".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Longest lockout duration or escalation window accepted, one year
+const MAX_LOCKOUT_SECS: u64 = 365 * 24 * 3600;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Lockout {
+    /// Failed logins within `failure_window_secs` that lock the account, 0 never locks
+    pub max_failed_logins: u32,
+    pub failure_window_secs: u64,
+    pub base_duration_secs: u64,
+    pub max_duration_secs: u64,
+    pub escalation_window_secs: u64,
+}
+
+impl Lockout {
+    pub fn validate_lockout(&self) -> Result<(), AppError> {
+        if self.base_duration_secs == 0 {
+            return Err(AppError::ConfigError("Lockout base duration must be greater than 0".to_string()));
+        }
+        if self.base_duration_secs > self.max_duration_secs {
+            return Err(AppError::ConfigError("Lockout base duration cannot exceed the max duration".to_string()));
+        }
+        if self.max_duration_secs > MAX_LOCKOUT_SECS
+            || self.escalation_window_secs > MAX_LOCKOUT_SECS
+            || self.failure_window_secs > MAX_LOCKOUT_SECS
+        {
+            return Err(AppError::ConfigError(format!(
+                "Lockout durations cannot exceed {} seconds", MAX_LOCKOUT_SECS
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TimeCheck {
+    pub enabled: bool,
+    pub ntp_server: String,
+    pub max_offset_secs: u64,
+    pub fail_on_drift: bool,
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Audit {
+    pub signing_key: String,
+    /// Event types written to the audit log, all of them when unset
+    pub enabled_event_types: Option<Vec<EventType>>,
+    pub metadata_limits: EventMetadataLimits,
+    #[serde(default)]
+    pub token_issuance: TokenIssuanceAudit,
+}
+
+/// Mirrors every minted token to integrators through the outbox
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TokenIssuanceAudit {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+impl Audit {
+    pub fn validate_audit(&self) -> Result<(), AppError> {
+        if self.signing_key.is_empty() {
+            return Err(AppError::ConfigError("Audit signing key cannot be empty".to_string()));
+        }
+        if self.metadata_limits.max_bytes < MIN_EVENT_METADATA_BYTES {
+            return Err(AppError::ConfigError(format!(
+                "Event metadata max_bytes must be at least {}", MIN_EVENT_METADATA_BYTES
+            )));
+        }
+        if self.metadata_limits.max_keys < 2 {
+            return Err(AppError::ConfigError("Event metadata max_keys must be at least 2".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Token issuance messages are only delivered by the outbox relay
+    pub fn validate_token_issuance(&self, outbox: &Outbox) -> Result<(), AppError> {
+        if self.token_issuance.enabled && !outbox.enabled {
+            return Err(AppError::ConfigError(
+                "audit.token_issuance requires the outbox to be enabled".to_string()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Smallest `max_bytes` allowed, leaving room for the truncation marker
+pub const MIN_EVENT_METADATA_BYTES: usize = 256;
+
+/// Caps on the metadata stored with each security event
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct EventMetadataLimits {
+    /// Largest metadata stored, in bytes of serialized JSON
+    pub max_bytes: usize,
+    /// Largest number of keys stored, nested keys included
+    pub max_keys: usize,
+    pub oversized: OversizedMetadata,
+}
+
+/// What happens to event metadata over its limits
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizedMetadata {
+    /// Keep the leading entries that fit, cutting the string that overflows
+    Truncate,
+    /// Store only a marker with the original size
+    Reject,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CryptoSelfTest {
+    /// Verify known signature vectors at startup and refuse to start on failure
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SignatureWorkers {
+    /// Signature recoveries running at once on the blocking thread pool
+    pub max_concurrency: usize,
+}
+
+impl SignatureWorkers {
+    pub fn validate_workers(&self) -> Result<(), AppError> {
+        if self.max_concurrency == 0 {
+            return Err(AppError::ConfigError("Signature worker concurrency must be greater than 0".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Storage of signing challenges, see `models::challenge_store`
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChallengeStoreConfig {
+    pub backend: ChallengeBackend,
+    /// Redis server holding challenges with `backend = "redis"`
+    pub redis_url: String,
+    /// Prefix of every Redis key, so deployments can share a server
+    pub key_prefix: String,
+    /// How long an issued nonce is remembered, to refuse it if drawn again
+    #[serde(default = "default_nonce_reuse_window_secs")]
+    pub nonce_reuse_window_secs: u64,
+    /// Nonce collisions within the window that raise a `challenge.nonce_collisions` alert
+    #[serde(default = "default_nonce_collision_alert_threshold")]
+    pub nonce_collision_alert_threshold: u32,
+}
+
+fn default_nonce_reuse_window_secs() -> u64 {
+    86400
+}
+
+fn default_nonce_collision_alert_threshold() -> u32 {
+    3
+}
+
+impl ChallengeStoreConfig {
+    pub fn validate_challenge_store(&self) -> Result<(), AppError> {
+        if self.nonce_reuse_window_secs == 0 {
+            return Err(AppError::ConfigError(
+                "challenge_store.nonce_reuse_window_secs must be greater than 0".to_string()
+            ));
+        }
+        if self.nonce_collision_alert_threshold == 0 {
+            return Err(AppError::ConfigError(
+                "challenge_store.nonce_collision_alert_threshold must be greater than 0".to_string()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeBackend {
+    /// The `auth_challenges` table
+    Postgres,
+    /// Redis keys expiring with the challenges
+    Redis,
+}
+
+/// Limits on the session keys payers authorize, see `models::session_keys`
+#[derive(Debug, Deserialize, Clone)]
+pub struct SessionKeys {
+    /// Longest lifetime a session key can be authorized for, in seconds
+    pub max_lifetime_secs: u64,
+    /// Unrevoked, unexpired keys a user may hold at once
+    pub max_keys_per_user: i64,
+}
+
+impl SessionKeys {
+    pub fn validate_session_keys(&self) -> Result<(), AppError> {
+        if self.max_lifetime_secs == 0 {
+            return Err(AppError::ConfigError("Session key max_lifetime_secs must be greater than 0".to_string()));
+        }
+        if self.max_keys_per_user <= 0 {
+            return Err(AppError::ConfigError("Session key max_keys_per_user must be greater than 0".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AnomalyDetection {
+    pub enabled: bool,
+    /// Seconds between two analyses
+    pub interval_secs: u64,
+    /// Logins of the last `window_secs` are compared against each other
+    pub window_secs: u64,
+    /// Distinct login countries of one user within the window that are flagged
+    pub max_countries_per_user: usize,
+    /// Failed logins within the window, across all addresses, that are flagged
+    pub failed_login_spike_threshold: i64,
+    /// Distinct addresses those failures must target to count as a spike
+    pub failed_login_spike_min_addresses: i64,
+    /// Days of login history an IP range is looked for in before it is new
+    pub known_range_lookback_days: i64,
+    /// Prefix lengths grouping IPv4 and IPv6 addresses into ranges
+    pub ipv4_range_prefix: u8,
+    pub ipv6_range_prefix: u8,
+    /// Address anomalies are also sent to, e.g. a security mailbox
+    pub alert_recipient: Option<String>,
+}
+
+impl AnomalyDetection {
+    pub fn validate_anomaly_detection(&self) -> Result<(), AppError> {
+        if self.interval_secs == 0 || self.window_secs == 0 {
+            return Err(AppError::ConfigError(
+                "anomaly_detection interval_secs and window_secs must be greater than 0".to_string()
+            ));
+        }
+        if self.max_countries_per_user < 2 {
+            return Err(AppError::ConfigError(
+                "anomaly_detection.max_countries_per_user must be at least 2".to_string()
+            ));
+        }
+        if self.ipv4_range_prefix > 32 || self.ipv6_range_prefix > 128 {
+            return Err(AppError::ConfigError(
+                "anomaly_detection range prefixes cannot exceed the address length".to_string()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GeoIp {
+    /// MaxMind City or Country database, lookups are disabled when unset
+    pub database_path: Option<String>,
+    /// IPs whose location is kept in memory before the cache is cleared
+    pub cache_size: usize,
+}
+
+/// Origins must be sent back verbatim in `Access-Control-Allow-Origin`,
+/// e.g. `https://app.example.com` without a path or trailing slash
+fn validate_origins(key: &str, origins: &[String]) -> Result<(), AppError> {
+    for origin in origins {
+        let well_formed = origin.strip_prefix("https://")
+            .or_else(|| origin.strip_prefix("http://"))
+            .is_some_and(|host| !host.is_empty() && !host.contains('/'))
+            && axum::http::HeaderValue::from_str(origin).is_ok();
+        if !well_formed {
+            return Err(AppError::ConfigError(format!(
+                "{} entries must be origins like https://app.example.com, got '{}'", key, origin
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Longest preflight cache accepted by browsers: Firefox caps at a day,
+/// Chromium at two hours
+const MAX_CORS_MAX_AGE_SECS: u64 = 86_400;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Cors {
+    /// Seconds browsers may cache a preflight response, 0 to disable caching
+    pub max_age_secs: u64,
+    /// Origins of the web app, allowed to send credentials
+    #[serde(default = "default_cors_origins")]
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    pub public_api: PublicApiCors,
+}
+
+/// CORS of the routes meant for third-party clients, see `utils::cors`
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PublicApiCors {
+    /// Origins allowed on the public API, `"*"` for any; empty to apply the
+    /// web app policy there too
+    pub allowed_origins: Vec<String>,
+    /// Path prefixes forming the public API
+    pub path_prefixes: Vec<String>,
+}
+
+fn default_cors_origins() -> Vec<String> {
+    vec!["http://localhost:3000".to_string()]
+}
+
+impl Cors {
+    /// Credentialed origins must be listed, browsers refuse `"*"` with
+    /// credentials; the public API may use `"*"`, but only on its own
+    pub fn validate_cors(&self) -> Result<(), AppError> {
+        self.max_age()?;
+
+        if self.allowed_origins.is_empty() {
+            return Err(AppError::ConfigError("cors.allowed_origins cannot be empty".to_string()));
+        }
+        if self.allowed_origins.iter().any(|origin| origin == "*") {
+            return Err(AppError::ConfigError(
+                "cors.allowed_origins cannot contain \"*\": the web app is sent credentials".to_string()
+            ));
+        }
+        validate_origins("cors.allowed_origins", &self.allowed_origins)?;
+
+        let public_api = &self.public_api;
+        if public_api.allowed_origins.iter().any(|origin| origin == "*") {
+            if public_api.allowed_origins.len() > 1 {
+                return Err(AppError::ConfigError(
+                    "cors.public_api.allowed_origins cannot combine \"*\" with other origins".to_string()
+                ));
+            }
+        } else {
+            validate_origins("cors.public_api.allowed_origins", &public_api.allowed_origins)?;
+        }
+        if !public_api.allowed_origins.is_empty() && public_api.path_prefixes.is_empty() {
+            return Err(AppError::ConfigError(
+                "cors.public_api.path_prefixes cannot be empty when public API origins are set".to_string()
+            ));
+        }
+        if let Some(prefix) = public_api.path_prefixes.iter().find(|prefix| !prefix.starts_with('/')) {
+            return Err(AppError::ConfigError(format!(
+                "cors.public_api.path_prefixes entries must start with '/', got '{}'", prefix
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn max_age(&self) -> Result<Duration, AppError> {
+        if self.max_age_secs > MAX_CORS_MAX_AGE_SECS {
+            return Err(AppError::ConfigError(format!(
+                "cors.max_age_secs must be at most {}, got {}",
+                MAX_CORS_MAX_AGE_SECS, self.max_age_secs
+            )));
+        }
+        Ok(Duration::from_secs(self.max_age_secs))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Csrf {
+    /// Require `X-CSRF-Token` on state-changing requests, see `utils::csrf`
+    pub enabled: bool,
+    /// Path prefixes whose `Authorization: Bearer` requests skip the check
+    pub bearer_exempt_prefixes: Vec<String>,
+}
+
+impl Csrf {
+    pub fn validate_csrf(&self) -> Result<(), AppError> {
+        if let Some(prefix) = self.bearer_exempt_prefixes.iter().find(|prefix| !prefix.starts_with('/')) {
+            return Err(AppError::ConfigError(format!(
+                "csrf.bearer_exempt_prefixes entries must start with '/', got '{}'", prefix
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PaymentWatch {
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct InvoiceTerms {
+    /// Days between creation and the due date when the issuer gives none
+    pub default_net_days: u32,
+    /// Pushes a default due date falling on a weekend or holiday to the next business day
+    pub skip_non_business_days: bool,
+    pub holidays: Vec<NaiveDate>,
+    /// Invoices of at least this amount can only be issued by verified users
+    pub verified_amount_threshold: Option<BigDecimal>,
+    /// Invoices a user can create per rolling period, unlimited when unset
+    pub invoice_quota: Option<u32>,
+    pub invoice_quota_period_days: u32,
+}
+
+impl InvoiceTerms {
+    /// Due date of an invoice created at `created_at` without an explicit one
+    ///
+    /// Net days are calendar days; only the resulting date is moved forward,
+    /// keeping the time of day of the creation.
+    pub fn default_due_date(&self, created_at: NaiveDateTime) -> NaiveDateTime {
+        let mut due_date = created_at + chrono::Duration::days(i64::from(self.default_net_days));
+        if self.skip_non_business_days {
+            while !self.is_business_day(due_date.date()) {
+                due_date += chrono::Duration::days(1);
+            }
+        }
+        due_date
+    }
+
+    fn is_business_day(&self, date: NaiveDate) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.holidays.contains(&date)
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -73,14 +1042,40 @@ pub struct AppConfig {
     pub database: Database,
     pub server: Server,
     pub ethereum: Ethereum,
+    pub approvals: Approvals,
     pub auth: Auth,
+    pub retention: Retention,
+    pub rate_limits: RateLimits,
+    pub bot_filter: BotFilter,
+    pub feature_flags: HashMap<String, bool>,
+    pub outbox: Outbox,
+    pub tarpit: Tarpit,
+    pub lockout: Lockout,
+    pub time_check: TimeCheck,
+    pub audit: Audit,
+    pub invoice_terms: InvoiceTerms,
+    pub payment_watch: PaymentWatch,
+    pub cors: Cors,
+    pub csrf: Csrf,
+    pub geoip: GeoIp,
+    pub signature_workers: SignatureWorkers,
+    pub crypto_self_test: CryptoSelfTest,
+    pub challenge_store: ChallengeStoreConfig,
+    pub session_keys: SessionKeys,
+    pub anomaly_detection: AnomalyDetection,
     pub frontend: FrontendConfig,
+    #[serde(default)]
+    pub errors: ErrorsConfig,
+}
+
+/// Environment the configuration is loaded for, `RUN_ENV` or "development"
+pub fn run_env() -> String {
+    env::var("RUN_ENV").unwrap_or_else(|_| "development".to_string())
 }
 
 impl AppConfig {
     pub fn new() -> Result<Self, ConfigError> {
-        let env = env::var("RUN_ENV")
-            .unwrap_or_else(|_| "development".to_string());
+        let env = run_env();
 
         let config = Config::builder()
             .add_source(File::with_name("config/default"))
@@ -120,6 +1115,9 @@ pub async fn init_config(config: AppConfig) -> Result<PgPool, sqlx::Error> {
     Ok(pool)
 }
 
+/// Fragments of a config key that mark it as sensitive
+const SENSITIVE_KEY_MARKERS: &[&str] = &["secret", "key", "private", "password"];
+
 #[derive(Serialize)]
 pub struct SerializableFrontendConfig {
     pub csrf_token: String,
@@ -129,15 +1127,187 @@ pub struct SerializableFrontendConfig {
     pub debug: bool
 }
 
+/// Builds the configuration injected into `window.BACKEND_CONFIG`
+///
+/// Only the CSRF token and the keys listed in `frontend.exposed_keys` are
+/// kept, and keys that look sensitive are dropped even when allowlisted, so
+/// a field added to `SerializableFrontendConfig` is not exposed by accident.
 pub fn get_serializable_frontend_config(
     config: &FrontendConfig,
     csrf_token: String,
-) -> SerializableFrontendConfig {
-    SerializableFrontendConfig {
+    api_url: String,
+) -> serde_json::Map<String, serde_json::Value> {
+    let frontend_config = SerializableFrontendConfig {
         csrf_token,
-        api_url: config.api_url.clone(),
+        api_url,
         dev_server_port: config.dev_server_port,
         assets_path: config.assets_path.clone(),
         debug: config.debug,
+    };
+
+    let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(&frontend_config) else {
+        return serde_json::Map::new();
+    };
+
+    fields
+        .into_iter()
+        .filter(|(name, _)| {
+            let exposed = name == "csrf_token" || config.exposed_keys.contains(name);
+            if exposed && is_sensitive_key(name) {
+                eprintln!("Refusing to expose sensitive config key {} to the frontend", name);
+                return false;
+            }
+            exposed
+        })
+        .collect()
+}
+
+fn is_sensitive_key(name: &str) -> bool {
+    let name = name.to_lowercase();
+    SENSITIVE_KEY_MARKERS.iter().any(|marker| name.contains(marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap().and_hms_opt(9, 30, 0).unwrap()
+    }
+
+    fn terms(default_net_days: u32, skip_non_business_days: bool) -> InvoiceTerms {
+        InvoiceTerms {
+            default_net_days,
+            skip_non_business_days,
+            holidays: vec![NaiveDate::from_ymd_opt(2026, 12, 25).unwrap()],
+            verified_amount_threshold: None,
+            invoice_quota: None,
+            invoice_quota_period_days: 30,
+        }
+    }
+
+    #[test]
+    fn default_due_date_counts_calendar_days() {
+        assert_eq!(terms(30, false).default_due_date(at("2026-12-01")), at("2026-12-31"));
+        // A Saturday, kept as is without skipping
+        assert_eq!(terms(1, false).default_due_date(at("2026-12-25")), at("2026-12-26"));
+    }
+
+    #[test]
+    fn default_due_date_skips_weekends_and_holidays() {
+        // Friday the 25th is a holiday, then the weekend
+        assert_eq!(terms(1, true).default_due_date(at("2026-12-24")), at("2026-12-28"));
+        // Lands on a Saturday
+        assert_eq!(terms(2, true).default_due_date(at("2026-12-17")), at("2026-12-21"));
+        // Already a business day
+        assert_eq!(terms(30, true).default_due_date(at("2026-12-01")), at("2026-12-31"));
+    }
+
+    fn exposed_keys(frontend: &FrontendConfig) -> Vec<String> {
+        let mut keys: Vec<String> = get_serializable_frontend_config(frontend, "token".to_string(), "https://api.example".to_string())
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn frontend_config_exposes_only_allowlisted_keys() {
+        let mut frontend = crate::test_support::config().frontend;
+        assert_eq!(exposed_keys(&frontend), ["api_url", "assets_path", "csrf_token", "debug", "dev_server_port"]);
+
+        // The CSRF token is always injected, other keys only when listed
+        frontend.exposed_keys = vec!["api_url".to_string(), "unknown".to_string()];
+        assert_eq!(exposed_keys(&frontend), ["api_url", "csrf_token"]);
+        frontend.exposed_keys.clear();
+        assert_eq!(exposed_keys(&frontend), ["csrf_token"]);
+    }
+
+    #[test]
+    fn sensitive_keys_are_recognized() {
+        for name in ["signing_key", "API_KEY", "client_secret", "private_url", "db_password", "keystore"] {
+            assert!(is_sensitive_key(name), "{name}");
+        }
+        for name in ["api_url", "dev_server_port", "assets_path", "debug", "csrf_token"] {
+            assert!(!is_sensitive_key(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn retention_is_bounded() {
+        let retention = crate::test_support::config().retention;
+        assert!(retention.validate_retention().is_ok());
+
+        for retention_days in [0, -1, MAX_RETENTION_DAYS + 1, i64::MAX] {
+            let invalid = Retention { retention_days, ..retention.clone() };
+            assert!(invalid.validate_retention().is_err(), "{retention_days}");
+        }
+        for purge_interval_secs in [0, MAX_PURGE_INTERVAL_SECS + 1] {
+            let invalid = Retention { purge_interval_secs, ..retention.clone() };
+            assert!(invalid.validate_retention().is_err(), "{purge_interval_secs}");
+        }
+        // A non-positive window would prune every diagnostics report
+        for diagnostics_retention_days in [0, -30, MAX_RETENTION_DAYS + 1] {
+            let invalid = Retention { diagnostics_retention_days, ..retention.clone() };
+            assert!(invalid.validate_retention().is_err(), "{diagnostics_retention_days}");
+        }
+    }
+
+    #[test]
+    fn approval_threshold_cannot_exceed_the_signers() {
+        let signers = vec![
+            "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23".to_string(),
+            "0x0000000000000000000000000000000000000001".to_string(),
+        ];
+        assert!(Approvals { signers: signers.clone(), threshold: 2 }.validate_approvals().is_ok());
+        assert!(Approvals { signers: signers.clone(), threshold: 3 }.validate_approvals().is_err());
+        assert!(Approvals { signers, threshold: 0 }.validate_approvals().is_err());
+        // Approvals are disabled without signers
+        assert!(Approvals { signers: vec![], threshold: 1 }.validate_approvals().is_ok());
+    }
+
+    #[test]
+    fn auth_requires_challenges_and_a_default_statement() {
+        let auth = crate::test_support::config().auth;
+        assert!(auth.validate_auth().is_ok());
+        assert!(Auth { max_active_challenges: 0, ..auth.clone() }.validate_auth().is_err());
+        assert!(Auth { default_locale: "xx".to_string(), ..auth.clone() }.validate_auth().is_err());
+        assert!(Auth { expiry_utc_offset: "later".to_string(), ..auth }.validate_auth().is_err());
+    }
+
+    #[test]
+    fn static_api_url_ignores_the_request_origin() {
+        let frontend = FrontendConfig {
+            allowed_api_origins: vec!["https://app.example.com".to_string()],
+            ..crate::test_support::config().frontend
+        };
+        assert_eq!(frontend.api_url_source, ApiUrlSource::Static);
+        assert_eq!(frontend.resolve_api_url(Some("https://app.example.com")), frontend.api_url);
+        assert_eq!(frontend.resolve_api_url(None), frontend.api_url);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn request_derived_api_url_is_limited_to_allowed_origins() {
+        let frontend = FrontendConfig {
+            api_url_source: ApiUrlSource::RequestHost,
+            allowed_api_origins: vec!["https://app.example.com".to_string(), "https://eu.example.com".to_string()],
+            ..crate::test_support::config().frontend
+        };
+        assert_eq!(frontend.resolve_api_url(Some("https://eu.example.com")), "https://eu.example.com");
+        // Hosts are case-insensitive, the allowed spelling is injected
+        assert_eq!(frontend.resolve_api_url(Some("https://APP.example.com")), "https://app.example.com");
+        // A forged Host, another scheme or no origin fall back to api_url
+        assert_eq!(frontend.resolve_api_url(Some("https://evil.example")), frontend.api_url);
+        assert_eq!(frontend.resolve_api_url(Some("http://app.example.com")), frontend.api_url);
+        assert_eq!(frontend.resolve_api_url(None), frontend.api_url);
+    }
+
+    #[test]
+    fn tarpit_base_delay_cannot_exceed_the_max() {
+        let tarpit = crate::test_support::config().tarpit;
+        assert!(tarpit.validate_tarpit().is_ok());
+        let invalid = Tarpit { base_delay_ms: tarpit.max_delay_ms + 1, ..tarpit };
+        assert!(invalid.validate_tarpit().is_err());
+    }
+}
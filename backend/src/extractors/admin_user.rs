@@ -0,0 +1,136 @@
+use axum::{extract::FromRequestParts, http::request::Parts};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::auth_user::AuthUser,
+    models::users::User,
+    AppState,
+};
+
+/// Caller authenticated as an administrator
+///
+/// Rejects like `AuthUser` when unauthenticated, and with 403 when either the
+/// token's `is_admin` claim or the account itself is not admin. The account is
+/// checked so that revoking admin takes effect before the token expires.
+#[derive(Debug, Clone)]
+pub struct AdminUser {
+    pub auth_user: AuthUser,
+}
+
+impl AdminUser {
+    pub fn user_id(&self) -> Uuid {
+        self.auth_user.user_id()
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for AdminUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let auth_user = AuthUser::from_request_parts(parts, app_state).await?;
+
+        let is_admin = auth_user.claims.is_admin
+            && User::get_user_by_id(&app_state.pool, auth_user.user_id())
+                .await?
+                .is_some_and(|user| user.is_admin());
+
+        if !is_admin {
+            return Err(AppError::ForbiddenError("Admin access required".to_string()));
+        }
+
+        Ok(AdminUser { auth_user })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::api_keys::ApiKey,
+        services::tokens::generate_token_pair,
+        test_support,
+        utils::clock::SystemClock,
+    };
+    use axum::http::{header, Request};
+    use sqlx::PgPool;
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    async fn extract(app_state: &Arc<AppState>, token: Option<&str>) -> Result<AdminUser, AppError> {
+        let mut request = Request::builder().uri("/api/admin");
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+        AdminUser::from_request_parts(&mut parts, app_state).await
+    }
+
+    async fn set_admin(pool: &PgPool, user_id: Uuid, is_admin: bool) {
+        sqlx::query!("UPDATE users SET is_admin = $2 WHERE id = $1", user_id, is_admin)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn access_token(app_state: &AppState, user_id: Uuid) -> String {
+        let user = User::get_user_by_id(&app_state.pool, user_id).await.unwrap().unwrap();
+        generate_token_pair(
+            &app_state.pool,
+            app_state.clock.as_ref(),
+            &app_state.config.auth,
+            &user,
+            test_support::client_ip(),
+            "test",
+        )
+        .await
+        .unwrap()
+        .access_token
+    }
+
+    fn assert_forbidden(result: Result<AdminUser, AppError>) {
+        match result {
+            Err(AppError::ForbiddenError(message)) => assert_eq!(message, "Admin access required"),
+            other => panic!("expected 403, got {other:?}"),
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn only_current_admins_are_accepted(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let app_state = test_support::app_state(pool.clone(), Arc::new(SystemClock));
+        let user = test_support::create_user(&pool, &SystemClock, ADDRESS).await;
+
+        assert!(matches!(extract(&app_state, None).await, Err(AppError::UnauthorizedError(_))));
+
+        let non_admin_token = access_token(&app_state, user.id).await;
+        assert_forbidden(extract(&app_state, Some(&non_admin_token)).await);
+
+        set_admin(&pool, user.id, true).await;
+        let admin_token = access_token(&app_state, user.id).await;
+        let admin = extract(&app_state, Some(&admin_token)).await.unwrap();
+        assert_eq!(admin.user_id(), user.id);
+        // The claim is not enough on its own: a token minted before the
+        // promotion stays non-admin
+        assert_forbidden(extract(&app_state, Some(&non_admin_token)).await);
+
+        // Revoked mid-session, while the token still claims admin
+        set_admin(&pool, user.id, false).await;
+        assert_forbidden(extract(&app_state, Some(&admin_token)).await);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn api_keys_never_grant_admin(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let app_state = test_support::app_state(pool.clone(), Arc::new(SystemClock));
+        let user = test_support::create_user(&pool, &SystemClock, ADDRESS).await;
+        set_admin(&pool, user.id, true).await;
+
+        let (_, plaintext) = ApiKey::create(&pool, &SystemClock, user.id, "ci").await.unwrap();
+        assert_forbidden(extract(&app_state, Some(&plaintext)).await);
+    }
+}
@@ -0,0 +1,145 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    app_error::app_error::AppError,
+    models::{
+        api_keys::{ApiKey, API_KEY_PREFIX},
+        users::User,
+    },
+    services::tokens::{extra_claims, validate_access_token, JwtClaims, TokenType},
+    AppState,
+};
+
+/// Caller authenticated by a `Bearer` access token or API key
+///
+/// Handlers taking an `AuthUser` reject unauthenticated requests with 401.
+/// API keys are looked up on every request, so a revoked key is refused at
+/// once. Their claims are derived from the user and never grant admin.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub claims: JwtClaims,
+    /// Key the request was authenticated with, absent for access tokens
+    pub api_key_id: Option<Uuid>,
+}
+
+impl AuthUser {
+    pub fn user_id(&self) -> Uuid {
+        self.claims.sub
+    }
+
+    pub fn address(&self) -> &str {
+        &self.claims.address
+    }
+
+    /// Rejects callers authenticated with an API key, for actions that need
+    /// a wallet sign-in such as managing the keys themselves
+    pub fn require_session(&self) -> Result<(), AppError> {
+        if self.api_key_id.is_some() {
+            return Err(AppError::ForbiddenError("This action requires signing in with your wallet".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts.headers.get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| AppError::UnauthorizedError("Missing bearer token".to_string()))?;
+
+        let token = token.trim();
+        if token.starts_with(API_KEY_PREFIX) {
+            return authenticate_api_key(app_state, token).await;
+        }
+
+        let claims = validate_access_token(
+            &app_state.pool,
+            app_state.clock.as_ref(),
+            &app_state.config.auth,
+            token,
+        ).await?;
+
+        Ok(AuthUser { claims, api_key_id: None })
+    }
+}
+
+async fn authenticate_api_key(app_state: &AppState, plaintext: &str) -> Result<AuthUser, AppError> {
+    let invalid = || AppError::UnauthorizedError("Invalid or revoked API key".to_string());
+
+    let key = ApiKey::authenticate(&app_state.pool, app_state.clock.as_ref(), plaintext)
+        .await?
+        .ok_or_else(invalid)?;
+    let user = User::get_user_by_id(&app_state.pool, key.user_id)
+        .await?
+        .filter(User::is_active)
+        .ok_or_else(invalid)?;
+
+    let extra = extra_claims(&user);
+    let claims = JwtClaims {
+        sub: user.id,
+        address: user.ethereum_address,
+        is_admin: false,
+        jti: key.id.to_string(),
+        token_type: TokenType::Access,
+        epoch: user.token_epoch,
+        iat: key.created_at.and_utc().timestamp(),
+        exp: i64::MAX,
+        extra,
+    };
+
+    Ok(AuthUser { claims, api_key_id: Some(key.id) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support, utils::clock::SystemClock};
+    use axum::http::Request;
+    use sqlx::PgPool;
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    async fn extract(app_state: &Arc<AppState>, token: &str) -> Result<AuthUser, AppError> {
+        let (mut parts, _) = Request::builder()
+            .uri("/api/invoices")
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        AuthUser::from_request_parts(&mut parts, app_state).await
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn rotated_api_keys_are_refused(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let app_state = test_support::app_state(pool.clone(), Arc::new(SystemClock));
+        let user = test_support::create_user(&pool, &SystemClock, ADDRESS).await;
+
+        let mut old_keys = Vec::new();
+        for name in ["ci", "deploy", "billing"] {
+            let (key, plaintext) = ApiKey::create(&pool, &SystemClock, user.id, name).await.unwrap();
+            assert_eq!(extract(&app_state, &plaintext).await.unwrap().api_key_id, Some(key.id));
+            old_keys.push(plaintext);
+        }
+
+        let rotation = ApiKey::rotate_for_user(&pool, &SystemClock, user.id, Some("ci")).await.unwrap();
+        assert_eq!(rotation.revoked, 3);
+
+        for plaintext in &old_keys {
+            assert!(matches!(extract(&app_state, plaintext).await, Err(AppError::UnauthorizedError(_))));
+        }
+        let (key, plaintext) = rotation.minted.unwrap();
+        assert_eq!(extract(&app_state, &plaintext).await.unwrap().api_key_id, Some(key.id));
+    }
+}
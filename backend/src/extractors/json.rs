@@ -0,0 +1,110 @@
+use axum::{
+    extract::{rejection::JsonRejection, FromRequest, OptionalFromRequest, Request},
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+
+use crate::app_error::app_error::AppError;
+
+/// Drop-in replacement for `axum::Json` whose rejections use the crate's
+/// JSON error body instead of axum's plain-text responses
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+/// `Option<Json<T>>` is `None` when the request has no `Content-Type`, e.g.
+/// an empty body
+impl<T, S> OptionalFromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Option<Self>, Self::Rejection> {
+        let body = <axum::Json<T> as OptionalFromRequest<S>>::from_request(req, state).await?;
+
+        Ok(body.map(|axum::Json(value)| Json(value)))
+    }
+}
+
+impl<T: serde::Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        // The body text of data and syntax errors points at the line and column
+        match rejection {
+            JsonRejection::JsonDataError(e) => {
+                AppError::ValidationError(format!("Invalid JSON body: {}", e.body_text()))
+            }
+            JsonRejection::JsonSyntaxError(e) => {
+                AppError::ValidationError(format!("Malformed JSON body: {}", e.body_text()))
+            }
+            JsonRejection::MissingJsonContentType(_) => {
+                AppError::ValidationError("Expected a request with Content-Type: application/json".to_string())
+            }
+            other => AppError::ValidationError(other.body_text()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{header, Request}};
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Payload {
+        amount: u64,
+    }
+
+    async fn extract(content_type: Option<&str>, body: &str) -> Result<Json<Payload>, AppError> {
+        let mut request = Request::builder().method("POST").uri("/");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        <Json<Payload> as FromRequest<()>>::from_request(request.body(Body::from(body.to_string())).unwrap(), &()).await
+    }
+
+    fn rejection(result: Result<Json<Payload>, AppError>) -> String {
+        match result {
+            Err(AppError::ValidationError(message)) => message,
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn valid_body_is_extracted() {
+        let Json(payload) = extract(Some("application/json"), r#"{"amount": 12}"#).await.unwrap();
+        assert_eq!(payload.amount, 12);
+    }
+
+    #[tokio::test]
+    async fn truncated_body_is_a_validation_error() {
+        let message = rejection(extract(Some("application/json"), r#"{"amount": 1"#).await);
+        assert!(message.starts_with("Malformed JSON body:"), "{message}");
+        assert!(message.contains("line 1 column"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn wrong_value_type_names_the_field() {
+        let message = rejection(extract(Some("application/json"), r#"{"amount": "twelve"}"#).await);
+        assert!(message.starts_with("Invalid JSON body:"), "{message}");
+        assert!(message.contains("amount"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn missing_content_type_is_a_validation_error() {
+        let message = rejection(extract(None, r#"{"amount": 12}"#).await);
+        assert_eq!(message, "Expected a request with Content-Type: application/json");
+
+        let error = extract(Some("text/plain"), r#"{"amount": 12}"#).await.unwrap_err();
+        assert_eq!(error.status_code(), axum::http::StatusCode::BAD_REQUEST);
+        assert_eq!(error.code(), "VALIDATION");
+    }
+}
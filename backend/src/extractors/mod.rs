@@ -0,0 +1,3 @@
+pub mod admin_user;
+pub mod auth_user;
+pub mod json;
@@ -3,6 +3,10 @@ mod utils;
 mod routes;
 mod models;
 mod app_error;
+mod services;
+mod extractors;
+#[cfg(test)]
+mod test_support;
 
 use axum::{
     Router,
@@ -15,9 +19,8 @@ use axum_csrf::{
 use hyper::header;
 use tower_cookies::CookieManagerLayer;
 use tokio;
-use tower_http::{services::ServeDir, cors::CorsLayer};
-use hyper::http::{Method, HeaderName, HeaderValue};
-use std::{sync::Arc, path::Path};
+use tower_http::services::ServeDir;
+use std::{net::SocketAddr, sync::{Arc, OnceLock}, path::Path};
 use crate::app_error::app_error::AppError;
 // Removed incomplete use statement
 
@@ -26,6 +29,16 @@ pub struct AppState {
     pub vue_dist_path: String,
     pub config: config::app_config::AppConfig,
     pub pool: sqlx::PgPool,
+    pub clock: Arc<dyn utils::clock::Clock>,
+    pub notifier: Arc<dyn services::notifier::Notifier>,
+    pub readiness: Arc<services::readiness::Readiness>,
+    pub chain: services::chain::ChainClient,
+    pub invoice_tasks: Arc<services::invoice_tasks::InvoiceTasks>,
+    pub geo_locator: Arc<services::geoip::GeoLocator>,
+    pub signature_verifier: services::signature_pool::SignatureVerifier,
+    pub pool_monitor: Arc<services::pool_monitor::PoolMonitor>,
+    pub challenge_store: Arc<dyn models::challenge_store::ChallengeStore>,
+    pub index_templates: Arc<services::index_templates::IndexTemplates>,
 }
 
 pub struct AppCsrfConfig {
@@ -40,9 +53,9 @@ impl AppCsrfConfig {
         .with_key(Some(csrf_key.clone()))
         .with_cookie_path("/".to_string())
         .with_http_only(true)    
-        .with_secure(true)       
+        // `Secure` is added per request, see utils::cookie_security
+        .with_secure(false)
         .with_cookie_same_site(axum_csrf::SameSite::Strict) 
-        .with_secure(Some("csrf_token".to_string()).is_some())
         .with_cookie_name(&"_csrf".to_string());
 
         AppCsrfConfig { csrf_key, csrf_config }
@@ -68,58 +81,231 @@ async fn main() -> Result<(), AppError> {
     // Set up configuration
     let config = config::app_config::AppConfig::new()
         .expect("Failed to load configuration");
+    config.database.validate_db()?;
+    models::isolation::set_transaction_isolation(config.database.isolation.clone());
+    config.ethereum.validate_tokens()?;
+    config.ethereum.validate_rpc_concurrency()?;
+    config.approvals.validate_approvals()?;
+    config.server.trusted_proxy_networks()?;
+    config.auth.validate_auth()?;
+    config.auth.purpose_tags.validate_tags()?;
+    config.auth.validate_extra_claims()?;
+    // Deployments needing per-user claims, e.g. a plan tier, set their own hook here
+    let extra_claims = config.auth.extra_claims.clone();
+    services::tokens::set_extra_claims_hook(Box::new(move |_user| extra_claims.clone()));
+    config.lockout.validate_lockout()?;
+    config.tarpit.validate_tarpit()?;
+    config.challenge_store.validate_challenge_store()?;
+    config.rate_limits.offenders.validate_offenders()?;
+    config.retention.validate_retention()?;
+    let cors = utils::cors::CorsPolicy::new(&config.cors)?;
+    config.csrf.validate_csrf()?;
+    config.frontend.validate_frontend()?;
+    let preload_links = match services::early_hints::preload_link_header(&vue_dist_path, &config.frontend.early_hints) {
+        Err(e) if config.server.dev_mode => {
+            eprintln!("{}", e);
+            None
+        }
+        result => result?,
+    };
+    let index_templates = Arc::new(services::index_templates::IndexTemplates::new(
+        &vue_dist_path,
+        &config.frontend,
+        &config::app_config::run_env(),
+        config.server.dev_mode,
+    ).with_preload_links(preload_links));
+    match index_templates.validate_templates() {
+        Err(e) if config.server.dev_mode => eprintln!("{}", e),
+        result => result?,
+    }
+    config.signature_workers.validate_workers()?;
+    config.audit.validate_audit()?;
+    config.audit.validate_token_issuance(&config.outbox)?;
+    services::tokens::set_issuance_audit(config.audit.token_issuance.enabled);
+    config.session_keys.validate_session_keys()?;
+    config.anomaly_detection.validate_anomaly_detection()?;
+    services::pool_monitor::set_retry_after_secs(config.database.retry_after_secs);
+    models::auth_challenges::set_strict_address_validation(config.auth.strict_address_validation);
+    models::security_events::set_recorded_event_types(config.audit.enabled_event_types.clone());
+    models::security_events::set_event_metadata_limits(config.audit.metadata_limits);
+    services::time_check::check_clock_drift(&config.time_check).await?;
+    services::crypto_self_test::run_crypto_self_test(&config.crypto_self_test)?;
+
+    // Readiness is shared with the signal handlers and survives maintenance mode
+    let readiness = Arc::new(services::readiness::Readiness::new());
+    services::readiness::spawn_readiness_signal_handler(readiness.clone());
 
     // Create pool for postgres
-    let pool = config::app_config::init_config(config.clone())
-        .await
-        .map_err(|e| {
-            AppError::DatabaseError(format!("Failed to initialize database: {}", e))
-        })
-        .expect("Failed to initialize database");
+    let (app, pool) = match config::app_config::init_config(config.clone()).await {
+        Ok(pool) => {
+            models::security_events::check_event_type_labels(&pool).await?;
+            let app = build_app(
+                &config,
+                vue_dist_path,
+                pool.clone(),
+                readiness.clone(),
+                index_templates.clone(),
+                csrf_config.csrf_config.clone(),
+                cors,
+            );
+            (app, Some(pool))
+        }
+        Err(e) if config.server.degraded_start => {
+            eprintln!("Database unavailable, starting in maintenance mode: {}", e);
 
-    // Create application state
-    let app_state = Arc::new(AppState {
-        vue_dist_path: vue_dist_path.clone(),
-        config: config.clone(),
-        pool: pool.clone(),
-    });
+            let full_app = Arc::new(OnceLock::new());
+            spawn_database_reconnect(
+                full_app.clone(),
+                config.clone(),
+                vue_dist_path,
+                readiness.clone(),
+                index_templates.clone(),
+                csrf_config.csrf_config.clone(),
+                cors,
+            );
 
-    // configure CORS
-    let cors = CorsLayer::new()
-        .allow_origin("http://localhost:3000".parse::<HeaderValue>()
-            .map_err(|e| {
-                AppError::ServerError(format!("Failed to parse CORS origin: {}", e))
-            })?)
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS])
-        .allow_headers([
-            HeaderName::from_static("content-type"),
-            HeaderName::from_static("authorization"),
-            HeaderName::from_static("x-csrf-token"),
-        ])
-        .allow_credentials(true);
+            let app = routes::maintenance::create_degraded_routes(full_app, config.server.db_retry_interval_secs);
+            (app, None)
+        }
+        Err(e) => panic!("Failed to initialize database: {}", e),
+    };
 
-    // Create the router
-    let app = routes::router::create_app_routes(
-        app_state.clone(),
-        csrf_config.csrf_config.clone(),
-        cors,
-    );
+    // Refuses new requests once shutdown begins, in maintenance mode too
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        readiness.clone(),
+        services::readiness::reject_during_shutdown,
+    ));
 
     let addr = format!("{}:{}", config.server.host, config.server.port);
 
     let listener = tokio::net::TcpListener::bind(&addr)
         .await
         .expect("Failed to bind TCP listener");
-    println!("Listening on port {}", config.server.port);
+    utils::server_utils::print_startup_summary(&config, &addr, pool.is_none());
 
-    axum::serve(listener, app)
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(
-            utils::server_utils::shutdown_signal(config.clone())
+            utils::server_utils::shutdown_signal(config.clone(), readiness)
         )
         .await
         .expect("Failed to start server");
 
-    pool.close().await;
+    if let Some(pool) = pool {
+        pool.close().await;
+    }
 
     Ok(())
+}
+
+/// Builds the full application once the database pool is available
+fn build_app(
+    config: &config::app_config::AppConfig,
+    vue_dist_path: String,
+    pool: sqlx::PgPool,
+    readiness: Arc<services::readiness::Readiness>,
+    index_templates: Arc<services::index_templates::IndexTemplates>,
+    csrf_config: CsrfConfig,
+    cors: utils::cors::CorsPolicy,
+) -> Router {
+    // Create application state
+    let app_state = Arc::new(AppState {
+        vue_dist_path,
+        config: config.clone(),
+        pool: pool.clone(),
+        clock: Arc::new(utils::clock::SystemClock),
+        notifier: Arc::new(services::notifier::LogNotifier),
+        readiness,
+        chain: services::chain::ChainClient::new(&config.ethereum)
+            .expect("Failed to build Ethereum RPC client"),
+        invoice_tasks: Arc::new(services::invoice_tasks::InvoiceTasks::default()),
+        geo_locator: Arc::new(services::geoip::GeoLocator::new(&config.geoip)),
+        signature_verifier: services::signature_pool::SignatureVerifier::new(&config.signature_workers),
+        pool_monitor: Arc::new(services::pool_monitor::PoolMonitor::new(pool.clone(), &config.database)),
+        challenge_store: models::challenge_store::build_challenge_store(
+            pool.clone(),
+            &config.challenge_store,
+            std::time::Duration::from_secs(config.auth.expired_challenge_grace_secs),
+        )
+            .expect("Failed to build challenge store"),
+        index_templates,
+    });
+
+    // Queue an alert when challenge nonces keep repeating
+    let (alert_pool, alert_clock) = (pool.clone(), app_state.clock.clone());
+    let window_secs = config.challenge_store.nonce_reuse_window_secs;
+    models::auth_challenges::set_nonce_collision_alert(models::auth_challenges::NonceCollisionAlert {
+        threshold: config.challenge_store.nonce_collision_alert_threshold,
+        window: chrono::Duration::seconds(window_secs as i64),
+        notify: Box::new(move |collisions| {
+            let (pool, clock) = (alert_pool.clone(), alert_clock.clone());
+            tokio::spawn(async move {
+                let reported = models::auth_challenges::report_nonce_collisions(
+                    &pool, clock.as_ref(), collisions, window_secs,
+                ).await;
+                if let Err(e) = reported {
+                    eprintln!("Failed to queue nonce collision alert: {}", e);
+                }
+            });
+        }),
+    });
+
+    services::pool_monitor::spawn_pool_probe(app_state.pool_monitor.clone());
+
+    // Start background maintenance tasks
+    services::retention::spawn_event_retention_task(
+        pool.clone(),
+        app_state.clock.clone(),
+        config.retention.clone(),
+    );
+    if config.anomaly_detection.enabled {
+        services::anomaly::spawn_anomaly_detection_task(
+            pool.clone(),
+            app_state.clock.clone(),
+            app_state.notifier.clone(),
+            app_state.geo_locator.clone(),
+            config.anomaly_detection.clone(),
+        );
+    }
+    services::outbox::spawn_outbox_relay(
+        pool,
+        app_state.clock.clone(),
+        config.outbox.clone(),
+    );
+
+    // Create the router
+    routes::router::create_app_routes(app_state, csrf_config, cors)
+}
+
+/// Retries the database connection in the background while in maintenance
+/// mode, then switches the server over to the full application
+fn spawn_database_reconnect(
+    full_app: Arc<OnceLock<Router>>,
+    config: config::app_config::AppConfig,
+    vue_dist_path: String,
+    readiness: Arc<services::readiness::Readiness>,
+    index_templates: Arc<services::index_templates::IndexTemplates>,
+    csrf_config: CsrfConfig,
+    cors: utils::cors::CorsPolicy,
+) {
+    tokio::spawn(async move {
+        let retry_interval = std::time::Duration::from_secs(config.server.db_retry_interval_secs);
+
+        loop {
+            tokio::time::sleep(retry_interval).await;
+
+            match config::app_config::init_config(config.clone()).await {
+                Ok(pool) => {
+                    if let Err(e) = models::security_events::check_event_type_labels(&pool).await {
+                        eprintln!("Database schema is incompatible, staying in maintenance mode: {}", e);
+                        return;
+                    }
+                    let app = build_app(&config, vue_dist_path, pool, readiness, index_templates, csrf_config, cors);
+                    let _ = full_app.set(app);
+                    println!("Database connection established, leaving maintenance mode");
+                    return;
+                }
+                Err(e) => eprintln!("Database still unavailable: {}", e),
+            }
+        }
+    });
 }
\ No newline at end of file
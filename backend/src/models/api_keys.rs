@@ -0,0 +1,170 @@
+use uuid::Uuid;
+use chrono::NaiveDateTime;
+use rand::Rng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as, FromRow, PgConnection, PgPool};
+
+use crate::app_error::app_error::AppError;
+use crate::utils::clock::Clock;
+
+/// Opens every API key, telling them apart from JWTs in a `Bearer` header
+pub const API_KEY_PREFIX: &str = "ci_";
+
+/// Characters of the plaintext kept to identify a key in listings
+const DISPLAY_PREFIX_LEN: usize = 11;
+
+/// A long-lived credential acting as its user, without admin rights
+///
+/// Only the sha256 of the key is stored, and never loaded back; the
+/// plaintext is returned once, when the key is minted.
+#[derive(Debug, FromRow, Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    /// First characters of the key, e.g. `ci_1a2b3c4d`
+    pub key_prefix: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+/// Outcome of `ApiKey::rotate_for_user`
+#[derive(Debug)]
+pub struct ApiKeyRotation {
+    pub revoked: u64,
+    /// Replacement key and its plaintext, when one was requested
+    pub minted: Option<(ApiKey, String)>,
+}
+
+impl ApiKey {
+    /// Mints a key for the user, returning it with its plaintext
+    pub async fn create(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        name: &str,
+    ) -> Result<(ApiKey, String), AppError> {
+        let mut conn = pool.acquire().await?;
+        insert_key(&mut conn, clock.now(), user_id, name).await
+    }
+
+    pub async fn list_for_user(
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> Result<Vec<ApiKey>, AppError> {
+        let keys = query_as!(
+            ApiKey,
+            r#"
+            SELECT id, user_id, name, key_prefix, created_at, last_used_at, revoked_at
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    /// Returns the unrevoked key matching `plaintext` and marks it as used
+    pub async fn authenticate(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        plaintext: &str,
+    ) -> Result<Option<ApiKey>, AppError> {
+        let key = query_as!(
+            ApiKey,
+            r#"
+            UPDATE api_keys
+            SET last_used_at = $2
+            WHERE key_hash = $1
+              AND revoked_at IS NULL
+            RETURNING id, user_id, name, key_prefix, created_at, last_used_at, revoked_at
+            "#,
+            hash_key(plaintext),
+            clock.now()
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(key)
+    }
+
+    /// Revokes every key of the user and optionally mints a replacement
+    ///
+    /// Both happen in one transaction, so the replacement never coexists
+    /// with the keys it replaces.
+    pub async fn rotate_for_user(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        replacement_name: Option<&str>,
+    ) -> Result<ApiKeyRotation, AppError> {
+        let now = clock.now();
+        let mut tx = pool.begin().await?;
+
+        let revoked = query!(
+            r#"
+            UPDATE api_keys
+            SET revoked_at = $2
+            WHERE user_id = $1
+              AND revoked_at IS NULL
+            "#,
+            user_id,
+            now
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        let minted = match replacement_name {
+            Some(name) => Some(insert_key(&mut tx, now, user_id, name).await?),
+            None => None,
+        };
+
+        tx.commit().await?;
+
+        Ok(ApiKeyRotation { revoked, minted })
+    }
+}
+
+async fn insert_key(
+    conn: &mut PgConnection,
+    now: NaiveDateTime,
+    user_id: Uuid,
+    name: &str,
+) -> Result<(ApiKey, String), AppError> {
+    let plaintext = generate_key();
+
+    let key = query_as!(
+        ApiKey,
+        r#"
+        INSERT INTO api_keys (id, user_id, name, key_prefix, key_hash, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, user_id, name, key_prefix, created_at, last_used_at, revoked_at
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        &plaintext[..DISPLAY_PREFIX_LEN],
+        hash_key(&plaintext),
+        now
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok((key, plaintext))
+}
+
+fn generate_key() -> String {
+    let bytes: [u8; 32] = rand::rng().random();
+    format!("{}{}", API_KEY_PREFIX, hex::encode(bytes))
+}
+
+fn hash_key(plaintext: &str) -> String {
+    hex::encode(Sha256::digest(plaintext.as_bytes()))
+}
@@ -1,21 +1,49 @@
 use uuid::Uuid;
-use chrono::{NaiveDateTime, Utc};
+use chrono::{Duration, NaiveDateTime, Timelike};
 use serde::{Deserialize, Serialize};
-use sqlx::{query, query_as, FromRow, PgPool};
-use validator::Validate;
+use sqlx::FromRow;
+use validator::{Validate, ValidationError};
 use rand::Rng;
 use sha3::{Keccak256, Digest};
 use hex;
 use secp256k1::{Message, PublicKey, Secp256k1};
 use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
 use tiny_keccak::{Hasher, Keccak};
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
 use std::str::FromStr;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Mutex, OnceLock},
+};
+use sqlx::PgPool;
 
 use crate::app_error::app_error::AppError;
+use crate::config::app_config::{AppConfig, PurposeTags};
+use crate::models::challenge_store::ChallengeStore;
+use crate::models::outbox::OutboxMessage;
+use crate::utils::clock::Clock;
+use crate::utils::eip712::LoginTypedData;
+use crate::utils::i18n::{ExpiryDisplay, LocalizedStatement};
+use crate::utils::siwe::{SiweMessage, TIMESTAMP_FORMAT};
 
 // https://eips.ethereum.org/EIPS/eip-4361
 
-#[derive(Debug, FromRow)]
+/// How long a challenge can be signed for
+const CHALLENGE_LIFETIME_MINUTES: i64 = 5;
+
+/// Stands in for the nonce of previewed messages, same length as a real one
+const PREVIEW_NONCE: &str = "00000000000000000000000000000000";
+
+/// Outbox aggregate and event type of nonce collision alerts
+const OUTBOX_AGGREGATE: &str = "challenge";
+const OUTBOX_NONCE_COLLISIONS: &str = "challenge.nonce_collisions";
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct AuthChallenge {
     pub id: Uuid,
     pub ethereum_address: String,
@@ -26,153 +54,583 @@ pub struct AuthChallenge {
     pub created_at: NaiveDateTime,
     pub domain: String,
     pub chal_timestamp: NaiveDateTime,
+    pub chain_id: i64,
+    pub uri: String,
+    /// Locale of the sign-in statement, absent for non-login challenges
+    pub locale: Option<String>,
+}
+
+/// A challenge ready to be stored, see `ChallengeStore::create`
+#[derive(Debug, Clone)]
+pub struct NewChallenge {
+    pub id: Uuid,
+    pub ethereum_address: String,
+    pub nonce: String,
+    pub challenge_message: String,
+    pub expires_at: NaiveDateTime,
+    pub domain: String,
+    pub chal_timestamp: NaiveDateTime,
+    pub chain_id: i64,
+    pub uri: String,
+    pub locale: Option<String>,
+}
+
+impl NewChallenge {
+    fn new(
+        now: NaiveDateTime,
+        normalized_address: String,
+        scope: &ChallengeScope,
+        nonce: String,
+        challenge_message: String,
+        locale: Option<String>,
+    ) -> Self {
+        NewChallenge {
+            id: Uuid::new_v4(),
+            ethereum_address: normalized_address,
+            nonce,
+            challenge_message,
+            // Same computation as for the message, so both carry the same expiry
+            expires_at: challenge_expiry(now),
+            domain: scope.domain.clone(),
+            chal_timestamp: now,
+            chain_id: scope.chain_id as i64,
+            uri: scope.uri.clone(),
+            locale,
+        }
+    }
+}
+
+/// What happens to the address's outstanding challenges when one is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActiveChallenges {
+    /// Leave them untouched
+    Keep,
+    /// Evict the oldest so that at most this many remain, the new one included
+    Cap(u32),
+    /// Invalidate all of them
+    Replace,
+}
+
+/// Application binding embedded in every challenge message
+///
+/// Including the chain id and URI prevents a signature captured for one chain
+/// or application from being replayed on another sharing the same domain.
+#[derive(Debug, Clone)]
+pub struct ChallengeScope {
+    pub domain: String,
+    pub uri: String,
+    pub chain_id: u64,
+    pub expiry_display: ExpiryDisplay,
+    /// Clock skew tolerated on the signed `Issued At`, which must fall between
+    /// the challenge creation and now
+    pub max_timestamp_skew: Duration,
+    pub purpose_tags: PurposeTags,
+}
+
+/// What a signed challenge authorizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignaturePurpose {
+    Login,
+    AcceptInvoice,
+}
+
+impl ChallengeScope {
+    pub fn from_config(config: &AppConfig) -> Self {
+        ChallengeScope {
+            domain: config.auth.domain.clone(),
+            uri: config.auth.uri.clone(),
+            chain_id: u64::from(config.ethereum.chain_id),
+            expiry_display: ExpiryDisplay::from_config(&config.auth),
+            max_timestamp_skew: Duration::seconds(config.auth.max_timestamp_skew_secs as i64),
+            purpose_tags: config.auth.purpose_tags.clone(),
+        }
+    }
+
+    /// Tag opening the statement of challenges signed for `purpose`
+    pub fn purpose_tag(&self, purpose: SignaturePurpose) -> &str {
+        match purpose {
+            SignaturePurpose::Login => &self.purpose_tags.login,
+            SignaturePurpose::AcceptInvoice => &self.purpose_tags.accept_invoice,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct ChallengeRequest {
-    #[validate(length(min = 42, max = 42))]
+    #[validate(custom(function = "validate_eth_address"))]
     pub ethereum_address: String,
 }
 
+/// How the wallet signed a login challenge
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureType {
+    /// EIP-191 `personal_sign` over the SIWE plaintext message
+    #[default]
+    PersonalSign,
+    /// EIP-712 `eth_signTypedData_v4` over the `Login` typed data
+    Eip712,
+}
+
+#[derive(Debug, Serialize)]
 pub struct ChallengeResponse {
     pub challenge_id: Uuid,
     pub message: String,
+    /// Same challenge as EIP-712 typed data, for wallets that prefer it
+    pub typed_data: serde_json::Value,
+    pub expires_at: NaiveDateTime,
+}
+
+impl From<AuthChallenge> for ChallengeResponse {
+    fn from(challenge: AuthChallenge) -> Self {
+        let typed_data = challenge.typed_data().to_json();
+        ChallengeResponse {
+            challenge_id: challenge.id,
+            message: challenge.challenge_message,
+            typed_data,
+            expires_at: challenge.expires_at,
+        }
+    }
+}
+
+/// State of a stored challenge, a used one counting as used even once expired
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengeStatus {
+    Active,
+    Used,
+    Expired,
+}
+
+/// A challenge as shown to admins, without its nonce or message
+///
+/// The message embeds the nonce, so neither leaves the server: a listed
+/// challenge that is still active could otherwise be signed by someone else.
+#[derive(Debug, Serialize)]
+pub struct ChallengeSummary {
+    pub id: Uuid,
+    pub ethereum_address: String,
+    pub status: ChallengeStatus,
+    /// Login challenges carry the locale of their statement, others have none
+    pub locale: Option<String>,
+    pub domain: String,
+    pub uri: String,
+    pub chain_id: i64,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+impl ChallengeSummary {
+    pub fn new(challenge: AuthChallenge, now: NaiveDateTime) -> Self {
+        let status = if challenge.used {
+            ChallengeStatus::Used
+        } else if challenge.expires_at <= now {
+            ChallengeStatus::Expired
+        } else {
+            ChallengeStatus::Active
+        };
+
+        ChallengeSummary {
+            id: challenge.id,
+            ethereum_address: challenge.ethereum_address,
+            status,
+            locale: challenge.locale,
+            domain: challenge.domain,
+            uri: challenge.uri,
+            chain_id: challenge.chain_id,
+            created_at: challenge.created_at,
+            expires_at: challenge.expires_at,
+        }
+    }
+}
+
+/// One page of a user's challenges, newest first
+#[derive(Debug, Serialize)]
+pub struct ChallengePage {
+    pub challenges: Vec<ChallengeSummary>,
+    pub has_more: bool,
+}
+
+/// Login message a challenge request would produce, with a placeholder nonce
+#[derive(Debug, Serialize)]
+pub struct ChallengePreview {
+    pub message: String,
+    pub typed_data: serde_json::Value,
     pub expires_at: NaiveDateTime,
 }
 
 impl AuthChallenge {
+    /// Builds the login message a challenge issued now would carry, without
+    /// storing anything
+    ///
+    /// Only the nonce differs from the real message: the timestamps are those
+    /// of a challenge issued at this instant.
+    pub fn preview_for_addr(
+        clock: &dyn Clock,
+        address: &str,
+        scope: &ChallengeScope,
+        statement: &LocalizedStatement,
+    ) -> Result<ChallengePreview, AppError> {
+        let normalized_address = normalize_ethereum_address(address)?;
+        let now = clock.now();
+        let expires_at = challenge_expiry(now);
+
+        let typed_data = LoginTypedData {
+            domain: scope.domain.clone(),
+            chain_id: scope.chain_id,
+            wallet: normalized_address.clone(),
+            uri: scope.uri.clone(),
+            nonce: PREVIEW_NONCE.to_string(),
+            issued_at: now.format(TIMESTAMP_FORMAT).to_string(),
+        };
+
+        Ok(ChallengePreview {
+            message: create_siwe_message(&normalized_address, scope, statement, PREVIEW_NONCE, &now, &expires_at),
+            typed_data: typed_data.to_json(),
+            expires_at,
+        })
+    }
+
+    /// Issues a new challenge for the address
+    ///
+    /// At most `max_active` unused, unexpired challenges are kept per address:
+    /// when the cap is reached the oldest ones are evicted to make room.
     pub async fn create_challenge_for_addr(
-        pool: &PgPool,
+        store: &dyn ChallengeStore,
+        clock: &dyn Clock,
         address: &str,
-        domain: &str,
+        scope: &ChallengeScope,
+        statement: &LocalizedStatement,
+        max_active: u32,
     ) -> Result<AuthChallenge, AppError> {
-        let now = Utc::now().naive_utc();
-        let expires_at = now + chrono::Duration::minutes(5);
+        let normalized_address = normalize_ethereum_address(address)?;
+        let now = clock.now();
 
-        let nonce = nonce_gen();
+        let nonce = unique_nonce(store, now).await?;
+        let expires_at = challenge_expiry(now);
+        let challenge_message = create_siwe_message(&normalized_address, scope, statement, &nonce, &now, &expires_at);
+        let new_challenge = NewChallenge::new(
+            now,
+            normalized_address,
+            scope,
+            nonce,
+            challenge_message,
+            Some(statement.locale.clone()),
+        );
 
+        store.create(new_challenge, ActiveChallenges::Cap(max_active)).await
+    }
+
+    /// Invalidates every outstanding challenge for the address and issues a
+    /// fresh one, atomically
+    pub async fn refresh_challenge_for_addr(
+        store: &dyn ChallengeStore,
+        clock: &dyn Clock,
+        address: &str,
+        scope: &ChallengeScope,
+        statement: &LocalizedStatement,
+    ) -> Result<AuthChallenge, AppError> {
         let normalized_address = normalize_ethereum_address(address)?;
-        let challenge_message = create_siwe_message(
-            &normalized_address,
-            domain,
-            &nonce,
-            &now
-        );
+        let now = clock.now();
 
-        let auth_challenge = query_as!(
-            AuthChallenge,
-            r#"
-            INSERT INTO auth_challenges (
-                id,
-                ethereum_address,
-                nonce,
-                challenge_message,
-                expires_at,
-                used,
-                domain,
-                chal_timestamp
-            )
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            RETURNING id, ethereum_address, nonce, challenge_message, expires_at, used, created_at, domain, chal_timestamp
-            "#,
-            Uuid::new_v4(),
+        let nonce = unique_nonce(store, now).await?;
+        let expires_at = challenge_expiry(now);
+        let challenge_message = create_siwe_message(&normalized_address, scope, statement, &nonce, &now, &expires_at);
+        let new_challenge = NewChallenge::new(
+            now,
             normalized_address,
+            scope,
             nonce,
             challenge_message,
-            expires_at,
-            false,
-            domain,
-            now,
-        )
-        .fetch_one(pool)
-        .await?;
+            Some(statement.locale.clone()),
+        );
 
-        Ok(auth_challenge)
+        store.create(new_challenge, ActiveChallenges::Replace).await
+    }
+
+    /// Issues a challenge the recipient of an invoice signs to accept it
+    ///
+    /// The message names the invoice so the signature cannot be used to
+    /// accept any other invoice.
+    pub async fn create_acceptance_challenge(
+        store: &dyn ChallengeStore,
+        clock: &dyn Clock,
+        address: &str,
+        scope: &ChallengeScope,
+        invoice_id: Uuid,
+    ) -> Result<AuthChallenge, AppError> {
+        let normalized_address = normalize_ethereum_address(address)?;
+        let now = clock.now();
+
+        let nonce = unique_nonce(store, now).await?;
+        let expires_at = challenge_expiry(now);
+        let challenge_message = create_acceptance_message(
+            &normalized_address,
+            scope,
+            invoice_id,
+            &nonce,
+            &now,
+            &expires_at,
+        );
+        let new_challenge = NewChallenge::new(now, normalized_address, scope, nonce, challenge_message, None);
+
+        store.create(new_challenge, ActiveChallenges::Keep).await
     }
 
     pub async fn find_active_challenge(
-        pool: PgPool,
+        store: &dyn ChallengeStore,
+        clock: &dyn Clock,
         address: &str,
         challenge_id: Uuid,
     ) -> Result<Option<AuthChallenge>, AppError> {
         let normalized_address = normalize_ethereum_address(address)?;
-        let now = Utc::now().naive_utc();
-
-        let challenge = query_as!(
-            AuthChallenge,
-            r#"
-            SELECT id, ethereum_address, nonce, challenge_message, expires_at, used, created_at, domain, chal_timestamp
-            FROM auth_challenges
-            WHERE ethereum_address = $1
-              AND id = $2
-              AND used = false
-              AND expires_at > $3
-            "#,
-            normalized_address,
-            challenge_id,
-            now
-        )
-        .fetch_optional(&pool)
-        .await?;
 
-        Ok(challenge)
+        store.find_active(&normalized_address, challenge_id, clock.now()).await
+    }
+
+    /// Finds a challenge that expired within the last `grace`, unused
+    ///
+    /// Lets a late signer be told to request a new challenge instead of
+    /// being told it never existed. The challenge is never accepted.
+    pub async fn find_recently_expired(
+        store: &dyn ChallengeStore,
+        clock: &dyn Clock,
+        address: &str,
+        challenge_id: Uuid,
+        grace: Duration,
+    ) -> Result<Option<AuthChallenge>, AppError> {
+        if grace <= Duration::zero() {
+            return Ok(None);
+        }
+        let normalized_address = normalize_ethereum_address(address)?;
+        let now = clock.now();
+
+        store.find_expired(&normalized_address, challenge_id, now - grace, now).await
     }
 
+    /// Consumes the challenge, failing when another request already did
     pub async fn mark_as_used(
-        pool: &PgPool,
+        store: &dyn ChallengeStore,
         challenge_id: Uuid,
     ) -> Result<(), AppError> {
-
-        query!(
-            r#"
-            UPDATE auth_challenges
-            SET used = true
-            WHERE id = $1
-            "#,
-            challenge_id
-        )
-        .execute(pool)
-        .await?;
+        if !store.mark_used(challenge_id).await? {
+            return Err(AppError::UnauthorizedError("Challenge was already used".to_string()));
+        }
 
         Ok(())
     }
 
     pub async fn cleanup_expired(
-        pool: &PgPool,
+        store: &dyn ChallengeStore,
+        clock: &dyn Clock,
     ) -> Result<u64, AppError> {
-        let now = Utc::now().naive_utc();
+        store.cleanup_expired(clock.now()).await
+    }
+
+    /// Counts challenges created since `since` and how many of them were used
+    pub async fn count_since(
+        store: &dyn ChallengeStore,
+        since: NaiveDateTime,
+    ) -> Result<(i64, i64), AppError> {
+        store.count_since(since).await
+    }
 
-        let result = query!(
-            r#"
-            DELETE FROM auth_challenges
-            WHERE expires_at < $1
-            "#,
-            now
-        )
-        .execute(pool)
-        .await?;
+    /// Checks that the signed message is bound to this application and chain
+    ///
+    /// `signed_message` is the message the wallet signed: the stored one, or
+    /// one the client rendered from the challenge with its own `Issued At`.
+    /// It is parsed back and its domain, URI, chain id, address and nonce
+    /// must match both the stored challenge and the expected scope. Its
+    /// statement must open with the tag of `purpose`, so a challenge signed
+    /// for one action cannot be used for another. Its `Issued At` must fall
+    /// between the challenge creation and `now`, give or take the allowed skew.
+    pub fn verify_scope(
+        &self,
+        scope: &ChallengeScope,
+        purpose: SignaturePurpose,
+        signed_message: &str,
+        now: NaiveDateTime,
+    ) -> Result<(), AppError> {
+        let message = SiweMessage::parse(signed_message)
+            .map_err(|_| AppError::UnauthorizedError("Malformed challenge message".to_string()))?;
+
+        // The signed statement must open with this action's tag, e.g. `[LOGIN] ...`
+        let tagged = message.statement.as_deref()
+            .and_then(|statement| statement.strip_prefix(scope.purpose_tag(purpose)))
+            .is_some_and(|rest| rest.starts_with(' '));
+        if !tagged {
+            return Err(AppError::UnauthorizedError("Challenge was not issued for this action".to_string()));
+        }
+
+        let stored_chain_id = u64::try_from(self.chain_id).ok();
+        if message.chain_id != scope.chain_id || stored_chain_id != Some(scope.chain_id) {
+            return Err(AppError::UnauthorizedError("Chain ID mismatch".to_string()));
+        }
+        if message.uri != scope.uri || self.uri != scope.uri {
+            return Err(AppError::UnauthorizedError("URI mismatch".to_string()));
+        }
+        if message.domain != scope.domain || self.domain != scope.domain {
+            return Err(AppError::UnauthorizedError("Domain mismatch".to_string()));
+        }
+        if message.address != self.ethereum_address || message.nonce != self.nonce {
+            return Err(AppError::UnauthorizedError("Challenge message does not match the challenge".to_string()));
+        }
+        if message.expiration_time != Some(self.expires_at) {
+            return Err(AppError::UnauthorizedError("Challenge message expiry does not match the challenge".to_string()));
+        }
+        // Neither pre-signed before its challenge was issued nor dated ahead of our clock
+        if message.issued_at < self.chal_timestamp - scope.max_timestamp_skew
+            || message.issued_at > now + scope.max_timestamp_skew
+        {
+            return Err(AppError::UnauthorizedError("Challenge message timestamp is outside the allowed window".to_string()));
+        }
 
-        Ok(result.rows_affected())
+        Ok(())
     }
 
-    pub fn is_valid(&self) -> bool {
-        let now = Utc::now().naive_utc();
+    /// EIP-712 representation of this challenge, bound to the same scope and nonce
+    pub fn typed_data(&self) -> LoginTypedData {
+        LoginTypedData {
+            domain: self.domain.clone(),
+            chain_id: u64::try_from(self.chain_id).unwrap_or_default(),
+            wallet: self.ethereum_address.clone(),
+            uri: self.uri.clone(),
+            nonce: self.nonce.clone(),
+            issued_at: self.chal_timestamp.format(TIMESTAMP_FORMAT).to_string(),
+        }
+    }
+
+    /// Recovers the address that signed this challenge
+    ///
+    /// `personal_sign` signatures cover `signed_message`, checked beforehand
+    /// by `verify_scope`, EIP-712 ones the digest of `typed_data()`.
+    pub fn recover_login_signer(
+        &self,
+        signature_type: SignatureType,
+        signature: &str,
+        signed_message: &str,
+    ) -> Result<String, AppError> {
+        match signature_type {
+            SignatureType::PersonalSign => Ok(recover_signer(signature, signed_message)?),
+            SignatureType::Eip712 => {
+                let digest = self.typed_data().signing_hash()?;
+                Ok(recover_signer_from_digest(signature, &digest)?)
+            }
+        }
+    }
+
+    pub fn is_valid(&self, clock: &dyn Clock) -> bool {
+        let now = clock.now();
         !self.used && self.expires_at > now
     }
 }
 
+/// Expiry of a challenge issued at `now`, truncated to the second so that the
+/// time written in the message is exactly the one enforced
+fn challenge_expiry(now: NaiveDateTime) -> NaiveDateTime {
+    let expires_at = now + Duration::minutes(CHALLENGE_LIFETIME_MINUTES);
+    expires_at.with_nanosecond(0).unwrap_or(expires_at)
+}
+
 fn nonce_gen() -> String {
     let mut rng = rand::rng();
     let bytes: [u8; 16] = rng.random();
     hex::encode(bytes)
 }
 
-fn normalize_ethereum_address(address: &str) -> Result<String, AppError> {
+/// Nonces drawn for one challenge before giving up
+const MAX_NONCE_ATTEMPTS: u32 = 5;
+
+/// Draws a nonce the store has not issued within its reuse window
+///
+/// 128 random bits should never repeat, so a repeat means the generator is
+/// failing: it is refused and counted towards `NonceCollisionAlert` rather
+/// than silently issued twice.
+async fn unique_nonce(store: &dyn ChallengeStore, now: NaiveDateTime) -> Result<String, AppError> {
+    for _ in 0..MAX_NONCE_ATTEMPTS {
+        let nonce = nonce_gen();
+        if store.claim_nonce(&nonce, now).await? {
+            return Ok(nonce);
+        }
+        eprintln!("Challenge nonce {} was already issued within the reuse window, drawing another", nonce);
+        record_nonce_collision(now);
+    }
+
+    Err(AppError::ServerError("Could not draw an unused challenge nonce".to_string()))
+}
+
+/// Raised when `threshold` nonce collisions happen within `window`
+pub struct NonceCollisionAlert {
+    pub threshold: u32,
+    pub window: Duration,
+    /// Called with the number of collisions, from a request task
+    pub notify: Box<dyn Fn(usize) + Send + Sync>,
+}
+
+/// Set once at startup; without it collisions are only logged
+static NONCE_COLLISION_ALERT: OnceLock<NonceCollisionAlert> = OnceLock::new();
+
+/// Collisions within the alert window, counted per process like the
+/// generator they reflect on
+static NONCE_COLLISIONS: Mutex<VecDeque<NaiveDateTime>> = Mutex::new(VecDeque::new());
+
+pub fn set_nonce_collision_alert(alert: NonceCollisionAlert) {
+    if NONCE_COLLISION_ALERT.set(alert).is_err() {
+        eprintln!("Nonce collision alert already set, keeping the first one");
+    }
+}
+
+/// Counts a collision, raising the alert and starting the count over once
+/// the threshold is reached
+fn record_nonce_collision(now: NaiveDateTime) {
+    let Some(alert) = NONCE_COLLISION_ALERT.get() else {
+        return;
+    };
+
+    let collisions = {
+        let mut collisions = NONCE_COLLISIONS.lock().unwrap();
+        collisions.push_back(now);
+        while collisions.front().is_some_and(|at| *at <= now - alert.window) {
+            collisions.pop_front();
+        }
+        if collisions.len() < alert.threshold as usize {
+            return;
+        }
+        let count = collisions.len();
+        collisions.clear();
+        count
+    };
+
+    (alert.notify)(collisions);
+}
+
+/// Queues the high-severity `challenge.nonce_collisions` outbox message
+pub async fn report_nonce_collisions(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    collisions: usize,
+    window_secs: u64,
+) -> Result<(), AppError> {
+    let mut conn = pool.acquire().await?;
+    OutboxMessage::enqueue(
+        &mut conn,
+        clock.now(),
+        OUTBOX_AGGREGATE,
+        Uuid::new_v4(),
+        OUTBOX_NONCE_COLLISIONS,
+        serde_json::json!({
+            "severity": "high",
+            "collisions": collisions,
+            "window_secs": window_secs,
+            "reason": "challenge nonces repeated, the random generator may be failing",
+        }),
+    ).await
+}
+
+pub fn normalize_ethereum_address(address: &str) -> Result<String, AppError> {
     let address = address.trim();
 
-    if !address.starts_with("0x") 
-        || address.len() != 42 
-        || !address.chars().skip(2).all(|c| c.is_ascii_hexdigit()) {
+    if !is_ethereum_address(address) {
         return Err(AppError::OtherError(
             format!("Invalid address: {}", address)
         ));
@@ -180,58 +638,307 @@ fn normalize_ethereum_address(address: &str) -> Result<String, AppError> {
     Ok(address.to_lowercase())
 }
 
+fn is_ethereum_address(address: &str) -> bool {
+    address.len() == 42
+        && address.starts_with("0x")
+        && address.chars().skip(2).all(|c| c.is_ascii_hexdigit())
+}
+
+/// Whether `validate_eth_address` checks the prefix and hex digits, set once at startup
+static STRICT_ADDRESS_VALIDATION: AtomicBool = AtomicBool::new(true);
+
+pub fn set_strict_address_validation(strict: bool) {
+    STRICT_ADDRESS_VALIDATION.store(strict, Ordering::Relaxed);
+}
+
+/// Validator for address fields of request bodies and queries
+///
+/// Addresses must be `0x` followed by 40 hex digits, surrounding whitespace
+/// allowed, so a malformed one is a field-level 400 like any other invalid
+/// input. With `auth.strict_address_validation` off only the length is
+/// checked and the rest is left to `normalize_ethereum_address`.
+pub fn validate_eth_address(address: &str) -> Result<(), ValidationError> {
+    check_eth_address(address, STRICT_ADDRESS_VALIDATION.load(Ordering::Relaxed))
+}
+
+fn check_eth_address(address: &str, strict: bool) -> Result<(), ValidationError> {
+    let address = address.trim();
+
+    if address.len() != 42 {
+        return Err(ValidationError::new("address_length")
+            .with_message("address must be 42 characters long".into()));
+    }
+    if !strict {
+        return Ok(());
+    }
+    if !address.starts_with("0x") {
+        return Err(ValidationError::new("address_prefix")
+            .with_message("address must start with 0x".into()));
+    }
+    if !is_ethereum_address(address) {
+        return Err(ValidationError::new("address_not_hex")
+            .with_message("address must be 40 hexadecimal digits after 0x".into()));
+    }
+
+    Ok(())
+}
+
+/// Builds the login message with the localized statement
+///
+/// Only the statement is translated: the EIP-4361 fields stay canonical so
+/// the message can still be parsed and verified.
 fn create_siwe_message(
     address: &str,
-    domain: &str,
+    scope: &ChallengeScope,
+    statement: &LocalizedStatement,
+    nonce: &str,
+    timestamp: &NaiveDateTime,
+    expires_at: &NaiveDateTime,
+) -> String {
+    build_message(
+        address,
+        scope,
+        format!(
+            "{} {}",
+            scope.purpose_tag(SignaturePurpose::Login),
+            statement.render(&scope.domain, address, &scope.expiry_display.render(expires_at))
+        ),
+        nonce,
+        timestamp,
+        expires_at,
+    )
+}
+
+pub fn create_acceptance_message(
+    address: &str,
+    scope: &ChallengeScope,
+    invoice_id: Uuid,
     nonce: &str,
     timestamp: &NaiveDateTime,
+    expires_at: &NaiveDateTime,
 ) -> String {
-    format!(
-        "Sign this message to verify ownership of this address {}: {}. This is a one-time nonce: {}. Timestamp: {}",
+    build_message(
         address,
-        domain,
+        scope,
+        format!(
+            "{} Sign this message to accept invoice {}. This request expires at {}.",
+            scope.purpose_tag(SignaturePurpose::AcceptInvoice),
+            invoice_id,
+            scope.expiry_display.render(expires_at)
+        ),
         nonce,
-        timestamp.format("%Y-%m-%d %H:%M:%S")
+        timestamp,
+        expires_at,
     )
 }
 
+fn build_message(
+    address: &str,
+    scope: &ChallengeScope,
+    statement: String,
+    nonce: &str,
+    timestamp: &NaiveDateTime,
+    expires_at: &NaiveDateTime,
+) -> String {
+    SiweMessage {
+        domain: scope.domain.clone(),
+        address: address.to_string(),
+        statement: Some(statement),
+        uri: scope.uri.clone(),
+        version: "1".to_string(),
+        chain_id: scope.chain_id,
+        nonce: nonce.to_string(),
+        issued_at: *timestamp,
+        expiration_time: Some(*expires_at),
+    }
+    .to_string()
+}
+
 pub fn verify_signature(
     signature: &str,
     message: &str,
     expected_address: &str,
 ) -> Result<bool, AppError> {
+    match check_signer(signature, message, expected_address) {
+        Ok(()) => Ok(true),
+        Err(SignatureError::SignerMismatch) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Checks that `expected_address` produced `signature` over `message`,
+/// telling a malformed signature apart from one by another address
+pub fn check_signer(
+    signature: &str,
+    message: &str,
+    expected_address: &str,
+) -> Result<(), SignatureError> {
+    let recovered_address = recover_signer(signature, message)?;
+
+    if !recovered_address.eq_ignore_ascii_case(expected_address.trim()) {
+        return Err(SignatureError::SignerMismatch);
+    }
+    Ok(())
+}
+
+/// Recovers the address that produced a personal_sign signature over `message`
+pub fn recover_signer(
+    signature: &str,
+    message: &str,
+) -> Result<String, SignatureError> {
+    recover_signer_from_digest(signature, &personal_message_hash(message))
+}
+
+/// EIP-191 digest a wallet signs for `personal_sign(message)`
+pub fn personal_message_hash(message: &str) -> [u8; 32] {
     let prefixed_message = format!("\x19Ethereum Signed Message:\n{}", message.len()) + message;
-    let message_hash = Keccak256::digest(prefixed_message.as_bytes());
 
-    let signature_bytes = hex::decode(&signature[2..])
-        .map_err(|_| AppError::OtherError("Invalid signature format".to_string()))?;
+    Keccak256::digest(prefixed_message.as_bytes()).into()
+}
+
+/// Why a signature was refused, kept apart so operators can tell clients
+/// sending garbage from wallets signing the wrong message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    /// Neither hex nor base64
+    MalformedEncoding(&'static str),
+    /// Decodes, but not to the 65 bytes of `r || s || v`
+    WrongLength(&'static str),
+    /// `v` is none of 0, 1, 27 or 28
+    InvalidRecoveryId(u8),
+    /// `r || s` is not a valid secp256k1 signature, or recovers no key
+    Unrecoverable(&'static str),
+    /// A valid signature, by another address than the expected one
+    SignerMismatch,
+}
+
+/// Categories of `SignatureError`, in the order of `SIGNATURE_FAILURES`
+pub const SIGNATURE_FAILURE_CATEGORIES: [&str; 5] = [
+    "malformed_encoding",
+    "wrong_length",
+    "invalid_recovery_id",
+    "unrecoverable",
+    "signer_mismatch",
+];
+
+/// Refused signatures per category since startup, served by `/metrics`
+static SIGNATURE_FAILURES: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+impl SignatureError {
+    fn index(&self) -> usize {
+        match self {
+            SignatureError::MalformedEncoding(_) => 0,
+            SignatureError::WrongLength(_) => 1,
+            SignatureError::InvalidRecoveryId(_) => 2,
+            SignatureError::Unrecoverable(_) => 3,
+            SignatureError::SignerMismatch => 4,
+        }
+    }
+
+    /// Label of the failure in `/metrics` and security event metadata
+    pub fn category(&self) -> &'static str {
+        SIGNATURE_FAILURE_CATEGORIES[self.index()]
+    }
+
+    /// Counts the failure in `/metrics`, for use where the refusal is final
+    ///
+    /// Not counted on creation, since some callers fall back to another
+    /// check, such as EIP-1271 for contract wallets.
+    pub fn recorded(self) -> Self {
+        SIGNATURE_FAILURES[self.index()].fetch_add(1, Ordering::Relaxed);
+        self
+    }
+}
+
+/// Refused signatures since startup, per category
+pub fn signature_failure_counts() -> impl Iterator<Item = (&'static str, u64)> {
+    SIGNATURE_FAILURE_CATEGORIES
+        .into_iter()
+        .zip(SIGNATURE_FAILURES.iter().map(|count| count.load(Ordering::Relaxed)))
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::MalformedEncoding(reason)
+            | SignatureError::WrongLength(reason) => write!(f, "Invalid signature format: {}", reason),
+            SignatureError::InvalidRecoveryId(v) => write!(f, "Invalid recovery ID: {}", v),
+            SignatureError::Unrecoverable(reason) => write!(f, "{}", reason),
+            SignatureError::SignerMismatch => write!(f, "Signature was made by another address"),
+        }
+    }
+}
+
+/// Malformed signatures keep their historical `OtherError`, a mismatch is a 401
+impl From<SignatureError> for AppError {
+    fn from(error: SignatureError) -> Self {
+        match error {
+            SignatureError::SignerMismatch => AppError::UnauthorizedError(error.to_string()),
+            _ => AppError::OtherError(error.to_string()),
+        }
+    }
+}
+
+/// Byte length of an `r || s || v` signature
+const SIGNATURE_LEN: usize = 65;
+/// Hex length of a 65-byte signature, without the `0x` prefix
+const SIGNATURE_HEX_LEN: usize = 130;
+/// Base64 length of a 65-byte signature, with and without its `=` padding
+const SIGNATURE_BASE64_LENS: [usize; 2] = [88, 87];
 
-    if signature_bytes.len() != 65 {
-        return Err(AppError::OtherError("Invalid Signature".to_string()));
-    } 
+/// Base64 with the standard alphabet, padding optional
+const SIGNATURE_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Decodes a signature given as `0x`-prefixed hex, bare hex or base64
+///
+/// The encoding is told apart by length, checked before decoding so that
+/// oversized input is never allocated or parsed: 130 hex digits and the 87 or
+/// 88 base64 characters of a 65-byte signature cannot be mistaken for each other.
+pub fn decode_signature(signature: &str) -> Result<[u8; SIGNATURE_LEN], SignatureError> {
+    use SignatureError::{MalformedEncoding, WrongLength};
+
+    let decoded = if let Some(signature_hex) = signature.strip_prefix("0x") {
+        if signature_hex.len() != SIGNATURE_HEX_LEN {
+            return Err(WrongLength("0x-prefixed signatures must have 130 hex digits"));
+        }
+        hex::decode(signature_hex).map_err(|_| MalformedEncoding("not a hex string"))?
+    } else if signature.len() == SIGNATURE_HEX_LEN {
+        hex::decode(signature).map_err(|_| MalformedEncoding("not a hex string"))?
+    } else if SIGNATURE_BASE64_LENS.contains(&signature.len()) {
+        SIGNATURE_BASE64.decode(signature).map_err(|_| MalformedEncoding("not a base64 string"))?
+    } else {
+        return Err(WrongLength("expected 65 bytes as hex or base64"));
+    };
+
+    decoded.try_into()
+        .map_err(|_| WrongLength("expected 65 bytes as hex or base64"))
+}
+
+/// Recovers the address that signed a precomputed 32-byte digest
+pub fn recover_signer_from_digest(
+    signature: &str,
+    message_hash: &[u8],
+) -> Result<String, SignatureError> {
+    let signature_bytes = decode_signature(signature)?;
 
     let recovery_id = signature_bytes[64];
     let signature_part = &signature_bytes[0..64];
 
-    let recovered_address = recover_address_from_signature(
-        &message_hash,
+    recover_address_from_signature(
+        message_hash,
         signature_part,
         recovery_id,
-    )?;
-
-    // normalize the recovered address
-    let normalized_recovered_address = normalize_ethereum_address(&recovered_address)?;
-    let normalized_expected_address = normalize_ethereum_address(expected_address)?;
-
-    // Return true if the addresses match
-    Ok(normalized_recovered_address == normalized_expected_address)
+    )
 }
 
 fn recover_address_from_signature(
     message_hash: &[u8],
     signature: &[u8],
     recovery_id: u8,
-) -> Result<String, AppError> {
+) -> Result<String, SignatureError> {
 
     let secp = Secp256k1::new();
 
@@ -239,20 +946,21 @@ fn recover_address_from_signature(
     let normalized_v = match recovery_id {
         27 | 28 => recovery_id - 27,
         0 | 1 => recovery_id,
-        _ => return Err(AppError::OtherError("Invalid recovery ID".to_string())),
+        _ => return Err(SignatureError::InvalidRecoveryId(recovery_id)),
     };
 
     let rec_id = RecoveryId::from_u8_masked(normalized_v);
 
     let rsig = RecoverableSignature::from_compact(signature, rec_id)
-        .map_err(|_| AppError::OtherError("Invalid signature".to_string()))?;
+        .map_err(|_| SignatureError::Unrecoverable("Invalid signature"))?;
 
+    // Digests are Keccak-256 outputs, only a caller bug can get this wrong
     let msg = Message::from_digest(
         message_hash.try_into()
-        .map_err(|_| AppError::OtherError("Invalid message hash length".to_string()))?);
+        .map_err(|_| SignatureError::Unrecoverable("Invalid message hash length"))?);
 
     let pub_key = secp.recover_ecdsa(msg, &rsig)
-        .map_err(|_| AppError::OtherError("Failed to recover public key".to_string()))?
+        .map_err(|_| SignatureError::Unrecoverable("Failed to recover public key"))?
         .serialize_uncompressed();
 
     let hash = Keccak256::digest(&pub_key[1..]);
@@ -260,3 +968,502 @@ fn recover_address_from_signature(
 
     Ok(format!("0x{}", hex::encode(address_bytes)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::challenge_store::{ChallengeStore, PgChallengeStore};
+    use crate::test_support;
+    use crate::utils::clock::MockClock;
+    use chrono::NaiveDate;
+    use proptest::prelude::*;
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    fn created_at() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap()
+    }
+
+    fn login_challenge(scope: &ChallengeScope) -> AuthChallenge {
+        challenge_for(scope, SignaturePurpose::Login)
+    }
+
+    fn challenge_for(scope: &ChallengeScope, purpose: SignaturePurpose) -> AuthChallenge {
+        let now = created_at();
+        let expires_at = challenge_expiry(now);
+        let statement = format!("{} Sign in", scope.purpose_tag(purpose));
+        AuthChallenge {
+            id: Uuid::new_v4(),
+            ethereum_address: ADDRESS.to_string(),
+            nonce: "f5c8353696c861a5dcf82ee5a876e1b0".to_string(),
+            challenge_message: build_message(ADDRESS, scope, statement, "f5c8353696c861a5dcf82ee5a876e1b0", &now, &expires_at),
+            expires_at,
+            used: false,
+            created_at: now,
+            domain: scope.domain.clone(),
+            chal_timestamp: now,
+            chain_id: scope.chain_id as i64,
+            uri: scope.uri.clone(),
+            locale: None,
+        }
+    }
+
+    /// The challenge as a client would render it, signed at `issued_at`
+    fn client_message(challenge: &AuthChallenge, issued_at: NaiveDateTime) -> String {
+        SiweMessage { issued_at, ..SiweMessage::parse(&challenge.challenge_message).unwrap() }.to_string()
+    }
+
+    #[test]
+    fn signed_issued_at_must_fall_between_the_challenge_and_now() {
+        let scope = ChallengeScope::from_config(&test_support::config());
+        let challenge = login_challenge(&scope);
+        let skew = scope.max_timestamp_skew;
+        let now = created_at() + Duration::minutes(1);
+        let verify = |message: &str| challenge.verify_scope(&scope, SignaturePurpose::Login, message, now);
+
+        verify(&challenge.challenge_message).unwrap();
+        for issued_at in [created_at() - skew, created_at() + Duration::seconds(30), now, now + skew] {
+            verify(&client_message(&challenge, issued_at)).unwrap();
+        }
+
+        let too_early = created_at() - skew - Duration::seconds(1);
+        let too_late = now + skew + Duration::seconds(1);
+        for issued_at in [too_early, created_at() - Duration::hours(1), too_late, now + Duration::days(1)] {
+            match verify(&client_message(&challenge, issued_at)) {
+                Err(AppError::UnauthorizedError(message)) => assert!(message.contains("outside the allowed window")),
+                other => panic!("Issued At {issued_at} was accepted: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn challenge_expires_exactly_at_its_expiry() {
+        let scope = ChallengeScope::from_config(&test_support::config());
+        let challenge = login_challenge(&scope);
+        assert_eq!(challenge.expires_at, created_at() + Duration::minutes(CHALLENGE_LIFETIME_MINUTES));
+
+        let clock = MockClock::new(challenge.expires_at - Duration::nanoseconds(1));
+        assert!(challenge.is_valid(&clock));
+        clock.set(challenge.expires_at);
+        assert!(!challenge.is_valid(&clock));
+
+        clock.set(created_at());
+        assert!(!AuthChallenge { used: true, ..challenge }.is_valid(&clock));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn stored_challenge_is_active_until_its_expiry(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let config = test_support::config();
+        let store = PgChallengeStore::new(pool, &config.challenge_store);
+        // Sub-second creation time, truncated away from the expiry
+        let clock = MockClock::new(created_at() + Duration::milliseconds(250));
+        let scope = ChallengeScope::from_config(&config);
+        let statement = LocalizedStatement::negotiate(&config.auth, None);
+
+        let challenge = AuthChallenge::create_challenge_for_addr(&store, &clock, ADDRESS, &scope, &statement, 5)
+            .await
+            .unwrap();
+        assert_eq!(challenge.expires_at, created_at() + Duration::minutes(CHALLENGE_LIFETIME_MINUTES));
+
+        let active = |now| store.find_active(ADDRESS, challenge.id, now);
+        assert!(active(challenge.expires_at - Duration::microseconds(1)).await.unwrap().is_some());
+        assert!(active(challenge.expires_at).await.unwrap().is_none());
+        assert!(store.find_expired(ADDRESS, challenge.id, challenge.expires_at - Duration::minutes(1), challenge.expires_at)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn signed_message_must_match_the_challenge_and_purpose() {
+        let scope = ChallengeScope::from_config(&test_support::config());
+        let challenge = login_challenge(&scope);
+        let now = created_at() + Duration::minutes(1);
+        let parsed = SiweMessage::parse(&challenge.challenge_message).unwrap();
+
+        assert!(challenge.verify_scope(&scope, SignaturePurpose::AcceptInvoice, &challenge.challenge_message, now).is_err());
+
+        let other_nonce = SiweMessage { nonce: "0".repeat(32), ..parsed.clone() }.to_string();
+        let other_chain = SiweMessage { chain_id: scope.chain_id + 1, ..parsed.clone() }.to_string();
+        let other_expiry = SiweMessage { expiration_time: None, ..parsed }.to_string();
+        for message in [other_nonce, other_chain, other_expiry, "not a SIWE message".to_string()] {
+            assert!(challenge.verify_scope(&scope, SignaturePurpose::Login, &message, now).is_err(), "{message}");
+        }
+    }
+
+    #[test]
+    fn challenge_is_bound_to_the_chain_id_and_uri() {
+        let scope = ChallengeScope::from_config(&test_support::config());
+        let challenge = login_challenge(&scope);
+        let now = created_at() + Duration::minutes(1);
+        let parsed = SiweMessage::parse(&challenge.challenge_message).unwrap();
+        assert!(challenge.challenge_message.contains(&format!("\nURI: {}\n", scope.uri)));
+        assert!(challenge.challenge_message.contains(&format!("\nChain ID: {}\n", scope.chain_id)));
+
+        let rejection = |challenge: &AuthChallenge, scope: &ChallengeScope, message: &str| {
+            match challenge.verify_scope(scope, SignaturePurpose::Login, message, now) {
+                Err(AppError::UnauthorizedError(reason)) => reason,
+                other => panic!("{message} was accepted: {other:?}"),
+            }
+        };
+
+        // Signed for another chain or application
+        let other_chain = SiweMessage { chain_id: 137, ..parsed.clone() }.to_string();
+        assert_eq!(rejection(&challenge, &scope, &other_chain), "Chain ID mismatch");
+        let other_uri = SiweMessage { uri: "https://other.example/login".to_string(), ..parsed }.to_string();
+        assert_eq!(rejection(&challenge, &scope, &other_uri), "URI mismatch");
+
+        // Issued for another chain or application sharing the domain
+        let on_other_chain = ChallengeScope { chain_id: 137, ..scope.clone() };
+        assert_eq!(rejection(&challenge, &on_other_chain, &challenge.challenge_message), "Chain ID mismatch");
+        let stored_elsewhere = AuthChallenge { uri: "https://other.example/login".to_string(), ..challenge.clone() };
+        assert_eq!(rejection(&stored_elsewhere, &scope, &challenge.challenge_message), "URI mismatch");
+    }
+
+    #[test]
+    fn challenge_signed_for_one_purpose_is_refused_for_another() {
+        let scope = ChallengeScope::from_config(&test_support::config());
+        let now = created_at() + Duration::minutes(1);
+        let purposes = [SignaturePurpose::Login, SignaturePurpose::AcceptInvoice];
+        assert_ne!(scope.purpose_tag(purposes[0]), scope.purpose_tag(purposes[1]));
+
+        for signed_for in purposes {
+            let challenge = challenge_for(&scope, signed_for);
+            for verified_for in purposes {
+                let verified = challenge.verify_scope(&scope, verified_for, &challenge.challenge_message, now);
+                if signed_for == verified_for {
+                    verified.unwrap();
+                } else {
+                    match verified {
+                        Err(AppError::UnauthorizedError(message)) => {
+                            assert_eq!(message, "Challenge was not issued for this action");
+                        }
+                        other => panic!("{signed_for:?} challenge verified as {verified_for:?}: {other:?}"),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn purpose_tag_must_open_the_statement_as_a_word() {
+        let scope = ChallengeScope::from_config(&test_support::config());
+        let challenge = login_challenge(&scope);
+        let parsed = SiweMessage::parse(&challenge.challenge_message).unwrap();
+        let tag = scope.purpose_tag(SignaturePurpose::Login);
+
+        for statement in [format!("{tag}Sign in"), format!("Sign in {tag}"), tag.to_lowercase(), String::new()] {
+            let message = SiweMessage { statement: Some(statement.clone()), ..parsed.clone() }.to_string();
+            let verified = challenge.verify_scope(&scope, SignaturePurpose::Login, &message, created_at());
+            assert!(verified.is_err(), "{statement:?} was accepted");
+        }
+    }
+
+    /// Signature by 0x2c75...5c23 over `KNOWN_DIGEST`, see the EIP-712 known-answer tests
+    const KNOWN_SIGNATURE: &str = "7c2eaabb50339ddc62321b63e185ac83468722a4c1ffe65dd89936dc1b928781\
+                                   735daa242c47059a914043afd5a456d8290e5d1d577fadaf6ea3c44fadee6d031c";
+    const KNOWN_DIGEST: &str = "e138e94301fe307bb952f575bede9c3e67b0a6e35ca3c331497161744e322693";
+
+    #[test]
+    fn signature_encodings_decode_to_the_same_signer() {
+        let bytes = hex::decode(KNOWN_SIGNATURE).unwrap();
+        let base64_padded = SIGNATURE_BASE64.encode(&bytes);
+        let base64_unpadded = base64_padded.trim_end_matches('=').to_string();
+        assert_eq!((base64_padded.len(), base64_unpadded.len()), (88, 87));
+
+        let digest = hex::decode(KNOWN_DIGEST).unwrap();
+        for encoded in [
+            format!("0x{KNOWN_SIGNATURE}"),
+            KNOWN_SIGNATURE.to_string(),
+            KNOWN_SIGNATURE.to_uppercase(),
+            base64_padded,
+            base64_unpadded,
+        ] {
+            assert_eq!(decode_signature(&encoded).unwrap().to_vec(), bytes, "{encoded}");
+            assert_eq!(recover_signer_from_digest(&encoded, &digest).unwrap(), ADDRESS, "{encoded}");
+        }
+    }
+
+    #[test]
+    fn malformed_signatures_are_rejected() {
+        use SignatureError::{MalformedEncoding, WrongLength};
+
+        let wrong_length = [
+            String::new(),
+            "0x".to_string(),
+            format!("0x{}", &KNOWN_SIGNATURE[..128]),
+            format!("0x{KNOWN_SIGNATURE}00"),
+            KNOWN_SIGNATURE[..128].to_string(),
+            format!("{KNOWN_SIGNATURE}00"),
+            "a".repeat(4096),
+        ];
+        for signature in wrong_length {
+            assert!(matches!(decode_signature(&signature), Err(WrongLength(_))), "{signature}");
+        }
+
+        let malformed = [
+            format!("0x{}zz", &KNOWN_SIGNATURE[..128]),
+            format!("{}zz", &KNOWN_SIGNATURE[..128]),
+            // Base64 length, but not base64
+            "!".repeat(88),
+            format!("{}-_", "A".repeat(85)),
+        ];
+        for signature in malformed {
+            assert!(matches!(decode_signature(&signature), Err(MalformedEncoding(_))), "{signature}");
+        }
+
+        // Decodes to 65 bytes, but v is not a recovery id
+        let mut bytes = hex::decode(KNOWN_SIGNATURE).unwrap();
+        bytes[64] = 5;
+        let digest = hex::decode(KNOWN_DIGEST).unwrap();
+        assert!(matches!(
+            recover_signer_from_digest(&hex::encode(&bytes), &digest),
+            Err(SignatureError::InvalidRecoveryId(5))
+        ));
+    }
+
+    #[test]
+    fn request_addresses_must_be_prefixed_hex() {
+        let code = |address: &str, strict: bool| check_eth_address(address, strict).err().map(|error| error.code);
+
+        for address in [ADDRESS, "0x2C7536E3605D9C16A7A3D7B1898E529396A65C23", &format!("  {ADDRESS}\n")] {
+            assert_eq!(code(address, true), None, "{address:?}");
+        }
+        assert_eq!(code(&ADDRESS[..41], true).as_deref(), Some("address_length"));
+        assert_eq!(code(&format!("{ADDRESS}0"), true).as_deref(), Some("address_length"));
+        assert_eq!(code(&format!("00{}", &ADDRESS[2..]), true).as_deref(), Some("address_prefix"));
+        assert_eq!(code(&format!("0X{}", &ADDRESS[2..]), true).as_deref(), Some("address_prefix"));
+        assert_eq!(code(&format!("0x{}zz", &ADDRESS[2..40]), true).as_deref(), Some("address_not_hex"));
+
+        // Lenient validation only checks the length
+        assert_eq!(code(&format!("00{}", &ADDRESS[2..]), false), None);
+        assert_eq!(code(&format!("0x{}zz", &ADDRESS[2..40]), false), None);
+        assert_eq!(code(&ADDRESS[..41], false).as_deref(), Some("address_length"));
+    }
+
+    #[test]
+    fn challenge_request_rejects_malformed_addresses_as_a_field_error() {
+        for address in [format!("00{}", &ADDRESS[2..]), format!("0x{}zz", &ADDRESS[2..40])] {
+            let request = ChallengeRequest { ethereum_address: address.clone() };
+            let errors = request.validate().unwrap_err();
+            assert!(errors.field_errors().contains_key("ethereum_address"), "{address}");
+        }
+    }
+
+    #[test]
+    fn signature_failures_are_categorized() {
+        let mut bad_v = hex::decode(KNOWN_SIGNATURE).unwrap();
+        bad_v[64] = 29;
+        let mut zero_rs = vec![0u8; 64];
+        zero_rs.push(27);
+
+        let cases = [
+            (format!("0x{}", "zz".repeat(65)), "malformed_encoding"),
+            ("0x1234".to_string(), "wrong_length"),
+            (hex::encode(&bad_v), "invalid_recovery_id"),
+            (hex::encode(&zero_rs), "unrecoverable"),
+            // Well formed, but over another message than the one checked
+            (KNOWN_SIGNATURE.to_string(), "signer_mismatch"),
+        ];
+        // One case per category
+        assert_eq!(cases.each_ref().map(|(_, category)| *category), SIGNATURE_FAILURE_CATEGORIES);
+
+        for (signature, category) in cases {
+            let error = check_signer(&signature, "Sign in", ADDRESS).unwrap_err();
+            assert_eq!(error.category(), category, "{signature}");
+
+            match (AppError::from(error), error) {
+                (AppError::UnauthorizedError(_), SignatureError::SignerMismatch) => {}
+                (AppError::OtherError(_), SignatureError::SignerMismatch) => panic!("mismatch is not a 401"),
+                (AppError::OtherError(_), _) => {}
+                (other, _) => panic!("{category} became {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn recorded_failures_are_counted_per_category() {
+        let count = |category: &str| {
+            signature_failure_counts().find(|(c, _)| *c == category).unwrap().1
+        };
+        let wrong_length = count("wrong_length");
+
+        let error = decode_signature("0x1234").unwrap_err();
+        assert_eq!(error.recorded(), error);
+        error.recorded();
+
+        // Other tests may count failures concurrently
+        assert!(count("wrong_length") >= wrong_length + 2);
+    }
+
+    /// Refuses the first `collisions` nonces as already issued, as a failing
+    /// generator would make it
+    struct CollidingStore {
+        inner: PgChallengeStore,
+        collisions: usize,
+        claims: Mutex<Vec<(String, bool)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ChallengeStore for CollidingStore {
+        async fn create(&self, challenge: NewChallenge, active: ActiveChallenges) -> Result<AuthChallenge, AppError> {
+            self.inner.create(challenge, active).await
+        }
+
+        async fn find_active(&self, address: &str, challenge_id: Uuid, now: NaiveDateTime)
+            -> Result<Option<AuthChallenge>, AppError>
+        {
+            self.inner.find_active(address, challenge_id, now).await
+        }
+
+        async fn find_expired(&self, address: &str, challenge_id: Uuid, expired_after: NaiveDateTime, now: NaiveDateTime)
+            -> Result<Option<AuthChallenge>, AppError>
+        {
+            self.inner.find_expired(address, challenge_id, expired_after, now).await
+        }
+
+        async fn mark_used(&self, challenge_id: Uuid) -> Result<bool, AppError> {
+            self.inner.mark_used(challenge_id).await
+        }
+
+        async fn claim_nonce(&self, nonce: &str, now: NaiveDateTime) -> Result<bool, AppError> {
+            let collides = self.claims.lock().unwrap().len() < self.collisions;
+            let claimed = !collides && self.inner.claim_nonce(nonce, now).await?;
+            self.claims.lock().unwrap().push((nonce.to_string(), claimed));
+            Ok(claimed)
+        }
+
+        async fn cleanup_expired(&self, now: NaiveDateTime) -> Result<u64, AppError> {
+            self.inner.cleanup_expired(now).await
+        }
+
+        async fn count_since(&self, since: NaiveDateTime) -> Result<(i64, i64), AppError> {
+            self.inner.count_since(since).await
+        }
+
+        async fn list_for_address(&self, address: &str, offset: i64, limit: i64) -> Result<Vec<AuthChallenge>, AppError> {
+            self.inner.list_for_address(address, offset, limit).await
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn issued_nonces_are_refused_within_the_reuse_window(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let config = test_support::config();
+        let store = PgChallengeStore::new(pool, &config.challenge_store);
+        let window = Duration::seconds(config.challenge_store.nonce_reuse_window_secs as i64);
+        let nonce = nonce_gen();
+
+        assert!(store.claim_nonce(&nonce, created_at()).await.unwrap());
+        assert!(!store.claim_nonce(&nonce, created_at()).await.unwrap());
+        assert!(!store.claim_nonce(&nonce, created_at() + window - Duration::seconds(1)).await.unwrap());
+        assert!(store.claim_nonce(&nonce_gen(), created_at()).await.unwrap());
+
+        // Claimed again once the window has passed, which restarts it
+        assert!(store.claim_nonce(&nonce, created_at() + window).await.unwrap());
+        assert!(!store.claim_nonce(&nonce, created_at() + window + Duration::seconds(1)).await.unwrap());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn colliding_nonces_are_redrawn_and_alerted_on(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let config = test_support::config();
+        let clock = MockClock::new(created_at());
+        let scope = ChallengeScope::from_config(&config);
+        let statement = LocalizedStatement::negotiate(&config.auth, None);
+        let colliding = |collisions| CollidingStore {
+            inner: PgChallengeStore::new(pool.clone(), &config.challenge_store),
+            collisions,
+            claims: Mutex::new(Vec::new()),
+        };
+
+        // Process wide: no other test makes nonces collide
+        static ALERTS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        set_nonce_collision_alert(NonceCollisionAlert {
+            threshold: 3,
+            window: Duration::minutes(1),
+            notify: Box::new(|collisions| ALERTS.lock().unwrap().push(collisions)),
+        });
+
+        let store = colliding(2);
+        let challenge = AuthChallenge::create_challenge_for_addr(&store, &clock, ADDRESS, &scope, &statement, 5)
+            .await
+            .unwrap();
+        let claims = store.claims.lock().unwrap().clone();
+        assert_eq!(claims.iter().map(|(_, claimed)| *claimed).collect::<Vec<_>>(), [false, false, true]);
+        assert_eq!(challenge.nonce, claims[2].0);
+        assert!(challenge.challenge_message.contains(&format!("Nonce: {}", claims[2].0)));
+        assert!(ALERTS.lock().unwrap().is_empty());
+
+        // A generator that keeps repeating itself fails the request
+        let store = colliding(usize::MAX);
+        let refused = AuthChallenge::create_challenge_for_addr(&store, &clock, ADDRESS, &scope, &statement, 5).await;
+        assert!(matches!(refused, Err(AppError::ServerError(_))), "{refused:?}");
+        assert_eq!(store.claims.lock().unwrap().len(), MAX_NONCE_ATTEMPTS as usize);
+
+        // 7 collisions within the window, the count restarting after each alert
+        assert_eq!(*ALERTS.lock().unwrap(), [3, 3]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn active_challenges_are_capped_per_address(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let config = test_support::config();
+        let store = PgChallengeStore::new(pool, &config.challenge_store);
+        let clock = MockClock::new(created_at());
+        let scope = ChallengeScope::from_config(&config);
+        let statement = LocalizedStatement::negotiate(&config.auth, None);
+
+        let mut created = Vec::new();
+        for _ in 0..8 {
+            clock.advance(Duration::seconds(1));
+            let challenge = AuthChallenge::create_challenge_for_addr(&store, &clock, ADDRESS, &scope, &statement, 3)
+                .await
+                .unwrap();
+            created.push(challenge.id);
+        }
+
+        let now = clock.now();
+        let mut active = Vec::new();
+        for challenge_id in &created {
+            if store.find_active(ADDRESS, *challenge_id, now).await.unwrap().is_some() {
+                active.push(*challenge_id);
+            }
+        }
+        // The newest ones survive, the oldest were evicted
+        assert_eq!(active, created[5..]);
+
+        // Other addresses are not affected
+        let other = "0x0000000000000000000000000000000000000001";
+        let challenge = AuthChallenge::create_challenge_for_addr(&store, &clock, other, &scope, &statement, 3)
+            .await
+            .unwrap();
+        assert!(store.find_active(other, challenge.id, now).await.unwrap().is_some());
+        assert!(store.find_active(ADDRESS, created[7], now).await.unwrap().is_some());
+    }
+
+    proptest! {
+        #[test]
+        fn decode_signature_never_panics(input in any::<String>()) {
+            let _ = decode_signature(&input);
+        }
+
+        #[test]
+        fn decode_signature_never_panics_near_signature_lengths(
+            input in "(0x)?[0-9a-fA-F+/=_\\-\\PC]{85,132}",
+        ) {
+            if let Ok(bytes) = decode_signature(&input) {
+                prop_assert_eq!(bytes.len(), SIGNATURE_LEN);
+            }
+        }
+
+        #[test]
+        fn any_65_bytes_decode_from_hex_and_base64(bytes in proptest::collection::vec(any::<u8>(), SIGNATURE_LEN)) {
+            let hex_encoded = hex::encode(&bytes);
+            for encoded in [format!("0x{hex_encoded}"), hex_encoded, SIGNATURE_BASE64.encode(&bytes)] {
+                prop_assert_eq!(decode_signature(&encoded).unwrap().to_vec(), bytes.clone());
+            }
+        }
+    }
+}
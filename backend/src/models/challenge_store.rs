@@ -0,0 +1,959 @@
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use redis::{
+    aio::{ConnectionManager, ConnectionManagerConfig},
+    AsyncCommands, Client, ExistenceCheck, Script, SetExpiry, SetOptions,
+};
+use sqlx::{query, query_as, PgConnection, PgPool};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+use crate::app_error::app_error::AppError;
+use crate::config::app_config::{ChallengeBackend, ChallengeStoreConfig};
+use crate::models::auth_challenges::{ActiveChallenges, AuthChallenge, NewChallenge};
+use crate::models::isolation::run_isolated;
+
+/// Where challenges live between issuance and use
+///
+/// Message building and verification stay in `AuthChallenge`; a store only
+/// keeps challenges and tells which ones are still active. Addresses are
+/// passed already normalized.
+#[async_trait]
+pub trait ChallengeStore: Send + Sync {
+    /// Stores `challenge`, first applying `active` to the address's outstanding
+    /// challenges, as one atomic step
+    async fn create(
+        &self,
+        challenge: NewChallenge,
+        active: ActiveChallenges,
+    ) -> Result<AuthChallenge, AppError>;
+
+    /// Returns the challenge when it belongs to `address`, is unused and has
+    /// not expired at `now`
+    async fn find_active(
+        &self,
+        address: &str,
+        challenge_id: Uuid,
+        now: NaiveDateTime,
+    ) -> Result<Option<AuthChallenge>, AppError>;
+
+    /// Returns the challenge when it belongs to `address`, is unused and
+    /// expired after `expired_after`, at `now` at the latest
+    ///
+    /// Only serves to explain a refusal, the challenge stays unusable.
+    async fn find_expired(
+        &self,
+        address: &str,
+        challenge_id: Uuid,
+        expired_after: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Result<Option<AuthChallenge>, AppError>;
+
+    /// Flags the challenge as used, returning whether this call did so
+    ///
+    /// Only one of several concurrent callers gets `true`, the others find
+    /// the challenge already used, or gone.
+    async fn mark_used(&self, challenge_id: Uuid) -> Result<bool, AppError>;
+
+    /// Records `nonce` as issued at `now`, returning false when it was
+    /// already issued within `nonce_reuse_window_secs`
+    ///
+    /// Only one of several concurrent callers with the same nonce gets `true`.
+    async fn claim_nonce(&self, nonce: &str, now: NaiveDateTime) -> Result<bool, AppError>;
+
+    /// Drops challenges expired at `now`, returning how many were removed
+    async fn cleanup_expired(&self, now: NaiveDateTime) -> Result<u64, AppError>;
+
+    /// Counts challenges created since `since` and how many of them were used
+    async fn count_since(&self, since: NaiveDateTime) -> Result<(i64, i64), AppError>;
+
+    /// Lists the challenges still kept for `address`, whatever their state,
+    /// newest first
+    async fn list_for_address(
+        &self,
+        address: &str,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<AuthChallenge>, AppError>;
+}
+
+/// Builds the store selected by `[challenge_store] backend`
+///
+/// Challenges are kept `expired_grace` past their expiry, for `find_expired`.
+pub fn build_challenge_store(
+    pool: PgPool,
+    config: &ChallengeStoreConfig,
+    expired_grace: Duration,
+) -> Result<Arc<dyn ChallengeStore>, AppError> {
+    let store: Arc<dyn ChallengeStore> = match config.backend {
+        ChallengeBackend::Postgres => Arc::new(PgChallengeStore::new(pool, config)),
+        ChallengeBackend::Redis => Arc::new(RedisChallengeStore::new(config, expired_grace)?),
+    };
+
+    Ok(Arc::new(CoalescingChallengeStore::new(store)))
+}
+
+/// Lookup shared by every caller asking for the same challenge meanwhile
+type InFlightLookup = Arc<OnceCell<Option<AuthChallenge>>>;
+
+/// Wraps a store so concurrent lookups of one challenge share a single query
+///
+/// A double-submitted acceptance, or a client retrying in a loop, would
+/// otherwise read the same row once per request. The first caller runs the
+/// query and the others wait for its result; if it fails, the next waiter
+/// runs it again. Nothing is kept once the lookup completes, so a later
+/// lookup always sees the challenge's current state.
+///
+/// Callers sharing a lookup may all get the challenge as active. Only one of
+/// them can consume it, since `mark_used` claims it atomically.
+pub struct CoalescingChallengeStore {
+    inner: Arc<dyn ChallengeStore>,
+    in_flight: Mutex<HashMap<(String, Uuid), InFlightLookup>>,
+}
+
+impl CoalescingChallengeStore {
+    pub fn new(inner: Arc<dyn ChallengeStore>) -> Self {
+        CoalescingChallengeStore {
+            inner,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl ChallengeStore for CoalescingChallengeStore {
+    async fn create(
+        &self,
+        challenge: NewChallenge,
+        active: ActiveChallenges,
+    ) -> Result<AuthChallenge, AppError> {
+        self.inner.create(challenge, active).await
+    }
+
+    /// Joins the lookup in flight for this address and challenge, if any
+    ///
+    /// Joiners get the result as of the `now` of the caller that started it,
+    /// at most one query earlier than their own.
+    async fn find_active(
+        &self,
+        address: &str,
+        challenge_id: Uuid,
+        now: NaiveDateTime,
+    ) -> Result<Option<AuthChallenge>, AppError> {
+        let key = (address.to_string(), challenge_id);
+        let lookup = self.in_flight.lock().unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        let result = lookup
+            .get_or_try_init(|| self.inner.find_active(address, challenge_id, now))
+            .await
+            .cloned();
+
+        // The first caller to finish retires the lookup, later ones query anew
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.get(&key).is_some_and(|current| Arc::ptr_eq(current, &lookup)) {
+            in_flight.remove(&key);
+        }
+
+        result
+    }
+
+    async fn find_expired(
+        &self,
+        address: &str,
+        challenge_id: Uuid,
+        expired_after: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Result<Option<AuthChallenge>, AppError> {
+        self.inner.find_expired(address, challenge_id, expired_after, now).await
+    }
+
+    async fn mark_used(&self, challenge_id: Uuid) -> Result<bool, AppError> {
+        self.inner.mark_used(challenge_id).await
+    }
+
+    async fn claim_nonce(&self, nonce: &str, now: NaiveDateTime) -> Result<bool, AppError> {
+        self.inner.claim_nonce(nonce, now).await
+    }
+
+    async fn cleanup_expired(&self, now: NaiveDateTime) -> Result<u64, AppError> {
+        self.inner.cleanup_expired(now).await
+    }
+
+    async fn count_since(&self, since: NaiveDateTime) -> Result<(i64, i64), AppError> {
+        self.inner.count_since(since).await
+    }
+
+    async fn list_for_address(
+        &self,
+        address: &str,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<AuthChallenge>, AppError> {
+        self.inner.list_for_address(address, offset, limit).await
+    }
+}
+
+/// Challenges kept in the `auth_challenges` table, the default
+///
+/// Issued nonces are kept apart in `challenge_nonces`, since challenges
+/// are deleted once expired.
+pub struct PgChallengeStore {
+    pool: PgPool,
+    nonce_reuse_window: chrono::Duration,
+}
+
+impl PgChallengeStore {
+    pub fn new(pool: PgPool, config: &ChallengeStoreConfig) -> Self {
+        PgChallengeStore {
+            pool,
+            nonce_reuse_window: chrono::Duration::seconds(config.nonce_reuse_window_secs as i64),
+        }
+    }
+}
+
+#[async_trait]
+impl ChallengeStore for PgChallengeStore {
+    async fn create(
+        &self,
+        challenge: NewChallenge,
+        active: ActiveChallenges,
+    ) -> Result<AuthChallenge, AppError> {
+        run_isolated(&self.pool, |conn| {
+            let challenge = challenge.clone();
+            Box::pin(async move { create_challenge(conn, &challenge, active).await })
+        })
+        .await
+    }
+
+    async fn find_active(
+        &self,
+        address: &str,
+        challenge_id: Uuid,
+        now: NaiveDateTime,
+    ) -> Result<Option<AuthChallenge>, AppError> {
+        let challenge = query_as!(
+            AuthChallenge,
+            r#"
+            SELECT id, ethereum_address, nonce, challenge_message, expires_at, used, created_at, domain, chal_timestamp, chain_id, uri, locale
+            FROM auth_challenges
+            WHERE ethereum_address = $1
+              AND id = $2
+              AND used = false
+              AND expires_at > $3
+            "#,
+            address,
+            challenge_id,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(challenge)
+    }
+
+    async fn find_expired(
+        &self,
+        address: &str,
+        challenge_id: Uuid,
+        expired_after: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Result<Option<AuthChallenge>, AppError> {
+        let challenge = query_as!(
+            AuthChallenge,
+            r#"
+            SELECT id, ethereum_address, nonce, challenge_message, expires_at, used, created_at, domain, chal_timestamp, chain_id, uri, locale
+            FROM auth_challenges
+            WHERE ethereum_address = $1
+              AND id = $2
+              AND used = false
+              AND expires_at > $3
+              AND expires_at <= $4
+            "#,
+            address,
+            challenge_id,
+            expired_after,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(challenge)
+    }
+
+    async fn mark_used(&self, challenge_id: Uuid) -> Result<bool, AppError> {
+        run_isolated(&self.pool, |conn| Box::pin(async move {
+            let marked = query!(
+                r#"
+                UPDATE auth_challenges
+                SET used = true
+                WHERE id = $1
+                  AND used = false
+                "#,
+                challenge_id
+            )
+            .execute(conn)
+            .await?
+            .rows_affected();
+
+            Ok(marked == 1)
+        }))
+        .await
+    }
+
+    /// A nonce last issued before the window is claimed again, as if new
+    async fn claim_nonce(&self, nonce: &str, now: NaiveDateTime) -> Result<bool, AppError> {
+        let claimed = query!(
+            r#"
+            INSERT INTO challenge_nonces (nonce, issued_at)
+            VALUES ($1, $2)
+            ON CONFLICT (nonce) DO UPDATE SET issued_at = EXCLUDED.issued_at
+            WHERE challenge_nonces.issued_at <= $3
+            RETURNING nonce
+            "#,
+            nonce,
+            now,
+            now - self.nonce_reuse_window
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(claimed.is_some())
+    }
+
+    /// Also forgets the nonces issued before the reuse window
+    async fn cleanup_expired(&self, now: NaiveDateTime) -> Result<u64, AppError> {
+        let result = query!(
+            r#"
+            DELETE FROM auth_challenges
+            WHERE expires_at < $1
+            "#,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        query!(
+            r#"
+            DELETE FROM challenge_nonces
+            WHERE issued_at <= $1
+            "#,
+            now - self.nonce_reuse_window
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn count_since(&self, since: NaiveDateTime) -> Result<(i64, i64), AppError> {
+        let counts = query!(
+            r#"
+            SELECT
+                COUNT(*) as "created!",
+                COUNT(*) FILTER (WHERE used = true) as "used!"
+            FROM auth_challenges
+            WHERE created_at >= $1
+            "#,
+            since
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((counts.created, counts.used))
+    }
+
+    /// Every challenge until `cleanup_expired` deletes it
+    async fn list_for_address(
+        &self,
+        address: &str,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<AuthChallenge>, AppError> {
+        let challenges = query_as!(
+            AuthChallenge,
+            r#"
+            SELECT id, ethereum_address, nonce, challenge_message, expires_at, used, created_at, domain, chal_timestamp, chain_id, uri, locale
+            FROM auth_challenges
+            WHERE ethereum_address = $1
+            ORDER BY created_at DESC, id DESC
+            OFFSET $2
+            LIMIT $3
+            "#,
+            address,
+            offset,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(challenges)
+    }
+}
+
+/// `PgChallengeStore::create`, within the caller's transaction
+async fn create_challenge(
+    conn: &mut PgConnection,
+    challenge: &NewChallenge,
+    active: ActiveChallenges,
+) -> Result<AuthChallenge, sqlx::Error> {
+    let now = challenge.chal_timestamp;
+
+    match active {
+        ActiveChallenges::Keep => {}
+        ActiveChallenges::Cap(max_active) => {
+            // Serialize challenge creation per address so the cap cannot be raced
+            query!(
+                r#"
+                SELECT 1 as "locked"
+                FROM (SELECT pg_advisory_xact_lock(hashtext($1))) AS address_lock
+                "#,
+                challenge.ethereum_address
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+
+            let active = count_active_challenges(conn, now, &challenge.ethereum_address).await?;
+            let excess = active - i64::from(max_active) + 1;
+            if excess > 0 {
+                query!(
+                    r#"
+                    UPDATE auth_challenges
+                    SET used = true
+                    WHERE id IN (
+                        SELECT id
+                        FROM auth_challenges
+                        WHERE ethereum_address = $1
+                          AND used = false
+                          AND expires_at > $2
+                        ORDER BY created_at ASC
+                        LIMIT $3
+                    )
+                    "#,
+                    challenge.ethereum_address,
+                    now,
+                    excess
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        ActiveChallenges::Replace => {
+            query!(
+                r#"
+                UPDATE auth_challenges
+                SET used = true
+                WHERE ethereum_address = $1
+                  AND used = false
+                  AND expires_at > $2
+                "#,
+                challenge.ethereum_address,
+                now
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    insert_challenge(conn, challenge).await
+}
+
+async fn count_active_challenges(
+    conn: &mut PgConnection,
+    now: NaiveDateTime,
+    normalized_address: &str,
+) -> Result<i64, sqlx::Error> {
+    let active = query!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM auth_challenges
+        WHERE ethereum_address = $1
+          AND used = false
+          AND expires_at > $2
+        "#,
+        normalized_address,
+        now
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(active.count)
+}
+
+async fn insert_challenge(
+    conn: &mut PgConnection,
+    challenge: &NewChallenge,
+) -> Result<AuthChallenge, sqlx::Error> {
+    let auth_challenge = query_as!(
+        AuthChallenge,
+        r#"
+        INSERT INTO auth_challenges (
+            id,
+            ethereum_address,
+            nonce,
+            challenge_message,
+            expires_at,
+            used,
+            domain,
+            chal_timestamp,
+            chain_id,
+            uri,
+            locale
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING id, ethereum_address, nonce, challenge_message, expires_at, used, created_at, domain, chal_timestamp, chain_id, uri, locale
+        "#,
+        challenge.id,
+        challenge.ethereum_address,
+        challenge.nonce,
+        challenge.challenge_message,
+        challenge.expires_at,
+        false,
+        challenge.domain,
+        challenge.chal_timestamp,
+        challenge.chain_id,
+        challenge.uri,
+        challenge.locale,
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(auth_challenge)
+}
+
+/// Bound on connecting to and awaiting Redis, so an unreachable server fails
+/// requests instead of holding them
+const REDIS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long created and used challenges are remembered for `count_since`
+const REDIS_STATS_RETENTION_DAYS: i64 = 1;
+
+/// Applies the `ActiveChallenges` policy and stores the new challenge
+///
+/// Challenges outlive their expiry by the grace period, so one only counts
+/// as outstanding while unused and before its `expires` score.
+///
+/// KEYS: address index, new challenge, created stats, used stats.
+/// ARGV: policy, cap, id, data, TTL in ms, score, challenge key prefix,
+/// stats cutoff score, expiry score.
+const REDIS_CREATE_SCRIPT: &str = r#"
+local active = {}
+for _, id in ipairs(redis.call('ZRANGE', KEYS[1], 0, -1)) do
+    local state = redis.call('HMGET', ARGV[7] .. id, 'used', 'expires')
+    if state[1] == '0' and (not state[2] or tonumber(state[2]) > tonumber(ARGV[6])) then
+        table.insert(active, id)
+    else
+        redis.call('ZREM', KEYS[1], id)
+    end
+end
+
+local evict = 0
+if ARGV[1] == 'replace' then
+    evict = #active
+elseif ARGV[1] == 'cap' then
+    evict = math.min(#active, #active - tonumber(ARGV[2]) + 1)
+end
+for i = 1, evict do
+    redis.call('HSET', ARGV[7] .. active[i], 'used', '1')
+    redis.call('ZADD', KEYS[4], redis.call('ZSCORE', KEYS[1], active[i]), active[i])
+    redis.call('ZREM', KEYS[1], active[i])
+end
+
+redis.call('HSET', KEYS[2], 'data', ARGV[4], 'used', '0', 'expires', ARGV[9])
+redis.call('PEXPIRE', KEYS[2], ARGV[5])
+redis.call('ZADD', KEYS[1], ARGV[6], ARGV[3])
+redis.call('PEXPIRE', KEYS[1], ARGV[5])
+redis.call('ZADD', KEYS[3], ARGV[6], ARGV[3])
+redis.call('ZREMRANGEBYSCORE', KEYS[3], '-inf', ARGV[8])
+redis.call('ZREMRANGEBYSCORE', KEYS[4], '-inf', ARGV[8])
+return 1
+"#;
+
+/// Marks a challenge used unless it already expired or was used, returning
+/// 1 when it did
+///
+/// KEYS: challenge, address index, used stats. ARGV: id, score.
+const REDIS_MARK_USED_SCRIPT: &str = r#"
+if redis.call('HGET', KEYS[1], 'used') ~= '0' then
+    return 0
+end
+redis.call('HSET', KEYS[1], 'used', '1')
+redis.call('ZREM', KEYS[2], ARGV[1])
+redis.call('ZADD', KEYS[3], ARGV[2], ARGV[1])
+return 1
+"#;
+
+/// Challenges kept in Redis, expiring on their own through key TTLs
+///
+/// Each challenge is a hash holding its JSON, a `used` flag and its expiry,
+/// kept until the grace period after it has passed. A sorted set
+/// per address indexes its outstanding challenges by creation time, which
+/// the creation script uses to apply the `ActiveChallenges` policy. Counts
+/// for `count_since` only cover the last `REDIS_STATS_RETENTION_DAYS`.
+pub struct RedisChallengeStore {
+    client: Client,
+    connection: OnceCell<ConnectionManager>,
+    key_prefix: String,
+    expired_grace: Duration,
+    nonce_reuse_window: Duration,
+    create_script: Script,
+    mark_used_script: Script,
+}
+
+impl RedisChallengeStore {
+    pub fn new(config: &ChallengeStoreConfig, expired_grace: Duration) -> Result<Self, AppError> {
+        let client = Client::open(config.redis_url.as_str())
+            .map_err(|e| AppError::ConfigError(format!("Invalid challenge store Redis URL: {}", e)))?;
+
+        Ok(RedisChallengeStore {
+            client,
+            connection: OnceCell::new(),
+            key_prefix: config.key_prefix.clone(),
+            expired_grace,
+            nonce_reuse_window: Duration::from_secs(config.nonce_reuse_window_secs),
+            create_script: Script::new(REDIS_CREATE_SCRIPT),
+            mark_used_script: Script::new(REDIS_MARK_USED_SCRIPT),
+        })
+    }
+
+    /// Shared connection, opened on first use and reconnecting on failure
+    async fn connection(&self) -> Result<ConnectionManager, AppError> {
+        let config = ConnectionManagerConfig::new()
+            .set_number_of_retries(1)
+            .set_connection_timeout(REDIS_TIMEOUT)
+            .set_response_timeout(REDIS_TIMEOUT);
+        let connection = self.connection
+            .get_or_try_init(|| ConnectionManager::new_with_config(self.client.clone(), config))
+            .await
+            .map_err(redis_error)?;
+
+        Ok(connection.clone())
+    }
+
+    fn challenge_key(&self, challenge_id: Uuid) -> String {
+        format!("{}challenge:{}", self.key_prefix, challenge_id)
+    }
+
+    fn address_key(&self, address: &str) -> String {
+        format!("{}challenges:{}", self.key_prefix, address)
+    }
+
+    fn nonce_key(&self, nonce: &str) -> String {
+        format!("{}challenge_nonce:{}", self.key_prefix, nonce)
+    }
+
+    fn stats_key(&self, kind: &str) -> String {
+        format!("{}challenge_stats:{}", self.key_prefix, kind)
+    }
+
+    /// Reads a challenge regardless of its state, `None` once its key expired
+    async fn load(&self, challenge_id: Uuid) -> Result<Option<AuthChallenge>, AppError> {
+        let mut connection = self.connection().await?;
+        let (data, used): (Option<String>, Option<String>) = connection
+            .hget(self.challenge_key(challenge_id), &["data", "used"])
+            .await
+            .map_err(redis_error)?;
+
+        let Some(data) = data else {
+            return Ok(None);
+        };
+        let mut challenge: AuthChallenge = serde_json::from_str(&data)
+            .map_err(|e| AppError::DatabaseError(format!("Corrupt challenge {} in Redis: {}", challenge_id, e)))?;
+        challenge.used = used.as_deref() == Some("1");
+
+        Ok(Some(challenge))
+    }
+}
+
+#[async_trait]
+impl ChallengeStore for RedisChallengeStore {
+    async fn create(
+        &self,
+        challenge: NewChallenge,
+        active: ActiveChallenges,
+    ) -> Result<AuthChallenge, AppError> {
+        let now = challenge.chal_timestamp;
+        let auth_challenge = AuthChallenge {
+            id: challenge.id,
+            ethereum_address: challenge.ethereum_address,
+            nonce: challenge.nonce,
+            challenge_message: challenge.challenge_message,
+            expires_at: challenge.expires_at,
+            used: false,
+            created_at: now,
+            domain: challenge.domain,
+            chal_timestamp: challenge.chal_timestamp,
+            chain_id: challenge.chain_id,
+            uri: challenge.uri,
+            locale: challenge.locale,
+        };
+
+        let data = serde_json::to_string(&auth_challenge)
+            .map_err(|e| AppError::ServerError(format!("Failed to serialize challenge: {}", e)))?;
+        let ttl_ms = (auth_challenge.expires_at - now).num_milliseconds().max(1)
+            + self.expired_grace.as_millis() as i64;
+        let stats_cutoff = now - chrono::Duration::days(REDIS_STATS_RETENTION_DAYS);
+        let (policy, cap) = match active {
+            ActiveChallenges::Keep => ("keep", 0),
+            ActiveChallenges::Cap(max_active) => ("cap", max_active),
+            ActiveChallenges::Replace => ("replace", 0),
+        };
+
+        let mut connection = self.connection().await?;
+        let _: i64 = self.create_script
+            .key(self.address_key(&auth_challenge.ethereum_address))
+            .key(self.challenge_key(auth_challenge.id))
+            .key(self.stats_key("created"))
+            .key(self.stats_key("used"))
+            .arg(policy)
+            .arg(cap)
+            .arg(auth_challenge.id.to_string())
+            .arg(data)
+            .arg(ttl_ms)
+            .arg(score(now))
+            .arg(format!("{}challenge:", self.key_prefix))
+            .arg(score(stats_cutoff))
+            .arg(score(auth_challenge.expires_at))
+            .invoke_async(&mut connection)
+            .await
+            .map_err(redis_error)?;
+
+        Ok(auth_challenge)
+    }
+
+    async fn find_active(
+        &self,
+        address: &str,
+        challenge_id: Uuid,
+        now: NaiveDateTime,
+    ) -> Result<Option<AuthChallenge>, AppError> {
+        let challenge = self.load(challenge_id).await?.filter(|challenge| {
+            challenge.ethereum_address == address && !challenge.used && challenge.expires_at > now
+        });
+
+        Ok(challenge)
+    }
+
+    async fn find_expired(
+        &self,
+        address: &str,
+        challenge_id: Uuid,
+        expired_after: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Result<Option<AuthChallenge>, AppError> {
+        let challenge = self.load(challenge_id).await?.filter(|challenge| {
+            challenge.ethereum_address == address
+                && !challenge.used
+                && challenge.expires_at > expired_after
+                && challenge.expires_at <= now
+        });
+
+        Ok(challenge)
+    }
+
+    async fn mark_used(&self, challenge_id: Uuid) -> Result<bool, AppError> {
+        let Some(challenge) = self.load(challenge_id).await? else {
+            return Ok(false);
+        };
+
+        let mut connection = self.connection().await?;
+        let marked: i64 = self.mark_used_script
+            .key(self.challenge_key(challenge_id))
+            .key(self.address_key(&challenge.ethereum_address))
+            .key(self.stats_key("used"))
+            .arg(challenge_id.to_string())
+            .arg(score(challenge.created_at))
+            .invoke_async(&mut connection)
+            .await
+            .map_err(redis_error)?;
+
+        Ok(marked == 1)
+    }
+
+    /// Nonces are remembered by keys expiring with the reuse window
+    async fn claim_nonce(&self, nonce: &str, _now: NaiveDateTime) -> Result<bool, AppError> {
+        let mut connection = self.connection().await?;
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::PX(self.nonce_reuse_window.as_millis() as u64));
+        let claimed: Option<String> = connection
+            .set_options(self.nonce_key(nonce), 1, options)
+            .await
+            .map_err(redis_error)?;
+
+        Ok(claimed.is_some())
+    }
+
+    /// Expired challenges are removed by their TTL, nothing is left to delete
+    async fn cleanup_expired(&self, _now: NaiveDateTime) -> Result<u64, AppError> {
+        Ok(0)
+    }
+
+    async fn count_since(&self, since: NaiveDateTime) -> Result<(i64, i64), AppError> {
+        let mut connection = self.connection().await?;
+        let min = score(since);
+
+        let created: i64 = connection.zcount(self.stats_key("created"), min, "+inf")
+            .await
+            .map_err(redis_error)?;
+        let used: i64 = connection.zcount(self.stats_key("used"), min, "+inf")
+            .await
+            .map_err(redis_error)?;
+
+        Ok((created, used))
+    }
+
+    /// Only the address's outstanding challenges: used ones leave the
+    /// address index, and every challenge's key expires shortly after it does
+    async fn list_for_address(
+        &self,
+        address: &str,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<AuthChallenge>, AppError> {
+        let mut connection = self.connection().await?;
+        let start = offset.max(0) as isize;
+        let ids: Vec<String> = connection
+            .zrevrange(self.address_key(address), start, start + limit.max(1) as isize - 1)
+            .await
+            .map_err(redis_error)?;
+
+        let mut challenges = Vec::with_capacity(ids.len());
+        for id in ids {
+            let Ok(challenge_id) = Uuid::parse_str(&id) else {
+                continue;
+            };
+            if let Some(challenge) = self.load(challenge_id).await? {
+                challenges.push(challenge);
+            }
+        }
+
+        Ok(challenges)
+    }
+}
+
+/// Sorted-set score of a timestamp, in microseconds
+fn score(at: NaiveDateTime) -> i64 {
+    at.and_utc().timestamp_micros()
+}
+
+fn redis_error(error: redis::RedisError) -> AppError {
+    AppError::DatabaseError(format!("Challenge store error: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::task::JoinSet;
+
+    /// Store whose lookups take a while, counting the queries it serves;
+    /// the first `failures` of them fail
+    #[derive(Default)]
+    struct SlowStore {
+        queries: AtomicUsize,
+        failures: usize,
+    }
+
+    #[async_trait]
+    impl ChallengeStore for SlowStore {
+        async fn create(&self, _: NewChallenge, _: ActiveChallenges) -> Result<AuthChallenge, AppError> {
+            unimplemented!()
+        }
+
+        async fn find_active(&self, _: &str, _: Uuid, _: NaiveDateTime) -> Result<Option<AuthChallenge>, AppError> {
+            let query = self.queries.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if query < self.failures {
+                return Err(AppError::DatabaseError("connection reset".to_string()));
+            }
+            Ok(None)
+        }
+
+        async fn find_expired(&self, _: &str, _: Uuid, _: NaiveDateTime, _: NaiveDateTime)
+            -> Result<Option<AuthChallenge>, AppError>
+        {
+            unimplemented!()
+        }
+
+        async fn mark_used(&self, _: Uuid) -> Result<bool, AppError> {
+            unimplemented!()
+        }
+
+        async fn claim_nonce(&self, _: &str, _: NaiveDateTime) -> Result<bool, AppError> {
+            unimplemented!()
+        }
+
+        async fn cleanup_expired(&self, _: NaiveDateTime) -> Result<u64, AppError> {
+            unimplemented!()
+        }
+
+        async fn count_since(&self, _: NaiveDateTime) -> Result<(i64, i64), AppError> {
+            unimplemented!()
+        }
+
+        async fn list_for_address(&self, _: &str, _: i64, _: i64) -> Result<Vec<AuthChallenge>, AppError> {
+            unimplemented!()
+        }
+    }
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    async fn look_up_concurrently(
+        store: &Arc<CoalescingChallengeStore>,
+        challenge_ids: &[Uuid],
+    ) -> Vec<Result<Option<AuthChallenge>, AppError>> {
+        let mut lookups = JoinSet::new();
+        for &challenge_id in challenge_ids {
+            let store = store.clone();
+            lookups.spawn(async move {
+                store.find_active(ADDRESS, challenge_id, chrono::Utc::now().naive_utc()).await
+            });
+        }
+        lookups.join_all().await
+    }
+
+    #[tokio::test]
+    async fn concurrent_lookups_of_one_challenge_share_a_query() {
+        let inner = Arc::new(SlowStore::default());
+        let store = Arc::new(CoalescingChallengeStore::new(inner.clone()));
+        let challenge_id = Uuid::new_v4();
+
+        let results = look_up_concurrently(&store, &[challenge_id; 50]).await;
+        assert!(results.iter().all(|result| matches!(result, Ok(None))));
+        assert_eq!(inner.queries.load(Ordering::SeqCst), 1);
+        assert!(store.in_flight.lock().unwrap().is_empty());
+
+        // Nothing is kept once the lookup completed
+        look_up_concurrently(&store, &[challenge_id]).await;
+        assert_eq!(inner.queries.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn lookups_of_different_challenges_are_not_shared() {
+        let inner = Arc::new(SlowStore::default());
+        let store = Arc::new(CoalescingChallengeStore::new(inner.clone()));
+        let challenge_ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+
+        look_up_concurrently(&store, &challenge_ids).await;
+        assert_eq!(inner.queries.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn failed_lookup_is_retried_by_a_waiter() {
+        let inner = Arc::new(SlowStore { failures: 1, ..SlowStore::default() });
+        let store = Arc::new(CoalescingChallengeStore::new(inner.clone()));
+
+        let results = look_up_concurrently(&store, &[Uuid::nil(); 10]).await;
+        assert_eq!(results.iter().filter(|result| result.is_err()).count(), 1);
+        assert_eq!(results.iter().filter(|result| matches!(result, Ok(None))).count(), 9);
+        assert_eq!(inner.queries.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,206 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as, query_scalar, FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::app_error::app_error::AppError;
+use crate::models::auth_challenges::normalize_ethereum_address;
+use crate::utils::clock::Clock;
+
+/// Replaces an erased address inside the metadata of other users' events
+pub const ERASED_ADDRESS: &str = "[erased]";
+
+/// Legal-hold record of one erasure, kept after the events themselves are gone
+#[derive(Debug, FromRow, Serialize)]
+pub struct EventErasure {
+    pub id: Uuid,
+    /// SHA-256 of the lowercase address, enough to prove an erasure happened
+    pub address_hash: String,
+    pub user_id: Option<Uuid>,
+    pub requested_by: Uuid,
+    pub reason: String,
+    pub deleted_events: i64,
+    pub anonymized_events: i64,
+    pub created_at: NaiveDateTime,
+}
+
+impl EventErasure {
+    /// Erases the security events of an Ethereum address
+    ///
+    /// Events of the account owning the address are deleted. Events of other
+    /// users that mention the address in their metadata, such as an invoice
+    /// accepted by it or a multisig approval it signed, are kept with the
+    /// address replaced by `ERASED_ADDRESS`. The erasure is written to the
+    /// append-only `event_erasures` log first, in the same transaction, so no
+    /// event is erased without a record.
+    pub async fn erase_address(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        address: &str,
+        requested_by: Uuid,
+        reason: &str,
+    ) -> Result<EventErasure, AppError> {
+        let address = normalize_ethereum_address(address)
+            .map_err(|_| AppError::ValidationError(format!("Invalid address: {}", address)))?;
+        let address_pattern = format!("%{}%", address);
+
+        let mut tx = pool.begin().await?;
+
+        let user_id = query_scalar!(
+            "SELECT id FROM users WHERE LOWER(ethereum_address) = $1",
+            address
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let counts = query!(
+            r#"
+            SELECT
+                COUNT(*) FILTER (WHERE user_id = $1) as "deleted!",
+                COUNT(*) FILTER (WHERE user_id IS DISTINCT FROM $1) as "anonymized!"
+            FROM security_events
+            WHERE user_id = $1 OR metadata::text ILIKE $2
+            "#,
+            user_id,
+            address_pattern
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let erasure = query_as!(
+            EventErasure,
+            r#"
+            INSERT INTO event_erasures (
+                id, address_hash, user_id, requested_by, reason,
+                deleted_events, anonymized_events, created_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id, address_hash, user_id, requested_by, reason,
+                      deleted_events, anonymized_events, created_at
+            "#,
+            Uuid::new_v4(),
+            hex::encode(Sha256::digest(address.as_bytes())),
+            user_id,
+            requested_by,
+            reason,
+            counts.deleted,
+            counts.anonymized,
+            clock.now()
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        query!(
+            "DELETE FROM security_events WHERE user_id = $1",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Addresses are plain hex, so replacing them in the JSON text cannot break it
+        query!(
+            r#"
+            UPDATE security_events
+            SET metadata = regexp_replace(metadata::text, $1, $2, 'gi')::jsonb
+            WHERE metadata::text ILIKE $3
+            "#,
+            address,
+            ERASED_ADDRESS,
+            address_pattern
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(erasure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::security_events::{record_event, EventType},
+        test_support,
+        utils::clock::MockClock,
+    };
+    use chrono::NaiveDate;
+
+    const SUBJECT: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    const OTHER: &str = "0x0000000000000000000000000000000000000001";
+
+    async fn record(pool: &PgPool, clock: &MockClock, event_type: EventType, user_id: Uuid, metadata: serde_json::Value) {
+        record_event(pool, clock, event_type, user_id, test_support::client_ip(), "test", metadata)
+            .await
+            .unwrap();
+    }
+
+    async fn metadata_of(pool: &PgPool, user_id: Uuid) -> Vec<serde_json::Value> {
+        query_scalar!(r#"SELECT metadata as "metadata!" FROM security_events WHERE user_id = $1"#, user_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn events_of_the_owner_are_deleted_and_mentions_anonymized(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap());
+        let subject = test_support::create_user(&pool, &clock, SUBJECT).await;
+        let other = test_support::create_user(&pool, &clock, OTHER).await;
+
+        record(&pool, &clock, EventType::Login, subject.id, serde_json::json!({})).await;
+        record(&pool, &clock, EventType::WalletConnected, subject.id, serde_json::json!({})).await;
+        // Mixed case, as wallets send checksummed addresses
+        let mention = serde_json::json!({ "signer": SUBJECT.to_uppercase().replace("0X", "0x"), "invoice": "INV-1" });
+        record(&pool, &clock, EventType::MultisigApproval, other.id, mention).await;
+        record(&pool, &clock, EventType::Login, other.id, serde_json::json!({})).await;
+
+        let erasure = EventErasure::erase_address(&pool, &clock, SUBJECT, other.id, "GDPR request").await.unwrap();
+        assert_eq!(erasure.user_id, Some(subject.id));
+        assert_eq!((erasure.deleted_events, erasure.anonymized_events), (2, 1));
+        assert_eq!(erasure.address_hash, hex::encode(Sha256::digest(SUBJECT.as_bytes())));
+
+        assert!(metadata_of(&pool, subject.id).await.is_empty());
+        let kept = metadata_of(&pool, other.id).await;
+        assert_eq!(kept.len(), 2);
+        assert!(kept.contains(&serde_json::json!({ "signer": ERASED_ADDRESS, "invoice": "INV-1" })));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn an_address_without_account_is_only_anonymized(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap());
+        let other = test_support::create_user(&pool, &clock, OTHER).await;
+        record(&pool, &clock, EventType::InvoiceAccepted, other.id, serde_json::json!({ "recipient": SUBJECT })).await;
+
+        let erasure = EventErasure::erase_address(&pool, &clock, SUBJECT, other.id, "GDPR request").await.unwrap();
+        assert_eq!(erasure.user_id, None);
+        assert_eq!((erasure.deleted_events, erasure.anonymized_events), (0, 1));
+        assert_eq!(metadata_of(&pool, other.id).await, [serde_json::json!({ "recipient": ERASED_ADDRESS })]);
+
+        let stored = query_scalar!("SELECT id FROM event_erasures").fetch_all(&pool).await.unwrap();
+        assert_eq!(stored, [erasure.id]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn the_erasure_log_is_append_only(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap());
+        let other = test_support::create_user(&pool, &clock, OTHER).await;
+        let erasure = EventErasure::erase_address(&pool, &clock, SUBJECT, other.id, "GDPR request").await.unwrap();
+
+        let update = query!("UPDATE event_erasures SET reason = 'none' WHERE id = $1", erasure.id).execute(&pool).await;
+        assert!(update.is_err());
+        let delete = query!("DELETE FROM event_erasures WHERE id = $1", erasure.id).execute(&pool).await;
+        assert!(delete.is_err());
+
+        let reason = query_scalar!("SELECT reason FROM event_erasures WHERE id = $1", erasure.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(reason, "GDPR request");
+    }
+}
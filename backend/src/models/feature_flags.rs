@@ -0,0 +1,118 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, FromRow, PgPool};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::app_error::app_error::AppError;
+use crate::utils::clock::Clock;
+
+/// Feature flags consulted by handlers
+pub const INVOICE_ACCEPTANCE: &str = "invoice_acceptance";
+pub const INVOICE_SHARING: &str = "invoice_sharing";
+pub const SIGNATURE_VERIFICATION: &str = "signature_verification";
+pub const WALLET_TELEMETRY: &str = "wallet_telemetry";
+
+/// State of a feature, as toggled by an operator
+///
+/// Defaults come from `[feature_flags]`; a row only exists once a flag was
+/// toggled at runtime, and then wins over the configured default.
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct FeatureFlag {
+    pub name: String,
+    pub enabled: bool,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+impl FeatureFlag {
+    /// Lists every configured flag with its effective state
+    pub async fn list(
+        pool: &PgPool,
+        defaults: &HashMap<String, bool>,
+    ) -> Result<Vec<FeatureFlag>, AppError> {
+        let overrides = query_as!(
+            FeatureFlag,
+            r#"
+            SELECT name, enabled, updated_at as "updated_at?"
+            FROM feature_flags
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut flags: BTreeMap<String, FeatureFlag> = defaults
+            .iter()
+            .map(|(name, enabled)| (name.clone(), FeatureFlag {
+                name: name.clone(),
+                enabled: *enabled,
+                updated_at: None,
+            }))
+            .collect();
+        for flag in overrides.into_iter().filter(|flag| defaults.contains_key(&flag.name)) {
+            flags.insert(flag.name.clone(), flag);
+        }
+
+        Ok(flags.into_values().collect())
+    }
+
+    /// Sets a configured flag, returning `None` for unknown flags
+    pub async fn set(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        defaults: &HashMap<String, bool>,
+        name: &str,
+        enabled: bool,
+    ) -> Result<Option<FeatureFlag>, AppError> {
+        if !defaults.contains_key(name) {
+            return Ok(None);
+        }
+        let now = clock.now();
+
+        let flag = query_as!(
+            FeatureFlag,
+            r#"
+            INSERT INTO feature_flags (name, enabled, updated_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE SET enabled = $2, updated_at = $3
+            RETURNING name, enabled, updated_at as "updated_at?"
+            "#,
+            name,
+            enabled,
+            now
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(Some(flag))
+    }
+}
+
+/// Rejects the request with 503 when the feature is turned off
+///
+/// Flags missing from `[feature_flags]` are always enabled.
+pub async fn ensure_enabled(
+    pool: &PgPool,
+    defaults: &HashMap<String, bool>,
+    name: &str,
+) -> Result<(), AppError> {
+    let Some(default) = defaults.get(name) else {
+        return Ok(());
+    };
+
+    let enabled = query!(
+        r#"
+        SELECT enabled FROM feature_flags WHERE name = $1
+        "#,
+        name
+    )
+    .fetch_optional(pool)
+    .await?
+    .map_or(*default, |flag| flag.enabled);
+
+    if !enabled {
+        return Err(AppError::ServiceUnavailableError(format!(
+            "The {} feature is temporarily disabled", name
+        )));
+    }
+
+    Ok(())
+}
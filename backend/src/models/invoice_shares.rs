@@ -0,0 +1,249 @@
+use uuid::Uuid;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, FromRow, PgPool};
+
+use crate::app_error::app_error::AppError;
+use crate::utils::clock::Clock;
+
+/// A read-only link to a single invoice, identified by the share token's `jti`
+///
+/// Expiry and the view cap are enforced here rather than by the token, so
+/// the issuer sees how often the link was opened.
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct InvoiceShare {
+    pub id: Uuid,
+    pub invoice_id: Uuid,
+    pub created_by: Uuid,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    /// Views allowed before the link is used up, unlimited when absent
+    pub max_views: Option<i32>,
+    pub view_count: i32,
+    pub last_viewed_at: Option<NaiveDateTime>,
+}
+
+impl InvoiceShare {
+    pub async fn create(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        invoice_id: Uuid,
+        created_by: Uuid,
+        expires_at: NaiveDateTime,
+        max_views: Option<i32>,
+    ) -> Result<InvoiceShare, AppError> {
+        let now = clock.now();
+
+        let share = query_as!(
+            InvoiceShare,
+            r#"
+            INSERT INTO invoice_shares (id, invoice_id, created_by, created_at, expires_at, max_views)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, invoice_id, created_by, created_at, expires_at, revoked_at,
+                      max_views, view_count, last_viewed_at
+            "#,
+            Uuid::new_v4(),
+            invoice_id,
+            created_by,
+            now,
+            expires_at,
+            max_views
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(share)
+    }
+
+    /// Counts a view of the share of `invoice_id` and returns it updated
+    ///
+    /// The view is only counted while the share is unrevoked, unexpired and
+    /// under its view cap, all checked in the same statement so concurrent
+    /// views cannot exceed the cap. Expired and used up links are refused
+    /// with 410, unknown and revoked ones with 401.
+    pub async fn record_view(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        share_id: Uuid,
+        invoice_id: Uuid,
+    ) -> Result<InvoiceShare, AppError> {
+        let now = clock.now();
+
+        let share = query_as!(
+            InvoiceShare,
+            r#"
+            UPDATE invoice_shares
+            SET view_count = view_count + 1,
+                last_viewed_at = $3
+            WHERE id = $1
+              AND invoice_id = $2
+              AND revoked_at IS NULL
+              AND expires_at > $3
+              AND (max_views IS NULL OR view_count < max_views)
+            RETURNING id, invoice_id, created_by, created_at, expires_at, revoked_at,
+                      max_views, view_count, last_viewed_at
+            "#,
+            share_id,
+            invoice_id,
+            now
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(share) = share {
+            return Ok(share);
+        }
+
+        let share = query_as!(
+            InvoiceShare,
+            r#"
+            SELECT id, invoice_id, created_by, created_at, expires_at, revoked_at,
+                   max_views, view_count, last_viewed_at
+            FROM invoice_shares
+            WHERE id = $1
+              AND invoice_id = $2
+            "#,
+            share_id,
+            invoice_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Err(match share {
+            Some(share) if share.revoked_at.is_none() && share.expires_at <= now => {
+                AppError::GoneError("Share link has expired".to_string())
+            }
+            Some(share) if share.revoked_at.is_none() => {
+                AppError::GoneError("Share link has reached its view limit".to_string())
+            }
+            _ => AppError::UnauthorizedError("Share link is no longer valid".to_string()),
+        })
+    }
+
+    /// Lists the shares of an invoice, newest first, with their view counts
+    pub async fn list_for_invoice(
+        pool: &PgPool,
+        invoice_id: Uuid,
+    ) -> Result<Vec<InvoiceShare>, AppError> {
+        let shares = query_as!(
+            InvoiceShare,
+            r#"
+            SELECT id, invoice_id, created_by, created_at, expires_at, revoked_at,
+                   max_views, view_count, last_viewed_at
+            FROM invoice_shares
+            WHERE invoice_id = $1
+            ORDER BY created_at DESC
+            "#,
+            invoice_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(shares)
+    }
+
+    /// Revokes a share of the given invoice on behalf of its issuer
+    ///
+    /// Returns `false` when no such active share belongs to the issuer.
+    pub async fn revoke(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        invoice_id: Uuid,
+        share_id: Uuid,
+        created_by: Uuid,
+    ) -> Result<bool, AppError> {
+        let now = clock.now();
+
+        let result = query!(
+            r#"
+            UPDATE invoice_shares
+            SET revoked_at = $1
+            WHERE id = $2
+              AND invoice_id = $3
+              AND created_by = $4
+              AND revoked_at IS NULL
+            "#,
+            now,
+            share_id,
+            invoice_id,
+            created_by
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::invoices::tests::{create, input},
+        test_support,
+        utils::clock::MockClock,
+    };
+    use chrono::{Duration, NaiveDate};
+
+    async fn shared_invoice(pool: &PgPool, clock: &MockClock, max_views: Option<i32>) -> InvoiceShare {
+        let user = test_support::create_user(pool, clock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+        let invoice = create(pool, clock, &test_support::config(), user.id, &input()).await.unwrap();
+        InvoiceShare::create(pool, clock, invoice.id, user.id, clock.now() + Duration::days(1), max_views)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn expired_links_are_gone(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap());
+        let share = shared_invoice(&pool, &clock, None).await;
+
+        clock.advance(Duration::days(1) - Duration::seconds(1));
+        let viewed = InvoiceShare::record_view(&pool, &clock, share.id, share.invoice_id).await.unwrap();
+        assert_eq!((viewed.view_count, viewed.last_viewed_at), (1, Some(clock.now())));
+
+        // Expired exactly at `expires_at`
+        clock.advance(Duration::seconds(1));
+        match InvoiceShare::record_view(&pool, &clock, share.id, share.invoice_id).await {
+            Err(AppError::GoneError(message)) => assert_eq!(message, "Share link has expired"),
+            other => panic!("expected 410, got {other:?}"),
+        }
+        let shares = InvoiceShare::list_for_invoice(&pool, share.invoice_id).await.unwrap();
+        assert_eq!(shares[0].view_count, 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn views_are_capped(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap());
+        let share = shared_invoice(&pool, &clock, Some(2)).await;
+
+        for expected in 1..=2 {
+            let viewed = InvoiceShare::record_view(&pool, &clock, share.id, share.invoice_id).await.unwrap();
+            assert_eq!(viewed.view_count, expected);
+        }
+        match InvoiceShare::record_view(&pool, &clock, share.id, share.invoice_id).await {
+            Err(AppError::GoneError(message)) => assert_eq!(message, "Share link has reached its view limit"),
+            other => panic!("expected 410, got {other:?}"),
+        }
+        // Refused views are not counted
+        assert_eq!(InvoiceShare::list_for_invoice(&pool, share.invoice_id).await.unwrap()[0].view_count, 2);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn revoked_and_mismatched_links_are_unauthorized(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap());
+        let share = shared_invoice(&pool, &clock, None).await;
+
+        // A share of another invoice
+        let result = InvoiceShare::record_view(&pool, &clock, share.id, Uuid::new_v4()).await;
+        assert!(matches!(result, Err(AppError::UnauthorizedError(_))));
+
+        assert!(InvoiceShare::revoke(&pool, &clock, share.invoice_id, share.id, share.created_by).await.unwrap());
+        let result = InvoiceShare::record_view(&pool, &clock, share.id, share.invoice_id).await;
+        assert!(matches!(result, Err(AppError::UnauthorizedError(_))));
+    }
+}
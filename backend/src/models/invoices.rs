@@ -0,0 +1,1202 @@
+use uuid::Uuid;
+use chrono::{Datelike, NaiveDateTime};
+use bigdecimal::BigDecimal;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as, query_scalar, FromRow, PgConnection, PgPool, Type};
+use validator::{Validate, ValidationError};
+
+use crate::app_error::app_error::AppError;
+use crate::config::app_config::{Ethereum, InvoiceTerms};
+use crate::models::auth_challenges::normalize_ethereum_address;
+use crate::models::outbox::OutboxMessage;
+use crate::models::users::require_verified;
+use crate::utils::clock::Clock;
+use crate::models::auth_challenges::validate_eth_address;
+use crate::utils::metadata::{validate_metadata, MetadataKey};
+
+/// Prefix used for display numbers when the issuer did not configure one
+pub const DEFAULT_INVOICE_PREFIX: &str = "INV";
+/// Display number layout used when the issuer did not configure one
+pub const DEFAULT_INVOICE_NUMBER_FORMAT: &str = "{prefix}-{year}-{number}";
+
+/// Outbox aggregate and event types of invoice state changes
+const OUTBOX_AGGREGATE: &str = "invoice";
+const OUTBOX_CREATED: &str = "invoice.created";
+const OUTBOX_ACCEPTED: &str = "invoice.accepted";
+const OUTBOX_STATUS_CHANGED: &str = "invoice.status_changed";
+
+/// Issuer's order identifier, in invoice metadata
+pub const ORDER_ID: MetadataKey<String> = MetadataKey::new("order_id");
+/// Issuer's customer reference, in invoice metadata
+pub const CUSTOMER_REF: MetadataKey<String> = MetadataKey::new("customer_ref");
+/// Display number prefix, in the issuer's user metadata
+pub const INVOICE_PREFIX: MetadataKey<String> = MetadataKey::new("invoice_prefix");
+/// Display number layout, in the issuer's user metadata
+pub const INVOICE_NUMBER_FORMAT: MetadataKey<String> = MetadataKey::new("invoice_number_format");
+/// Per-user override of `invoice_terms.invoice_quota`, in the issuer's user metadata
+pub const INVOICE_QUOTA: MetadataKey<u32> = MetadataKey::new("invoice_quota");
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Type)]
+#[sqlx(type_name = "invoice_status", rename_all = "lowercase")]
+pub enum InvoiceStatus {
+    Pending,
+    Paid,
+    Disputed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Invoice {
+    pub id: Uuid,
+    pub on_chain_id: String,
+    pub title: String,
+    pub description: Option<String>,
+    /// Decimal string on the wire, see `utils::amount`
+    #[serde(with = "crate::utils::amount")]
+    pub amount: BigDecimal,
+    pub currency: String,
+    pub due_date: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub status: InvoiceStatus,
+    pub created_by: Uuid,
+    pub sequence_number: i64,
+    pub display_number: String,
+    pub recipient_address: Option<String>,
+    pub accepted_at: Option<NaiveDateTime>,
+    /// ERC-20 token the invoice is paid in, one of `ethereum.tokens`
+    pub token_address: Option<String>,
+    /// Integration-specific data, see the `MetadataKey` constants below
+    pub metadata: JsonValue,
+    /// Issuer's own identifier of the invoice, unique among its invoices
+    pub external_ref: Option<String>,
+}
+
+/// Side of an invoice a user is on
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InvoiceRole {
+    /// The user created the invoice
+    Issuer,
+    /// The invoice is addressed to the user's wallet
+    Recipient,
+}
+
+/// An invoice as shown to one of its parties, or to anyone holding a share link
+///
+/// Everyone sees what is needed to pay the invoice. `party` fields are left
+/// out for share links, `issuer` fields for everyone but the issuer; absent
+/// groups are omitted from the JSON rather than sent as null.
+#[derive(Debug, Serialize)]
+pub struct InvoiceView {
+    pub id: Uuid,
+    pub on_chain_id: String,
+    pub title: String,
+    pub description: Option<String>,
+    #[serde(with = "crate::utils::amount")]
+    pub amount: BigDecimal,
+    pub currency: String,
+    pub due_date: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub status: InvoiceStatus,
+    pub display_number: String,
+    pub accepted_at: Option<NaiveDateTime>,
+    pub token_address: Option<String>,
+    #[serde(flatten)]
+    pub party: Option<InvoicePartyFields>,
+    #[serde(flatten)]
+    pub issuer: Option<InvoiceIssuerFields>,
+}
+
+/// Fields of an invoice shown to its issuer and recipient
+#[derive(Debug, Serialize)]
+pub struct InvoicePartyFields {
+    pub created_by: Uuid,
+    pub recipient_address: Option<String>,
+}
+
+/// Fields of an invoice only its issuer sees
+#[derive(Debug, Serialize)]
+pub struct InvoiceIssuerFields {
+    /// Position in the issuer's numbering, the payer only needs `display_number`
+    pub sequence_number: i64,
+    /// Order ids, customer references and other integration data
+    pub metadata: JsonValue,
+    pub external_ref: Option<String>,
+}
+
+/// One page of a user's invoices, newest first
+#[derive(Debug, Serialize)]
+pub struct InvoicePage {
+    /// Each as seen from the user's side of it
+    pub invoices: Vec<InvoiceView>,
+    /// Invoices matching the filters across all pages
+    pub total: i64,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct InvoiceInput {
+    #[validate(length(min = 1, max = 255))]
+    pub on_chain_id: String,
+    #[validate(length(min = 1, max = 255))]
+    pub title: String,
+    pub description: Option<String>,
+    /// Decimal string on the wire, see `utils::amount`
+    #[serde(with = "crate::utils::amount")]
+    pub amount: BigDecimal,
+    #[validate(length(equal = 3))]
+    pub currency: String,
+    /// Defaults to the configured net term from creation when omitted
+    pub due_date: Option<NaiveDateTime>,
+    #[validate(custom(function = "validate_eth_address"))]
+    pub recipient_address: Option<String>,
+    #[validate(custom(function = "validate_eth_address"))]
+    pub token_address: Option<String>,
+    #[validate(custom(function = "validate_metadata"))]
+    pub metadata: Option<JsonValue>,
+    /// The issuer's own identifier, e.g. an order number, see `validate_external_ref`
+    #[validate(custom(function = "validate_external_ref"))]
+    pub external_ref: Option<String>,
+}
+
+/// Longest `external_ref` accepted
+const EXTERNAL_REF_MAX_LEN: usize = 64;
+
+/// Validator for `external_ref`: up to 64 letters, digits, `.`, `_`, `:` or
+/// `-`, so it can be used as is in `/invoices/by-ref/{external_ref}`
+pub fn validate_external_ref(external_ref: &str) -> Result<(), ValidationError> {
+    if external_ref.is_empty() || external_ref.len() > EXTERNAL_REF_MAX_LEN {
+        return Err(ValidationError::new("external_ref_length")
+            .with_message("external_ref must be 1 to 64 characters long".into()));
+    }
+    if !external_ref.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | ':' | '-')) {
+        return Err(ValidationError::new("external_ref_characters")
+            .with_message("external_ref may only contain letters, digits, '.', '_', ':' and '-'".into()));
+    }
+    Ok(())
+}
+
+impl Invoice {
+    /// Creates an invoice and assigns it the issuer's next sequence number
+    ///
+    /// The per-issuer counter row is incremented in the same transaction as
+    /// the insert: its row lock serializes concurrent creations and a failed
+    /// insert rolls the counter back, so numbering stays gap-free.
+    ///
+    /// A payment token, when given, must be one of the supported tokens on
+    /// the configured chain and the amount must fit its decimals. An explicit
+    /// due date must be in the future, a missing one follows `invoice_terms`.
+    /// Amounts from `verified_amount_threshold` up need a verified issuer, and
+    /// an issuer at their invoice quota is refused with `QuotaExceededError`.
+    /// An `external_ref` the issuer already used is refused with `ConflictError`.
+    pub async fn create(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        ethereum: &Ethereum,
+        invoice_terms: &InvoiceTerms,
+        created_by: Uuid,
+        input: &InvoiceInput,
+    ) -> Result<Invoice, AppError> {
+        let now = clock.now();
+        let due_date = match input.due_date {
+            Some(due_date) if due_date <= now => {
+                return Err(AppError::ValidationError("due_date must be in the future".to_string()));
+            }
+            Some(due_date) => due_date,
+            None => invoice_terms.default_due_date(now),
+        };
+
+        let is_high_value = invoice_terms.verified_amount_threshold.as_ref()
+            .is_some_and(|threshold| &input.amount >= threshold);
+        if is_high_value {
+            require_verified(pool, created_by).await?;
+        }
+        let recipient_address = input.recipient_address
+            .as_deref()
+            .map(normalize_ethereum_address)
+            .transpose()?;
+
+        let token_address = match input.token_address.as_deref() {
+            Some(address) => {
+                let token = ethereum.find_token(ethereum.chain_id, address)
+                    .ok_or_else(|| AppError::ValidationError(format!("Unsupported payment token {}", address)))?;
+                token.amount_to_base_units(&input.amount)?;
+                Some(normalize_ethereum_address(&token.contract_address)?)
+            }
+            None => None,
+        };
+
+        let mut tx = pool.begin().await?;
+
+        let sequence_number = next_sequence_number(&mut tx, created_by).await?;
+
+        let issuer = query!(
+            r#"
+            SELECT metadata as "metadata: JsonValue"
+            FROM users
+            WHERE id = $1
+            "#,
+            created_by
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Checked under the counter row lock, so concurrent creations cannot overshoot
+        if let Some(quota) = INVOICE_QUOTA.get(&issuer.metadata).or(invoice_terms.invoice_quota) {
+            check_quota(&mut tx, now, created_by, quota, invoice_terms.invoice_quota_period_days).await?;
+        }
+
+        // Also under the counter row lock, the unique index only backs this up
+        if let Some(external_ref) = &input.external_ref {
+            let taken = query_scalar!(
+                r#"
+                SELECT EXISTS (
+                    SELECT 1
+                    FROM invoices
+                    WHERE created_by = $1
+                      AND external_ref = $2
+                ) as "taken!"
+                "#,
+                created_by,
+                external_ref
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if taken {
+                return Err(AppError::ConflictError(format!(
+                    "An invoice with external_ref '{}' already exists", external_ref
+                )));
+            }
+        }
+
+        let display_number = format_display_number(&issuer.metadata, sequence_number, &now);
+
+        let invoice = query_as!(
+            Invoice,
+            r#"
+            INSERT INTO invoices (
+                id,
+                on_chain_id,
+                title,
+                description,
+                amount,
+                currency,
+                due_date,
+                created_at,
+                updated_at,
+                status,
+                created_by,
+                sequence_number,
+                display_number,
+                recipient_address,
+                token_address,
+                metadata,
+                external_ref
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+            RETURNING id, on_chain_id, title, description, amount, currency, due_date,
+                      created_at as "created_at!", updated_at as "updated_at!",
+                      status as "status!: InvoiceStatus", created_by as "created_by!",
+                      sequence_number, display_number, recipient_address, accepted_at,
+                      token_address, metadata as "metadata: JsonValue", external_ref
+            "#,
+            Uuid::new_v4(),
+            input.on_chain_id,
+            input.title,
+            input.description,
+            input.amount,
+            input.currency,
+            due_date,
+            now,
+            now,
+            InvoiceStatus::Pending as InvoiceStatus,
+            created_by,
+            sequence_number,
+            display_number,
+            recipient_address,
+            token_address,
+            input.metadata.clone().unwrap_or_else(|| serde_json::json!({})),
+            input.external_ref,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        OutboxMessage::enqueue(&mut tx, now, OUTBOX_AGGREGATE, invoice.id, OUTBOX_CREATED, invoice.outbox_payload()?).await?;
+
+        tx.commit().await?;
+
+        Ok(invoice)
+    }
+
+    fn outbox_payload(&self) -> Result<JsonValue, AppError> {
+        serde_json::to_value(self)
+            .map_err(|e| AppError::OtherError(format!("Failed to serialize invoice: {}", e)))
+    }
+
+    pub async fn get_invoice_by_id(
+        pool: &PgPool,
+        invoice_id: Uuid,
+    ) -> Result<Option<Invoice>, AppError> {
+        let invoice = query_as!(
+            Invoice,
+            r#"
+            SELECT id, on_chain_id, title, description, amount, currency, due_date,
+                   created_at as "created_at!", updated_at as "updated_at!",
+                   status as "status!: InvoiceStatus", created_by as "created_by!",
+                   sequence_number, display_number, recipient_address, accepted_at,
+                   token_address, metadata as "metadata: JsonValue", external_ref
+            FROM invoices
+            WHERE id = $1
+            "#,
+            invoice_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(invoice)
+    }
+
+    /// Lists an issuer's invoices whose metadata holds `key` set to the string `value`
+    /// Streams every invoice of an issuer in numbering order, without buffering
+    pub fn stream_issued_by(
+        pool: &PgPool,
+        created_by: Uuid,
+    ) -> BoxStream<'_, Result<Invoice, AppError>> {
+        query_as!(
+            Invoice,
+            r#"
+            SELECT id, on_chain_id, title, description, amount, currency, due_date,
+                   created_at as "created_at!", updated_at as "updated_at!",
+                   status as "status!: InvoiceStatus", created_by as "created_by!",
+                   sequence_number, display_number, recipient_address, accepted_at,
+                   token_address, metadata as "metadata: JsonValue", external_ref
+            FROM invoices
+            WHERE created_by = $1
+            ORDER BY sequence_number
+            "#,
+            created_by
+        )
+        .fetch(pool)
+        .map_err(AppError::from)
+        .boxed()
+    }
+
+    /// The invoice an issuer created with `external_ref`
+    pub async fn find_by_external_ref(
+        pool: &PgPool,
+        created_by: Uuid,
+        external_ref: &str,
+    ) -> Result<Option<Invoice>, AppError> {
+        let invoice = query_as!(
+            Invoice,
+            r#"
+            SELECT id, on_chain_id, title, description, amount, currency, due_date,
+                   created_at as "created_at!", updated_at as "updated_at!",
+                   status as "status!: InvoiceStatus", created_by as "created_by!",
+                   sequence_number, display_number, recipient_address, accepted_at,
+                   token_address, metadata as "metadata: JsonValue", external_ref
+            FROM invoices
+            WHERE created_by = $1
+              AND external_ref = $2
+            "#,
+            created_by,
+            external_ref
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(invoice)
+    }
+
+    pub async fn find_by_metadata(
+        pool: &PgPool,
+        created_by: Uuid,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<Invoice>, AppError> {
+        let invoices = query_as!(
+            Invoice,
+            r#"
+            SELECT id, on_chain_id, title, description, amount, currency, due_date,
+                   created_at as "created_at!", updated_at as "updated_at!",
+                   status as "status!: InvoiceStatus", created_by as "created_by!",
+                   sequence_number, display_number, recipient_address, accepted_at,
+                   token_address, metadata as "metadata: JsonValue", external_ref
+            FROM invoices
+            WHERE created_by = $1
+              AND metadata @> jsonb_build_object($2::text, $3::text)
+            ORDER BY sequence_number DESC
+            "#,
+            created_by,
+            key,
+            value
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(invoices)
+    }
+
+    /// Lists the invoices a user issued or is the recipient of
+    ///
+    /// `role` narrows the list to one side, `status` to one status. Only
+    /// invoices created by `user_id` or addressed to `address` are ever
+    /// returned.
+    pub async fn list_for_user(
+        pool: &PgPool,
+        user_id: Uuid,
+        address: &str,
+        role: Option<InvoiceRole>,
+        status: Option<InvoiceStatus>,
+        offset: i64,
+        limit: i64,
+    ) -> Result<InvoicePage, AppError> {
+        let address = address.to_lowercase();
+        let as_issuer = role != Some(InvoiceRole::Recipient);
+        let as_recipient = role != Some(InvoiceRole::Issuer);
+
+        let total = query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!"
+            FROM invoices
+            WHERE ((created_by = $1 AND $3) OR (recipient_address = $2 AND $4))
+              AND ($5::invoice_status IS NULL OR status = $5)
+            "#,
+            user_id,
+            address,
+            as_issuer,
+            as_recipient,
+            status as Option<InvoiceStatus>
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let invoices = query_as!(
+            Invoice,
+            r#"
+            SELECT id, on_chain_id, title, description, amount, currency, due_date,
+                   created_at as "created_at!", updated_at as "updated_at!",
+                   status as "status!: InvoiceStatus", created_by as "created_by!",
+                   sequence_number, display_number, recipient_address, accepted_at,
+                   token_address, metadata as "metadata: JsonValue", external_ref
+            FROM invoices
+            WHERE ((created_by = $1 AND $3) OR (recipient_address = $2 AND $4))
+              AND ($5::invoice_status IS NULL OR status = $5)
+            ORDER BY created_at DESC, id DESC
+            OFFSET $6
+            LIMIT $7
+            "#,
+            user_id,
+            address,
+            as_issuer,
+            as_recipient,
+            status as Option<InvoiceStatus>,
+            offset,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let has_more = offset + (invoices.len() as i64) < total;
+        let invoices = invoices.into_iter()
+            .map(|invoice| {
+                let role = invoice.role_of(user_id, &address);
+                invoice.into_view(role)
+            })
+            .collect();
+
+        Ok(InvoicePage { invoices, total, has_more })
+    }
+
+    /// Whether the user issued the invoice or owns its recipient address
+    pub fn is_party(&self, user_id: Uuid, address: &str) -> bool {
+        self.role_of(user_id, address).is_some()
+    }
+
+    /// Side of the invoice the user is on, issuer first when on both
+    pub fn role_of(&self, user_id: Uuid, address: &str) -> Option<InvoiceRole> {
+        if self.created_by == user_id {
+            return Some(InvoiceRole::Issuer);
+        }
+        self.recipient_address.as_deref()
+            .is_some_and(|recipient| recipient.eq_ignore_ascii_case(address))
+            .then_some(InvoiceRole::Recipient)
+    }
+
+    /// The fields of the invoice `role` may see, `None` for share links
+    pub fn into_view(self, role: Option<InvoiceRole>) -> InvoiceView {
+        let party = role.is_some().then_some(InvoicePartyFields {
+            created_by: self.created_by,
+            recipient_address: self.recipient_address,
+        });
+        let issuer = (role == Some(InvoiceRole::Issuer)).then_some(InvoiceIssuerFields {
+            sequence_number: self.sequence_number,
+            metadata: self.metadata,
+            external_ref: self.external_ref,
+        });
+
+        InvoiceView {
+            id: self.id,
+            on_chain_id: self.on_chain_id,
+            title: self.title,
+            description: self.description,
+            amount: self.amount,
+            currency: self.currency,
+            due_date: self.due_date,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            status: self.status,
+            display_number: self.display_number,
+            accepted_at: self.accepted_at,
+            token_address: self.token_address,
+            party,
+            issuer,
+        }
+    }
+
+    /// Strong entity tag of this version of the invoice, e.g. `"3f2a..."`
+    ///
+    /// Every write bumps `updated_at`, so the tag changes with any update.
+    pub fn etag(&self) -> String {
+        let digest = Sha256::new()
+            .chain_update(self.id.as_bytes())
+            .chain_update(self.updated_at.and_utc().timestamp_micros().to_be_bytes())
+            .chain_update(format!("{:?}", self.status))
+            .finalize();
+        format!("\"{}\"", hex::encode(&digest[..16]))
+    }
+
+    /// EIP-681 URI calling `payInvoice` for this invoice, suitable for a QR code
+    pub fn payment_uri(&self, ethereum: &Ethereum) -> String {
+        format!(
+            "ethereum:{}@{}/payInvoice?uint256={}",
+            ethereum.contract_address, ethereum.chain_id, self.on_chain_id
+        )
+    }
+
+    /// Lists up to `limit` invoices ordered by id, starting after `after`
+    pub async fn list_after(
+        pool: &PgPool,
+        after: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<Invoice>, AppError> {
+        let invoices = query_as!(
+            Invoice,
+            r#"
+            SELECT id, on_chain_id, title, description, amount, currency, due_date,
+                   created_at as "created_at!", updated_at as "updated_at!",
+                   status as "status!: InvoiceStatus", created_by as "created_by!",
+                   sequence_number, display_number, recipient_address, accepted_at,
+                   token_address, metadata as "metadata: JsonValue", external_ref
+            FROM invoices
+            WHERE $1::uuid IS NULL OR id > $1
+            ORDER BY id
+            LIMIT $2
+            "#,
+            after,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(invoices)
+    }
+
+    /// Moves an invoice from `from` to `to`
+    ///
+    /// Returns `false` when the invoice is no longer in `from`, so a concurrent
+    /// change is never overwritten.
+    pub async fn update_status(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        invoice_id: Uuid,
+        from: InvoiceStatus,
+        to: InvoiceStatus,
+    ) -> Result<bool, AppError> {
+        let mut tx = pool.begin().await?;
+
+        if !set_status(&mut tx, clock.now(), invoice_id, from, to).await? {
+            return Ok(false);
+        }
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Invoice a transaction was already confirmed as paying, if any
+    pub async fn find_by_payment(
+        pool: &PgPool,
+        chain_id: u32,
+        tx_hash: &str,
+    ) -> Result<Option<Uuid>, AppError> {
+        let invoice_id = query!(
+            r#"
+            SELECT invoice_id
+            FROM invoice_payments
+            WHERE chain_id = $1 AND tx_hash = $2
+            "#,
+            i64::from(chain_id),
+            tx_hash
+        )
+        .fetch_optional(pool)
+        .await?
+        .map(|row| row.invoice_id);
+
+        Ok(invoice_id)
+    }
+
+    /// Records a verified payment transaction and marks a pending invoice paid
+    ///
+    /// Each transaction hash can settle a single invoice per chain: claiming
+    /// one already recorded for another invoice returns `Replayed` and
+    /// changes nothing. Confirming the same pair twice is harmless.
+    pub async fn confirm_payment(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        invoice_id: Uuid,
+        chain_id: u32,
+        tx_hash: &str,
+    ) -> Result<PaymentClaim, AppError> {
+        let now = clock.now();
+
+        let mut tx = pool.begin().await?;
+
+        let claimed_by = query!(
+            r#"
+            INSERT INTO invoice_payments (chain_id, tx_hash, invoice_id, confirmed_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (chain_id, tx_hash) DO UPDATE SET chain_id = EXCLUDED.chain_id
+            RETURNING invoice_id
+            "#,
+            i64::from(chain_id),
+            tx_hash,
+            invoice_id,
+            now
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .invoice_id;
+
+        if claimed_by != invoice_id {
+            return Ok(PaymentClaim::Replayed { paid_invoice_id: claimed_by });
+        }
+
+        let status_changed = set_status(&mut tx, now, invoice_id, InvoiceStatus::Pending, InvoiceStatus::Paid).await?;
+
+        tx.commit().await?;
+
+        Ok(PaymentClaim::Recorded { status_changed })
+    }
+
+    /// Records that the designated recipient accepted a pending invoice
+    ///
+    /// Returns `None` when the invoice is no longer pending or was already
+    /// accepted.
+    pub async fn mark_accepted(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        invoice_id: Uuid,
+    ) -> Result<Option<Invoice>, AppError> {
+        let mut tx = pool.begin().await?;
+        let invoice = Invoice::accept_in(&mut tx, clock.now(), invoice_id).await?;
+        tx.commit().await?;
+
+        Ok(invoice)
+    }
+
+    /// `mark_accepted` within the caller's transaction, so the acceptance can
+    /// be undone together with the caller's other writes
+    pub async fn accept_in(
+        conn: &mut PgConnection,
+        now: NaiveDateTime,
+        invoice_id: Uuid,
+    ) -> Result<Option<Invoice>, AppError> {
+        let invoice = query_as!(
+            Invoice,
+            r#"
+            UPDATE invoices
+            SET accepted_at = $1, updated_at = $1
+            WHERE id = $2
+              AND status = 'pending'
+              AND accepted_at IS NULL
+            RETURNING id, on_chain_id, title, description, amount, currency, due_date,
+                      created_at as "created_at!", updated_at as "updated_at!",
+                      status as "status!: InvoiceStatus", created_by as "created_by!",
+                      sequence_number, display_number, recipient_address, accepted_at,
+                      token_address, metadata as "metadata: JsonValue", external_ref
+            "#,
+            now,
+            invoice_id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if let Some(invoice) = &invoice {
+            OutboxMessage::enqueue(conn, now, OUTBOX_AGGREGATE, invoice.id, OUTBOX_ACCEPTED, invoice.outbox_payload()?).await?;
+        }
+
+        Ok(invoice)
+    }
+}
+
+/// Outcome of `Invoice::confirm_payment`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentClaim {
+    Recorded { status_changed: bool },
+    /// The transaction already settled another invoice
+    Replayed { paid_invoice_id: Uuid },
+}
+
+/// Moves an invoice from one status to another and queues the change
+///
+/// Returns `false`, writing nothing, when the invoice is not in `from`.
+async fn set_status(
+    conn: &mut PgConnection,
+    now: NaiveDateTime,
+    invoice_id: Uuid,
+    from: InvoiceStatus,
+    to: InvoiceStatus,
+) -> Result<bool, AppError> {
+    let result = query!(
+        r#"
+        UPDATE invoices
+        SET status = $1, updated_at = $2
+        WHERE id = $3 AND status = $4
+        "#,
+        to as InvoiceStatus,
+        now,
+        invoice_id,
+        from as InvoiceStatus
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    OutboxMessage::enqueue(
+        conn,
+        now,
+        OUTBOX_AGGREGATE,
+        invoice_id,
+        OUTBOX_STATUS_CHANGED,
+        serde_json::json!({ "invoice_id": invoice_id, "from": from, "to": to }),
+    ).await?;
+
+    Ok(true)
+}
+
+/// Refuses a creation once the issuer made `quota` invoices in the rolling period
+///
+/// The error tells when the oldest invoice counting against the quota leaves
+/// the period, freeing a slot.
+async fn check_quota(
+    conn: &mut PgConnection,
+    now: NaiveDateTime,
+    created_by: Uuid,
+    quota: u32,
+    period_days: u32,
+) -> Result<(), AppError> {
+    let period = chrono::Duration::days(i64::from(period_days));
+
+    let created = query!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM invoices
+        WHERE created_by = $1 AND created_at > $2
+        "#,
+        created_by,
+        now - period
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .count;
+
+    if created < i64::from(quota) {
+        return Ok(());
+    }
+
+    let freeing = query!(
+        r#"
+        SELECT created_at as "created_at!"
+        FROM invoices
+        WHERE created_by = $1 AND created_at > $2
+        ORDER BY created_at
+        OFFSET $3
+        LIMIT 1
+        "#,
+        created_by,
+        now - period,
+        created - i64::from(quota)
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .map_or(now, |row| row.created_at);
+
+    Err(AppError::QuotaExceededError(format!(
+        "Invoice quota of {} per {} days reached, next invoice allowed after {}",
+        quota, period_days, (freeing + period).format("%Y-%m-%dT%H:%M:%SZ")
+    )))
+}
+
+/// Lowercases a transaction hash after checking it is `0x` and 64 hex digits
+pub fn normalize_tx_hash(tx_hash: &str) -> Result<String, AppError> {
+    let tx_hash = tx_hash.trim();
+    let is_hash = tx_hash.strip_prefix("0x")
+        .is_some_and(|digits| digits.len() == 64 && digits.chars().all(|c| c.is_ascii_hexdigit()));
+    if !is_hash {
+        return Err(AppError::ValidationError(format!("Invalid transaction hash: {}", tx_hash)));
+    }
+    Ok(tx_hash.to_lowercase())
+}
+
+async fn next_sequence_number(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<i64, AppError> {
+    let counter = query!(
+        r#"
+        INSERT INTO invoice_counters (user_id, last_number)
+        VALUES ($1, 1)
+        ON CONFLICT (user_id)
+        DO UPDATE SET last_number = invoice_counters.last_number + 1
+        RETURNING last_number
+        "#,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(counter.last_number)
+}
+
+/// Builds the human-friendly invoice number, e.g. `INV-2024-0001`
+///
+/// Issuers can override `invoice_prefix` and `invoice_number_format` in their
+/// metadata; the format supports the `{prefix}`, `{year}` and `{number}`
+/// placeholders, the number being zero-padded to four digits.
+fn format_display_number(
+    metadata: &JsonValue,
+    sequence_number: i64,
+    created_at: &NaiveDateTime,
+) -> String {
+    let prefix = INVOICE_PREFIX.get(metadata)
+        .unwrap_or_else(|| DEFAULT_INVOICE_PREFIX.to_string());
+    let format = INVOICE_NUMBER_FORMAT.get(metadata)
+        .unwrap_or_else(|| DEFAULT_INVOICE_NUMBER_FORMAT.to_string());
+
+    format
+        .replace("{prefix}", &prefix)
+        .replace("{year}", &created_at.year().to_string())
+        .replace("{number}", &format!("{:04}", sequence_number))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{config::app_config::AppConfig, models::users::User, test_support, utils::clock::MockClock};
+    use chrono::{Duration, NaiveDate};
+
+    pub(crate) fn input() -> InvoiceInput {
+        InvoiceInput {
+            on_chain_id: "1".to_string(),
+            title: "Consulting".to_string(),
+            description: None,
+            amount: "1500".parse().unwrap(),
+            currency: "ETH".to_string(),
+            due_date: None,
+            recipient_address: None,
+            token_address: None,
+            metadata: None,
+            external_ref: None,
+        }
+    }
+
+    pub(crate) async fn create(pool: &PgPool, clock: &MockClock, config: &AppConfig, created_by: Uuid, input: &InvoiceInput)
+        -> Result<Invoice, AppError>
+    {
+        Invoice::create(pool, clock, &config.ethereum, &config.invoice_terms, created_by, input).await
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn missing_due_date_follows_the_net_term(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let config = test_support::config();
+        // Tuesday, net-30 lands on a Thursday
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 3).unwrap().and_hms_opt(10, 0, 0).unwrap());
+        let user = test_support::create_user(&pool, &clock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+
+        let invoice = create(&pool, &clock, &config, user.id, &input()).await.unwrap();
+        assert_eq!(invoice.due_date, config.invoice_terms.default_due_date(clock.now()));
+        assert_eq!(invoice.due_date, clock.now() + Duration::days(30));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn explicit_due_date_must_be_in_the_future(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let config = test_support::config();
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 3).unwrap().and_hms_opt(10, 0, 0).unwrap());
+        let user = test_support::create_user(&pool, &clock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+
+        for due_date in [clock.now(), clock.now() - Duration::days(1)] {
+            let past = InvoiceInput { due_date: Some(due_date), ..input() };
+            assert!(matches!(create(&pool, &clock, &config, user.id, &past).await, Err(AppError::ValidationError(_))));
+        }
+
+        let due_date = clock.now() + Duration::days(7);
+        let future = InvoiceInput { due_date: Some(due_date), ..input() };
+        assert_eq!(create(&pool, &clock, &config, user.id, &future).await.unwrap().due_date, due_date);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn high_value_invoices_require_a_verified_issuer(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let config = test_support::config();
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 3).unwrap().and_hms_opt(10, 0, 0).unwrap());
+        let user = test_support::create_user(&pool, &clock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+        let threshold = config.invoice_terms.verified_amount_threshold.clone().unwrap();
+
+        let high_value = |on_chain_id: &str| InvoiceInput { on_chain_id: on_chain_id.to_string(), amount: threshold.clone(), ..input() };
+        let result = create(&pool, &clock, &config, user.id, &high_value("1")).await;
+        assert!(matches!(result, Err(AppError::ForbiddenError(_))));
+        // Below the threshold no verification is needed
+        create(&pool, &clock, &config, user.id, &input()).await.unwrap();
+
+        User::set_verified(&pool, &clock, user.id, true).await.unwrap().unwrap();
+        create(&pool, &clock, &config, user.id, &high_value("2")).await.unwrap();
+
+        User::set_verified(&pool, &clock, user.id, false).await.unwrap().unwrap();
+        assert!(matches!(require_verified(&pool, user.id).await, Err(AppError::ForbiddenError(_))));
+        assert!(matches!(require_verified(&pool, Uuid::new_v4()).await, Err(AppError::ForbiddenError(_))));
+    }
+
+    fn numbered(on_chain_id: u32) -> InvoiceInput {
+        InvoiceInput { on_chain_id: on_chain_id.to_string(), ..input() }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn invoice_quota_is_enforced_over_the_period(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let mut config = test_support::config();
+        config.invoice_terms.invoice_quota = Some(2);
+        config.invoice_terms.invoice_quota_period_days = 30;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 3).unwrap().and_hms_opt(10, 0, 0).unwrap());
+        let user = test_support::create_user(&pool, &clock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+
+        create(&pool, &clock, &config, user.id, &numbered(1)).await.unwrap();
+        clock.advance(Duration::days(10));
+        // Reaching the quota is allowed, exceeding it is not
+        create(&pool, &clock, &config, user.id, &numbered(2)).await.unwrap();
+        match create(&pool, &clock, &config, user.id, &numbered(3)).await {
+            Err(AppError::QuotaExceededError(message)) => assert!(message.ends_with("after 2026-04-02T10:00:00Z"), "{message}"),
+            other => panic!("expected the quota to be exceeded, got {other:?}"),
+        }
+
+        // The first invoice leaves the period
+        clock.advance(Duration::days(20));
+        create(&pool, &clock, &config, user.id, &numbered(3)).await.unwrap();
+        assert!(matches!(create(&pool, &clock, &config, user.id, &numbered(4)).await, Err(AppError::QuotaExceededError(_))));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn invoice_quota_can_be_overridden_per_user(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let mut config = test_support::config();
+        config.invoice_terms.invoice_quota = Some(1);
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 3).unwrap().and_hms_opt(10, 0, 0).unwrap());
+        let user = test_support::create_user(&pool, &clock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+        sqlx::query!(r#"UPDATE users SET metadata = '{"invoice_quota": 3}' WHERE id = $1"#, user.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        for on_chain_id in 1..=3 {
+            create(&pool, &clock, &config, user.id, &numbered(on_chain_id)).await.unwrap();
+        }
+        assert!(matches!(create(&pool, &clock, &config, user.id, &numbered(4)).await, Err(AppError::QuotaExceededError(_))));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn users_list_only_invoices_they_are_party_to(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let config = test_support::config();
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 3).unwrap().and_hms_opt(10, 0, 0).unwrap());
+        let issuer = test_support::create_user(&pool, &clock, "0x0000000000000000000000000000000000000002").await;
+        let recipient = test_support::create_user(&pool, &clock, RECIPIENT).await;
+        let third_party = test_support::create_user(&pool, &clock, "0x0000000000000000000000000000000000000001").await;
+
+        let billed = InvoiceInput { recipient_address: Some(RECIPIENT.to_uppercase().replace("0X", "0x")), ..numbered(1) };
+        let billed = create(&pool, &clock, &config, issuer.id, &billed).await.unwrap();
+        let unaddressed = create(&pool, &clock, &config, issuer.id, &numbered(2)).await.unwrap();
+        let reverse = InvoiceInput { recipient_address: Some(issuer.ethereum_address.clone()), ..numbered(3) };
+        let reverse = create(&pool, &clock, &config, recipient.id, &reverse).await.unwrap();
+        assert!(Invoice::update_status(&pool, &clock, unaddressed.id, InvoiceStatus::Pending, InvoiceStatus::Cancelled).await.unwrap());
+
+        let list = |user: &User, role: Option<InvoiceRole>, status: Option<InvoiceStatus>| {
+            let (pool, user_id, address) = (pool.clone(), user.id, user.ethereum_address.clone());
+            async move {
+                let page = Invoice::list_for_user(&pool, user_id, &address, role, status, 0, 50).await.unwrap();
+                assert_eq!(page.total, page.invoices.len() as i64);
+                let mut ids: Vec<Uuid> = page.invoices.into_iter().map(|invoice| invoice.id).collect();
+                ids.sort();
+                ids
+            }
+        };
+        let sorted = |mut ids: Vec<Uuid>| {
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(list(&issuer, None, None).await, sorted(vec![billed.id, unaddressed.id, reverse.id]));
+        assert_eq!(list(&issuer, Some(InvoiceRole::Issuer), None).await, sorted(vec![billed.id, unaddressed.id]));
+        assert_eq!(list(&issuer, Some(InvoiceRole::Recipient), None).await, [reverse.id]);
+        assert_eq!(list(&issuer, None, Some(InvoiceStatus::Cancelled)).await, [unaddressed.id]);
+        assert_eq!(list(&issuer, Some(InvoiceRole::Issuer), Some(InvoiceStatus::Pending)).await, [billed.id]);
+        assert_eq!(list(&recipient, Some(InvoiceRole::Recipient), None).await, [billed.id]);
+        assert_eq!(list(&recipient, None, None).await, sorted(vec![billed.id, reverse.id]));
+
+        for role in [None, Some(InvoiceRole::Issuer), Some(InvoiceRole::Recipient)] {
+            assert!(list(&third_party, role, None).await.is_empty());
+        }
+    }
+
+    #[test]
+    fn external_refs_must_be_url_safe() {
+        for external_ref in ["A-42", "order_2026.03:7", &"x".repeat(EXTERNAL_REF_MAX_LEN)] {
+            assert!(validate_external_ref(external_ref).is_ok(), "{external_ref}");
+        }
+
+        let code = |external_ref: &str| validate_external_ref(external_ref).unwrap_err().code;
+        assert_eq!(code(""), "external_ref_length");
+        assert_eq!(code(&"x".repeat(EXTERNAL_REF_MAX_LEN + 1)), "external_ref_length");
+        for external_ref in ["order/42", "order 42", "order?42", "commande-é", "../admin"] {
+            assert_eq!(code(external_ref), "external_ref_characters", "{external_ref}");
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn external_refs_are_unique_per_issuer(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let config = test_support::config();
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 3).unwrap().and_hms_opt(10, 0, 0).unwrap());
+        let issuer = test_support::create_user(&pool, &clock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+        let other = test_support::create_user(&pool, &clock, "0x0000000000000000000000000000000000000001").await;
+        let with_ref = |on_chain_id: &str| InvoiceInput {
+            on_chain_id: on_chain_id.to_string(),
+            external_ref: Some("order-42".to_string()),
+            ..input()
+        };
+
+        let invoice = create(&pool, &clock, &config, issuer.id, &with_ref("1")).await.unwrap();
+        assert_eq!(invoice.external_ref.as_deref(), Some("order-42"));
+        match create(&pool, &clock, &config, issuer.id, &with_ref("2")).await {
+            Err(AppError::ConflictError(message)) => assert!(message.contains("order-42"), "{message}"),
+            other => panic!("expected a conflict, got {other:?}"),
+        }
+
+        // Another issuer may use the same reference
+        let others = create(&pool, &clock, &config, other.id, &with_ref("3")).await.unwrap();
+        let found = Invoice::find_by_external_ref(&pool, issuer.id, "order-42").await.unwrap().unwrap();
+        assert_eq!(found.id, invoice.id);
+        let found = Invoice::find_by_external_ref(&pool, other.id, "order-42").await.unwrap().unwrap();
+        assert_eq!(found.id, others.id);
+        assert!(Invoice::find_by_external_ref(&pool, issuer.id, "order-43").await.unwrap().is_none());
+
+        // Without a reference, any number of invoices
+        for on_chain_id in ["4", "5"] {
+            let without_ref = InvoiceInput { on_chain_id: on_chain_id.to_string(), ..input() };
+            create(&pool, &clock, &config, issuer.id, &without_ref).await.unwrap();
+        }
+    }
+
+    const RECIPIENT: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    /// A pending invoice to `RECIPIENT`, with an order id in its metadata
+    pub(crate) fn invoice(issuer: Uuid) -> Invoice {
+        let now = NaiveDate::from_ymd_opt(2026, 3, 3).unwrap().and_hms_opt(10, 0, 0).unwrap();
+        Invoice {
+            id: Uuid::new_v4(),
+            on_chain_id: "1".to_string(),
+            title: "Consulting".to_string(),
+            description: None,
+            amount: "1500".parse().unwrap(),
+            currency: "ETH".to_string(),
+            due_date: now + Duration::days(30),
+            created_at: now,
+            updated_at: now,
+            status: InvoiceStatus::Pending,
+            created_by: issuer,
+            sequence_number: 7,
+            display_number: "INV-2026-0007".to_string(),
+            recipient_address: Some(RECIPIENT.to_string()),
+            accepted_at: None,
+            token_address: None,
+            metadata: serde_json::json!({ "order_id": "A-42" }),
+            external_ref: Some("order-42".to_string()),
+        }
+    }
+
+    fn view_keys(invoice: Invoice, role: Option<InvoiceRole>) -> Vec<String> {
+        let serde_json::Value::Object(fields) = serde_json::to_value(invoice.into_view(role)).unwrap() else {
+            panic!("views serialize to objects");
+        };
+        fields.into_iter().map(|(key, _)| key).collect()
+    }
+
+    #[test]
+    fn role_of_tells_the_side_of_the_invoice() {
+        let issuer = Uuid::new_v4();
+        let invoice = invoice(issuer);
+
+        assert_eq!(invoice.role_of(issuer, "0x0000000000000000000000000000000000000001"), Some(InvoiceRole::Issuer));
+        // Issuer first when invoicing oneself
+        assert_eq!(invoice.role_of(issuer, RECIPIENT), Some(InvoiceRole::Issuer));
+        assert_eq!(invoice.role_of(Uuid::new_v4(), &RECIPIENT.to_uppercase().replace("0X", "0x")), Some(InvoiceRole::Recipient));
+        assert_eq!(invoice.role_of(Uuid::new_v4(), "0x0000000000000000000000000000000000000001"), None);
+        assert!(!invoice.is_party(Uuid::new_v4(), "0x0000000000000000000000000000000000000001"));
+    }
+
+    #[test]
+    fn views_omit_the_fields_of_other_roles() {
+        let issuer = Uuid::new_v4();
+        let payable = [
+            "id", "on_chain_id", "title", "description", "amount", "currency", "due_date",
+            "created_at", "updated_at", "status", "display_number", "accepted_at", "token_address",
+        ];
+        let party = ["created_by", "recipient_address"];
+        let issuer_only = ["sequence_number", "metadata", "external_ref"];
+
+        let anonymous = view_keys(invoice(issuer), None);
+        let recipient = view_keys(invoice(issuer), Some(InvoiceRole::Recipient));
+        let mut full = view_keys(invoice(issuer), Some(InvoiceRole::Issuer));
+
+        for key in payable {
+            assert!(anonymous.contains(&key.to_string()) && recipient.contains(&key.to_string()), "{key}");
+        }
+        for key in party {
+            assert!(!anonymous.contains(&key.to_string()), "{key}");
+            assert!(recipient.contains(&key.to_string()), "{key}");
+        }
+        for key in issuer_only {
+            assert!(!anonymous.contains(&key.to_string()) && !recipient.contains(&key.to_string()), "{key}");
+        }
+        assert_eq!(anonymous.len(), payable.len());
+        assert_eq!(recipient.len(), payable.len() + party.len());
+        assert_eq!(full.len(), payable.len() + party.len() + issuer_only.len());
+
+        // The issuer sees every field of the invoice
+        let serde_json::Value::Object(stored) = serde_json::to_value(invoice(issuer)).unwrap() else {
+            panic!("invoices serialize to objects");
+        };
+        let mut stored: Vec<String> = stored.into_iter().map(|(key, _)| key).collect();
+        stored.sort();
+        full.sort();
+        assert_eq!(full, stored);
+    }
+}
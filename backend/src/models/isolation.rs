@@ -0,0 +1,214 @@
+use futures::future::BoxFuture;
+use rand::Rng;
+use sqlx::{PgConnection, PgPool};
+use std::{sync::OnceLock, time::Duration};
+
+use crate::app_error::app_error::AppError;
+use crate::config::app_config::{IsolationLevel, TransactionIsolation};
+use crate::services::pool_monitor;
+
+/// SQLSTATE of a transaction aborted by `SERIALIZABLE` or `REPEATABLE READ`
+/// because a concurrent one changed what it read
+const SERIALIZATION_FAILURE: &str = "40001";
+
+/// Upper bound of the random pause before retrying, multiplied by the attempt
+const RETRY_JITTER_MS: u64 = 20;
+
+/// `database.isolation`, set once at startup
+static ISOLATION: OnceLock<TransactionIsolation> = OnceLock::new();
+
+pub fn set_transaction_isolation(config: TransactionIsolation) {
+    _ = ISOLATION.set(config);
+}
+
+/// Runs `body` in a transaction at the isolation level of
+/// `database.isolation`, for the read-then-write flows of the rate limiter
+/// and challenges
+///
+/// When a concurrent transaction makes this one fail to serialize, it is
+/// rolled back and `body` runs again from scratch, up to `max_retries`
+/// times; `body` must therefore only touch the database. Once retries are
+/// exhausted the request fails with 503 so the client retries later.
+pub async fn run_isolated<T, F>(pool: &PgPool, body: F) -> Result<T, AppError>
+where
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+{
+    run_with(pool, ISOLATION.get_or_init(TransactionIsolation::default), body).await
+}
+
+async fn run_with<T, F>(pool: &PgPool, config: &TransactionIsolation, mut body: F) -> Result<T, AppError>
+where
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match run_once(pool, config.level, &mut body).await {
+            Err(e) if is_serialization_failure(&e) => {
+                if attempt >= config.max_retries {
+                    eprintln!("Transaction still conflicting after {} retries: {}", attempt, e);
+                    return Err(AppError::OverloadedError(
+                        "Too many concurrent requests, please retry later".to_string(),
+                        pool_monitor::retry_after_secs(),
+                    ));
+                }
+                attempt += 1;
+                // Spread the retries of transactions that collided together
+                let pause = rand::rng().random_range(0..=RETRY_JITTER_MS * u64::from(attempt));
+                tokio::time::sleep(Duration::from_millis(pause)).await;
+            }
+            result => return result.map_err(AppError::from),
+        }
+    }
+}
+
+async fn run_once<T, F>(pool: &PgPool, level: IsolationLevel, body: &mut F) -> Result<T, sqlx::Error>
+where
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<T, sqlx::Error>>,
+{
+    let mut tx = pool.begin().await?;
+    if let Some(statement) = level.set_statement() {
+        sqlx::query(statement).execute(&mut *tx).await?;
+    }
+
+    let value = body(&mut tx).await?;
+    // Serialization failures of SERIALIZABLE may only show at commit
+    tx.commit().await?;
+
+    Ok(value)
+}
+
+fn is_serialization_failure(error: &sqlx::Error) -> bool {
+    error.as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == SERIALIZATION_FAILURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::http::StatusCode;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+    use tokio::sync::Barrier;
+
+    fn isolation(level: IsolationLevel, max_retries: u32) -> TransactionIsolation {
+        TransactionIsolation { level, max_retries }
+    }
+
+    /// Fails its first `failures` runs as if a concurrent transaction conflicted
+    async fn conflicting(pool: &PgPool, config: &TransactionIsolation, failures: u32) -> (Result<u32, AppError>, u32) {
+        let runs = AtomicU32::new(0);
+        let result = run_with(pool, config, |conn| {
+            let run = runs.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                if run < failures {
+                    sqlx::query("DO $$ BEGIN RAISE EXCEPTION 'conflict' USING ERRCODE = 'serialization_failure'; END $$")
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(run)
+            })
+        })
+        .await;
+        (result, runs.load(Ordering::SeqCst))
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn serialization_failures_are_retried(pool: PgPool) {
+        let (result, runs) = conflicting(&pool, &isolation(IsolationLevel::Serializable, 3), 3).await;
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(runs, 4);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn exhausted_retries_are_a_503(pool: PgPool) {
+        let (result, runs) = conflicting(&pool, &isolation(IsolationLevel::Serializable, 2), u32::MAX).await;
+        let error = result.unwrap_err();
+        assert!(matches!(error, AppError::OverloadedError(_, _)), "{error:?}");
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(runs, 3);
+
+        let (result, runs) = conflicting(&pool, &isolation(IsolationLevel::Serializable, 0), 1).await;
+        assert!(result.is_err());
+        assert_eq!(runs, 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn other_errors_are_not_retried(pool: PgPool) {
+        let runs = AtomicU32::new(0);
+        let result: Result<(), AppError> = run_with(&pool, &isolation(IsolationLevel::Serializable, 3), |conn| {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                sqlx::query("SELECT 1 / 0").execute(conn).await?;
+                Ok(())
+            })
+        })
+        .await;
+        assert!(matches!(result, Err(AppError::DatabaseError(_))));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn transactions_run_at_the_configured_level(pool: PgPool) {
+        for (level, expected) in [
+            (IsolationLevel::ReadCommitted, "read committed"),
+            (IsolationLevel::RepeatableRead, "repeatable read"),
+            (IsolationLevel::Serializable, "serializable"),
+        ] {
+            let shown: String = run_with(&pool, &isolation(level, 0), |conn| {
+                Box::pin(async move { sqlx::query_scalar("SHOW transaction_isolation").fetch_one(conn).await })
+            })
+            .await
+            .unwrap();
+            assert_eq!(shown, expected);
+        }
+    }
+
+    /// Two requests each claim the single slot if it is free, both reading
+    /// before either writes: the write skew `SERIALIZABLE` prevents
+    async fn claim_slot_concurrently(pool: &PgPool, level: IsolationLevel) -> i64 {
+        sqlx::raw_sql("DROP TABLE IF EXISTS slots; CREATE TABLE slots (claimed_by INT NOT NULL)")
+            .execute(pool)
+            .await
+            .unwrap();
+
+        let barrier = Arc::new(Barrier::new(2));
+        let claims = (0..2).map(|claimant| {
+            let (pool, barrier) = (pool.clone(), barrier.clone());
+            tokio::spawn(async move {
+                let first_run = Arc::new(AtomicU32::new(0));
+                run_with(&pool, &isolation(level, 3), |conn| {
+                    let (barrier, first_run) = (barrier.clone(), first_run.clone());
+                    Box::pin(async move {
+                        let taken: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM slots").fetch_one(&mut *conn).await?;
+                        // Only the first runs meet, retries go alone
+                        if first_run.fetch_add(1, Ordering::SeqCst) == 0 {
+                            barrier.wait().await;
+                        }
+                        if taken == 0 {
+                            sqlx::query("INSERT INTO slots (claimed_by) VALUES ($1)")
+                                .bind(claimant)
+                                .execute(&mut *conn)
+                                .await?;
+                        }
+                        Ok(())
+                    })
+                })
+                .await
+            })
+        });
+        for claim in claims.collect::<Vec<_>>() {
+            claim.await.unwrap().unwrap();
+        }
+
+        sqlx::query_scalar("SELECT COUNT(*) FROM slots").fetch_one(pool).await.unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn serializable_retries_prevent_write_skew(pool: PgPool) {
+        assert_eq!(claim_slot_concurrently(&pool, IsolationLevel::ReadCommitted).await, 2);
+        assert_eq!(claim_slot_concurrently(&pool, IsolationLevel::Serializable).await, 1);
+    }
+}
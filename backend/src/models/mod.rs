@@ -1,4 +1,16 @@
+pub mod api_keys;
+pub mod feature_flags;
 pub mod invoices;
+pub mod invoice_shares;
+pub mod isolation;
+pub mod outbox;
 pub mod users;
+pub mod wallet_diagnostics;
+pub mod event_erasures;
 pub mod security_events;
-pub mod auth_challenges;
\ No newline at end of file
+pub mod auth_challenges;
+pub mod challenge_store;
+pub mod sessions;
+pub mod session_keys;
+pub mod token_blacklist;
+pub mod rate_limits;
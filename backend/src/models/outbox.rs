@@ -0,0 +1,246 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::{query, query_as, FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::app_error::app_error::AppError;
+
+/// A state change waiting to be delivered to integrators
+///
+/// Rows are written in the same transaction as the change they describe, so
+/// a committed change always has its message and a rolled back one never
+/// does. The relay in `services::outbox` delivers them at least once.
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub aggregate_type: String,
+    pub aggregate_id: Uuid,
+    pub event_type: String,
+    pub payload: JsonValue,
+    pub created_at: NaiveDateTime,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub sent_at: Option<NaiveDateTime>,
+    pub dead_at: Option<NaiveDateTime>,
+}
+
+/// A webhook call made for an outbox message
+#[derive(Debug, FromRow, Serialize)]
+pub struct OutboxDeliveryAttempt {
+    pub id: i64,
+    pub message_id: i64,
+    pub webhook_url: String,
+    pub attempted_at: NaiveDateTime,
+    /// `None` when the webhook accepted the message
+    pub error: Option<String>,
+    /// Admin who replayed the message, `None` for relay deliveries
+    pub replayed_by: Option<Uuid>,
+}
+
+impl OutboxMessage {
+    pub async fn get(pool: &PgPool, id: i64) -> Result<Option<OutboxMessage>, AppError> {
+        let message = query_as!(
+            OutboxMessage,
+            r#"
+            SELECT id, aggregate_type, aggregate_id, event_type, payload as "payload: JsonValue",
+                   created_at, attempts, next_attempt_at, last_error, sent_at, dead_at
+            FROM outbox_messages
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(message)
+    }
+
+    /// Queues a message inside the caller's transaction
+    pub async fn enqueue(
+        conn: &mut PgConnection,
+        now: NaiveDateTime,
+        aggregate_type: &str,
+        aggregate_id: Uuid,
+        event_type: &str,
+        payload: JsonValue,
+    ) -> Result<(), AppError> {
+        query!(
+            r#"
+            INSERT INTO outbox_messages (
+                aggregate_type, aggregate_id, event_type, payload, created_at, next_attempt_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $5)
+            "#,
+            aggregate_type,
+            aggregate_id,
+            event_type,
+            payload,
+            now
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the messages ready for delivery, at most one per aggregate
+    ///
+    /// Only the oldest pending message of an aggregate is eligible, so an
+    /// aggregate's messages are delivered in order and a failing one holds
+    /// back the ones after it until it is sent or dead-lettered.
+    pub async fn fetch_due(
+        pool: &PgPool,
+        now: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<OutboxMessage>, AppError> {
+        let messages = query_as!(
+            OutboxMessage,
+            r#"
+            SELECT id as "id!", aggregate_type as "aggregate_type!", aggregate_id as "aggregate_id!",
+                   event_type as "event_type!", payload as "payload!: JsonValue",
+                   created_at as "created_at!", attempts as "attempts!",
+                   next_attempt_at as "next_attempt_at!", last_error, sent_at, dead_at
+            FROM (
+                SELECT DISTINCT ON (aggregate_type, aggregate_id) *
+                FROM outbox_messages
+                WHERE sent_at IS NULL AND dead_at IS NULL
+                ORDER BY aggregate_type, aggregate_id, id
+            ) AS heads
+            WHERE next_attempt_at <= $1
+            ORDER BY id
+            LIMIT $2
+            "#,
+            now,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    pub async fn mark_sent(pool: &PgPool, id: i64, now: NaiveDateTime) -> Result<(), AppError> {
+        query!(
+            r#"
+            UPDATE outbox_messages
+            SET sent_at = $2, attempts = attempts + 1, last_error = NULL
+            WHERE id = $1
+            "#,
+            id,
+            now
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt and schedules the next one
+    pub async fn mark_failed(
+        pool: &PgPool,
+        id: i64,
+        error: &str,
+        next_attempt_at: NaiveDateTime,
+    ) -> Result<(), AppError> {
+        query!(
+            r#"
+            UPDATE outbox_messages
+            SET attempts = attempts + 1, last_error = $2, next_attempt_at = $3
+            WHERE id = $1
+            "#,
+            id,
+            error,
+            next_attempt_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves a message to the dead letters, where it is kept for inspection
+    pub async fn mark_dead(
+        pool: &PgPool,
+        id: i64,
+        error: &str,
+        now: NaiveDateTime,
+    ) -> Result<(), AppError> {
+        query!(
+            r#"
+            UPDATE outbox_messages
+            SET attempts = attempts + 1, last_error = $2, dead_at = $3
+            WHERE id = $1
+            "#,
+            id,
+            error,
+            now
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records the outcome of a replayed delivery
+    ///
+    /// A message the webhook accepted counts as sent, so the relay does not
+    /// deliver it again; a dead-lettered one keeps its `dead_at` as history.
+    /// A failed replay leaves the message's schedule untouched.
+    pub async fn record_replay(
+        pool: &PgPool,
+        id: i64,
+        error: Option<&str>,
+        now: NaiveDateTime,
+    ) -> Result<OutboxMessage, AppError> {
+        let message = query_as!(
+            OutboxMessage,
+            r#"
+            UPDATE outbox_messages
+            SET attempts = attempts + 1,
+                last_error = $2,
+                sent_at = CASE WHEN $2::TEXT IS NULL THEN COALESCE(sent_at, $3) ELSE sent_at END
+            WHERE id = $1
+            RETURNING id, aggregate_type, aggregate_id, event_type, payload as "payload: JsonValue",
+                      created_at, attempts, next_attempt_at, last_error, sent_at, dead_at
+            "#,
+            id,
+            error,
+            now
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(message)
+    }
+}
+
+impl OutboxDeliveryAttempt {
+    pub async fn record(
+        pool: &PgPool,
+        message_id: i64,
+        webhook_url: &str,
+        now: NaiveDateTime,
+        error: Option<&str>,
+        replayed_by: Option<Uuid>,
+    ) -> Result<OutboxDeliveryAttempt, AppError> {
+        let attempt = query_as!(
+            OutboxDeliveryAttempt,
+            r#"
+            INSERT INTO outbox_delivery_attempts (message_id, webhook_url, attempted_at, error, replayed_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, message_id, webhook_url, attempted_at, error, replayed_by
+            "#,
+            message_id,
+            webhook_url,
+            now,
+            error,
+            replayed_by
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(attempt)
+    }
+}
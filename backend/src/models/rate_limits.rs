@@ -0,0 +1,452 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, query_scalar, FromRow, PgConnection, PgPool};
+
+use crate::app_error::app_error::AppError;
+use crate::config::app_config::{RateLimitOffenders, RateLimitRule};
+use crate::models::isolation::run_isolated;
+use crate::utils::clock::Clock;
+
+/// Attempts made by an identifier (address, IP, ...) for an action in the current window
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub identifier: String,
+    pub action: String,
+    pub attempt_count: i32,
+    pub window_start: NaiveDateTime,
+    pub last_attempt: NaiveDateTime,
+}
+
+/// An identifier ranked by its recent rate-limit violations
+#[derive(Debug, FromRow, Serialize)]
+pub struct RateLimitOffender {
+    pub identifier: String,
+    /// Windows in which a limit was exceeded
+    pub violations: i64,
+    /// Attempts made in those windows, rejected ones included
+    pub attempts: i64,
+    pub actions: Vec<String>,
+    pub last_violation_at: NaiveDateTime,
+    /// Set while the identifier is denied, see `rate_limits.offenders`
+    pub blocked_until: Option<NaiveDateTime>,
+    pub blocked: bool,
+}
+
+/// Counts an attempt and rejects it once the rule's limit is exceeded
+///
+/// Uses a fixed window: the counter restarts with the first attempt made
+/// after `window_secs` have elapsed since the window started. The attempt is
+/// counted in a single upsert so concurrent requests cannot slip through.
+///
+/// Identifiers denied by `offenders` are rejected without counting. Every
+/// window in which the limit is exceeded is recorded as a violation, and
+/// with `offenders.auto_block` enough of them deny the identifier. These
+/// steps run in one transaction at the level of `database.isolation`.
+pub async fn check_rate_limit(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    identifier: &str,
+    action: &str,
+    rule: &RateLimitRule,
+    offenders: &RateLimitOffenders,
+) -> Result<(), AppError> {
+    let now = clock.now();
+
+    let attempt = run_isolated(pool, |conn| {
+        let (identifier, action) = (identifier.to_string(), action.to_string());
+        let (rule, offenders) = (rule.clone(), offenders.clone());
+        Box::pin(async move { count_attempt(conn, now, &identifier, &action, &rule, &offenders).await })
+    })
+    .await?;
+
+    match attempt {
+        Attempt::Allowed => Ok(()),
+        Attempt::Blocked(Some(blocked_until)) => Err(AppError::RateLimitError(
+            "Too many rate limit violations, please retry later".to_string(),
+            (blocked_until - now).num_seconds().max(1) as u64,
+        )),
+        Attempt::Blocked(None) => Err(AppError::ForbiddenError(
+            "Blocked after repeated rate limit violations".to_string()
+        )),
+        Attempt::Limited { limit, block } => {
+            if let Some((violations, blocked_until)) = block {
+                eprintln!(
+                    "Blocked {} after {} rate limit violations, until {}",
+                    limit.identifier,
+                    violations,
+                    blocked_until.map_or("cleared".to_string(), |until| until.to_string())
+                );
+            }
+
+            let window_ends = limit.window_start + chrono::Duration::seconds(rule.window_secs as i64);
+            let retry_after = (window_ends - now).num_seconds().max(1) as u64;
+            Err(AppError::RateLimitError(
+                "Too many requests, please retry later".to_string(),
+                retry_after,
+            ))
+        }
+    }
+}
+
+/// What `count_attempt` decided
+enum Attempt {
+    Allowed,
+    /// Denied by `rate_limits.offenders` until the given time, or for good
+    Blocked(Option<NaiveDateTime>),
+    /// Over the limit, with the violation count and expiry of the block
+    /// this attempt triggered, if any
+    Limited {
+        limit: RateLimit,
+        block: Option<(i64, Option<NaiveDateTime>)>,
+    },
+}
+
+async fn count_attempt(
+    conn: &mut PgConnection,
+    now: NaiveDateTime,
+    identifier: &str,
+    action: &str,
+    rule: &RateLimitRule,
+    offenders: &RateLimitOffenders,
+) -> Result<Attempt, sqlx::Error> {
+    if let Some(blocked_until) = find_block(conn, now, identifier).await? {
+        return Ok(Attempt::Blocked(blocked_until));
+    }
+
+    let window_started_after = now - chrono::Duration::seconds(rule.window_secs as i64);
+
+    let limit = query_as!(
+        RateLimit,
+        r#"
+        INSERT INTO rate_limits (identifier, action, attempt_count, window_start, last_attempt)
+        VALUES ($1, $2, 1, $3, $3)
+        ON CONFLICT (identifier, action) DO UPDATE SET
+            attempt_count = CASE
+                WHEN rate_limits.window_start <= $4 THEN 1
+                ELSE rate_limits.attempt_count + 1
+            END,
+            window_start = CASE
+                WHEN rate_limits.window_start <= $4 THEN $3
+                ELSE rate_limits.window_start
+            END,
+            last_attempt = $3
+        RETURNING identifier, action, attempt_count, window_start, last_attempt
+        "#,
+        identifier,
+        action,
+        now,
+        window_started_after
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if limit.attempt_count as u32 > rule.max_attempts {
+        let block = record_violation(conn, now, &limit, rule, offenders).await?;
+        return Ok(Attempt::Limited { limit, block });
+    }
+
+    Ok(Attempt::Allowed)
+}
+
+/// Expiry of the block denying an identifier, `Some(None)` when permanent
+async fn find_block(
+    conn: &mut PgConnection,
+    now: NaiveDateTime,
+    identifier: &str,
+) -> Result<Option<Option<NaiveDateTime>>, sqlx::Error> {
+    let block = query!(
+        r#"
+        SELECT blocked_until
+        FROM rate_limit_blocks
+        WHERE identifier = $1
+          AND (blocked_until IS NULL OR blocked_until > $2)
+        "#,
+        identifier,
+        now
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(block.map(|block| block.blocked_until))
+}
+
+/// Records the window of `limit` as a violation, blocking the identifier
+/// once it has too many of them
+///
+/// Rejected attempts within a window update the same violation, so a flood
+/// adds one row per window, not one per request. Returns the violations
+/// counted and the block's expiry when this one blocked the identifier.
+async fn record_violation(
+    conn: &mut PgConnection,
+    now: NaiveDateTime,
+    limit: &RateLimit,
+    rule: &RateLimitRule,
+    offenders: &RateLimitOffenders,
+) -> Result<Option<(i64, Option<NaiveDateTime>)>, sqlx::Error> {
+    let first_of_window = query_scalar!(
+        r#"
+        INSERT INTO rate_limit_violations (
+            identifier, action, window_start, attempt_count, max_attempts,
+            first_violation_at, last_violation_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $6)
+        ON CONFLICT (identifier, action, window_start) DO UPDATE SET
+            attempt_count = EXCLUDED.attempt_count,
+            last_violation_at = EXCLUDED.last_violation_at
+        RETURNING (xmax = 0) as "inserted!"
+        "#,
+        limit.identifier,
+        limit.action,
+        limit.window_start,
+        limit.attempt_count,
+        rule.max_attempts as i32,
+        now
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if !first_of_window || !offenders.auto_block {
+        return Ok(None);
+    }
+
+    let since = now - chrono::Duration::seconds(offenders.window_secs as i64);
+    let violations = query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM rate_limit_violations
+        WHERE identifier = $1
+          AND first_violation_at >= $2
+        "#,
+        limit.identifier,
+        since
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if violations < offenders.block_after_violations {
+        return Ok(None);
+    }
+
+    let blocked_until = (offenders.block_duration_secs > 0)
+        .then(|| now + chrono::Duration::seconds(offenders.block_duration_secs as i64));
+    query!(
+        r#"
+        INSERT INTO rate_limit_blocks (identifier, violations, blocked_at, blocked_until)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (identifier) DO UPDATE SET
+            violations = EXCLUDED.violations,
+            blocked_at = EXCLUDED.blocked_at,
+            blocked_until = EXCLUDED.blocked_until
+        "#,
+        limit.identifier,
+        violations,
+        now,
+        blocked_until
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(Some((violations, blocked_until)))
+}
+
+/// Identifiers with the most violations since `since`, most first
+pub async fn top_offenders(
+    pool: &PgPool,
+    now: NaiveDateTime,
+    since: NaiveDateTime,
+    limit: i64,
+) -> Result<Vec<RateLimitOffender>, AppError> {
+    let offenders = query_as!(
+        RateLimitOffender,
+        r#"
+        SELECT
+            v.identifier as "identifier!",
+            COUNT(*) as "violations!",
+            SUM(v.attempt_count)::BIGINT as "attempts!",
+            ARRAY_AGG(DISTINCT v.action) as "actions!",
+            MAX(v.last_violation_at) as "last_violation_at!",
+            b.blocked_until,
+            (b.identifier IS NOT NULL AND (b.blocked_until IS NULL OR b.blocked_until > $2)) as "blocked!"
+        FROM rate_limit_violations v
+        LEFT JOIN rate_limit_blocks b ON b.identifier = v.identifier
+        WHERE v.last_violation_at >= $1
+        GROUP BY v.identifier, b.identifier, b.blocked_until
+        ORDER BY COUNT(*) DESC, MAX(v.last_violation_at) DESC
+        LIMIT $3
+        "#,
+        since,
+        now,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(offenders)
+}
+
+/// Lists the rate-limit entries of an identifier, across all actions
+pub async fn list_rate_limits(
+    pool: &PgPool,
+    identifier: &str,
+) -> Result<Vec<RateLimit>, AppError> {
+    let limits = query_as!(
+        RateLimit,
+        r#"
+        SELECT identifier, action, attempt_count, window_start, last_attempt
+        FROM rate_limits
+        WHERE identifier = $1
+        ORDER BY action
+        "#,
+        identifier
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(limits)
+}
+
+/// Clears every rate-limit entry of an identifier and lifts its block,
+/// returning the cleared actions and whether it was blocked
+///
+/// Past violations are kept, so the identifier stays visible among offenders.
+pub async fn clear_rate_limits(
+    pool: &PgPool,
+    identifier: &str,
+) -> Result<(Vec<String>, bool), AppError> {
+    let mut tx = pool.begin().await?;
+
+    let cleared = query!(
+        r#"
+        DELETE FROM rate_limits
+        WHERE identifier = $1
+        RETURNING action
+        "#,
+        identifier
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let unblocked = query!(
+        r#"
+        DELETE FROM rate_limit_blocks
+        WHERE identifier = $1
+        "#,
+        identifier
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected() > 0;
+
+    tx.commit().await?;
+
+    Ok((cleared.into_iter().map(|row| row.action).collect(), unblocked))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support, utils::clock::MockClock};
+    use chrono::{Duration, NaiveDate};
+
+    const IP: &str = "203.0.113.7";
+
+    fn clock() -> MockClock {
+        MockClock::new(NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap())
+    }
+
+    fn rule() -> RateLimitRule {
+        RateLimitRule { max_attempts: 2, window_secs: 60 }
+    }
+
+    fn offenders(auto_block: bool, block_duration_secs: u64) -> RateLimitOffenders {
+        RateLimitOffenders { auto_block, block_after_violations: 2, window_secs: 3600, block_duration_secs }
+    }
+
+    async fn attempt(pool: &PgPool, clock: &MockClock, action: &str, offenders: &RateLimitOffenders) -> Result<(), AppError> {
+        check_rate_limit(pool, clock, IP, action, &rule(), offenders).await
+    }
+
+    /// Exceeds the limit of a fresh window, leaving it over the limit
+    async fn violate(pool: &PgPool, clock: &MockClock, offenders: &RateLimitOffenders) -> Result<(), AppError> {
+        clock.advance(Duration::seconds(61));
+        attempt(pool, clock, "challenge", offenders).await?;
+        attempt(pool, clock, "challenge", offenders).await?;
+        attempt(pool, clock, "challenge", offenders).await
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn violations_are_recorded_once_per_window(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = clock();
+        let offenders = offenders(false, 0);
+
+        attempt(&pool, &clock, "challenge", &offenders).await.unwrap();
+        clock.advance(Duration::seconds(20));
+        attempt(&pool, &clock, "challenge", &offenders).await.unwrap();
+        for _ in 0..3 {
+            match attempt(&pool, &clock, "challenge", &offenders).await {
+                Err(AppError::RateLimitError(_, retry_after)) => assert_eq!(retry_after, 40),
+                other => panic!("expected 429, got {other:?}"),
+            }
+        }
+
+        let offender = &top_offenders(&pool, clock.now(), clock.now() - Duration::hours(1), 10).await.unwrap()[0];
+        assert_eq!(offender.identifier, IP);
+        assert_eq!(offender.violations, 1);
+        assert_eq!(offender.attempts, 5);
+        assert_eq!(offender.actions, ["challenge"]);
+        assert!(!offender.blocked);
+
+        // A new window starts over, without auto_block nothing is denied
+        violate(&pool, &clock, &offenders).await.unwrap_err();
+        violate(&pool, &clock, &offenders).await.unwrap_err();
+        clock.advance(Duration::seconds(61));
+        attempt(&pool, &clock, "challenge", &offenders).await.unwrap();
+        let offender = &top_offenders(&pool, clock.now(), clock.now() - Duration::hours(1), 10).await.unwrap()[0];
+        assert_eq!(offender.violations, 3);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn repeat_offenders_are_blocked_from_every_action(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = clock();
+        let offenders = offenders(true, 600);
+
+        violate(&pool, &clock, &offenders).await.unwrap_err();
+        clock.advance(Duration::seconds(61));
+        attempt(&pool, &clock, "login", &offenders).await.unwrap();
+
+        // The second violation blocks, other actions included
+        violate(&pool, &clock, &offenders).await.unwrap_err();
+        clock.advance(Duration::seconds(100));
+        match attempt(&pool, &clock, "login", &offenders).await {
+            Err(AppError::RateLimitError(message, retry_after)) => {
+                assert!(message.contains("violations"), "{message}");
+                assert_eq!(retry_after, 500);
+            }
+            other => panic!("expected a block, got {other:?}"),
+        }
+        assert!(top_offenders(&pool, clock.now(), clock.now() - Duration::hours(1), 10).await.unwrap()[0].blocked);
+
+        // Lifted when an admin clears the identifier
+        let (cleared, unblocked) = clear_rate_limits(&pool, IP).await.unwrap();
+        assert!(unblocked);
+        assert_eq!(cleared, ["challenge", "login"]);
+        attempt(&pool, &clock, "login", &offenders).await.unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn blocks_without_a_duration_are_permanent(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = clock();
+        let offenders = offenders(true, 0);
+
+        violate(&pool, &clock, &offenders).await.unwrap_err();
+        violate(&pool, &clock, &offenders).await.unwrap_err();
+        clock.advance(Duration::days(30));
+        assert!(matches!(
+            attempt(&pool, &clock, "challenge", &offenders).await,
+            Err(AppError::ForbiddenError(_))
+        ));
+    }
+}
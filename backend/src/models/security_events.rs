@@ -1,13 +1,17 @@
 use uuid::Uuid;
-use chrono::{NaiveDateTime, Utc};
+use chrono::NaiveDateTime;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use sqlx::{query, types::{ipnetwork::IpNetwork, JsonValue}, FromRow, PgPool, Type};
-use std::collections::HashMap;
+use sqlx::{query, query_scalar, types::{ipnetwork::IpNetwork, JsonValue}, FromRow, PgPool, Type};
+use std::{collections::HashMap, sync::OnceLock};
 
 use crate::app_error::app_error::AppError;
+use crate::config::app_config::EventMetadataLimits;
+use crate::utils::clock::Clock;
+use crate::utils::metadata::cap_event_metadata;
 type PgInet = IpNetwork;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Type)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Type)]
 #[sqlx(type_name = "event_type", rename_all = "lowercase")]
 pub enum EventType {
     Login,
@@ -15,7 +19,129 @@ pub enum EventType {
     WalletConnected,
     WalletDisconnected,
     AccountLocked,
-    AccountUnlocked
+    AccountUnlocked,
+    MultisigApproval,
+    InvoiceAccepted,
+    SessionsRevoked,
+    InvoiceStatusChanged,
+    RateLimitCleared,
+    PaymentReplayed,
+    VerificationChanged,
+    InvoiceQuotaExceeded,
+    ApiKeysRotated,
+    SharedInvoiceViewed,
+    SessionKeyAuthorized,
+    SessionKeyRevoked,
+    ExpiredChallengeSigned,
+    SessionEvicted,
+    AnomalyDetected,
+    WebhookReplayed
+}
+
+/// Event types `record_event` writes, set once at startup; unset records all
+static RECORDED_EVENT_TYPES: OnceLock<Vec<EventType>> = OnceLock::new();
+
+/// Caps `record_event` applies to metadata, set once at startup; unset stores it as is
+static METADATA_LIMITS: OnceLock<EventMetadataLimits> = OnceLock::new();
+
+/// Length of the `user_agent` column, longer user agents are cut to fit
+const USER_AGENT_MAX_CHARS: usize = 255;
+
+pub fn set_event_metadata_limits(limits: EventMetadataLimits) {
+    let _ = METADATA_LIMITS.set(limits);
+}
+
+/// Restricts recording to the `enabled` event types, `None` records every type
+///
+/// Mandatory types are recorded whether listed or not.
+pub fn set_recorded_event_types(enabled: Option<Vec<EventType>>) {
+    if let Some(enabled) = enabled {
+        let _ = RECORDED_EVENT_TYPES.set(enabled);
+    }
+}
+
+impl EventType {
+    /// Every variant, checked against the database enum at startup
+    pub const ALL: [EventType; 22] = [
+        EventType::Login,
+        EventType::FailedLogin,
+        EventType::WalletConnected,
+        EventType::WalletDisconnected,
+        EventType::AccountLocked,
+        EventType::AccountUnlocked,
+        EventType::MultisigApproval,
+        EventType::InvoiceAccepted,
+        EventType::SessionsRevoked,
+        EventType::InvoiceStatusChanged,
+        EventType::RateLimitCleared,
+        EventType::PaymentReplayed,
+        EventType::VerificationChanged,
+        EventType::InvoiceQuotaExceeded,
+        EventType::ApiKeysRotated,
+        EventType::SharedInvoiceViewed,
+        EventType::SessionKeyAuthorized,
+        EventType::SessionKeyRevoked,
+        EventType::ExpiredChallengeSigned,
+        EventType::SessionEvicted,
+        EventType::AnomalyDetected,
+        EventType::WebhookReplayed,
+    ];
+
+    /// Label of the variant in the `event_type` database enum, following
+    /// the `rename_all = "lowercase"` mapping
+    pub fn db_label(&self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+
+    /// Security-critical types that are recorded even when not enabled
+    pub fn is_mandatory(&self) -> bool {
+        matches!(self, EventType::FailedLogin | EventType::AccountLocked | EventType::AnomalyDetected)
+    }
+
+    fn is_recorded(&self) -> bool {
+        self.is_mandatory()
+            || RECORDED_EVENT_TYPES.get().is_none_or(|enabled| enabled.contains(self))
+    }
+}
+
+/// Compares the `event_type` database enum with `EventType` before serving
+///
+/// Labels missing from the database mean the schema is behind the code:
+/// recording those events would fail, so startup is refused with the list.
+/// Labels only the database knows are tolerated with a warning, since rows
+/// carrying them merely fail to decode.
+pub async fn check_event_type_labels(pool: &PgPool) -> Result<(), AppError> {
+    let db_labels = query_scalar!(
+        r#"SELECT unnest(enum_range(NULL::event_type))::text AS "label!""#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let code_labels: Vec<String> = EventType::ALL.iter().map(EventType::db_label).collect();
+
+    let unknown: Vec<&str> = db_labels.iter()
+        .filter(|label| !code_labels.contains(label))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        eprintln!(
+            "WARNING: event_type labels unknown to this build: {}; events of these types cannot be read",
+            unknown.join(", ")
+        );
+    }
+
+    let missing: Vec<&str> = code_labels.iter()
+        .filter(|label| !db_labels.contains(label))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(AppError::ConfigError(format!(
+            "Database enum event_type is missing labels: {}. Add them with ALTER TYPE event_type ADD VALUE before starting",
+            missing.join(", ")
+        )));
+    }
+
+    Ok(())
 }
 
 #[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
@@ -30,15 +156,22 @@ pub struct SecurityEvent {
     pub metadata: JsonValue,
 }
 
+/// Appends an event to the audit log, a no-op for disabled event types
 pub async fn record_event(
     pool: &PgPool,
+    clock: &dyn Clock,
     event_type: EventType,
     user_id: Uuid,
     client_ip: IpNetwork,
     user_agent: &str,
     metadata: JsonValue,
 ) -> Result<(), AppError> {
-    let now = Utc::now().naive_utc();
+    if !event_type.is_recorded() {
+        return Ok(());
+    }
+
+    let now = clock.now();
+    let user_agent: String = user_agent.chars().take(USER_AGENT_MAX_CHARS).collect();
     let metadata = if metadata.is_null() {
         serde_json::json!({
             "ip": client_ip.to_string(),
@@ -47,6 +180,10 @@ pub async fn record_event(
     } else {
         metadata
     };
+    let metadata = match METADATA_LIMITS.get() {
+        Some(limits) => cap_event_metadata(metadata, limits),
+        None => metadata,
+    };
 
     let _query = sqlx::query!(
 
@@ -129,15 +266,203 @@ pub async fn get_all_events(
     Ok(events)
 }
 
+/// Streams every event in chronological order, for exports too large to buffer
+pub fn stream_all_events(
+    pool: &PgPool,
+) -> BoxStream<'_, Result<SecurityEvent, AppError>> {
+    sqlx::query_as!(
+        SecurityEvent,
+        r#"
+        SELECT
+            id,
+            user_id,
+            event_type as "event_type!: EventType",
+            timestamp,
+            client_ip as "client_ip?: PgInet",
+            user_agent,
+            metadata as "metadata: JsonValue"
+        FROM security_events
+        ORDER BY timestamp, id
+        "#,
+    )
+    .fetch(pool)
+    .map_err(AppError::from)
+    .boxed()
+}
+
+/// Position in the event log, ordered by `(timestamp, id)` from newest to oldest
+///
+/// Encoded as an opaque hex string so clients pass it back unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventCursor {
+    pub timestamp: NaiveDateTime,
+    pub id: Uuid,
+}
+
+impl EventCursor {
+    pub fn encode(&self) -> String {
+        hex::encode(format!("{}:{}", self.timestamp.and_utc().timestamp_micros(), self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::ValidationError("Invalid cursor".to_string());
+
+        let raw = hex::decode(cursor).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let (micros, id) = raw.split_once(':').ok_or_else(invalid)?;
+
+        let timestamp = micros.parse::<i64>().ok()
+            .and_then(chrono::DateTime::from_timestamp_micros)
+            .ok_or_else(invalid)?
+            .naive_utc();
+        let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+
+        Ok(EventCursor { timestamp, id })
+    }
+
+    fn of(event: &SecurityEvent) -> Self {
+        EventCursor { timestamp: event.timestamp, id: event.id }
+    }
+}
+
+/// One page of events, newest first
+#[derive(Debug, Serialize)]
+pub struct EventPage {
+    pub events: Vec<SecurityEvent>,
+    pub next_cursor: Option<String>,
+    pub has_more: bool,
+}
+
+impl EventPage {
+    /// Builds a page from up to `limit + 1` rows, the extra row only signalling more
+    fn from_rows(mut events: Vec<SecurityEvent>, limit: i64) -> Self {
+        let has_more = events.len() as i64 > limit;
+        events.truncate(limit as usize);
+        let next_cursor = events.last()
+            .filter(|_| has_more)
+            .map(|event| EventCursor::of(event).encode());
+
+        EventPage { events, next_cursor, has_more }
+    }
+}
+
+/// Lists events older than the cursor, or the newest ones without a cursor
+///
+/// Seeks on the `(timestamp, id)` index, so deep pages cost the same as the first.
+pub async fn list_events_after(
+    pool: &PgPool,
+    cursor: Option<&EventCursor>,
+    limit: i64,
+) -> Result<EventPage, AppError> {
+    let events = match cursor {
+        Some(cursor) => sqlx::query_as!(
+            SecurityEvent,
+            r#"
+            SELECT
+                id,
+                user_id,
+                event_type as "event_type!: EventType",
+                timestamp,
+                client_ip as "client_ip?: PgInet",
+                user_agent,
+                metadata as "metadata: JsonValue"
+            FROM security_events
+            WHERE (timestamp, id) < ($1, $2)
+            ORDER BY timestamp DESC, id DESC
+            LIMIT $3
+            "#,
+            cursor.timestamp,
+            cursor.id,
+            limit + 1
+        )
+        .fetch_all(pool)
+        .await?,
+        None => sqlx::query_as!(
+            SecurityEvent,
+            r#"
+            SELECT
+                id,
+                user_id,
+                event_type as "event_type!: EventType",
+                timestamp,
+                client_ip as "client_ip?: PgInet",
+                user_agent,
+                metadata as "metadata: JsonValue"
+            FROM security_events
+            ORDER BY timestamp DESC, id DESC
+            LIMIT $1
+            "#,
+            limit + 1
+        )
+        .fetch_all(pool)
+        .await?,
+    };
+
+    Ok(EventPage::from_rows(events, limit))
+}
+
+/// Lists events by offset, newest first; fine for small logs, slow for deep pages
+pub async fn list_events_at_offset(
+    pool: &PgPool,
+    offset: i64,
+    limit: i64,
+) -> Result<EventPage, AppError> {
+    let events = sqlx::query_as!(
+        SecurityEvent,
+        r#"
+        SELECT
+            id,
+            user_id,
+            event_type as "event_type!: EventType",
+            timestamp,
+            client_ip as "client_ip?: PgInet",
+            user_agent,
+            metadata as "metadata: JsonValue"
+        FROM security_events
+        ORDER BY timestamp DESC, id DESC
+        OFFSET $1
+        LIMIT $2
+        "#,
+        offset,
+        limit + 1
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(EventPage::from_rows(events, limit))
+}
+
+/// Reason codes stored with blacklisted tokens, see `revocation_message`
+pub const REVOKED_BY_LOGOUT: &str = "logout";
+pub const REVOKED_FOR_SECURITY: &str = "security";
+pub const REVOKED_BY_ROTATION: &str = "rotation";
+pub const REVOKED_BY_SESSION_LIMIT: &str = "session_limit";
+
+/// What a client is told when presenting a token blacklisted for `reason`
+///
+/// Reasons other than the codes above may hold internal details and are
+/// not shown.
+pub fn revocation_message(reason: &str) -> String {
+    let detail = match reason {
+        REVOKED_BY_LOGOUT => "you signed out of this session",
+        REVOKED_FOR_SECURITY => "session was revoked for security",
+        REVOKED_BY_ROTATION => "token was replaced by a newer one",
+        REVOKED_BY_SESSION_LIMIT => "too many newer sessions were opened",
+        _ => return "Token has been revoked".to_string(),
+    };
+    format!("Token has been revoked: {}", detail)
+}
+
 pub async fn add_token_to_blacklist(
     pool: &PgPool,
+    clock: &dyn Clock,
     user_id: Uuid,
     jti: &str,
     issued_at: NaiveDateTime,
     expires_at: NaiveDateTime,
     reason: &str,
 ) -> Result<(), AppError> {
-    let now = Utc::now().naive_utc();
+    let now = clock.now();
 
     query!(
         r#"
@@ -160,20 +485,383 @@ pub async fn add_token_to_blacklist(
     Ok(())
 }
 
-pub async fn is_blacklisted(
+/// Reason `jti` was blacklisted, `None` when it is not blacklisted
+pub async fn blacklist_reason(
     pool: &PgPool,
     jti: &str,
+) -> Result<Option<String>, AppError> {
+    let reason = query_scalar!(
+        r#"
+        SELECT reason FROM token_blacklist
+        WHERE jti = $1
+        ORDER BY blacklisted_at DESC
+        LIMIT 1
+        "#,
+        jti
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(reason)
+}
+
+/// Returns the most recent failed login for an address that has not been
+/// followed by a successful login
+pub async fn last_failed_login_at(
+    pool: &PgPool,
+    address: &str,
+) -> Result<Option<NaiveDateTime>, AppError> {
+    let normalized_address = address.to_lowercase();
+
+    let last_failed = query!(
+        r#"
+        SELECT MAX(se.timestamp) as "last_failed"
+        FROM security_events se
+        JOIN users u ON u.id = se.user_id
+        WHERE u.ethereum_address = $1
+          AND se.event_type = 'failedlogin'
+          AND se.timestamp > COALESCE((
+              SELECT MAX(s.timestamp)
+              FROM security_events s
+              WHERE s.user_id = u.id
+                AND s.event_type = 'login'
+          ), '-infinity'::timestamp)
+        "#,
+        normalized_address
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(last_failed.last_failed)
+}
+
+/// Rejects a login attempt made too soon after a failed one
+///
+/// Meant to run at the start of the login flow, independently of the windowed
+/// rate limiting. A successful login clears the cooldown.
+pub async fn check_login_cooldown(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    address: &str,
+    cooldown_secs: u64,
+) -> Result<(), AppError> {
+    let Some(last_failed) = last_failed_login_at(pool, address).await? else {
+        return Ok(());
+    };
+
+    let now = clock.now();
+    let cooldown_ends = last_failed + chrono::Duration::seconds(cooldown_secs as i64);
+
+    if now < cooldown_ends {
+        let retry_after = (cooldown_ends - now).num_seconds().max(1) as u64;
+        return Err(AppError::RateLimitError(
+            "Too many failed login attempts, please wait before retrying".to_string(),
+            retry_after,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Counts failed logins since `since` for an address or from an IP
+pub async fn count_failed_logins_since(
+    pool: &PgPool,
+    address: &str,
+    client_ip: IpNetwork,
+    since: NaiveDateTime,
+) -> Result<i64, AppError> {
+    let normalized_address = address.to_lowercase();
+
+    let failures = query!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM security_events se
+        JOIN users u ON u.id = se.user_id
+        WHERE se.event_type = 'failedlogin'
+          AND se.timestamp >= $3
+          AND (u.ethereum_address = $1 OR se.client_ip = $2)
+        "#,
+        normalized_address,
+        client_ip,
+        since
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(failures.count)
+}
+
+/// Counts a user's events of one type recorded since `since`
+pub async fn count_user_events_since(
+    pool: &PgPool,
+    user_id: Uuid,
+    event_type: EventType,
+    since: NaiveDateTime,
+) -> Result<i64, AppError> {
+    let count = query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM security_events
+        WHERE user_id = $1
+          AND event_type = $2
+          AND timestamp >= $3
+        "#,
+        user_id,
+        event_type as EventType,
+        since
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+/// Counts successful and failed logins recorded since `since`
+pub async fn count_logins_since(
+    pool: &PgPool,
+    since: NaiveDateTime,
+) -> Result<(i64, i64), AppError> {
+    let counts = query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE event_type = 'login') as "logins!",
+            COUNT(*) FILTER (WHERE event_type = 'failedlogin') as "failed_logins!"
+        FROM security_events
+        WHERE timestamp >= $1
+        "#,
+        since
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((counts.logins, counts.failed_logins))
+}
+
+/// User and IP of every login recorded since `since`, oldest first
+pub async fn login_ips_since(
+    pool: &PgPool,
+    since: NaiveDateTime,
+) -> Result<Vec<(Uuid, IpNetwork)>, AppError> {
+    let rows = query!(
+        r#"
+        SELECT user_id, client_ip as "client_ip!"
+        FROM security_events
+        WHERE event_type = 'login'
+          AND timestamp >= $1
+          AND client_ip IS NOT NULL
+        ORDER BY timestamp ASC
+        "#,
+        since
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.user_id, row.client_ip)).collect())
+}
+
+/// Failed logins recorded since `since`, per targeted user
+pub async fn failed_logins_per_user_since(
+    pool: &PgPool,
+    since: NaiveDateTime,
+) -> Result<Vec<(Uuid, i64)>, AppError> {
+    let rows = query!(
+        r#"
+        SELECT user_id, COUNT(*) as "count!"
+        FROM security_events
+        WHERE event_type = 'failedlogin'
+          AND timestamp >= $1
+        GROUP BY user_id
+        "#,
+        since
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.user_id, row.count)).collect())
+}
+
+/// Counts a user's logins in `[from, until)`, in total and from within `range`
+pub async fn count_logins_in_range(
+    pool: &PgPool,
+    user_id: Uuid,
+    range: IpNetwork,
+    from: NaiveDateTime,
+    until: NaiveDateTime,
+) -> Result<(i64, i64), AppError> {
+    let counts = query!(
+        r#"
+        SELECT
+            COUNT(*) as "total!",
+            COUNT(*) FILTER (WHERE client_ip <<= $2) as "in_range!"
+        FROM security_events
+        WHERE user_id = $1
+          AND event_type = 'login'
+          AND timestamp >= $3
+          AND timestamp < $4
+        "#,
+        user_id,
+        range,
+        from,
+        until
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((counts.total, counts.in_range))
+}
+
+/// Whether an `AnomalyDetected` event of `kind` was recorded for the user since `since`
+pub async fn anomaly_recorded_since(
+    pool: &PgPool,
+    user_id: Uuid,
+    kind: &str,
+    since: NaiveDateTime,
 ) -> Result<bool, AppError> {
-    let blacklisted = query!(
+    let recorded = query_scalar!(
         r#"
         SELECT EXISTS (
-            SELECT 1 FROM token_blacklist WHERE jti = $1
-        ) as "exists!"
+            SELECT 1
+            FROM security_events
+            WHERE user_id = $1
+              AND event_type = 'anomalydetected'
+              AND metadata->>'kind' = $2
+              AND timestamp >= $3
+        ) as "recorded!"
         "#,
-        jti
+        user_id,
+        kind,
+        since
     )
     .fetch_one(pool)
     .await?;
 
-    Ok(blacklisted.exists)
+    Ok(recorded)
+}
+
+/// Deletes events older than `cutoff`, except for the preserved event types
+pub async fn delete_events_older_than(
+    pool: &PgPool,
+    cutoff: NaiveDateTime,
+    preserved: &[EventType],
+) -> Result<u64, AppError> {
+    let result = query!(
+        r#"
+        DELETE FROM security_events
+        WHERE timestamp < $1
+          AND NOT (event_type = ANY($2))
+        "#,
+        cutoff,
+        preserved as &[EventType]
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::app_config::{EventMetadataLimits, OversizedMetadata},
+        test_support,
+        utils::clock::MockClock,
+    };
+    use chrono::{Duration, NaiveDate};
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    async fn record(pool: &PgPool, clock: &MockClock, event_type: EventType, user_id: Uuid) {
+        record_event(pool, clock, event_type, user_id, test_support::client_ip(), "test", serde_json::json!({}))
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn oversized_metadata_is_stored_capped(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+        let user = test_support::create_user(&pool, &clock, ADDRESS).await;
+
+        // Process wide, generous enough for the metadata of the other tests
+        set_event_metadata_limits(EventMetadataLimits {
+            max_bytes: 1024,
+            max_keys: 16,
+            oversized: OversizedMetadata::Truncate,
+        });
+        let user_agent = "A".repeat(100_000);
+        record_event(
+            &pool,
+            &clock,
+            EventType::Login,
+            user.id,
+            test_support::client_ip(),
+            &user_agent,
+            serde_json::json!({ "user_agent": user_agent }),
+        ).await.unwrap();
+
+        let stored = query!("SELECT user_agent, metadata FROM security_events WHERE user_id = $1", user.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let metadata = stored.metadata.unwrap();
+        assert!(metadata.to_string().len() <= 1024);
+        assert!(metadata["user_agent"].as_str().unwrap().ends_with('…'));
+        assert_eq!(metadata["_truncated"]["keys"], 1);
+        assert_eq!(stored.user_agent.unwrap().chars().count(), USER_AGENT_MAX_CHARS);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn disabled_event_types_are_not_recorded(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+        let user = test_support::create_user(&pool, &clock, ADDRESS).await;
+
+        // Process wide: only disables types the other tests never record
+        let disabled = [EventType::SharedInvoiceViewed, EventType::FailedLogin];
+        set_recorded_event_types(Some(
+            EventType::ALL.into_iter().filter(|event_type| !disabled.contains(event_type)).collect(),
+        ));
+
+        for event_type in [EventType::SharedInvoiceViewed, EventType::Login, EventType::FailedLogin] {
+            record(&pool, &clock, event_type, user.id).await;
+        }
+
+        let recorded: Vec<EventType> = query!(
+            r#"SELECT event_type as "event_type: EventType" FROM security_events WHERE user_id = $1 ORDER BY event_type"#,
+            user.id
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.event_type)
+        .collect();
+        // Failed logins are mandatory, recorded even when not enabled
+        assert_eq!(recorded, [EventType::Login, EventType::FailedLogin]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn login_cooldown_follows_a_failed_login_until_a_successful_one(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+        let user = test_support::create_user(&pool, &clock, ADDRESS).await;
+
+        check_login_cooldown(&pool, &clock, ADDRESS, 2).await.unwrap();
+
+        record(&pool, &clock, EventType::FailedLogin, user.id).await;
+        clock.advance(Duration::seconds(1));
+        match check_login_cooldown(&pool, &clock, &ADDRESS.to_uppercase().replace("0X", "0x"), 2).await {
+            Err(AppError::RateLimitError(_, retry_after)) => assert_eq!(retry_after, 1),
+            other => panic!("expected a cooldown, got {other:?}"),
+        }
+
+        clock.advance(Duration::seconds(1));
+        check_login_cooldown(&pool, &clock, ADDRESS, 2).await.unwrap();
+
+        record(&pool, &clock, EventType::FailedLogin, user.id).await;
+        clock.advance(Duration::milliseconds(500));
+        record(&pool, &clock, EventType::Login, user.id).await;
+        check_login_cooldown(&pool, &clock, ADDRESS, 2).await.unwrap();
+    }
 }
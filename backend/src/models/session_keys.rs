@@ -0,0 +1,344 @@
+use uuid::Uuid;
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{query, query_as, query_scalar, FromRow, PgPool};
+
+use crate::app_error::app_error::AppError;
+use crate::models::invoices::Invoice;
+use crate::utils::clock::Clock;
+
+/// A key a payer lets accept invoices addressed to it, within limits
+///
+/// The payer's wallet signs `authorization_message` once; from then on the
+/// session key's own signature over `acceptance_message` accepts an invoice
+/// without prompting the wallet, as long as the invoice is in `currency`,
+/// within `max_amount_per_invoice` and keeps `spent` under `spend_limit`.
+#[derive(Debug, FromRow, Serialize)]
+pub struct SessionKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub owner_address: String,
+    /// Address of the session key pair, the signer of acceptance messages
+    pub session_address: String,
+    pub currency: String,
+    #[serde(with = "crate::utils::amount")]
+    pub max_amount_per_invoice: BigDecimal,
+    #[serde(with = "crate::utils::amount")]
+    pub spend_limit: BigDecimal,
+    #[serde(with = "crate::utils::amount")]
+    pub spent: BigDecimal,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub authorized_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+/// Limits a session key is requested with
+#[derive(Debug)]
+pub struct SessionKeyLimits {
+    pub currency: String,
+    pub max_amount_per_invoice: BigDecimal,
+    pub spend_limit: BigDecimal,
+    pub expires_at: NaiveDateTime,
+}
+
+impl SessionKey {
+    /// Registers a key awaiting the owner's authorization signature
+    ///
+    /// Fails once the user holds `max_keys` unrevoked, unexpired keys.
+    pub async fn create(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        owner_address: &str,
+        session_address: &str,
+        limits: SessionKeyLimits,
+        max_keys: i64,
+    ) -> Result<SessionKey, AppError> {
+        let now = clock.now();
+        let mut tx = pool.begin().await?;
+
+        // Serializes key creation per user, so the count below stays exact
+        query!("SELECT pg_advisory_xact_lock(hashtext($1))", user_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        let held = query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!"
+            FROM session_keys
+            WHERE user_id = $1
+              AND revoked_at IS NULL
+              AND expires_at > $2
+            "#,
+            user_id,
+            now
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if held >= max_keys {
+            return Err(AppError::QuotaExceededError(format!(
+                "You cannot hold more than {} session keys, revoke one first", max_keys
+            )));
+        }
+
+        let key = query_as!(
+            SessionKey,
+            r#"
+            INSERT INTO session_keys (
+                id, user_id, owner_address, session_address, currency,
+                max_amount_per_invoice, spend_limit, created_at, expires_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id, user_id, owner_address, session_address, currency,
+                      max_amount_per_invoice, spend_limit, spent, created_at,
+                      expires_at, authorized_at, revoked_at
+            "#,
+            Uuid::new_v4(),
+            user_id,
+            owner_address,
+            session_address,
+            limits.currency,
+            limits.max_amount_per_invoice,
+            limits.spend_limit,
+            now,
+            limits.expires_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(key)
+    }
+
+    pub async fn find_for_user(
+        pool: &PgPool,
+        user_id: Uuid,
+        key_id: Uuid,
+    ) -> Result<Option<SessionKey>, AppError> {
+        let key = query_as!(
+            SessionKey,
+            r#"
+            SELECT id, user_id, owner_address, session_address, currency,
+                   max_amount_per_invoice, spend_limit, spent, created_at,
+                   expires_at, authorized_at, revoked_at
+            FROM session_keys
+            WHERE id = $1
+              AND user_id = $2
+            "#,
+            key_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(key)
+    }
+
+    /// Returns the key when it is authorized, unrevoked and unexpired
+    pub async fn find_usable(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        key_id: Uuid,
+    ) -> Result<Option<SessionKey>, AppError> {
+        let key = query_as!(
+            SessionKey,
+            r#"
+            SELECT id, user_id, owner_address, session_address, currency,
+                   max_amount_per_invoice, spend_limit, spent, created_at,
+                   expires_at, authorized_at, revoked_at
+            FROM session_keys
+            WHERE id = $1
+              AND authorized_at IS NOT NULL
+              AND revoked_at IS NULL
+              AND expires_at > $2
+            "#,
+            key_id,
+            clock.now()
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(key)
+    }
+
+    pub async fn list_for_user(
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> Result<Vec<SessionKey>, AppError> {
+        let keys = query_as!(
+            SessionKey,
+            r#"
+            SELECT id, user_id, owner_address, session_address, currency,
+                   max_amount_per_invoice, spend_limit, spent, created_at,
+                   expires_at, authorized_at, revoked_at
+            FROM session_keys
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    /// Activates a pending key once its authorization signature was checked
+    ///
+    /// Returns `None` when the key is already authorized, revoked or expired.
+    pub async fn mark_authorized(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        key_id: Uuid,
+    ) -> Result<Option<SessionKey>, AppError> {
+        let now = clock.now();
+
+        let key = query_as!(
+            SessionKey,
+            r#"
+            UPDATE session_keys
+            SET authorized_at = $3
+            WHERE id = $1
+              AND user_id = $2
+              AND authorized_at IS NULL
+              AND revoked_at IS NULL
+              AND expires_at > $3
+            RETURNING id, user_id, owner_address, session_address, currency,
+                      max_amount_per_invoice, spend_limit, spent, created_at,
+                      expires_at, authorized_at, revoked_at
+            "#,
+            key_id,
+            user_id,
+            now
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(key)
+    }
+
+    /// Revokes one of the user's keys, `false` when none was active
+    pub async fn revoke(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        key_id: Uuid,
+    ) -> Result<bool, AppError> {
+        let result = query!(
+            r#"
+            UPDATE session_keys
+            SET revoked_at = $3
+            WHERE id = $1
+              AND user_id = $2
+              AND revoked_at IS NULL
+            "#,
+            key_id,
+            user_id,
+            clock.now()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Accepts `invoice` under this key, charging its amount to the key
+    ///
+    /// The charge and the acceptance are one transaction, and the charge is
+    /// only made while the key is usable and the invoice within its limits,
+    /// so concurrent acceptances can never exceed `spend_limit`. Returns
+    /// `None`, charging nothing, when the invoice was no longer pending.
+    pub async fn accept_invoice(
+        &self,
+        pool: &PgPool,
+        clock: &dyn Clock,
+        invoice: &Invoice,
+    ) -> Result<Option<Invoice>, AppError> {
+        let now = clock.now();
+        let mut tx = pool.begin().await?;
+
+        let charged = query!(
+            r#"
+            UPDATE session_keys
+            SET spent = spent + $2
+            WHERE id = $1
+              AND authorized_at IS NOT NULL
+              AND revoked_at IS NULL
+              AND expires_at > $3
+              AND currency = $4
+              AND owner_address = $5
+              AND $2 <= max_amount_per_invoice
+              AND spent + $2 <= spend_limit
+            "#,
+            self.id,
+            invoice.amount,
+            now,
+            invoice.currency,
+            invoice.recipient_address
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if charged == 0 {
+            return Err(AppError::ForbiddenError(
+                "Invoice exceeds the limits of this session key".to_string()
+            ));
+        }
+
+        let accepted = Invoice::accept_in(&mut tx, now, invoice.id).await?;
+        if accepted.is_some() {
+            tx.commit().await?;
+        }
+
+        Ok(accepted)
+    }
+
+    /// Message the owner's wallet signs to authorize the key
+    pub fn authorization_message(&self, domain: &str, chain_id: u32) -> String {
+        format!(
+            "{domain} wants you to authorize a session key for {owner}:\n\
+             \n\
+             The session key may accept invoices addressed to you without asking your wallet again.\n\
+             \n\
+             Session key: {session}\n\
+             Key ID: {id}\n\
+             Currency: {currency}\n\
+             Max per invoice: {max}\n\
+             Spend limit: {limit}\n\
+             Expiration Time: {expires}\n\
+             Chain ID: {chain_id}",
+            domain = domain,
+            owner = self.owner_address,
+            session = self.session_address,
+            id = self.id,
+            currency = self.currency,
+            max = self.max_amount_per_invoice.to_plain_string(),
+            limit = self.spend_limit.to_plain_string(),
+            expires = self.expires_at.and_utc().to_rfc3339(),
+            chain_id = chain_id,
+        )
+    }
+}
+
+/// Message a session key signs to accept `invoice`
+///
+/// Bound to the invoice and the key, so a signature cannot be reused for
+/// another invoice, and an invoice can only be accepted once.
+pub fn acceptance_message(invoice: &Invoice, key_id: Uuid, chain_id: u32) -> String {
+    format!(
+        "Accept invoice {display_number} ({id})\n\
+         Amount: {amount} {currency}\n\
+         Session key ID: {key_id}\n\
+         Chain ID: {chain_id}",
+        display_number = invoice.display_number,
+        id = invoice.id,
+        amount = invoice.amount.to_plain_string(),
+        currency = invoice.currency,
+        key_id = key_id,
+        chain_id = chain_id,
+    )
+}
@@ -0,0 +1,312 @@
+use uuid::Uuid;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, query_scalar, FromRow, PgPool};
+
+use crate::app_error::app_error::AppError;
+use crate::config::app_config::SessionLimitPolicy;
+use crate::models::security_events::REVOKED_BY_SESSION_LIMIT;
+use crate::models::users::User;
+use crate::utils::clock::Clock;
+
+/// A refresh token issued to a user, identified by its `jti`
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub refresh_jti: String,
+    pub issued_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl Session {
+    pub async fn create(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        refresh_jti: &str,
+        expires_at: NaiveDateTime,
+    ) -> Result<Session, AppError> {
+        let now = clock.now();
+
+        let session = query_as!(
+            Session,
+            r#"
+            INSERT INTO sessions (id, user_id, refresh_jti, issued_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, user_id, refresh_jti, issued_at, expires_at, revoked_at
+            "#,
+            Uuid::new_v4(),
+            user_id,
+            refresh_jti,
+            now,
+            expires_at
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    /// Records a session while keeping the user within `max_sessions`
+    ///
+    /// At the limit, `EvictOldest` revokes the oldest active sessions and
+    /// blacklists their refresh `jti`s, which are returned, while `Reject`
+    /// fails without creating anything. Sign-ins of one user are serialized
+    /// so concurrent ones cannot both slip under the limit. A `max_sessions`
+    /// of 0 means no limit.
+    pub async fn create_within_limit(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        refresh_jti: &str,
+        expires_at: NaiveDateTime,
+        max_sessions: u32,
+        policy: SessionLimitPolicy,
+    ) -> Result<(Session, Vec<Session>), AppError> {
+        if max_sessions == 0 {
+            return Ok((Session::create(pool, clock, user_id, refresh_jti, expires_at).await?, Vec::new()));
+        }
+
+        let now = clock.now();
+        let mut tx = pool.begin().await?;
+
+        query!("SELECT pg_advisory_xact_lock(hashtext('sessions:' || $1))", user_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        let active = query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!"
+            FROM sessions
+            WHERE user_id = $1
+              AND revoked_at IS NULL
+              AND expires_at > $2
+            "#,
+            user_id,
+            now
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Room for the new session, which counts toward the limit
+        let excess = active - i64::from(max_sessions) + 1;
+        let evicted = if excess <= 0 {
+            Vec::new()
+        } else if policy == SessionLimitPolicy::Reject {
+            return Err(AppError::QuotaExceededError(format!(
+                "You cannot have more than {} active sessions, sign out of one first", max_sessions
+            )));
+        } else {
+            query_as!(
+                Session,
+                r#"
+                WITH oldest AS (
+                    SELECT id
+                    FROM sessions
+                    WHERE user_id = $1
+                      AND revoked_at IS NULL
+                      AND expires_at > $2
+                    ORDER BY issued_at ASC
+                    LIMIT $3
+                ),
+                revoked AS (
+                    UPDATE sessions
+                    SET revoked_at = $2
+                    WHERE id IN (SELECT id FROM oldest)
+                    RETURNING id, user_id, refresh_jti, issued_at, expires_at, revoked_at
+                ),
+                blacklisted AS (
+                    INSERT INTO token_blacklist (
+                        id, user_id, jti, expires_at, issued_at, blacklisted_at, reason
+                    )
+                    SELECT uuid_generate_v4(), user_id, refresh_jti, expires_at, issued_at, $2, $4
+                    FROM revoked
+                )
+                SELECT id as "id!", user_id as "user_id!", refresh_jti as "refresh_jti!",
+                       issued_at as "issued_at!", expires_at as "expires_at!", revoked_at
+                FROM revoked
+                ORDER BY issued_at ASC
+                "#,
+                user_id,
+                now,
+                excess,
+                REVOKED_BY_SESSION_LIMIT
+            )
+            .fetch_all(&mut *tx)
+            .await?
+        };
+
+        let session = query_as!(
+            Session,
+            r#"
+            INSERT INTO sessions (id, user_id, refresh_jti, issued_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, user_id, refresh_jti, issued_at, expires_at, revoked_at
+            "#,
+            Uuid::new_v4(),
+            user_id,
+            refresh_jti,
+            now,
+            expires_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok((session, evicted))
+    }
+
+    /// Revokes the session of a refresh token and blacklists the token
+    ///
+    /// Returns false when the session was already revoked or does not exist,
+    /// e.g. for a refresh token presented a second time.
+    pub async fn revoke_by_jti(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        refresh_jti: &str,
+        reason: &str,
+    ) -> Result<bool, AppError> {
+        let now = clock.now();
+
+        let result = query!(
+            r#"
+            WITH revoked AS (
+                UPDATE sessions
+                SET revoked_at = $2
+                WHERE refresh_jti = $1
+                  AND revoked_at IS NULL
+                RETURNING user_id, refresh_jti, issued_at, expires_at
+            )
+            INSERT INTO token_blacklist (
+                id, user_id, jti, expires_at, issued_at, blacklisted_at, reason
+            )
+            SELECT uuid_generate_v4(), user_id, refresh_jti, expires_at, issued_at, $2, $3
+            FROM revoked
+            "#,
+            refresh_jti,
+            now,
+            reason
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revokes every active session of a user and blacklists their refresh `jti`s
+    ///
+    /// Both happen in a single statement so a session cannot be revoked without
+    /// its token being blacklisted. The user's token epoch is bumped in the
+    /// same transaction so outstanding access tokens stop working immediately.
+    /// `reason` should be one of the `REVOKED_*` codes of `security_events`.
+    /// Returns the number of revoked sessions.
+    pub async fn revoke_all_for_user(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        reason: &str,
+    ) -> Result<u64, AppError> {
+        let now = clock.now();
+        let mut tx = pool.begin().await?;
+
+        let result = query!(
+            r#"
+            WITH revoked AS (
+                UPDATE sessions
+                SET revoked_at = $2
+                WHERE user_id = $1
+                  AND revoked_at IS NULL
+                  AND expires_at > $2
+                RETURNING user_id, refresh_jti, issued_at, expires_at
+            )
+            INSERT INTO token_blacklist (
+                id, user_id, jti, expires_at, issued_at, blacklisted_at, reason
+            )
+            SELECT uuid_generate_v4(), user_id, refresh_jti, expires_at, issued_at, $2, $3
+            FROM revoked
+            "#,
+            user_id,
+            now,
+            reason
+        )
+        .execute(&mut *tx)
+        .await?;
+        User::bump_token_epoch(&mut tx, user_id).await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support, utils::clock::MockClock};
+    use chrono::{Duration, NaiveDate};
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    async fn sign_in(pool: &PgPool, clock: &MockClock, user_id: Uuid, jti: &str, policy: SessionLimitPolicy)
+        -> Result<(Session, Vec<Session>), AppError>
+    {
+        clock.advance(Duration::minutes(1));
+        let expires_at = clock.now() + Duration::days(1);
+        Session::create_within_limit(pool, clock, user_id, jti, expires_at, 2, policy).await
+    }
+
+    fn start() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn sign_in_past_the_limit_evicts_the_oldest_session(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(start());
+        let user = test_support::create_user(&pool, &clock, ADDRESS).await;
+
+        for jti in ["first", "second"] {
+            let (_, evicted) = sign_in(&pool, &clock, user.id, jti, SessionLimitPolicy::EvictOldest).await.unwrap();
+            assert!(evicted.is_empty());
+        }
+        let (_, evicted) = sign_in(&pool, &clock, user.id, "third", SessionLimitPolicy::EvictOldest).await.unwrap();
+        assert_eq!(evicted.iter().map(|s| s.refresh_jti.as_str()).collect::<Vec<_>>(), ["first"]);
+
+        let active = query_scalar!(
+            "SELECT refresh_jti FROM sessions WHERE user_id = $1 AND revoked_at IS NULL ORDER BY issued_at",
+            user.id
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(active, ["second", "third"]);
+
+        let reason = query_scalar!("SELECT reason FROM token_blacklist WHERE jti = 'first'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(reason, REVOKED_BY_SESSION_LIMIT);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn reject_policy_refuses_a_sign_in_past_the_limit(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(start());
+        let user = test_support::create_user(&pool, &clock, ADDRESS).await;
+
+        for jti in ["first", "second"] {
+            sign_in(&pool, &clock, user.id, jti, SessionLimitPolicy::Reject).await.unwrap();
+        }
+        let refused = sign_in(&pool, &clock, user.id, "third", SessionLimitPolicy::Reject).await;
+        assert!(matches!(refused, Err(AppError::QuotaExceededError(_))));
+
+        let sessions = query_scalar!(r#"SELECT COUNT(*) as "count!" FROM sessions WHERE user_id = $1"#, user.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(sessions, 2);
+    }
+}
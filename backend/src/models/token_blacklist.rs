@@ -0,0 +1,200 @@
+use chrono::{Duration, DurationRound, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, PgPool};
+use std::collections::BTreeMap;
+
+use crate::app_error::app_error::AppError;
+use crate::models::security_events::REVOKED_FOR_SECURITY;
+
+/// Security revocations in the latest bucket must reach this count to be a spike
+const SPIKE_MIN_COUNT: i64 = 10;
+
+/// How many times the average of the preceding buckets the latest one must reach
+const SPIKE_FACTOR: f64 = 3.0;
+
+/// Width of the time buckets blacklist rows are counted in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BucketWidth {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl BucketWidth {
+    /// Field name understood by Postgres' `date_trunc`
+    fn pg_unit(self) -> &'static str {
+        match self {
+            BucketWidth::Hour => "hour",
+            BucketWidth::Day => "day",
+        }
+    }
+
+    pub fn duration(self) -> Duration {
+        match self {
+            BucketWidth::Hour => Duration::hours(1),
+            BucketWidth::Day => Duration::days(1),
+        }
+    }
+
+    /// Start of the bucket holding `at`, as `date_trunc` computes it
+    pub fn truncate(self, at: NaiveDateTime) -> NaiveDateTime {
+        at.duration_trunc(self.duration()).unwrap_or(at)
+    }
+}
+
+/// Revocations blacklisted within one bucket, by reason
+#[derive(Debug, Serialize)]
+pub struct BlacklistBucket {
+    pub start: NaiveDateTime,
+    pub total: i64,
+    pub reasons: BTreeMap<String, i64>,
+}
+
+/// Whether security revocations just jumped above their recent level
+///
+/// The latest bucket, still filling, is compared with the average of the
+/// other buckets of the window, empty ones included. A mass revocation after
+/// an incident shows up here before anyone reads the event log.
+#[derive(Debug, Serialize)]
+pub struct SecuritySpike {
+    pub detected: bool,
+    pub bucket_start: NaiveDateTime,
+    pub count: i64,
+    pub baseline: f64,
+}
+
+/// Blacklist activity over a window, newest buckets first
+#[derive(Debug, Serialize)]
+pub struct BlacklistStats {
+    pub since: NaiveDateTime,
+    pub bucket: BucketWidth,
+    /// Revocations in the whole window, by reason
+    pub totals: BTreeMap<String, i64>,
+    pub security_spike: SecuritySpike,
+    /// Non-empty buckets of this page
+    pub buckets: Vec<BlacklistBucket>,
+    pub has_more: bool,
+}
+
+/// Counts blacklisted tokens by reason and bucket since `since`
+///
+/// Only buckets holding at least one revocation are listed; `offset` and
+/// `limit` page through them, newest first.
+pub async fn blacklist_stats(
+    pool: &PgPool,
+    now: NaiveDateTime,
+    since: NaiveDateTime,
+    width: BucketWidth,
+    offset: i64,
+    limit: i64,
+) -> Result<BlacklistStats, AppError> {
+    let totals = query!(
+        r#"
+        SELECT reason, COUNT(*) as "count!"
+        FROM token_blacklist
+        WHERE blacklisted_at >= $1
+        GROUP BY reason
+        "#,
+        since
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.reason, row.count))
+    .collect();
+
+    let rows = query!(
+        r#"
+        WITH counts AS (
+            SELECT date_trunc($1, blacklisted_at) as bucket, reason, COUNT(*) as count
+            FROM token_blacklist
+            WHERE blacklisted_at >= $2
+            GROUP BY 1, 2
+        ),
+        page AS (
+            SELECT DISTINCT bucket
+            FROM counts
+            ORDER BY bucket DESC
+            OFFSET $3
+            LIMIT $4
+        )
+        SELECT counts.bucket as "bucket!", counts.reason, counts.count as "count!"
+        FROM counts
+        JOIN page ON page.bucket = counts.bucket
+        ORDER BY counts.bucket DESC, counts.reason
+        "#,
+        width.pg_unit(),
+        since,
+        offset,
+        limit + 1
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut buckets: Vec<BlacklistBucket> = Vec::new();
+    for row in rows {
+        match buckets.last_mut() {
+            Some(bucket) if bucket.start == row.bucket => {
+                bucket.total += row.count;
+                bucket.reasons.insert(row.reason, row.count);
+            }
+            _ => buckets.push(BlacklistBucket {
+                start: row.bucket,
+                total: row.count,
+                reasons: BTreeMap::from([(row.reason, row.count)]),
+            }),
+        }
+    }
+    let has_more = buckets.len() as i64 > limit;
+    buckets.truncate(limit as usize);
+
+    let security_spike = security_spike(pool, now, since, width).await?;
+
+    Ok(BlacklistStats {
+        since,
+        bucket: width,
+        totals,
+        security_spike,
+        buckets,
+        has_more,
+    })
+}
+
+async fn security_spike(
+    pool: &PgPool,
+    now: NaiveDateTime,
+    since: NaiveDateTime,
+    width: BucketWidth,
+) -> Result<SecuritySpike, AppError> {
+    let bucket_start = width.truncate(now);
+
+    let (count, earlier) = query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE blacklisted_at >= $3) as "count!",
+            COUNT(*) FILTER (WHERE blacklisted_at < $3) as "earlier!"
+        FROM token_blacklist
+        WHERE reason = $1
+          AND blacklisted_at >= $2
+        "#,
+        REVOKED_FOR_SECURITY,
+        since,
+        bucket_start
+    )
+    .fetch_one(pool)
+    .await
+    .map(|row| (row.count, row.earlier))?;
+
+    let earlier_buckets = ((bucket_start - since).num_seconds() as f64
+        / width.duration().num_seconds() as f64)
+        .max(1.0);
+    let baseline = earlier as f64 / earlier_buckets;
+
+    Ok(SecuritySpike {
+        detected: count >= SPIKE_MIN_COUNT && count as f64 >= baseline * SPIKE_FACTOR,
+        bucket_start,
+        count,
+        baseline,
+    })
+}
@@ -1,12 +1,15 @@
 use uuid::Uuid;
-use chrono::{NaiveDateTime, Utc};
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sqlx::{query, query_as, FromRow, PgPool};
+use sqlx::{query, query_as, query_scalar, FromRow, PgConnection, PgPool};
 use validator::Validate;
 use serde_json::Value as JsonValue;
 // use rand::Rng;
 
 use crate::app_error::app_error::AppError;
+use crate::utils::clock::Clock;
+use crate::models::auth_challenges::validate_eth_address;
+use crate::utils::metadata::validate_metadata;
 
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct User {
@@ -19,15 +22,53 @@ pub struct User {
     is_active: bool,
     is_admin: bool,
     is_verified: bool,
-    pub metadata: Option<JsonValue>
+    pub metadata: Option<JsonValue>,
+    pub token_epoch: i32,
+}
+
+/// User as listed to admins, without metadata or token state
+#[derive(Debug, FromRow, Serialize)]
+pub struct UserSummary {
+    pub id: Uuid,
+    pub ethereum_address: String,
+    pub email: String,
+    pub username: String,
+    pub is_active: bool,
+    pub is_admin: bool,
+    pub is_verified: bool,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<User> for UserSummary {
+    fn from(user: User) -> Self {
+        UserSummary {
+            id: user.id,
+            ethereum_address: user.ethereum_address,
+            email: user.email,
+            username: user.username,
+            is_active: user.is_active,
+            is_admin: user.is_admin,
+            is_verified: user.is_verified,
+            created_at: user.created_at,
+        }
+    }
+}
+
+/// One page of an admin user search, newest accounts first
+#[derive(Debug, Serialize)]
+pub struct UserPage {
+    pub users: Vec<UserSummary>,
+    pub has_more: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct UserInput {
+    #[validate(custom(function = "validate_eth_address"))]
     pub ethereum_address: String,
     #[validate(email)]
     pub email: String,
     pub username: String,
+    #[validate(custom(function = "validate_metadata"))]
     pub metadata: JsonValue
 }
 
@@ -38,6 +79,7 @@ pub struct UserInputUpdate {
     pub username: String,
     pub is_active: bool,
     pub is_admin: bool,
+    #[validate(custom(function = "validate_metadata"))]
     pub metadata: Option<JsonValue>
 
 }
@@ -70,9 +112,10 @@ pub struct AuthChallenge {
 impl User {
     pub async fn create(
         pool: &PgPool,
+        clock: &dyn Clock,
         user_input: &UserInput,
     ) -> Result<User, AppError> {
-        let now = Utc::now().naive_utc();
+        let now = clock.now();
 
         let metadata = if user_input.metadata.is_null() {
             serde_json::json!({})
@@ -84,6 +127,7 @@ impl User {
             User,
             r#"
             INSERT INTO users (
+                id,
                 ethereum_address, 
                 email, 
                 username, 
@@ -93,11 +137,12 @@ impl User {
                 is_admin, 
                 is_verified, 
                 metadata
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
             RETURNING id, ethereum_address, email, username, created_at, updated_at,
-                      is_active, is_admin, is_verified, metadata as "metadata: JsonValue"
+                      is_active, is_admin, is_verified, metadata as "metadata: JsonValue", token_epoch
 
             "#,
+            Uuid::new_v4(),
             user_input.ethereum_address,
             user_input.email,
             user_input.username,
@@ -115,19 +160,51 @@ impl User {
         Ok(user)
     }
 
+    /// Finds the user of a wallet, creating it at its first sign-in
+    ///
+    /// Wallet users start without an email and with their address as
+    /// username. Also returns whether the user was created; concurrent first
+    /// sign-ins create it once.
+    pub async fn find_or_create_by_address(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        address: &str,
+    ) -> Result<(User, bool), AppError> {
+        if let Some(user) = Self::get_user_by_eth_address(pool, address).await? {
+            return Ok((user, false));
+        }
+
+        let normalized_address = address.to_lowercase();
+        let user_input = UserInput {
+            ethereum_address: normalized_address.clone(),
+            email: String::new(),
+            username: normalized_address.clone(),
+            metadata: JsonValue::Null,
+        };
+        match Self::create(pool, clock, &user_input).await {
+            Ok(user) => Ok((user, true)),
+            // Another sign-in of the same wallet created it first
+            Err(e) => match Self::get_user_by_eth_address(pool, &normalized_address).await? {
+                Some(user) => Ok((user, false)),
+                None => Err(e),
+            },
+        }
+    }
+
     pub async fn update_user(
         pool: &PgPool,
+        clock: &dyn Clock,
         user_id: Uuid,
         user_input: &UserInputUpdate,
     ) -> Result<User, AppError> {
-        let now = Utc::now().naive_utc();
+        let now = clock.now();
 
         // Fetch the existing user
         let mut user = query_as!(
             User,
             r#"
             SELECT id, ethereum_address, email, username, created_at, updated_at,
-                   is_active, is_admin, is_verified, metadata as "metadata: JsonValue"
+                   is_active, is_admin, is_verified, metadata as "metadata: JsonValue", token_epoch
 
             FROM users
             WHERE id = $1
@@ -146,6 +223,10 @@ impl User {
             user.username = user_input.username.clone();
         }
 
+        // Privilege changes invalidate every token already issued to the user
+        let privileges_changed = user.is_active != user_input.is_active
+            || user.is_admin != user_input.is_admin;
+
         user.is_active = user_input.is_active;
         user.is_admin = user_input.is_admin;
 
@@ -158,7 +239,7 @@ impl User {
 
         user.updated_at = now;
 
-        query!(
+        let updated = query!(
             r#"
             UPDATE users
             SET 
@@ -167,8 +248,10 @@ impl User {
                 is_active = $3,
                 is_admin = $4,
                 updated_at = $5,
-                metadata = $6
-            WHERE id = $7
+                metadata = $6,
+                token_epoch = token_epoch + $7
+            WHERE id = $8
+            RETURNING token_epoch
             "#,
             user.email,
             user.username,
@@ -176,14 +259,138 @@ impl User {
             user.is_admin,
             user.updated_at,
             user.metadata,
+            i32::from(privileges_changed),
             user.id
         )
+        .fetch_one(pool)
+        .await?;
+
+        user.token_epoch = updated.token_epoch;
+
+        Ok(user)
+    }
+
+    /// Bumps the user's token epoch, instantly invalidating all their tokens
+    pub async fn bump_token_epoch(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+    ) -> Result<i32, AppError> {
+        let updated = query!(
+            r#"
+            UPDATE users
+            SET token_epoch = token_epoch + 1
+            WHERE id = $1
+            RETURNING token_epoch
+            "#,
+            user_id
+        )
+        .fetch_one(conn)
+        .await?;
+
+        Ok(updated.token_epoch)
+    }
+
+    pub async fn get_token_epoch(
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> Result<Option<i32>, AppError> {
+        let epoch = query!(
+            r#"
+            SELECT token_epoch
+            FROM users
+            WHERE id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(epoch.map(|row| row.token_epoch))
+    }
+
+    /// When the account of an address unlocks, if it was ever locked
+    pub async fn get_locked_until(
+        pool: &PgPool,
+        address: &str,
+    ) -> Result<Option<NaiveDateTime>, AppError> {
+        let locked_until = query_scalar!(
+            r#"
+            SELECT locked_until
+            FROM users
+            WHERE ethereum_address = $1
+            "#,
+            address.to_lowercase()
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(locked_until.flatten())
+    }
+
+    /// Locks the account until `locked_until` unless it is locked already
+    ///
+    /// Returns false when another failed login locked it first, so a lock is
+    /// applied and recorded once.
+    pub async fn lock_until(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        locked_until: NaiveDateTime,
+    ) -> Result<bool, AppError> {
+        let result = query!(
+            r#"
+            UPDATE users
+            SET locked_until = $2
+            WHERE id = $1
+              AND (locked_until IS NULL OR locked_until <= $3)
+            "#,
+            user_id,
+            locked_until,
+            clock.now()
+        )
         .execute(pool)
         .await?;
 
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.is_admin
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.is_verified
+    }
+
+    /// Grants or withdraws the verified mark, `None` when there is no such user
+    pub async fn set_verified(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        user_id: Uuid,
+        verified: bool,
+    ) -> Result<Option<UserSummary>, AppError> {
+        let user = query_as!(
+            UserSummary,
+            r#"
+            UPDATE users
+            SET is_verified = $1, updated_at = $2
+            WHERE id = $3
+            RETURNING id, ethereum_address, email, username, is_active, is_admin, is_verified, created_at
+            "#,
+            verified,
+            clock.now(),
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
         Ok(user)
     }
 
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+
     pub async fn get_user_by_eth_address(
         pool: &PgPool,
         address: &str,
@@ -194,7 +401,7 @@ impl User {
             User,
             r#"
             SELECT id, ethereum_address, email, username, created_at, updated_at,
-                   is_active, is_admin, is_verified, metadata as "metadata: JsonValue"
+                   is_active, is_admin, is_verified, metadata as "metadata: JsonValue", token_epoch
             FROM users
             WHERE ethereum_address = $1
             "#,
@@ -206,6 +413,43 @@ impl User {
         Ok(user)
     }
 
+    /// Finds users whose address, email or username contains `term`, ignoring case
+    ///
+    /// `%` and `_` in the term match literally.
+    pub async fn search(
+        pool: &PgPool,
+        term: &str,
+        offset: i64,
+        limit: i64,
+    ) -> Result<UserPage, AppError> {
+        let pattern = format!(
+            "%{}%",
+            term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+        );
+
+        let mut users = query_as!(
+            UserSummary,
+            r#"
+            SELECT id, ethereum_address, email, username, is_active, is_admin, is_verified, created_at
+            FROM users
+            WHERE ethereum_address ILIKE $1 OR email ILIKE $1 OR username ILIKE $1
+            ORDER BY created_at DESC, id DESC
+            OFFSET $2
+            LIMIT $3
+            "#,
+            pattern,
+            offset,
+            limit + 1
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let has_more = users.len() as i64 > limit;
+        users.truncate(limit as usize);
+
+        Ok(UserPage { users, has_more })
+    }
+
     pub async fn get_user_by_id(
         pool: &PgPool,
         user_id: Uuid,
@@ -214,7 +458,7 @@ impl User {
             User,
             r#"
             SELECT id, ethereum_address, email, username, created_at, updated_at,
-                   is_active, is_admin, is_verified, metadata as "metadata: JsonValue"
+                   is_active, is_admin, is_verified, metadata as "metadata: JsonValue", token_epoch
             FROM users
             WHERE id = $1
             "#,
@@ -227,6 +471,19 @@ impl User {
     }
 }
 
+/// Rejects users who are not verified, for actions reserved to verified accounts
+pub async fn require_verified(pool: &PgPool, user_id: Uuid) -> Result<(), AppError> {
+    let verified = User::get_user_by_id(pool, user_id)
+        .await?
+        .is_some_and(|user| user.is_verified());
+
+    if !verified {
+        return Err(AppError::ForbiddenError("This action requires a verified account".to_string()));
+    }
+
+    Ok(())
+}
+
 // impl AuthChallenge {
 //     pub async fn create_challenge_for_addr(
 //         pool: &Pool,
@@ -0,0 +1,132 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, FromRow, PgPool, Type};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::app_error::app_error::AppError;
+use crate::utils::clock::Clock;
+
+/// Why a wallet interaction failed, as classified by the frontend
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "failure_category", rename_all = "snake_case")]
+pub enum FailureCategory {
+    UserRejected,
+    InvalidSignature,
+    WrongChain,
+    WalletError,
+    Timeout,
+    Other,
+}
+
+/// A failed sign-in reported by a client, anonymous by design
+///
+/// Reports carry no address, signature or user id, only what identifies
+/// the wallet software and the kind of failure.
+#[derive(Debug, FromRow, Serialize)]
+pub struct WalletReport {
+    pub id: Uuid,
+    pub wallet_name: String,
+    pub wallet_version: Option<String>,
+    pub chain_id: Option<i64>,
+    pub failure_category: FailureCategory,
+    pub created_at: NaiveDateTime,
+}
+
+/// Failures of one wallet release over a period, for the admin view
+#[derive(Debug, Serialize)]
+pub struct WalletFailureSummary {
+    pub wallet_name: String,
+    pub wallet_version: Option<String>,
+    pub total: i64,
+    pub by_category: BTreeMap<String, i64>,
+}
+
+impl WalletReport {
+    pub async fn record(
+        pool: &PgPool,
+        clock: &dyn Clock,
+        wallet_name: &str,
+        wallet_version: Option<&str>,
+        chain_id: Option<i64>,
+        failure_category: FailureCategory,
+    ) -> Result<(), AppError> {
+        query!(
+            r#"
+            INSERT INTO wallet_diagnostics (id, wallet_name, wallet_version, chain_id, failure_category, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::new_v4(),
+            wallet_name,
+            wallet_version,
+            chain_id,
+            failure_category as FailureCategory,
+            clock.now()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Failure counts per wallet release since `since`, most failing first
+pub async fn summarize_by_wallet(
+    pool: &PgPool,
+    since: NaiveDateTime,
+) -> Result<Vec<WalletFailureSummary>, AppError> {
+    let rows = query!(
+        r#"
+        SELECT wallet_name, wallet_version,
+               failure_category as "failure_category!: FailureCategory",
+               COUNT(*) as "count!"
+        FROM wallet_diagnostics
+        WHERE created_at >= $1
+        GROUP BY wallet_name, wallet_version, failure_category
+        "#,
+        since
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut summaries: BTreeMap<(String, Option<String>), WalletFailureSummary> = BTreeMap::new();
+    for row in rows {
+        let category = serde_json::to_value(row.failure_category)
+            .ok()
+            .and_then(|category| category.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let summary = summaries
+            .entry((row.wallet_name.clone(), row.wallet_version.clone()))
+            .or_insert_with(|| WalletFailureSummary {
+                wallet_name: row.wallet_name,
+                wallet_version: row.wallet_version,
+                total: 0,
+                by_category: BTreeMap::new(),
+            });
+        summary.total += row.count;
+        *summary.by_category.entry(category).or_default() += row.count;
+    }
+
+    let mut summaries: Vec<WalletFailureSummary> = summaries.into_values().collect();
+    summaries.sort_by_key(|summary| std::cmp::Reverse(summary.total));
+
+    Ok(summaries)
+}
+
+pub async fn delete_reports_older_than(
+    pool: &PgPool,
+    cutoff: NaiveDateTime,
+) -> Result<u64, AppError> {
+    let result = query!(
+        r#"
+        DELETE FROM wallet_diagnostics
+        WHERE created_at < $1
+        "#,
+        cutoff
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,118 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+use validator::Validate;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{auth_user::AuthUser, json::Json},
+    models::{
+        api_keys::ApiKey,
+        security_events::{record_event, EventType},
+    },
+    utils::server_utils::extract_client_info,
+    AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RotateApiKeysRequest {
+    /// Name of the key minted in place of the revoked ones, none is minted when absent
+    #[validate(length(min = 1, max = 64))]
+    pub replacement_name: Option<String>,
+}
+
+/// A freshly minted key, the only response carrying its plaintext
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    pub api_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotateApiKeysResponse {
+    pub revoked: u64,
+    pub replacement: Option<CreatedApiKey>,
+}
+
+/// Lists the caller's API keys, revoked ones included, without their plaintext
+pub async fn list_api_keys(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<ApiKey>>, AppError> {
+    let keys = ApiKey::list_for_user(&app_state.pool, auth_user.user_id()).await?;
+
+    Ok(Json(keys))
+}
+
+/// Mints an API key, sent as `Authorization: Bearer ci_...`
+///
+/// The plaintext is returned once and cannot be retrieved afterwards.
+pub async fn create_api_key(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), AppError> {
+    auth_user.require_session()?;
+    payload.validate()?;
+
+    let (key, api_key) = ApiKey::create(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        auth_user.user_id(),
+        payload.name.trim(),
+    ).await?;
+
+    Ok((StatusCode::CREATED, Json(CreatedApiKey { key, api_key })))
+}
+
+/// Revokes all of the caller's API keys at once, e.g. after a suspected leak
+///
+/// With `replacement_name` a single new key is minted in the same
+/// transaction and its plaintext returned. Requests made with a revoked key
+/// get 401 from then on.
+pub async fn rotate_api_keys(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth_user: AuthUser,
+    Json(payload): Json<RotateApiKeysRequest>,
+) -> Result<Json<RotateApiKeysResponse>, AppError> {
+    auth_user.require_session()?;
+    payload.validate()?;
+
+    let rotation = ApiKey::rotate_for_user(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        auth_user.user_id(),
+        payload.replacement_name.as_deref().map(str::trim),
+    ).await?;
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::ApiKeysRotated,
+        auth_user.user_id(),
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "revoked": rotation.revoked,
+            "replacement_key_id": rotation.minted.as_ref().map(|(key, _)| key.id),
+        }),
+    ).await?;
+
+    Ok(Json(RotateApiKeysResponse {
+        revoked: rotation.revoked,
+        replacement: rotation.minted.map(|(key, api_key)| CreatedApiKey { key, api_key }),
+    }))
+}
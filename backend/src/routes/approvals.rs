@@ -0,0 +1,125 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
+use validator::Validate;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::json::Json,
+    models::{
+        auth_challenges::{normalize_ethereum_address, recover_signer, SignatureError},
+        security_events::{record_event, EventType},
+        users::User,
+    },
+    utils::server_utils::extract_client_info,
+    AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ApprovalRequest {
+    #[validate(length(min = 1))]
+    pub message: String,
+    #[validate(length(min = 1, max = 32))]
+    pub signatures: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SignatureResult {
+    pub signature: String,
+    pub valid: bool,
+    pub signer: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApprovalResponse {
+    pub results: Vec<SignatureResult>,
+    pub approved_by: Vec<String>,
+    pub threshold: usize,
+    pub threshold_met: bool,
+}
+
+/// Verifies a batch of signatures over the same approval message
+///
+/// Each signature is checked against the configured approval signers; a signer
+/// is only counted once towards the threshold even if it signed several times.
+pub async fn verify_approvals(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<ApprovalRequest>,
+) -> Result<Json<ApprovalResponse>, AppError> {
+    payload.validate()?;
+
+    let approvals = &app_state.config.approvals;
+    let allowed_signers = approvals.signers
+        .iter()
+        .map(|signer| normalize_ethereum_address(signer))
+        .collect::<Result<HashSet<String>, AppError>>()?;
+
+    let mut results = Vec::with_capacity(payload.signatures.len());
+    let mut approved_by: Vec<String> = Vec::new();
+
+    // The whole batch is one job on the signature workers
+    let (signatures, message) = (payload.signatures.clone(), payload.message.clone());
+    let signers = app_state.signature_verifier
+        .run(move || {
+            Ok(signatures
+                .iter()
+                .map(|signature| {
+                    // A malformed signature only invalidates itself, not the whole batch
+                    recover_signer(signature, &message)
+                        .map_err(SignatureError::recorded)
+                        .ok()
+                })
+                .collect::<Vec<_>>())
+        })
+        .await?;
+
+    for (signature, signer) in payload.signatures.iter().zip(signers) {
+        let signer = signer.filter(|address| allowed_signers.contains(address));
+
+        if let Some(address) = &signer
+            && !approved_by.contains(address) {
+            approved_by.push(address.clone());
+        }
+
+        results.push(SignatureResult {
+            signature: signature.clone(),
+            valid: signer.is_some(),
+            signer,
+        });
+    }
+
+    let threshold_met = !approved_by.is_empty() && approved_by.len() >= approvals.threshold;
+
+    // Record the approval for every signer that has an account
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    for address in &approved_by {
+        if let Some(user) = User::get_user_by_eth_address(&app_state.pool, address).await? {
+            record_event(
+                &app_state.pool,
+                app_state.clock.as_ref(),
+                EventType::MultisigApproval,
+                user.id,
+                client_ip,
+                &user_agent,
+                serde_json::json!({
+                    "message": payload.message,
+                    "approved_by": approved_by,
+                    "threshold": approvals.threshold,
+                    "threshold_met": threshold_met,
+                }),
+            ).await?;
+        }
+    }
+
+    Ok(Json(ApprovalResponse {
+        results,
+        approved_by,
+        threshold: approvals.threshold,
+        threshold_met,
+    }))
+}
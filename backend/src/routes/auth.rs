@@ -0,0 +1,355 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::ipnetwork::IpNetwork;
+use std::{net::SocketAddr, sync::Arc};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::json::Json,
+    models::{
+        auth_challenges::{
+            normalize_ethereum_address, recover_signer, validate_eth_address, AuthChallenge,
+            ChallengeScope, SignatureError, SignaturePurpose, SignatureType,
+        },
+        feature_flags::{ensure_enabled, SIGNATURE_VERIFICATION},
+        rate_limits::check_rate_limit,
+        security_events::{check_login_cooldown, record_event, EventType, REVOKED_BY_ROTATION},
+        sessions::Session,
+        users::User,
+    },
+    services::{
+        lockout::{check_account_lock, lock_after_failed_login},
+        registration::record_account_created,
+        tarpit::delay_failed_login,
+        tokens::{generate_token_pair, validate_refresh_token, TokenPair},
+    },
+    utils::server_utils::extract_client_info,
+    AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct LoginRequest {
+    #[validate(custom(function = "validate_eth_address"))]
+    pub address: String,
+    pub challenge_id: Uuid,
+    #[validate(length(min = 1, max = 132))]
+    pub signature: String,
+    /// How `signature` was made, `personal_sign` unless told otherwise
+    #[serde(default)]
+    pub signature_type: SignatureType,
+    /// Message the wallet signed with `personal_sign`, when rendered by the
+    /// client from the challenge; the challenge's own message otherwise.
+    /// EIP-712 signatures always cover the challenge's typed data.
+    #[validate(length(min = 1, max = 2048))]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RefreshRequest {
+    #[validate(length(min = 1, max = 4096))]
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct VerifySignatureRequest {
+    #[validate(custom(function = "validate_eth_address"))]
+    pub address: String,
+    #[validate(length(min = 1, max = 8192))]
+    pub message: String,
+    #[validate(length(min = 1, max = 132))]
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifySignatureResponse {
+    pub valid: bool,
+    pub recovered_address: String,
+}
+
+/// Signs a wallet in with a login challenge it signed, see `POST /challenge`
+///
+/// The challenge is consumed whether or not the signature matches, so each
+/// one allows a single attempt. The user is created at their first sign-in
+/// and gets an access/refresh token pair. Attempts are rate limited per
+/// client IP, and a signature by another address is recorded as a
+/// `FailedLogin` event of the address's user, after which the address must
+/// wait `auth.login_cooldown_secs` before its next attempt. Too many failures
+/// lock the account, see `lock_after_failed_login`.
+pub async fn login(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<LoginRequest>,
+) -> Result<Json<TokenPair>, AppError> {
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    check_rate_limit(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &client_ip.ip().to_string(),
+        "login",
+        &app_state.config.rate_limits.login,
+        &app_state.config.rate_limits.offenders,
+    ).await?;
+
+    payload.validate()?;
+
+    check_login_cooldown(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &payload.address,
+        app_state.config.auth.login_cooldown_secs,
+    ).await?;
+    check_account_lock(&app_state.pool, app_state.clock.as_ref(), &payload.address).await?;
+
+    let challenge = AuthChallenge::find_active_challenge(
+        app_state.challenge_store.as_ref(),
+        app_state.clock.as_ref(),
+        &payload.address,
+        payload.challenge_id,
+    )
+    .await?
+    .ok_or_else(|| AppError::UnauthorizedError("No active challenge".to_string()))?;
+    let message = match payload.signature_type {
+        SignatureType::PersonalSign => payload.message,
+        SignatureType::Eip712 => None,
+    }
+    .unwrap_or_else(|| challenge.challenge_message.clone());
+    challenge.verify_scope(
+        &ChallengeScope::from_config(&app_state.config),
+        SignaturePurpose::Login,
+        &message,
+        app_state.clock.now(),
+    )?;
+    AuthChallenge::mark_as_used(app_state.challenge_store.as_ref(), challenge.id).await?;
+
+    let (signed, signature_type, signature) = (challenge.clone(), payload.signature_type, payload.signature);
+    let recovered_address = app_state.signature_verifier
+        .run(move || signed.recover_login_signer(signature_type, &signature, &message))
+        .await?;
+    if recovered_address != challenge.ethereum_address {
+        SignatureError::SignerMismatch.recorded();
+        return Err(failed_login(&app_state, &challenge, client_ip, &user_agent).await?);
+    }
+
+    let (user, created) = User::find_or_create_by_address(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &challenge.ethereum_address,
+    ).await?;
+    if created {
+        record_account_created(
+            &app_state.pool,
+            app_state.clock.as_ref(),
+            &app_state.geo_locator,
+            &user,
+            client_ip,
+            &user_agent,
+        ).await?;
+    }
+    if !user.is_active() {
+        return Err(AppError::ForbiddenError("This account is disabled".to_string()));
+    }
+
+    let tokens = generate_token_pair(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &app_state.config.auth,
+        &user,
+        client_ip,
+        &user_agent,
+    ).await?;
+
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::Login,
+        user.id,
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "challenge_id": challenge.id,
+            "new_account": created,
+        }),
+    ).await?;
+
+    Ok(Json(tokens))
+}
+
+/// Records a sign-in refused because another address signed the challenge,
+/// returning the error to answer with once the tarpit delay has passed
+///
+/// Addresses that never signed in have no user to record the event on.
+async fn failed_login(
+    app_state: &AppState,
+    challenge: &AuthChallenge,
+    client_ip: IpNetwork,
+    user_agent: &str,
+) -> Result<AppError, AppError> {
+    if let Some(user) = User::get_user_by_eth_address(&app_state.pool, &challenge.ethereum_address).await? {
+        record_event(
+            &app_state.pool,
+            app_state.clock.as_ref(),
+            EventType::FailedLogin,
+            user.id,
+            client_ip,
+            user_agent,
+            serde_json::json!({
+                "challenge_id": challenge.id,
+                "reason": SignatureError::SignerMismatch.category(),
+            }),
+        ).await?;
+
+        lock_after_failed_login(
+            &app_state.pool,
+            app_state.clock.as_ref(),
+            &app_state.config.lockout,
+            app_state.config.auth.notify_on_lockout.then_some(app_state.notifier.as_ref()),
+            &user,
+            client_ip,
+            user_agent,
+        ).await?;
+    }
+
+    delay_failed_login(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &app_state.config.tarpit,
+        &challenge.ethereum_address,
+        client_ip,
+    ).await?;
+
+    Ok(AppError::UnauthorizedError("Signature was not made by this address".to_string()))
+}
+
+/// Exchanges a refresh token for a new token pair
+///
+/// Refresh tokens are single use: the presented one is blacklisted along
+/// with its session, so replaying it after the owner refreshed fails.
+pub async fn refresh_tokens(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<Json<TokenPair>, AppError> {
+    payload.validate()?;
+
+    let claims = validate_refresh_token(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &app_state.config.auth,
+        &payload.refresh_token,
+    ).await?;
+
+    let rotated = Session::revoke_by_jti(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &claims.jti,
+        REVOKED_BY_ROTATION,
+    ).await?;
+    if !rotated {
+        return Err(AppError::UnauthorizedError("Refresh token was already used".to_string()));
+    }
+
+    let user = User::get_user_by_id(&app_state.pool, claims.sub)
+        .await?
+        .filter(User::is_active)
+        .ok_or_else(|| AppError::UnauthorizedError("Unknown or disabled user".to_string()))?;
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    let tokens = generate_token_pair(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &app_state.config.auth,
+        &user,
+        client_ip,
+        &user_agent,
+    ).await?;
+
+    Ok(Json(tokens))
+}
+
+/// Checks a `personal_sign` signature over an arbitrary message
+///
+/// A utility for integrators: no challenge is consumed and no user, session
+/// or token is created. Requests are rate limited per client IP since each
+/// one costs a public key recovery. A malformed address or signature is a
+/// 400, while a well-formed signature from another address is `valid: false`.
+pub async fn verify_signature(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<VerifySignatureRequest>,
+) -> Result<Json<VerifySignatureResponse>, AppError> {
+    ensure_enabled(&app_state.pool, &app_state.config.feature_flags, SIGNATURE_VERIFICATION).await?;
+
+    let (client_ip, _) = extract_client_info(&headers, addr);
+    check_rate_limit(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &client_ip.ip().to_string(),
+        "verify_signature",
+        &app_state.config.rate_limits.verify_signature,
+        &app_state.config.rate_limits.offenders,
+    ).await?;
+
+    payload.validate()?;
+
+    let expected = normalize_ethereum_address(&payload.address)
+        .map_err(|_| AppError::ValidationError("Invalid address".to_string()))?;
+    let (signature, message) = (payload.signature, payload.message);
+    let recovered_address = app_state.signature_verifier
+        .run(move || {
+            recover_signer(&signature, &message)
+                .map_err(|e| AppError::ValidationError(format!("Malformed signature: {}", e.recorded().category())))
+        })
+        .await?;
+    if recovered_address != expected {
+        SignatureError::SignerMismatch.recorded();
+    }
+
+    Ok(Json(VerifySignatureResponse {
+        valid: recovered_address == expected,
+        recovered_address,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::feature_flags::FeatureFlag, test_support, utils::clock::SystemClock};
+    use sqlx::PgPool;
+
+    #[sqlx::test(migrations = false)]
+    async fn signature_verification_can_be_turned_off(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let app_state = test_support::app_state(pool.clone(), Arc::new(SystemClock));
+        let addr = SocketAddr::new(test_support::client_ip().ip(), 443);
+        let verify = || verify_signature(
+            State(app_state.clone()),
+            ConnectInfo(addr),
+            HeaderMap::new(),
+            Json(VerifySignatureRequest {
+                address: "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23".to_string(),
+                message: "hello".to_string(),
+                signature: "0x1234".to_string(),
+            }),
+        );
+        let set = |enabled| FeatureFlag::set(&pool, &SystemClock, &app_state.config.feature_flags, SIGNATURE_VERIFICATION, enabled);
+
+        // Enabled by default, the malformed signature is a 400
+        assert!(matches!(verify().await, Err(AppError::ValidationError(_))));
+
+        set(false).await.unwrap().unwrap();
+        let error = verify().await.unwrap_err();
+        assert!(matches!(error, AppError::ServiceUnavailableError(_)), "{error:?}");
+        assert_eq!(error.status_code(), axum::http::StatusCode::SERVICE_UNAVAILABLE);
+
+        set(true).await.unwrap().unwrap();
+        assert!(matches!(verify().await, Err(AppError::ValidationError(_))));
+    }
+}
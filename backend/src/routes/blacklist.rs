@@ -0,0 +1,66 @@
+use axum::extract::{Query, State};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{admin_user::AdminUser, json::Json},
+    models::token_blacklist::{blacklist_stats as collect_blacklist_stats, BlacklistStats, BucketWidth},
+    AppState,
+};
+
+/// Default window, in hours, over which blacklist stats are computed
+const DEFAULT_WINDOW_HOURS: i64 = 24;
+const MAX_WINDOW_HOURS: i64 = 24 * 90;
+
+const DEFAULT_PAGE_SIZE: i64 = 48;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct BlacklistStatsQuery {
+    pub window_hours: Option<i64>,
+    #[serde(default)]
+    pub bucket: BucketWidth,
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Counts revoked tokens by reason, e.g. `?window_hours=168&bucket=day`
+///
+/// `security_spike.detected` flags a burst of security revocations in the
+/// current bucket, a possible incident. Buckets are paged with `offset` and
+/// `limit` while `has_more` is true.
+pub async fn blacklist_stats(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+    Query(params): Query<BlacklistStatsQuery>,
+) -> Result<Json<BlacklistStats>, AppError> {
+    let window_hours = params.window_hours.unwrap_or(DEFAULT_WINDOW_HOURS);
+    if !(1..=MAX_WINDOW_HOURS).contains(&window_hours) {
+        return Err(AppError::ValidationError(format!(
+            "window_hours must be between 1 and {}", MAX_WINDOW_HOURS
+        )));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}", MAX_PAGE_SIZE
+        )));
+    }
+    let offset = params.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(AppError::ValidationError("offset cannot be negative".to_string()));
+    }
+
+    let now = app_state.clock.now();
+    let stats = collect_blacklist_stats(
+        &app_state.pool,
+        now,
+        now - chrono::Duration::hours(window_hours),
+        params.bucket,
+        offset,
+        limit,
+    ).await?;
+
+    Ok(Json(stats))
+}
@@ -0,0 +1,301 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap},
+};
+use serde::Deserialize;
+use std::{net::SocketAddr, sync::Arc};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{admin_user::AdminUser, json::Json},
+    models::{
+        auth_challenges::{
+            normalize_ethereum_address, validate_eth_address, AuthChallenge, ChallengePage, ChallengePreview, ChallengeRequest,
+            ChallengeResponse, ChallengeScope, ChallengeSummary,
+        },
+        users::User,
+        rate_limits::check_rate_limit,
+    },
+    utils::{i18n::LocalizedStatement, server_utils::extract_client_info},
+    AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChallengePreviewQuery {
+    #[validate(custom(function = "validate_eth_address"))]
+    pub address: String,
+}
+
+/// Shows the login message a challenge for `address` would carry, so the
+/// wallet prompt can be rendered before a challenge is requested
+///
+/// Nothing is stored and the nonce is a placeholder; the message is
+/// otherwise built exactly like a real one, in the negotiated language.
+/// Previews have their own per-IP limit, separate from sign-in attempts.
+pub async fn preview_challenge(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<ChallengePreviewQuery>,
+) -> Result<Json<ChallengePreview>, AppError> {
+    let (client_ip, _) = extract_client_info(&headers, addr);
+    check_rate_limit(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &client_ip.ip().to_string(),
+        "challenge_preview",
+        &app_state.config.rate_limits.challenge_preview,
+        &app_state.config.rate_limits.offenders,
+    ).await?;
+
+    query.validate()?;
+
+    let accept_language = headers.get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    let statement = LocalizedStatement::negotiate(&app_state.config.auth, accept_language);
+
+    let preview = AuthChallenge::preview_for_addr(
+        app_state.clock.as_ref(),
+        &query.address,
+        &ChallengeScope::from_config(&app_state.config),
+        &statement,
+    )?;
+
+    Ok(Json(preview))
+}
+
+/// Issues a login challenge for an address, to sign and send to `/auth/login`
+///
+/// The message is in the language negotiated from `Accept-Language`. At most
+/// `auth.max_active_challenges` stay outstanding per address, older ones
+/// being evicted. Requests are rate limited per client IP.
+pub async fn create_challenge(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<ChallengeRequest>,
+) -> Result<Json<ChallengeResponse>, AppError> {
+    let (client_ip, _) = extract_client_info(&headers, addr);
+    check_rate_limit(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &client_ip.ip().to_string(),
+        "challenge",
+        &app_state.config.rate_limits.challenge,
+        &app_state.config.rate_limits.offenders,
+    ).await?;
+
+    payload.validate()?;
+
+    let accept_language = headers.get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    let statement = LocalizedStatement::negotiate(&app_state.config.auth, accept_language);
+
+    let challenge = AuthChallenge::create_challenge_for_addr(
+        app_state.challenge_store.as_ref(),
+        app_state.clock.as_ref(),
+        &payload.ethereum_address,
+        &ChallengeScope::from_config(&app_state.config),
+        &statement,
+        app_state.config.auth.max_active_challenges,
+    ).await?;
+
+    Ok(Json(ChallengeResponse::from(challenge)))
+}
+
+/// Replaces any outstanding challenges for an address with a fresh one
+///
+/// Used when a challenge expired while the user was signing, so that older
+/// challenges do not pile up as still valid. Per client IP, refreshes count
+/// against the same limit as `create_challenge`; they are also limited per
+/// address, since each one invalidates the address's outstanding challenges.
+pub async fn refresh_challenge(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<ChallengeRequest>,
+) -> Result<Json<ChallengeResponse>, AppError> {
+    let (client_ip, _) = extract_client_info(&headers, addr);
+    let rate_limits = &app_state.config.rate_limits;
+    check_rate_limit(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &client_ip.ip().to_string(),
+        "challenge",
+        &rate_limits.challenge,
+        &rate_limits.offenders,
+    ).await?;
+
+    payload.validate()?;
+
+    let address = normalize_ethereum_address(&payload.ethereum_address)
+        .map_err(|_| AppError::ValidationError("Invalid address".to_string()))?;
+    check_rate_limit(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &address,
+        "refresh_challenge",
+        &rate_limits.refresh_challenge,
+        &rate_limits.offenders,
+    ).await?;
+
+    let accept_language = headers.get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    let statement = LocalizedStatement::negotiate(&app_state.config.auth, accept_language);
+
+    let challenge = AuthChallenge::refresh_challenge_for_addr(
+        app_state.challenge_store.as_ref(),
+        app_state.clock.as_ref(),
+        &payload.ethereum_address,
+        &ChallengeScope::from_config(&app_state.config),
+        &statement,
+    ).await?;
+
+    Ok(Json(ChallengeResponse::from(challenge)))
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct ChallengeListQuery {
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Lists a user's recent challenges with their status, for diagnosing
+/// failing sign-ins, e.g. `?limit=20`
+///
+/// Nonces and messages are left out, see `ChallengeSummary`. Page with
+/// `offset` while `has_more` is true. With the Redis store only outstanding
+/// challenges are listed.
+pub async fn list_user_challenges(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Query(params): Query<ChallengeListQuery>,
+) -> Result<Json<ChallengePage>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}", MAX_PAGE_SIZE
+        )));
+    }
+
+    let offset = params.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(AppError::ValidationError("offset cannot be negative".to_string()));
+    }
+
+    let user = User::get_user_by_id(&app_state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+    let mut challenges = app_state.challenge_store
+        .list_for_address(&user.ethereum_address.to_lowercase(), offset, limit + 1)
+        .await?;
+    let has_more = challenges.len() as i64 > limit;
+    challenges.truncate(limit as usize);
+
+    let now = app_state.clock.now();
+    Ok(Json(ChallengePage {
+        challenges: challenges.into_iter()
+            .map(|challenge| ChallengeSummary::new(challenge, now))
+            .collect(),
+        has_more,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use sqlx::PgPool;
+
+    use crate::{test_support, utils::clock::MockClock};
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    fn request() -> Json<ChallengeRequest> {
+        Json(ChallengeRequest { ethereum_address: ADDRESS.to_string() })
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn refreshes_count_against_the_challenge_limit(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = Arc::new(MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap()));
+        let mut config = test_support::config();
+        config.rate_limits.challenge.max_attempts = 2;
+        let app_state = test_support::app_state_with(pool, clock, config);
+        let addr = SocketAddr::new(test_support::client_ip().ip(), 443);
+
+        create_challenge(State(app_state.clone()), ConnectInfo(addr), HeaderMap::new(), request()).await.unwrap();
+        refresh_challenge(State(app_state.clone()), ConnectInfo(addr), HeaderMap::new(), request()).await.unwrap();
+
+        // Alternating between both endpoints does not double the per-IP allowance
+        let result = refresh_challenge(State(app_state.clone()), ConnectInfo(addr), HeaderMap::new(), request()).await;
+        assert!(matches!(result, Err(AppError::RateLimitError(..))));
+        let result = create_challenge(State(app_state), ConnectInfo(addr), HeaderMap::new(), request()).await;
+        assert!(matches!(result, Err(AppError::RateLimitError(..))));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn refreshes_are_also_limited_per_address(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = Arc::new(MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap()));
+        let mut config = test_support::config();
+        config.rate_limits.refresh_challenge.max_attempts = 1;
+        let app_state = test_support::app_state_with(pool, clock, config);
+
+        let first = SocketAddr::new(test_support::client_ip().ip(), 443);
+        refresh_challenge(State(app_state.clone()), ConnectInfo(first), HeaderMap::new(), request()).await.unwrap();
+
+        // Another IP cannot refresh the same address again within the window
+        let second = SocketAddr::new("198.51.100.1".parse().unwrap(), 443);
+        let result = refresh_challenge(State(app_state), ConnectInfo(second), HeaderMap::new(), request()).await;
+        assert!(matches!(result, Err(AppError::RateLimitError(..))));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn listed_challenges_are_those_of_the_user(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = Arc::new(MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap()));
+        let app_state = test_support::app_state(pool.clone(), clock.clone());
+        let addr = SocketAddr::new(test_support::client_ip().ip(), 443);
+        const OTHER: &str = "0x0000000000000000000000000000000000000002";
+        let user = test_support::create_user(&pool, clock.as_ref(), ADDRESS).await;
+        let other = test_support::create_user(&pool, clock.as_ref(), OTHER).await;
+
+        for address in [ADDRESS, OTHER, ADDRESS] {
+            let request = Json(ChallengeRequest { ethereum_address: address.to_string() });
+            create_challenge(State(app_state.clone()), ConnectInfo(addr), HeaderMap::new(), request).await.unwrap();
+        }
+
+        let list = |user_id: Uuid, offset: Option<i64>, limit: Option<i64>| {
+            list_user_challenges(State(app_state.clone()), test_support::admin(&other), Path(user_id), Query(ChallengeListQuery { offset, limit }))
+        };
+
+        let Json(page) = list(user.id, None, None).await.unwrap();
+        assert_eq!(page.challenges.len(), 2);
+        assert!(page.challenges.iter().all(|challenge| challenge.ethereum_address == ADDRESS));
+        assert!(!page.has_more);
+
+        let Json(page) = list(other.id, None, None).await.unwrap();
+        assert_eq!(page.challenges.len(), 1);
+        assert_eq!(page.challenges[0].ethereum_address, OTHER);
+
+        // Paging stays within the user's challenges
+        let Json(first) = list(user.id, Some(0), Some(1)).await.unwrap();
+        let Json(second) = list(user.id, Some(1), Some(1)).await.unwrap();
+        assert!(first.has_more);
+        assert!(!second.has_more);
+        assert_eq!(second.challenges.len(), 1);
+        assert_ne!(first.challenges[0].id, second.challenges[0].id);
+        assert_eq!(second.challenges[0].ethereum_address, ADDRESS);
+
+        let result = list(Uuid::new_v4(), None, None).await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))));
+    }
+}
@@ -0,0 +1,99 @@
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{HeaderMap, StatusCode},
+};
+use serde::Deserialize;
+use std::{net::SocketAddr, sync::Arc};
+use validator::Validate;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{admin_user::AdminUser, json::Json},
+    models::{
+        feature_flags::{ensure_enabled, WALLET_TELEMETRY},
+        rate_limits::check_rate_limit,
+        wallet_diagnostics::{summarize_by_wallet, FailureCategory, WalletFailureSummary, WalletReport},
+    },
+    utils::server_utils::extract_client_info,
+    AppState,
+};
+
+const DEFAULT_SUMMARY_DAYS: i64 = 7;
+
+/// Unknown fields are rejected so that clients cannot attach addresses or
+/// signatures to a report by mistake
+#[derive(Debug, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct WalletReportRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub wallet_name: String,
+    #[validate(length(min = 1, max = 32))]
+    pub wallet_version: Option<String>,
+    #[validate(range(min = 1))]
+    pub chain_id: Option<i64>,
+    pub failure_category: FailureCategory,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WalletSummaryQuery {
+    pub days: Option<i64>,
+}
+
+/// Records an anonymous report of a failed wallet sign-in, e.g.
+/// `{"wallet_name": "MetaMask", "chain_id": 1, "failure_category": "wrong_chain"}`
+///
+/// Unauthenticated since the failure usually happens before a session
+/// exists, so reports are rate limited per client IP. The client IP is only
+/// used for the rate limit and is not stored with the report.
+pub async fn report_wallet_failure(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<WalletReportRequest>,
+) -> Result<StatusCode, AppError> {
+    ensure_enabled(&app_state.pool, &app_state.config.feature_flags, WALLET_TELEMETRY).await?;
+
+    let (client_ip, _) = extract_client_info(&headers, addr);
+    check_rate_limit(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &client_ip.ip().to_string(),
+        "wallet_telemetry",
+        &app_state.config.rate_limits.wallet_telemetry,
+        &app_state.config.rate_limits.offenders,
+    ).await?;
+
+    payload.validate()?;
+
+    WalletReport::record(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        payload.wallet_name.trim(),
+        payload.wallet_version.as_deref().map(str::trim),
+        payload.chain_id,
+        payload.failure_category,
+    ).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Wallet failure counts over the last `days` days (default 7), grouped by
+/// wallet name and version with a breakdown by failure category
+pub async fn list_wallet_failures(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+    Query(params): Query<WalletSummaryQuery>,
+) -> Result<Json<Vec<WalletFailureSummary>>, AppError> {
+    let days = params.days.unwrap_or(DEFAULT_SUMMARY_DAYS);
+    let retention_days = app_state.config.retention.diagnostics_retention_days;
+    if !(1..=retention_days).contains(&days) {
+        return Err(AppError::ValidationError(format!(
+            "days must be between 1 and {}", retention_days
+        )));
+    }
+
+    let since = app_state.clock.now() - chrono::Duration::days(days);
+    let summaries = summarize_by_wallet(&app_state.pool, since).await?;
+
+    Ok(Json(summaries))
+}
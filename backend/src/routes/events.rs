@@ -0,0 +1,120 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use validator::Validate;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{admin_user::AdminUser, json::Json},
+    models::{
+        auth_challenges::validate_eth_address,
+        event_erasures::EventErasure,
+        security_events::{list_events_after, list_events_at_offset, EventCursor, EventPage},
+    },
+    services::audit_export::export_signed_events,
+    AppState,
+};
+
+/// Downloads the security event log as signed JSON Lines
+///
+/// See `services::audit_export` for the format and how to verify it.
+pub async fn export_events(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+) -> Result<impl IntoResponse, AppError> {
+    let export = export_signed_events(app_state.pool.clone(), &app_state.config.audit.signing_key)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_static("attachment; filename=\"security_events.jsonl\""),
+    );
+
+    Ok((StatusCode::OK, headers, Body::from_stream(export)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct EraseEventsRequest {
+    #[validate(custom(function = "validate_eth_address"))]
+    pub ethereum_address: String,
+    /// Why the events are erased, e.g. the reference of the erasure request
+    #[validate(length(min = 1, max = 1000))]
+    pub reason: String,
+}
+
+/// Erases the security events of an address, e.g. for a GDPR erasure request
+///
+/// The erasure is recorded in the append-only `event_erasures` log before
+/// any event is touched; see `EventErasure::erase_address`.
+pub async fn erase_events(
+    State(app_state): State<Arc<AppState>>,
+    admin: AdminUser,
+    Json(payload): Json<EraseEventsRequest>,
+) -> Result<Json<EventErasure>, AppError> {
+    payload.validate()?;
+
+    let erasure = EventErasure::erase_address(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &payload.ethereum_address,
+        admin.user_id(),
+        payload.reason.trim(),
+    ).await?;
+
+    Ok(Json(erasure))
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct EventListQuery {
+    pub cursor: Option<String>,
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Lists security events newest first, e.g. `?cursor=<next_cursor>&limit=100`
+///
+/// Follow `next_cursor` while `has_more` is true. `offset` is still accepted
+/// for small logs but cannot be combined with a cursor.
+pub async fn list_events(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+    Query(params): Query<EventListQuery>,
+) -> Result<Json<EventPage>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}", MAX_PAGE_SIZE
+        )));
+    }
+
+    let page = match (params.cursor, params.offset) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::ValidationError(
+                "cursor and offset cannot be combined".to_string()
+            ));
+        }
+        (Some(cursor), None) => {
+            let cursor = EventCursor::decode(&cursor)?;
+            list_events_after(&app_state.pool, Some(&cursor), limit).await?
+        }
+        (None, Some(offset)) if offset < 0 => {
+            return Err(AppError::ValidationError("offset cannot be negative".to_string()));
+        }
+        (None, Some(offset)) => list_events_at_offset(&app_state.pool, offset, limit).await?,
+        (None, None) => list_events_after(&app_state.pool, None, limit).await?,
+    };
+
+    Ok(Json(page))
+}
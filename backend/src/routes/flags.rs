@@ -0,0 +1,47 @@
+use axum::extract::State;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{admin_user::AdminUser, json::Json},
+    models::feature_flags::FeatureFlag,
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct SetFlagRequest {
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// Lists the feature flags with their effective state
+pub async fn list_flags(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+) -> Result<Json<Vec<FeatureFlag>>, AppError> {
+    let flags = FeatureFlag::list(&app_state.pool, &app_state.config.feature_flags).await?;
+
+    Ok(Json(flags))
+}
+
+/// Turns a feature on or off at runtime, without a redeploy
+pub async fn set_flag(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+    Json(payload): Json<SetFlagRequest>,
+) -> Result<Json<FeatureFlag>, AppError> {
+    let flag = FeatureFlag::set(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &app_state.config.feature_flags,
+        &payload.name,
+        payload.enabled,
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFoundError(format!("Unknown feature flag {}", payload.name)))?;
+
+    println!("Feature flag {} {}", flag.name, if flag.enabled { "enabled" } else { "disabled" });
+
+    Ok(Json(flag))
+}
@@ -0,0 +1,178 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::admin_user::AdminUser,
+    models::{
+        auth_challenges::AuthChallenge,
+        challenge_store::ChallengeStore,
+        security_events::count_logins_since,
+    },
+    services::{chain::ChainHead, pool_monitor::PoolStatus},
+    utils::clock::Clock,
+    AppState,
+};
+
+/// Default window, in minutes, over which auth health is computed
+pub const AUTH_HEALTH_WINDOW_MINUTES: i64 = 60;
+
+/// Widest window, in minutes, auth health can be computed over, one year
+const MAX_AUTH_HEALTH_WINDOW_MINUTES: i64 = 365 * 24 * 60;
+
+#[derive(Debug, Deserialize)]
+pub struct AuthHealthQuery {
+    pub window_minutes: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthHealth {
+    pub window_minutes: i64,
+    pub logins: i64,
+    pub failed_logins: i64,
+    /// Ratio of `Login` to `Login + FailedLogin`, absent when there were no attempts
+    pub success_ratio: Option<f64>,
+    pub challenges_created: i64,
+    pub challenges_used: i64,
+}
+
+/// Body of `GET /status`, a time reference and chain heads for client countdowns
+#[derive(Debug, Serialize)]
+pub struct ServerStatus {
+    /// Current UTC time of the server, which all expiries are computed against
+    pub server_time: NaiveDateTime,
+    pub chains: Vec<ChainHead>,
+}
+
+/// Body of `GET /health`, also served while in maintenance mode
+pub fn health_response(healthy: bool) -> impl IntoResponse {
+    let (status, label) = if healthy {
+        (StatusCode::OK, "healthy")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+    };
+
+    (status, Json(serde_json::json!({
+        "status": label,
+        "database": if healthy { "up" } else { "down" },
+    })))
+}
+
+/// Reports whether the server can reach its database
+pub async fn health_check(
+    State(app_state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let healthy = sqlx::query("SELECT 1")
+        .execute(&app_state.pool)
+        .await
+        .is_ok();
+
+    health_response(healthy)
+}
+
+/// Reports server time and chain heads, an unreachable chain only marks itself unhealthy
+pub async fn server_status(
+    State(app_state): State<Arc<AppState>>,
+) -> Json<ServerStatus> {
+    let chains = vec![app_state.chain.head().await];
+
+    Json(ServerStatus {
+        server_time: app_state.clock.now(),
+        chains,
+    })
+}
+
+/// Body of `GET /ready`, 503 while the instance is draining
+///
+/// `database_pool` is null while no pool exists, in maintenance mode.
+pub fn readiness_response(ready: bool, database_pool: Option<PoolStatus>) -> impl IntoResponse {
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (status, Json(serde_json::json!({
+        "ready": ready,
+        "state": if ready { "ready" } else { "draining" },
+        "database_pool": database_pool,
+    })))
+}
+
+/// Tells the load balancer whether to route new requests to this instance
+pub async fn readiness_check(
+    State(app_state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    readiness_response(app_state.readiness.is_ready(), Some(app_state.pool_monitor.status()))
+}
+
+/// Computes login and challenge statistics over the last `window_minutes`
+pub async fn collect_auth_health(
+    pool: &PgPool,
+    challenge_store: &dyn ChallengeStore,
+    clock: &dyn Clock,
+    window_minutes: i64,
+) -> Result<AuthHealth, AppError> {
+    let since = clock.now() - chrono::Duration::minutes(window_minutes);
+
+    let (logins, failed_logins) = count_logins_since(pool, since).await?;
+    let (challenges_created, challenges_used) = AuthChallenge::count_since(challenge_store, since).await?;
+
+    let attempts = logins + failed_logins;
+    let success_ratio = (attempts > 0).then(|| logins as f64 / attempts as f64);
+
+    Ok(AuthHealth {
+        window_minutes,
+        logins,
+        failed_logins,
+        success_ratio,
+        challenges_created,
+        challenges_used,
+    })
+}
+
+/// Reports the login success ratio and challenge usage for operators
+pub async fn auth_health(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+    Query(params): Query<AuthHealthQuery>,
+) -> Result<Json<AuthHealth>, AppError> {
+    let window_minutes = check_window_minutes(params.window_minutes.unwrap_or(AUTH_HEALTH_WINDOW_MINUTES))?;
+
+    let health = collect_auth_health(
+        &app_state.pool,
+        app_state.challenge_store.as_ref(),
+        app_state.clock.as_ref(),
+        window_minutes,
+    ).await?;
+
+    Ok(Json(health))
+}
+
+fn check_window_minutes(window_minutes: i64) -> Result<i64, AppError> {
+    if !(1..=MAX_AUTH_HEALTH_WINDOW_MINUTES).contains(&window_minutes) {
+        return Err(AppError::ValidationError(format!(
+            "window_minutes must be between 1 and {}", MAX_AUTH_HEALTH_WINDOW_MINUTES
+        )));
+    }
+    Ok(window_minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_minutes_is_bounded() {
+        assert_eq!(check_window_minutes(1).unwrap(), 1);
+        assert_eq!(check_window_minutes(MAX_AUTH_HEALTH_WINDOW_MINUTES).unwrap(), MAX_AUTH_HEALTH_WINDOW_MINUTES);
+        // i64::MAX minutes would overflow the window arithmetic
+        for window_minutes in [0, -1, MAX_AUTH_HEALTH_WINDOW_MINUTES + 1, i64::MAX] {
+            assert!(matches!(check_window_minutes(window_minutes), Err(AppError::ValidationError(_))), "{window_minutes}");
+        }
+    }
+}
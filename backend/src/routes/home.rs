@@ -1,43 +1,59 @@
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, State},
     http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{Html, IntoResponse}
 };
 use axum_csrf::CsrfToken;
-use std::{fs, path::Path, sync::Arc};
+use std::{io, net::SocketAddr, sync::Arc};
 
 use crate::{
     app_error::app_error::AppError, 
-    config::app_config::get_serializable_frontend_config, 
+    config::app_config::{get_serializable_frontend_config, ApiUrlSource},
+    utils::cookie_security::CookiePolicy,
     AppState
 };
 
 /// Serves the home page with injected frontend configuration
 ///
-/// This function reads the index.html file, injects the backend configuration
-/// (including the CSRF token) and returns the HTML page to the client.
+/// This function picks the index template for the request (see
+/// `IndexTemplates`), injects the backend configuration (including the CSRF
+/// token) and returns the HTML page to the client.
 #[axum::debug_handler]
 pub async fn serve_home(
     State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
     csrf_token: CsrfToken,
 ) -> Result<impl IntoResponse, AppError> {
-    // Build the complete path to the index.html file
-    let index_path = format!("{}/index.html", app_state.vue_dist_path);
-    
-    // Read the HTML file content
-    let mut html_content = fs::read_to_string(Path::new(&index_path))
-        .map_err(|e| AppError::ServerError(format!(
-            "Failed to read index.html: {}", e
-        )))?;
+    // Pick and read the index template of the request
+    let templates = &app_state.index_templates;
+    let variant = templates.select(&request_headers);
+    let template = match templates.load(variant) {
+        Ok(template) => template,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && app_state.config.server.dev_mode => {
+            let index_path = templates.template_path(variant);
+            return Ok((StatusCode::OK, create_security_headers()?, Html(dev_placeholder_page(&index_path))));
+        }
+        Err(e) => {
+            return Err(AppError::ServerError(format!("Failed to read index.html: {}", e)));
+        }
+    };
     
     // Extract the CSRF token
     let token = csrf_token.authenticity_token()
         .map_err(|_| AppError::ServerError("Failed to retrieve CSRF token".to_string()))?;
     
+    let frontend = &app_state.config.frontend;
+    let request_origin = (frontend.api_url_source == ApiUrlSource::RequestHost)
+        .then(|| request_origin(&app_state, &request_headers, addr))
+        .flatten();
+    let api_url = frontend.resolve_api_url(request_origin.as_deref());
+
     // Get the frontend configuration with the CSRF token
     let frontend_config = get_serializable_frontend_config(
-        &app_state.config.frontend, 
-        token
+        frontend,
+        token,
+        api_url,
     );
     
     // Serialize the configuration to JSON
@@ -47,18 +63,60 @@ pub async fn serve_home(
         )))?;
     
     // Inject the configuration into the HTML by replacing the placeholder
-    html_content = html_content.replace(
+    let html_content = template.replace(
         "<!-- BACKEND_CONFIG -->", 
         &format!("<script>window.BACKEND_CONFIG = {};</script>", config_json)
     );
     
     // Configure HTTP headers for the response
-    let headers = create_security_headers()?;
+    let mut headers = create_security_headers()?;
+    if let Some(header) = templates.variant_header() {
+        headers.insert(header::VARY, HeaderValue::from_name(header.clone()));
+    }
+    if let Some(links) = templates.preload_links() {
+        headers.insert(header::LINK, links.clone());
+    }
     
     // Return the complete response
     Ok((StatusCode::OK, headers, Html(html_content)))
 }
 
+/// Origin the page was requested on, as `scheme://host[:port]`
+///
+/// `X-Forwarded-Host` and `X-Forwarded-Proto` are only honored from the
+/// proxies trusted in `[server]`, like for secure cookies.
+fn request_origin(app_state: &AppState, headers: &HeaderMap, peer: SocketAddr) -> Option<String> {
+    let policy = CookiePolicy::new(&app_state.config.server, false);
+    let forwarded_host = policy.trusts_forwarded_headers(peer)
+        .then(|| headers.get("x-forwarded-host"))
+        .flatten();
+    let host = forwarded_host
+        .or_else(|| headers.get(header::HOST))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|host| !host.is_empty())?;
+    let scheme = if policy.is_secure(headers, peer) { "https" } else { "http" };
+
+    Some(format!("{}://{}", scheme, host))
+}
+
+/// Page served in `server.dev_mode` when the frontend has not been built
+fn dev_placeholder_page(index_path: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n\
+         <html><head><meta charset=\"utf-8\"><title>crypto_invoice backend</title></head>\n\
+         <body>\n\
+         <h1>Backend is running</h1>\n\
+         <p>No frontend build was found at <code>{}</code>. Build the Vue app or set \
+         <code>VUE_DIST_PATH</code> to serve it from here.</p>\n\
+         <p>The API is served under <code>/api</code>; see <code>/health</code> and \
+         <code>/api/tokens</code> for a quick check.</p>\n\
+         </body></html>\n",
+        index_path
+    )
+}
+
 /// Creates security headers for HTML responses
 fn create_security_headers() -> Result<HeaderMap, AppError> {
     let mut headers = HeaderMap::new();
@@ -89,3 +147,103 @@ fn create_security_headers() -> Result<HeaderMap, AppError> {
     
     Ok(headers)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::app_config::EarlyHints,
+        services::{early_hints::preload_link_header, index_templates::IndexTemplates},
+        test_support,
+        utils::clock::SystemClock,
+    };
+    use axum::{body::Body, extract::Request, response::Response, routing::get, Router};
+    use axum_csrf::{CsrfConfig, CsrfLayer};
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        pairs.iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    fn app_state(pool: PgPool) -> Arc<AppState> {
+        let mut config = test_support::config();
+        config.server.trust_proxy_tls = true;
+        config.server.trusted_proxies = vec!["10.0.0.0/8".to_string()];
+        test_support::app_state_with(pool, Arc::new(SystemClock), config)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn request_origin_uses_the_host_of_direct_clients(pool: PgPool) {
+        let app_state = app_state(pool);
+        let client: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+
+        let request = headers(&[
+            ("host", "app.example.com:8080"),
+            ("x-forwarded-host", "evil.example"),
+            ("x-forwarded-proto", "https"),
+        ]);
+        assert_eq!(request_origin(&app_state, &request, client).as_deref(), Some("http://app.example.com:8080"));
+        assert_eq!(request_origin(&app_state, &HeaderMap::new(), client), None);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn request_origin_honors_forwarded_headers_of_trusted_proxies(pool: PgPool) {
+        let app_state = app_state(pool);
+        let proxy: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+
+        let request = headers(&[
+            ("host", "backend.internal"),
+            ("x-forwarded-host", "app.example.com, edge.example.com"),
+            ("x-forwarded-proto", "https"),
+        ]);
+        assert_eq!(request_origin(&app_state, &request, proxy).as_deref(), Some("https://app.example.com"));
+        // Falls back to Host when the proxy does not forward it
+        let request = headers(&[("host", "backend.internal")]);
+        assert_eq!(request_origin(&app_state, &request, proxy).as_deref(), Some("http://backend.internal"));
+    }
+
+    /// The home page, built from a dist directory with early hints `enabled`
+    async fn home(pool: PgPool, enabled: bool) -> Response {
+        let dist = std::env::temp_dir().join(format!("home-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dist.join("assets")).unwrap();
+        std::fs::write(dist.join("index.html"), "<head><!-- BACKEND_CONFIG --></head>").unwrap();
+        std::fs::write(dist.join("assets/index-3f2a.js"), "").unwrap();
+        let dist = dist.to_string_lossy().into_owned();
+
+        let mut config = test_support::config();
+        config.frontend.early_hints = EarlyHints { enabled, preload: vec!["assets/index-*.js".to_string()] };
+        let preload_links = preload_link_header(&dist, &config.frontend.early_hints).unwrap();
+        let templates = IndexTemplates::new(&dist, &config.frontend, "test", false).with_preload_links(preload_links);
+        let mut app_state = Arc::into_inner(test_support::app_state_with(pool, Arc::new(SystemClock), config)).unwrap();
+        app_state.index_templates = Arc::new(templates);
+
+        let app = Router::new()
+            .route("/", get(serve_home))
+            .layer(CsrfLayer::new(CsrfConfig::default()))
+            .with_state(Arc::new(app_state));
+        let mut request = Request::get("/").header(header::HOST, "localhost:8080").body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 40000))));
+        app.oneshot(request).await.unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn home_page_announces_preloads_when_enabled(pool: PgPool) {
+        let response = home(pool, true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::LINK], "</assets/index-3f2a.js>; rel=modulepreload");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("window.BACKEND_CONFIG = {"), "{body}");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn home_page_has_no_link_header_when_disabled(pool: PgPool) {
+        let response = home(pool, false).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(header::LINK));
+    }
+}
@@ -0,0 +1,884 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::types::ipnetwork::IpNetwork;
+use std::{net::SocketAddr, sync::Arc};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{admin_user::AdminUser, auth_user::AuthUser, json::Json},
+    models::{
+        auth_challenges::{
+            check_signer, normalize_ethereum_address, AuthChallenge, ChallengeResponse,
+            ChallengeScope, SignatureError, SignaturePurpose,
+        },
+        feature_flags::{ensure_enabled, INVOICE_ACCEPTANCE, INVOICE_SHARING},
+        invoice_shares::InvoiceShare,
+        session_keys::{acceptance_message, SessionKey},
+        invoices::{
+            normalize_tx_hash, Invoice, InvoiceInput, InvoicePage, InvoiceRole, InvoiceStatus, InvoiceView,
+        },
+        security_events::{record_event, EventType},
+    },
+    services::{
+        fee_estimate::{estimate_payment_fee, FeeEstimate},
+        invoice_export::export_invoices_csv,
+        payments::{settle_payment, spawn_payment_watcher, Requester},
+        tokens::{decode_share_token, mint_share_token},
+    },
+    utils::{
+        conditional::{check_if_match, is_not_modified, validator_headers},
+        server_utils::extract_client_info,
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmPaymentRequest {
+    pub tx_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvoiceRequest {
+    pub challenge_id: Uuid,
+    pub signature: String,
+    /// Message the wallet signed, when rendered by the client from the
+    /// challenge; the challenge's own message otherwise
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionKeyAcceptRequest {
+    pub session_key_id: Uuid,
+    /// Session key's `personal_sign` of `session_keys::acceptance_message`
+    pub signature: String,
+}
+
+/// Largest number of invoices checked against the chain in one reconcile call
+const MAX_RECONCILE_BATCH: i64 = 200;
+const DEFAULT_RECONCILE_BATCH: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct ReconcileRequest {
+    /// `next_cursor` of the previous call, omitted to start from the beginning
+    pub cursor: Option<Uuid>,
+    pub batch_size: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReconcileResponse {
+    pub checked: usize,
+    pub changed: usize,
+    /// Invoices the contract does not know about, left untouched
+    pub missing: usize,
+    pub next_cursor: Option<Uuid>,
+}
+
+const DEFAULT_INVOICE_PAGE_SIZE: i64 = 50;
+const MAX_INVOICE_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct InvoiceListQuery {
+    pub status: Option<InvoiceStatus>,
+    pub role: Option<InvoiceRole>,
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetadataSearchQuery {
+    pub key: String,
+    pub value: String,
+}
+
+/// Shortest lifetime a share link can be given
+const MIN_SHARE_EXPIRES_IN_SECS: u64 = 60;
+
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct ShareInvoiceRequest {
+    /// Lifetime of the link, `share_token_expires_in` when absent and never above it
+    pub expires_in_secs: Option<u64>,
+    /// Views allowed before the link is used up, unlimited when absent
+    #[validate(range(min = 1))]
+    pub max_views: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareInvoiceResponse {
+    pub share_id: Uuid,
+    pub token: String,
+    pub url: String,
+    pub expires_at: NaiveDateTime,
+    pub max_views: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedInvoiceResponse {
+    /// Without the parties' identities and the issuer's data
+    pub invoice: InvoiceView,
+    /// EIP-681 payment URI the payer can scan as a QR code
+    pub payment_uri: String,
+}
+
+/// Issues the challenge the invoice recipient signs to accept the invoice
+pub async fn create_acceptance_challenge(
+    State(app_state): State<Arc<AppState>>,
+    Path(invoice_id): Path<Uuid>,
+) -> Result<Json<ChallengeResponse>, AppError> {
+    ensure_enabled(&app_state.pool, &app_state.config.feature_flags, INVOICE_ACCEPTANCE).await?;
+
+    let invoice = find_invoice(&app_state, invoice_id).await?;
+    let recipient = invoice.recipient_address
+        .ok_or_else(|| AppError::ValidationError("Invoice has no designated recipient".to_string()))?;
+
+    let challenge = AuthChallenge::create_acceptance_challenge(
+        app_state.challenge_store.as_ref(),
+        app_state.clock.as_ref(),
+        &recipient,
+        &ChallengeScope::from_config(&app_state.config),
+        invoice.id,
+    ).await?;
+
+    Ok(Json(ChallengeResponse::from(challenge)))
+}
+
+/// Accepts an invoice on behalf of its designated recipient
+///
+/// The recipient proves control of the recipient address by signing the
+/// acceptance challenge issued for this invoice.
+pub async fn accept_invoice(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(invoice_id): Path<Uuid>,
+    Json(payload): Json<AcceptInvoiceRequest>,
+) -> Result<(HeaderMap, Json<InvoiceView>), AppError> {
+    ensure_enabled(&app_state.pool, &app_state.config.feature_flags, INVOICE_ACCEPTANCE).await?;
+
+    let invoice = find_invoice(&app_state, invoice_id).await?;
+    check_if_match(&headers, &invoice.etag())?;
+    let recipient = invoice.recipient_address
+        .ok_or_else(|| AppError::ValidationError("Invoice has no designated recipient".to_string()))?;
+
+    let challenge = AuthChallenge::find_active_challenge(
+        app_state.challenge_store.as_ref(),
+        app_state.clock.as_ref(),
+        &recipient,
+        payload.challenge_id,
+    )
+    .await?;
+    let Some(challenge) = challenge else {
+        let (client_ip, user_agent) = extract_client_info(&headers, addr);
+        return Err(inactive_challenge_error(
+            &app_state,
+            invoice.id,
+            invoice.created_by,
+            &recipient,
+            payload.challenge_id,
+            client_ip,
+            &user_agent,
+        ).await?);
+    };
+
+    let message = payload.message.clone().unwrap_or_else(|| challenge.challenge_message.clone());
+    challenge.verify_scope(
+        &ChallengeScope::from_config(&app_state.config),
+        SignaturePurpose::AcceptInvoice,
+        &message,
+        app_state.clock.now(),
+    )?;
+
+    // The challenge must have been issued, and signed, for this very invoice
+    let invoice_id = invoice.id.to_string();
+    if !challenge.challenge_message.contains(&invoice_id) || !message.contains(&invoice_id) {
+        return Err(AppError::UnauthorizedError("Challenge was not issued for this invoice".to_string()));
+    }
+
+    let (signature, expected) = (payload.signature.clone(), recipient.clone());
+    app_state.signature_verifier
+        .run(move || check_acceptance_signer(&signature, &message, &expected, "Signer is not the invoice recipient"))
+        .await?;
+
+    AuthChallenge::mark_as_used(app_state.challenge_store.as_ref(), challenge.id).await?;
+
+    let invoice = Invoice::mark_accepted(&app_state.pool, app_state.clock.as_ref(), invoice.id)
+        .await?
+        .ok_or_else(|| AppError::ConflictError("Invoice is not awaiting acceptance".to_string()))?;
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::InvoiceAccepted,
+        invoice.created_by,
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "invoice_id": invoice.id,
+            "display_number": invoice.display_number,
+            "recipient_address": recipient,
+        }),
+    ).await?;
+
+    let validators = validator_headers(&invoice.etag(), invoice.updated_at);
+    Ok((validators, Json(invoice.into_view(Some(InvoiceRole::Recipient)))))
+}
+
+/// Accepts an invoice with a session key its recipient authorized
+///
+/// The session key signs `session_keys::acceptance_message` in place of the
+/// recipient's wallet signing a challenge, and the invoice amount is charged
+/// to the key's spending limit.
+pub async fn accept_invoice_with_session_key(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(invoice_id): Path<Uuid>,
+    Json(payload): Json<SessionKeyAcceptRequest>,
+) -> Result<(HeaderMap, Json<InvoiceView>), AppError> {
+    ensure_enabled(&app_state.pool, &app_state.config.feature_flags, INVOICE_ACCEPTANCE).await?;
+
+    let invoice = find_invoice(&app_state, invoice_id).await?;
+    check_if_match(&headers, &invoice.etag())?;
+    let recipient = invoice.recipient_address.as_deref()
+        .ok_or_else(|| AppError::ValidationError("Invoice has no designated recipient".to_string()))?;
+
+    let key = SessionKey::find_usable(&app_state.pool, app_state.clock.as_ref(), payload.session_key_id)
+        .await?
+        .ok_or_else(|| AppError::UnauthorizedError("Session key is not authorized, or was revoked or expired".to_string()))?;
+    if key.owner_address != normalize_ethereum_address(recipient)? {
+        return Err(AppError::ForbiddenError("Session key does not belong to the invoice recipient".to_string()));
+    }
+
+    let message = acceptance_message(&invoice, key.id, app_state.config.ethereum.chain_id);
+    let (signature, expected) = (payload.signature.clone(), key.session_address.clone());
+    app_state.signature_verifier
+        .run(move || check_acceptance_signer(&signature, &message, &expected, "Signer is not the session key"))
+        .await?;
+
+    let invoice = key.accept_invoice(&app_state.pool, app_state.clock.as_ref(), &invoice)
+        .await?
+        .ok_or_else(|| AppError::ConflictError("Invoice is not awaiting acceptance".to_string()))?;
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::InvoiceAccepted,
+        invoice.created_by,
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "invoice_id": invoice.id,
+            "display_number": invoice.display_number,
+            "recipient_address": key.owner_address,
+            "session_key_id": key.id,
+        }),
+    ).await?;
+
+    let validators = validator_headers(&invoice.etag(), invoice.updated_at);
+    Ok((validators, Json(invoice.into_view(Some(InvoiceRole::Recipient)))))
+}
+
+/// Refusal for an acceptance whose challenge is not active
+///
+/// A challenge that expired within `auth.expired_challenge_grace_secs` gets
+/// a 410 saying so, and the near miss is recorded as an
+/// `ExpiredChallengeSigned` event; anything else is "No active challenge".
+async fn inactive_challenge_error(
+    app_state: &AppState,
+    invoice_id: Uuid,
+    issuer_id: Uuid,
+    recipient: &str,
+    challenge_id: Uuid,
+    client_ip: IpNetwork,
+    user_agent: &str,
+) -> Result<AppError, AppError> {
+    let grace = chrono::Duration::seconds(app_state.config.auth.expired_challenge_grace_secs as i64);
+    let expired = AuthChallenge::find_recently_expired(
+        app_state.challenge_store.as_ref(),
+        app_state.clock.as_ref(),
+        recipient,
+        challenge_id,
+        grace,
+    ).await?;
+
+    let Some(challenge) = expired else {
+        return Ok(AppError::UnauthorizedError("No active challenge found".to_string()));
+    };
+    let expired_secs_ago = (app_state.clock.now() - challenge.expires_at).num_seconds();
+
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::ExpiredChallengeSigned,
+        issuer_id,
+        client_ip,
+        user_agent,
+        serde_json::json!({
+            "invoice_id": invoice_id,
+            "challenge_id": challenge.id,
+            "recipient_address": challenge.ethereum_address,
+            "expired_secs_ago": expired_secs_ago,
+        }),
+    ).await?;
+
+    Ok(AppError::GoneError(format!(
+        "Your challenge expired {} seconds ago, please request a new one", expired_secs_ago
+    )))
+}
+
+/// Checks an acceptance signature, counting refusals in `/metrics`
+///
+/// Another signer is a 403 with `not_signer`, a malformed signature keeps
+/// the error of `SignatureError`.
+fn check_acceptance_signer(
+    signature: &str,
+    message: &str,
+    expected: &str,
+    not_signer: &str,
+) -> Result<(), AppError> {
+    check_signer(signature, message, expected).map_err(|e| match e.recorded() {
+        SignatureError::SignerMismatch => AppError::ForbiddenError(not_signer.to_string()),
+        e => e.into(),
+    })
+}
+
+/// Issues an invoice from the caller
+///
+/// Refusals because of the invoice quota are recorded as
+/// `InvoiceQuotaExceeded` events.
+pub async fn create_invoice(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth_user: AuthUser,
+    Json(payload): Json<InvoiceInput>,
+) -> Result<(StatusCode, Json<Invoice>), AppError> {
+    payload.validate()?;
+
+    let created = Invoice::create(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &app_state.config.ethereum,
+        &app_state.config.invoice_terms,
+        auth_user.user_id(),
+        &payload,
+    ).await;
+
+    if let Err(AppError::QuotaExceededError(reason)) = &created {
+        let (client_ip, user_agent) = extract_client_info(&headers, addr);
+        record_event(
+            &app_state.pool,
+            app_state.clock.as_ref(),
+            EventType::InvoiceQuotaExceeded,
+            auth_user.user_id(),
+            client_ip,
+            &user_agent,
+            serde_json::json!({ "reason": reason }),
+        ).await?;
+    }
+
+    Ok((StatusCode::CREATED, Json(created?)))
+}
+
+/// Downloads every invoice the caller issued as CSV, streamed from the database
+pub async fn export_invoices(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+) -> impl IntoResponse {
+    let export = export_invoices_csv(app_state.pool.clone(), auth_user.user_id());
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/csv; charset=utf-8"),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_static("attachment; filename=\"invoices.csv\""),
+    );
+
+    (StatusCode::OK, headers, Body::from_stream(export))
+}
+
+/// Lists the invoices the caller issued or received, e.g. `?role=recipient&status=Pending`
+///
+/// Without `role` both sides are listed. Page with `offset` while `has_more` is true.
+pub async fn list_invoices(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    Query(params): Query<InvoiceListQuery>,
+) -> Result<Json<InvoicePage>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_INVOICE_PAGE_SIZE);
+    if !(1..=MAX_INVOICE_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}", MAX_INVOICE_PAGE_SIZE
+        )));
+    }
+
+    let offset = params.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(AppError::ValidationError("offset cannot be negative".to_string()));
+    }
+
+    let page = Invoice::list_for_user(
+        &app_state.pool,
+        auth_user.user_id(),
+        auth_user.address(),
+        params.role,
+        params.status,
+        offset,
+        limit,
+    ).await?;
+
+    Ok(Json(page))
+}
+
+/// Finds the caller's invoices by a metadata entry, e.g. `?key=order_id&value=1234`
+pub async fn search_invoices_by_metadata(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    Query(params): Query<MetadataSearchQuery>,
+) -> Result<Json<Vec<Invoice>>, AppError> {
+    if params.key.is_empty() {
+        return Err(AppError::ValidationError("key cannot be empty".to_string()));
+    }
+
+    let invoices = Invoice::find_by_metadata(
+        &app_state.pool,
+        auth_user.user_id(),
+        &params.key,
+        &params.value,
+    ).await?;
+
+    Ok(Json(invoices))
+}
+
+/// Finds the caller's invoice by the `external_ref` it was created with
+pub async fn get_invoice_by_ref(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(external_ref): Path<String>,
+) -> Result<Json<Invoice>, AppError> {
+    let invoice = Invoice::find_by_external_ref(&app_state.pool, auth_user.user_id(), &external_ref)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError(format!("No invoice with external_ref '{}'", external_ref)))?;
+
+    Ok(Json(invoice))
+}
+
+/// Mints a short-lived, read-only link to an invoice for its issuer
+///
+/// The body is optional, e.g. `{"expires_in_secs": 3600, "max_views": 5}`.
+/// Once expired or viewed `max_views` times the link answers 410.
+pub async fn share_invoice(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<Uuid>,
+    payload: Option<Json<ShareInvoiceRequest>>,
+) -> Result<Json<ShareInvoiceResponse>, AppError> {
+    ensure_enabled(&app_state.pool, &app_state.config.feature_flags, INVOICE_SHARING).await?;
+
+    let payload = payload.map(|Json(payload)| payload).unwrap_or_default();
+    payload.validate()?;
+
+    let auth = &app_state.config.auth;
+    let expires_in = payload.expires_in_secs.unwrap_or(auth.share_token_expires_in);
+    if !(MIN_SHARE_EXPIRES_IN_SECS..=auth.share_token_expires_in).contains(&expires_in) {
+        return Err(AppError::ValidationError(format!(
+            "expires_in_secs must be between {} and {}",
+            MIN_SHARE_EXPIRES_IN_SECS, auth.share_token_expires_in
+        )));
+    }
+
+    let invoice = find_invoice(&app_state, invoice_id).await?;
+    if invoice.created_by != auth_user.user_id() {
+        return Err(AppError::ForbiddenError("Only the issuer can share this invoice".to_string()));
+    }
+
+    let expires_at = app_state.clock.now() + chrono::Duration::seconds(expires_in as i64);
+    let share = InvoiceShare::create(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        invoice.id,
+        auth_user.user_id(),
+        expires_at,
+        payload.max_views,
+    ).await?;
+
+    let token = mint_share_token(auth, &share)?;
+    let url = format!("{}/api/invoices/shared/{}", auth.uri.trim_end_matches('/'), token);
+
+    Ok(Json(ShareInvoiceResponse {
+        share_id: share.id,
+        token,
+        url,
+        expires_at: share.expires_at,
+        max_views: share.max_views,
+    }))
+}
+
+/// Lists the share links of an invoice for its issuer, with how often and
+/// when each was last opened
+pub async fn list_invoice_shares(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<Uuid>,
+) -> Result<Json<Vec<InvoiceShare>>, AppError> {
+    let invoice = find_invoice(&app_state, invoice_id).await?;
+    if invoice.created_by != auth_user.user_id() {
+        return Err(AppError::ForbiddenError("Only the issuer can list the shares of this invoice".to_string()));
+    }
+
+    let shares = InvoiceShare::list_for_invoice(&app_state.pool, invoice.id).await?;
+
+    Ok(Json(shares))
+}
+
+/// Revokes a share link before it expires
+pub async fn revoke_invoice_share(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path((invoice_id, share_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    let revoked = InvoiceShare::revoke(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        invoice_id,
+        share_id,
+        auth_user.user_id(),
+    ).await?;
+
+    if !revoked {
+        return Err(AppError::NotFoundError(format!("Share {} not found", share_id)));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Returns the invoice a share token grants access to, without login
+///
+/// The token only ever reads the single invoice it was minted for. Each
+/// view is counted and recorded as a `SharedInvoiceViewed` event of the
+/// issuer; expired and used up links get 410.
+pub async fn get_shared_invoice(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> Result<Json<SharedInvoiceResponse>, AppError> {
+    ensure_enabled(&app_state.pool, &app_state.config.feature_flags, INVOICE_SHARING).await?;
+
+    let claims = decode_share_token(&app_state.config.auth, &token)?;
+
+    let share = InvoiceShare::record_view(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        claims.jti,
+        claims.sub,
+    ).await?;
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::SharedInvoiceViewed,
+        share.created_by,
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "invoice_id": share.invoice_id,
+            "share_id": share.id,
+            "view_count": share.view_count,
+            "max_views": share.max_views,
+        }),
+    ).await?;
+
+    let invoice = find_invoice(&app_state, share.invoice_id).await?;
+    let payment_uri = invoice.payment_uri(&app_state.config.ethereum);
+
+    Ok(Json(SharedInvoiceResponse { invoice: invoice.into_view(None), payment_uri }))
+}
+
+async fn find_invoice(
+    app_state: &AppState,
+    invoice_id: Uuid,
+) -> Result<Invoice, AppError> {
+    Invoice::get_invoice_by_id(&app_state.pool, invoice_id)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError(format!("Invoice {} not found", invoice_id)))
+}
+
+/// Reads an invoice for its issuer or recipient
+///
+/// Answers with `ETag` and `Last-Modified`, and with 304 when the client's
+/// `If-None-Match` or `If-Modified-Since` shows its copy is current. The
+/// `ETag` can be sent back as `If-Match` on accept, confirm and cancel to
+/// have them refused with 412 if the invoice changed in between.
+pub async fn get_invoice(
+    State(app_state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let invoice = find_invoice(&app_state, invoice_id).await?;
+    let role = invoice.role_of(auth_user.user_id(), auth_user.address())
+        .ok_or_else(|| AppError::ForbiddenError("Only the issuer or recipient can read this invoice".to_string()))?;
+
+    let etag = invoice.etag();
+    let validators = validator_headers(&etag, invoice.updated_at);
+    if is_not_modified(&headers, &etag, invoice.updated_at) {
+        return Ok((StatusCode::NOT_MODIFIED, validators).into_response());
+    }
+
+    Ok((validators, Json(invoice.into_view(Some(role)))).into_response())
+}
+
+/// Estimates the gas cost of paying a pending invoice from the caller's wallet
+///
+/// Open to the issuer and the designated recipient. Fees are cached for a
+/// few seconds; see `services::fee_estimate` for what is estimated.
+pub async fn estimate_invoice_fee(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<Uuid>,
+) -> Result<Json<FeeEstimate>, AppError> {
+    let invoice = find_invoice(&app_state, invoice_id).await?;
+    if !invoice.is_party(auth_user.user_id(), auth_user.address()) {
+        return Err(AppError::ForbiddenError("Only the issuer or recipient can estimate fees for this invoice".to_string()));
+    }
+    if invoice.status != InvoiceStatus::Pending {
+        return Err(AppError::ConflictError("Only pending invoices can be paid".to_string()));
+    }
+
+    let estimate = estimate_payment_fee(&app_state, &invoice, auth_user.address()).await?;
+
+    Ok(Json(estimate))
+}
+
+/// Marks an invoice paid from the transaction that paid it on chain
+///
+/// Open to the issuer and the designated recipient. The transaction must
+/// have emitted `PaymentMade` for this invoice, and a transaction hash can
+/// settle only one invoice: reusing one is refused with 409 and recorded
+/// as a `PaymentReplayed` event. A transaction that is not mined yet is
+/// answered with 202 and watched until it is, see `spawn_payment_watcher`.
+pub async fn confirm_invoice_payment(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<Uuid>,
+    Json(payload): Json<ConfirmPaymentRequest>,
+) -> Result<(StatusCode, HeaderMap, Json<InvoiceView>), AppError> {
+    let invoice = find_invoice(&app_state, invoice_id).await?;
+    let role = invoice.role_of(auth_user.user_id(), auth_user.address())
+        .ok_or_else(|| AppError::ForbiddenError("Only the issuer or recipient can confirm a payment".to_string()))?;
+    check_if_match(&headers, &invoice.etag())?;
+    if invoice.status == InvoiceStatus::Cancelled {
+        return Err(AppError::ConflictError("Invoice is cancelled".to_string()));
+    }
+
+    let tx_hash = normalize_tx_hash(&payload.tx_hash)?;
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    let requester = Requester { user_id: auth_user.user_id(), client_ip, user_agent };
+
+    if !settle_payment(&app_state, &invoice, &tx_hash, &requester).await? {
+        spawn_payment_watcher(app_state.clone(), invoice.clone(), tx_hash, requester);
+        let validators = validator_headers(&invoice.etag(), invoice.updated_at);
+        return Ok((StatusCode::ACCEPTED, validators, Json(invoice.into_view(Some(role)))));
+    }
+
+    let invoice = find_invoice(&app_state, invoice.id).await?;
+    let validators = validator_headers(&invoice.etag(), invoice.updated_at);
+    Ok((StatusCode::OK, validators, Json(invoice.into_view(Some(role)))))
+}
+
+/// Cancels a pending invoice for its issuer and stops its background work
+pub async fn cancel_invoice(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<Uuid>,
+) -> Result<(HeaderMap, Json<Invoice>), AppError> {
+    let invoice = find_invoice(&app_state, invoice_id).await?;
+    if invoice.created_by != auth_user.user_id() {
+        return Err(AppError::ForbiddenError("Only the issuer can cancel this invoice".to_string()));
+    }
+    check_if_match(&headers, &invoice.etag())?;
+
+    let cancelled = Invoice::update_status(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        invoice.id,
+        InvoiceStatus::Pending,
+        InvoiceStatus::Cancelled,
+    ).await?;
+    if !cancelled {
+        return Err(AppError::ConflictError("Only pending invoices can be cancelled".to_string()));
+    }
+    app_state.invoice_tasks.cancel(invoice.id);
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::InvoiceStatusChanged,
+        invoice.created_by,
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "invoice_id": invoice.id,
+            "display_number": invoice.display_number,
+            "from": invoice.status,
+            "to": InvoiceStatus::Cancelled,
+            "source": "cancel",
+        }),
+    ).await?;
+
+    let invoice = find_invoice(&app_state, invoice.id).await?;
+    Ok((validator_headers(&invoice.etag(), invoice.updated_at), Json(invoice)))
+}
+
+/// Re-reads a batch of invoices from the contract and corrects stored statuses
+///
+/// Each correction records an `InvoiceStatusChanged` event for the issuer.
+/// Call again with `next_cursor` until it is `null`. An RPC failure aborts
+/// the batch with 503; corrections made before it are kept, and retrying
+/// with the same cursor is safe.
+pub async fn reconcile_invoices(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    _admin: AdminUser,
+    Json(payload): Json<ReconcileRequest>,
+) -> Result<Json<ReconcileResponse>, AppError> {
+    let batch_size = payload.batch_size.unwrap_or(DEFAULT_RECONCILE_BATCH);
+    if !(1..=MAX_RECONCILE_BATCH).contains(&batch_size) {
+        return Err(AppError::ValidationError(format!(
+            "batch_size must be between 1 and {}", MAX_RECONCILE_BATCH
+        )));
+    }
+
+    let invoices = Invoice::list_after(&app_state.pool, payload.cursor, batch_size).await?;
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+
+    let mut changed = 0;
+    let mut missing = 0;
+    for invoice in &invoices {
+        // Cancellation is off-chain only, the contract keeps such invoices pending
+        if invoice.status == InvoiceStatus::Cancelled {
+            continue;
+        }
+        let Some(on_chain) = app_state.chain.invoice_status(&invoice.on_chain_id).await? else {
+            missing += 1;
+            continue;
+        };
+
+        let status = on_chain.invoice_status();
+        if status == invoice.status {
+            continue;
+        }
+        let updated = Invoice::update_status(
+            &app_state.pool,
+            app_state.clock.as_ref(),
+            invoice.id,
+            invoice.status,
+            status,
+        ).await?;
+        if !updated {
+            continue;
+        }
+
+        changed += 1;
+        record_event(
+            &app_state.pool,
+            app_state.clock.as_ref(),
+            EventType::InvoiceStatusChanged,
+            invoice.created_by,
+            client_ip,
+            &user_agent,
+            serde_json::json!({
+                "invoice_id": invoice.id,
+                "display_number": invoice.display_number,
+                "from": invoice.status,
+                "to": status,
+                "source": "reconcile",
+            }),
+        ).await?;
+    }
+
+    let next_cursor = invoices.last()
+        .filter(|_| invoices.len() as i64 == batch_size)
+        .map(|invoice| invoice.id);
+
+    Ok(Json(ReconcileResponse {
+        checked: invoices.len(),
+        changed,
+        missing,
+        next_cursor,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support, utils::{clock::MockClock, i18n::LocalizedStatement}};
+    use chrono::{Duration, NaiveDate};
+    use sqlx::PgPool;
+
+    const RECIPIENT: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    async fn expired_events(pool: &PgPool) -> Vec<serde_json::Value> {
+        sqlx::query_scalar!(r#"SELECT metadata as "metadata!" FROM security_events WHERE event_type = 'expiredchallengesigned'"#)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn challenges_expired_within_the_grace_window_are_gone(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = Arc::new(MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap()));
+        let app_state = test_support::app_state(pool.clone(), clock.clone());
+        let issuer = test_support::create_user(&pool, clock.as_ref(), "0x0000000000000000000000000000000000000001").await;
+        let statement = LocalizedStatement::negotiate(&app_state.config.auth, None);
+        let challenge = AuthChallenge::create_challenge_for_addr(
+            app_state.challenge_store.as_ref(),
+            clock.as_ref(),
+            RECIPIENT,
+            &ChallengeScope::from_config(&app_state.config),
+            &statement,
+            app_state.config.auth.max_active_challenges,
+        ).await.unwrap();
+        let invoice_id = Uuid::new_v4();
+        let refuse = || inactive_challenge_error(
+            &app_state, invoice_id, issuer.id, RECIPIENT, challenge.id, test_support::client_ip(), "test",
+        );
+
+        clock.set(challenge.expires_at + Duration::seconds(30));
+        match refuse().await.unwrap() {
+            AppError::GoneError(message) => assert!(message.starts_with("Your challenge expired 30 seconds ago"), "{message}"),
+            other => panic!("expected 410, got {other:?}"),
+        }
+        let events = expired_events(&pool).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["challenge_id"], challenge.id.to_string());
+        assert_eq!(events[0]["expired_secs_ago"], 30);
+
+        // Past the grace window the challenge is treated as unknown
+        let grace = app_state.config.auth.expired_challenge_grace_secs as i64;
+        clock.set(challenge.expires_at + Duration::seconds(grace + 1));
+        assert!(matches!(refuse().await.unwrap(), AppError::UnauthorizedError(_)));
+        assert_eq!(expired_events(&pool).await.len(), 1);
+    }
+}
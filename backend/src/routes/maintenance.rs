@@ -0,0 +1,64 @@
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Router,
+};
+use std::sync::{Arc, OnceLock};
+use tower::ServiceExt;
+
+use crate::{
+    app_error::app_error::AppError,
+    routes::health::{health_response, readiness_response},
+};
+
+const MAINTENANCE_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>Maintenance</title>
+</head>
+<body>
+    <h1>We'll be back shortly</h1>
+    <p>The service is temporarily unavailable. Please try again in a few moments.</p>
+</body>
+</html>
+"#;
+
+/// Router served while the database is unreachable at startup
+///
+/// Every request gets a maintenance response until `full_app` is set, at
+/// which point requests are handed to the full application instead.
+pub fn create_degraded_routes(
+    full_app: Arc<OnceLock<Router>>,
+    retry_after_secs: u64,
+) -> Router {
+    Router::new().fallback(move |request: Request| {
+        let full_app = full_app.clone();
+        async move {
+            match full_app.get() {
+                Some(app) => app.clone().oneshot(request).await.into_response(),
+                None => maintenance_response(request.uri().path(), retry_after_secs),
+            }
+        }
+    })
+}
+
+fn maintenance_response(path: &str, retry_after_secs: u64) -> Response {
+    let retry_after = [(header::RETRY_AFTER, retry_after_secs.to_string())];
+
+    if path == "/health" {
+        return (retry_after, health_response(false)).into_response();
+    }
+
+    if path == "/ready" {
+        return (retry_after, readiness_response(false, None)).into_response();
+    }
+
+    if path.starts_with("/api/") {
+        let error = AppError::ServiceUnavailableError("Service is under maintenance".to_string());
+        return (retry_after, error).into_response();
+    }
+
+    (StatusCode::SERVICE_UNAVAILABLE, retry_after, Html(MAINTENANCE_PAGE)).into_response()
+}
@@ -0,0 +1,90 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
+};
+use std::{fmt::Write, sync::Arc};
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::{PoolSaturation, RpcSaturation},
+    models::auth_challenges::signature_failure_counts,
+    routes::health::{collect_auth_health, AUTH_HEALTH_WINDOW_MINUTES},
+    AppState,
+};
+
+/// Serves operational gauges in the Prometheus text exposition format
+pub async fn serve_metrics(
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let health = collect_auth_health(
+        &app_state.pool,
+        app_state.challenge_store.as_ref(),
+        app_state.clock.as_ref(),
+        AUTH_HEALTH_WINDOW_MINUTES,
+    ).await?;
+
+    let mut body = String::new();
+    write_gauge(
+        &mut body,
+        "auth_login_success_ratio",
+        "Ratio of successful logins to all login attempts over the window",
+        health.success_ratio.unwrap_or(1.0),
+    );
+    write_gauge(&mut body, "auth_logins", "Successful logins over the window", health.logins as f64);
+    write_gauge(&mut body, "auth_failed_logins", "Failed logins over the window", health.failed_logins as f64);
+    write_gauge(&mut body, "auth_challenges_created", "Challenges created over the window", health.challenges_created as f64);
+    write_gauge(&mut body, "auth_challenges_used", "Challenges used over the window", health.challenges_used as f64);
+
+    let pool = app_state.pool_monitor.status();
+    write_gauge(
+        &mut body,
+        "db_pool_load_shedding",
+        "1 when requests are rejected while the pool is saturated, 0 when they queue",
+        if pool.saturation_mode == PoolSaturation::Shed { 1.0 } else { 0.0 },
+    );
+    write_gauge(&mut body, "db_pool_max_connections", "Connections the pool may open", pool.max_connections as f64);
+    write_gauge(&mut body, "db_pool_open_connections", "Connections currently open", pool.open_connections as f64);
+    write_gauge(&mut body, "db_pool_idle_connections", "Open connections not checked out", pool.idle_connections as f64);
+    write_gauge(&mut body, "db_pool_utilization", "Share of max connections checked out", pool.utilization);
+    write_gauge(
+        &mut body,
+        "db_pool_acquire_wait_seconds",
+        "Time the last probe waited for a connection",
+        pool.acquire_wait_secs,
+    );
+    write_gauge(&mut body, "db_pool_acquire_timeouts", "Connection acquires that timed out since startup", pool.acquire_timeouts as f64);
+    write_gauge(&mut body, "db_pool_shed_requests", "Requests rejected while the pool was saturated since startup", pool.shed_requests as f64);
+
+    let rpc = app_state.chain.rpc_status();
+    write_gauge(
+        &mut body,
+        "eth_rpc_load_shedding",
+        "1 when RPC requests fail while the concurrency limit is reached, 0 when they queue",
+        if rpc.saturation == RpcSaturation::Shed { 1.0 } else { 0.0 },
+    );
+    write_gauge(&mut body, "eth_rpc_max_concurrent", "RPC requests allowed in flight at once", rpc.max_concurrent as f64);
+    write_gauge(&mut body, "eth_rpc_in_flight", "RPC requests currently in flight", rpc.in_flight as f64);
+    write_gauge(&mut body, "eth_rpc_rejected_requests", "RPC requests failed at the concurrency limit since startup", rpc.rejected as f64);
+
+    let _ = writeln!(body, "# HELP signature_verification_failures_total Signatures refused since startup, by failure category");
+    let _ = writeln!(body, "# TYPE signature_verification_failures_total counter");
+    for (category, count) in signature_failure_counts() {
+        let _ = writeln!(body, "signature_verification_failures_total{{category=\"{}\"}} {}", category, count);
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+    );
+
+    Ok((StatusCode::OK, headers, body))
+}
+
+fn write_gauge(body: &mut String, name: &str, help: &str, value: f64) {
+    // Writing into a String cannot fail
+    let _ = writeln!(body, "# HELP {} {}", name, help);
+    let _ = writeln!(body, "# TYPE {} gauge", name);
+    let _ = writeln!(body, "{} {}", name, value);
+}
@@ -1,2 +1,19 @@
+pub mod api_keys;
+pub mod approvals;
+pub mod auth;
+pub mod blacklist;
+pub mod challenges;
+pub mod diagnostics;
+pub mod events;
+pub mod flags;
+pub mod health;
 pub mod home;
-pub mod router;
\ No newline at end of file
+pub mod invoices;
+pub mod maintenance;
+pub mod metrics;
+pub mod rate_limits;
+pub mod router;
+pub mod session_keys;
+pub mod tokens;
+pub mod users;
+pub mod webhooks;
\ No newline at end of file
@@ -0,0 +1,110 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::HeaderMap,
+};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{admin_user::AdminUser, json::Json},
+    models::{
+        rate_limits::{clear_rate_limits, list_rate_limits, top_offenders, RateLimit, RateLimitOffender},
+        security_events::{record_event, EventType},
+    },
+    utils::server_utils::extract_client_info,
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct RateLimitQuery {
+    pub identifier: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClearRateLimitResponse {
+    pub identifier: String,
+    pub cleared_actions: Vec<String>,
+    /// Whether the identifier was blocked for repeated violations
+    pub unblocked: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OffendersQuery {
+    /// Violations of the last `hours` count, 24 by default
+    pub hours: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+const DEFAULT_OFFENDERS: i64 = 20;
+const MAX_OFFENDERS: i64 = 200;
+const MAX_OFFENDER_HOURS: i64 = 24 * 90;
+
+/// Shows the live rate-limit entries of an identifier (client IP, address...)
+pub async fn list_rate_limit(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+    Query(params): Query<RateLimitQuery>,
+) -> Result<Json<Vec<RateLimit>>, AppError> {
+    let limits = list_rate_limits(&app_state.pool, &params.identifier).await?;
+
+    Ok(Json(limits))
+}
+
+/// Identifiers that exceeded rate limits most often lately, e.g. `?hours=24&limit=20`,
+/// with whether each is currently blocked
+pub async fn list_rate_limit_offenders(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+    Query(params): Query<OffendersQuery>,
+) -> Result<Json<Vec<RateLimitOffender>>, AppError> {
+    let hours = params.hours.unwrap_or(24);
+    if !(1..=MAX_OFFENDER_HOURS).contains(&hours) {
+        return Err(AppError::ValidationError(format!(
+            "hours must be between 1 and {}", MAX_OFFENDER_HOURS
+        )));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_OFFENDERS);
+    if !(1..=MAX_OFFENDERS).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}", MAX_OFFENDERS
+        )));
+    }
+
+    let now = app_state.clock.now();
+    let offenders = top_offenders(&app_state.pool, now, now - chrono::Duration::hours(hours), limit).await?;
+
+    Ok(Json(offenders))
+}
+
+/// Lifts every rate limit of an identifier, e.g. for a user stuck after an incident,
+/// including a block for repeated violations
+pub async fn clear_rate_limit(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    admin: AdminUser,
+    Path(identifier): Path<String>,
+) -> Result<Json<ClearRateLimitResponse>, AppError> {
+    let (cleared_actions, unblocked) = clear_rate_limits(&app_state.pool, &identifier).await?;
+    if cleared_actions.is_empty() && !unblocked {
+        return Err(AppError::NotFoundError(format!("No rate limit for {}", identifier)));
+    }
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::RateLimitCleared,
+        admin.user_id(),
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "identifier": identifier,
+            "actions": cleared_actions,
+            "unblocked": unblocked,
+        }),
+    ).await?;
+
+    Ok(Json(ClearRateLimitResponse { identifier, cleared_actions, unblocked }))
+}
@@ -1,38 +1,162 @@
 use crate::{
     AppState,
-    routes::home::serve_home,
+    app_error::{app_error::AppError, problem_details::render_problem_details},
+    services::pool_monitor::shed_when_pool_saturated,
+    utils::{
+        bot_filter::reject_blocked_user_agents,
+        cookie_security::{secure_cookies, CookiePolicy},
+        cors::{apply_cors, CorsPolicy},
+        csrf::{verify_csrf, CsrfPolicy},
+    },
+    routes::{
+        api_keys::{create_api_key, list_api_keys, rotate_api_keys},
+        approvals::verify_approvals,
+        auth::{login, refresh_tokens, verify_signature},
+        blacklist::blacklist_stats,
+        challenges::{create_challenge, list_user_challenges, preview_challenge, refresh_challenge},
+        diagnostics::{list_wallet_failures, report_wallet_failure},
+        events::{erase_events, export_events, list_events},
+        flags::{list_flags, set_flag},
+        health::{auth_health, health_check, readiness_check, server_status},
+        home::serve_home,
+        invoices::{
+            accept_invoice, accept_invoice_with_session_key, cancel_invoice,
+            confirm_invoice_payment, create_acceptance_challenge, create_invoice, estimate_invoice_fee,
+            export_invoices,
+            get_invoice, get_invoice_by_ref, get_shared_invoice, list_invoice_shares, list_invoices, reconcile_invoices,
+            revoke_invoice_share, search_invoices_by_metadata, share_invoice,
+        },
+        metrics::serve_metrics,
+        rate_limits::{clear_rate_limit, list_rate_limit, list_rate_limit_offenders},
+        session_keys::{
+            authorize_session_key, create_session_key, list_session_keys, revoke_session_key,
+        },
+        tokens::{list_tokens, verify_token},
+        users::{get_me, revoke_user_sessions, search_users, set_user_verification},
+        webhooks::replay_webhook,
+    },
 };
-use tower_http::{services::ServeDir, cors::CorsLayer};
+use tower_http::services::ServeDir;
 use hyper::header;
 use std::sync::Arc;
-use axum::{Router, routing::get};
+use axum::{
+    Router,
+    extract::OriginalUri,
+    http::Method,
+    middleware::from_fn_with_state,
+    routing::{delete, get, post, put},
+};
 use axum_csrf::{CsrfConfig, CsrfLayer};
 use tower_cookies::CookieManagerLayer;
 
 pub fn create_app_routes(
     app_state: Arc<AppState>,
     csrf_config: CsrfConfig,
-    cors_config: CorsLayer,
+    cors_policy: CorsPolicy,
 ) -> Router {
+    // Sign-in and challenge routes, the usual targets of scrapers
+    let auth_routes = Router::new()
+        .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh_tokens))
+        .route("/auth/verify-signature", post(verify_signature))
+        .route("/auth/telemetry", post(report_wallet_failure))
+        .route("/challenge", post(create_challenge))
+        .route("/challenge/preview", get(preview_challenge))
+        .route("/challenge/refresh", post(refresh_challenge))
+        .route("/invoices/{id}/accept/challenge", post(create_acceptance_challenge))
+        .route_layer(from_fn_with_state(app_state.clone(), reject_blocked_user_agents));
+
+    // API routes
+    let api_routes = Router::new()
+        .merge(auth_routes)
+        .route("/approvals/verify", post(verify_approvals))
+        .route("/invoices", get(list_invoices).post(create_invoice))
+        .route("/invoices/by-metadata", get(search_invoices_by_metadata))
+        .route("/invoices/by-ref/{external_ref}", get(get_invoice_by_ref))
+        .route("/invoices/export.csv", get(export_invoices))
+        .route("/invoices/{id}", get(get_invoice))
+        .route("/invoices/{id}/accept", post(accept_invoice))
+        .route("/invoices/{id}/accept/session-key", post(accept_invoice_with_session_key))
+        .route("/invoices/{id}/fee-estimate", get(estimate_invoice_fee))
+        .route("/invoices/{id}/confirm", post(confirm_invoice_payment))
+        .route("/invoices/{id}/cancel", post(cancel_invoice))
+        .route("/invoices/{id}/share", post(share_invoice))
+        .route("/invoices/{id}/shares", get(list_invoice_shares))
+        .route("/invoices/{id}/shares/{share_id}", delete(revoke_invoice_share))
+        .route("/invoices/shared/{token}", get(get_shared_invoice))
+        .route("/tokens", get(list_tokens))
+        .route("/tokens/{address}/verify", get(verify_token))
+        .route("/me", get(get_me))
+        .route("/me/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/me/api-keys/rotate", post(rotate_api_keys))
+        .route("/me/session-keys", get(list_session_keys).post(create_session_key))
+        .route("/me/session-keys/{id}", delete(revoke_session_key))
+        .route("/me/session-keys/{id}/authorize", post(authorize_session_key))
+        .route("/admin/health/auth", get(auth_health))
+        .route("/admin/events", get(list_events))
+        .route("/admin/diagnostics/wallets", get(list_wallet_failures))
+        .route("/admin/users", get(search_users))
+        .route("/admin/users/{id}/challenges", get(list_user_challenges))
+        .route("/admin/users/{id}/verification", put(set_user_verification))
+        .route("/admin/users/{id}/revoke-sessions", post(revoke_user_sessions))
+        .route("/admin/rate-limits", get(list_rate_limit))
+        .route("/admin/rate-limits/offenders", get(list_rate_limit_offenders))
+        .route("/admin/rate-limits/{identifier}", delete(clear_rate_limit))
+        .route("/admin/flags", get(list_flags).put(set_flag))
+        .route("/admin/invoices/reconcile", post(reconcile_invoices))
+        .route("/admin/events/export.jsonl", get(export_events))
+        .route("/admin/events/erasures", post(erase_events))
+        .route("/admin/blacklist/stats", get(blacklist_stats))
+        .route("/admin/webhooks/{delivery_id}/replay", post(replay_webhook))
+        .route_layer(from_fn_with_state(app_state.clone(), shed_when_pool_saturated))
+        .fallback(api_not_found)
+        .method_not_allowed_fallback(api_method_not_allowed);
+
+    // axum::serve runs on a plain TCP listener, TLS can only be terminated upstream
+    let cookie_policy = CookiePolicy::new(&app_state.config.server, false);
+    let csrf_policy = CsrfPolicy::new(&app_state.config.csrf);
+
     // Create router
     let app = Router::new()
         .route("/", get(serve_home))
+        .route("/health", get(health_check))
+        .route("/ready", get(readiness_check))
+        .route("/status", get(server_status))
+        .route("/metrics", get(serve_metrics))
+        .nest("/api", api_routes)
         // other routes to be added here
         .nest_service(
             "/assets", ServeDir::new(format!("{}/assets", app_state.vue_dist_path))
         )
         .layer(CookieManagerLayer::new())
+        // Inside CsrfLayer, which provides the CsrfToken it checks against
+        .layer(from_fn_with_state(csrf_policy, verify_csrf))
         .layer(CsrfLayer::new(csrf_config.clone()))
+        .layer(from_fn_with_state(cookie_policy, secure_cookies))
         .layer(
             tower_http::set_header::SetResponseHeaderLayer::if_not_present(
                 header::X_CONTENT_TYPE_OPTIONS,
                 header::HeaderValue::from_static("nosniff"),
             )
         )
-        .layer(cors_config)
+        .layer(from_fn_with_state(app_state.config.errors.format, render_problem_details))
+        .layer(from_fn_with_state(cors_policy, apply_cors))
         // .layer(from_fn(utils::server_utils::restrict_origin))
         .with_state(app_state);
 
     // Return the configured router
     app
-}
\ No newline at end of file
+}
+
+/// JSON 404 for unmatched routes under `/api`
+async fn api_not_found(OriginalUri(uri): OriginalUri) -> AppError {
+    AppError::NotFoundError(format!("No route for {}", uri.path()))
+}
+
+/// JSON 405 for known `/api` routes called with the wrong method
+async fn api_method_not_allowed(
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+) -> AppError {
+    AppError::MethodNotAllowedError(format!("Method {} not allowed for {}", method, uri.path()))
+}
@@ -0,0 +1,214 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+};
+use bigdecimal::{BigDecimal, Zero};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{auth_user::AuthUser, json::Json},
+    models::{
+        auth_challenges::{check_signer, normalize_ethereum_address, personal_message_hash, validate_eth_address},
+        security_events::{record_event, EventType},
+        session_keys::{SessionKey, SessionKeyLimits},
+    },
+    utils::server_utils::extract_client_info,
+    AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateSessionKeyRequest {
+    /// Address of the session key pair
+    #[validate(custom(function = "validate_eth_address"))]
+    pub session_address: String,
+    #[validate(length(equal = 3))]
+    pub currency: String,
+    #[serde(with = "crate::utils::amount")]
+    pub max_amount_per_invoice: BigDecimal,
+    #[serde(with = "crate::utils::amount")]
+    pub spend_limit: BigDecimal,
+    #[validate(range(min = 60))]
+    pub expires_in_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedSessionKey {
+    #[serde(flatten)]
+    pub key: SessionKey,
+    /// To be signed with `personal_sign` by the caller's wallet
+    pub authorization_message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeSessionKeyRequest {
+    pub signature: String,
+}
+
+/// Lists the caller's session keys, with what each has spent
+pub async fn list_session_keys(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<SessionKey>>, AppError> {
+    let keys = SessionKey::list_for_user(&app_state.pool, auth_user.user_id()).await?;
+
+    Ok(Json(keys))
+}
+
+/// Registers a session key and returns the message authorizing it
+///
+/// The key can be used once `authorization_message` is signed by the
+/// caller's wallet and sent to `POST /me/session-keys/{id}/authorize`.
+pub async fn create_session_key(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateSessionKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedSessionKey>), AppError> {
+    auth_user.require_session()?;
+    payload.validate()?;
+
+    let config = &app_state.config.session_keys;
+    if payload.expires_in_secs > config.max_lifetime_secs {
+        return Err(AppError::ValidationError(format!(
+            "expires_in_secs cannot exceed {}", config.max_lifetime_secs
+        )));
+    }
+    if payload.max_amount_per_invoice.is_zero() {
+        return Err(AppError::ValidationError("max_amount_per_invoice must be greater than 0".to_string()));
+    }
+    if payload.spend_limit < payload.max_amount_per_invoice {
+        return Err(AppError::ValidationError(
+            "spend_limit cannot be lower than max_amount_per_invoice".to_string()
+        ));
+    }
+    let session_address = normalize_ethereum_address(&payload.session_address)
+        .map_err(|_| AppError::ValidationError("session_address is not a valid address".to_string()))?;
+
+    let limits = SessionKeyLimits {
+        currency: payload.currency.to_uppercase(),
+        max_amount_per_invoice: payload.max_amount_per_invoice,
+        spend_limit: payload.spend_limit,
+        expires_at: app_state.clock.now() + chrono::Duration::seconds(payload.expires_in_secs as i64),
+    };
+    let key = SessionKey::create(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        auth_user.user_id(),
+        &normalize_ethereum_address(auth_user.address())?,
+        &session_address,
+        limits,
+        config.max_keys_per_user,
+    ).await?;
+
+    let authorization_message = key.authorization_message(
+        &app_state.config.auth.domain,
+        app_state.config.ethereum.chain_id,
+    );
+
+    Ok((StatusCode::CREATED, Json(CreatedSessionKey { key, authorization_message })))
+}
+
+/// Activates a session key with the wallet's signature of its authorization message
+///
+/// Signatures of externally owned accounts are recovered locally; any other
+/// signature is submitted to the owner address as an EIP-1271 contract wallet.
+pub async fn authorize_session_key(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth_user: AuthUser,
+    Path(key_id): Path<Uuid>,
+    Json(payload): Json<AuthorizeSessionKeyRequest>,
+) -> Result<Json<SessionKey>, AppError> {
+    auth_user.require_session()?;
+
+    let key = SessionKey::find_for_user(&app_state.pool, auth_user.user_id(), key_id)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError(format!("Session key {} not found", key_id)))?;
+    let message = key.authorization_message(
+        &app_state.config.auth.domain,
+        app_state.config.ethereum.chain_id,
+    );
+
+    // A signature a wallet key cannot have produced may still be a contract wallet's
+    let (signature, signed, owner) = (payload.signature.clone(), message.clone(), key.owner_address.clone());
+    let local_check = app_state.signature_verifier
+        .run(move || Ok(check_signer(&signature, &signed, &owner)))
+        .await?;
+    let signed_by_owner = local_check.is_ok();
+    let authorized = signed_by_owner || match contract_signature_bytes(&payload.signature) {
+        Some(signature) => app_state.chain
+            .is_valid_signature(&key.owner_address, &personal_message_hash(&message), &signature)
+            .await?,
+        None => false,
+    };
+    if !authorized {
+        // Counted only now, the contract wallet check may have accepted it
+        if let Err(e) = local_check {
+            e.recorded();
+        }
+        return Err(AppError::UnauthorizedError("Signature does not authorize this session key".to_string()));
+    }
+
+    let key = SessionKey::mark_authorized(&app_state.pool, app_state.clock.as_ref(), auth_user.user_id(), key.id)
+        .await?
+        .ok_or_else(|| AppError::ConflictError("Session key is already authorized, revoked or expired".to_string()))?;
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::SessionKeyAuthorized,
+        auth_user.user_id(),
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "session_key_id": key.id,
+            "session_address": key.session_address,
+            "currency": key.currency,
+            "spend_limit": key.spend_limit.to_plain_string(),
+            "expires_at": key.expires_at,
+            "contract_wallet": !signed_by_owner,
+        }),
+    ).await?;
+
+    Ok(Json(key))
+}
+
+/// Revokes a session key; invoices it already accepted stay accepted
+pub async fn revoke_session_key(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth_user: AuthUser,
+    Path(key_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let revoked = SessionKey::revoke(&app_state.pool, app_state.clock.as_ref(), auth_user.user_id(), key_id).await?;
+    if !revoked {
+        return Err(AppError::NotFoundError(format!("Session key {} not found", key_id)));
+    }
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::SessionKeyRevoked,
+        auth_user.user_id(),
+        client_ip,
+        &user_agent,
+        serde_json::json!({ "session_key_id": key_id }),
+    ).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Hex signature of any length, as contract wallets may produce
+fn contract_signature_bytes(signature: &str) -> Option<Vec<u8>> {
+    let signature = signature.trim();
+    hex::decode(signature.strip_prefix("0x").unwrap_or(signature))
+        .ok()
+        .filter(|bytes| !bytes.is_empty())
+}
@@ -0,0 +1,190 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::TokenConfig,
+    models::auth_challenges::normalize_ethereum_address,
+    services::chain::TokenMetadata,
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyTokenQuery {
+    pub chain_id: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenVerification {
+    pub address: String,
+    pub chain_id: u32,
+    #[serde(flatten)]
+    pub on_chain: TokenMetadata,
+    /// Entry of the accepted token list for this contract, if any
+    pub listed: Option<TokenConfig>,
+    /// Listed, and the contract reports the listed symbol and decimals
+    pub matches_listing: bool,
+}
+
+/// Lists the tokens accepted for invoice payments
+pub async fn list_tokens(
+    State(app_state): State<Arc<AppState>>,
+) -> Json<Vec<TokenConfig>> {
+    Json(app_state.config.ethereum.tokens.clone())
+}
+
+/// Reads a token contract's name, symbol and decimals and compares them with
+/// the accepted token list
+///
+/// Lets payers check that the token of an invoice is the one they expect
+/// before paying. Only the chain of the configured RPC endpoint can be read.
+pub async fn verify_token(
+    State(app_state): State<Arc<AppState>>,
+    Path(address): Path<String>,
+    Query(query): Query<VerifyTokenQuery>,
+) -> Result<Json<TokenVerification>, AppError> {
+    let address = normalize_ethereum_address(&address)
+        .map_err(|_| AppError::ValidationError("Invalid token address".to_string()))?;
+    if query.chain_id != app_state.chain.chain_id() {
+        return Err(AppError::ValidationError(format!(
+            "Tokens can only be verified on chain {}", app_state.chain.chain_id()
+        )));
+    }
+
+    let on_chain = app_state.chain.token_metadata(&address).await?;
+
+    let listed = app_state.config.ethereum.tokens
+        .iter()
+        .find(|token| token.chain_id == query.chain_id && token.contract_address.eq_ignore_ascii_case(&address))
+        .cloned();
+    let matches_listing = listed.as_ref().is_some_and(|token| {
+        on_chain.symbol.as_deref() == Some(token.symbol.as_str())
+            && on_chain.decimals.map(u32::from) == Some(token.decimals)
+    });
+
+    Ok(Json(TokenVerification {
+        address,
+        chain_id: query.chain_id,
+        on_chain,
+        listed,
+        matches_listing,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support, utils::clock::SystemClock};
+    use serde_json::{json, Value as JsonValue};
+    use sqlx::PgPool;
+
+    const USDC: &str = "0x1c7d4b196cb0c7b01d743fbc6116a902379c7238";
+    const CHAIN_ID: u32 = 11155111;
+
+    /// ABI encoding of a `string` return value
+    fn abi_string(value: &str) -> JsonValue {
+        let mut data = format!("{:064x}{:064x}{}", 0x20, value.len(), hex::encode(value));
+        while data.len() % 64 != 0 {
+            data.push('0');
+        }
+        json!(format!("0x{}", data))
+    }
+
+    fn abi_uint(value: u64) -> JsonValue {
+        json!(format!("0x{:064x}", value))
+    }
+
+    /// State whose RPC endpoint holds a token contract at every address,
+    /// answering its getters with `getter(selector)`
+    async fn app_state<F>(pool: PgPool, code: &'static str, getter: F) -> Arc<AppState>
+    where
+        F: Fn(&str) -> Result<JsonValue, JsonValue> + Send + Sync + 'static,
+    {
+        let mut config = test_support::config();
+        config.ethereum.rpc_url = test_support::stub_rpc(move |method, params| match method {
+            "eth_getCode" => Ok(json!(code)),
+            "eth_call" => getter(params[0]["data"].as_str().unwrap()),
+            _ => Err(json!({ "code": -32601, "message": "method not found" })),
+        }).await;
+        test_support::app_state_with(pool, Arc::new(SystemClock), config)
+    }
+
+    async fn verify(app_state: Arc<AppState>, address: &str, chain_id: u32) -> Result<TokenVerification, AppError> {
+        verify_token(State(app_state), Path(address.to_string()), Query(VerifyTokenQuery { chain_id }))
+            .await
+            .map(|Json(verification)| verification)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn listed_token_matches_its_contract(pool: PgPool) {
+        let app_state = app_state(pool, "0x6080", |selector| match selector {
+            "0x06fdde03" => Ok(abi_string("USD Coin")),
+            "0x95d89b41" => Ok(abi_string("USDC")),
+            "0x313ce567" => Ok(abi_uint(6)),
+            _ => unreachable!("{selector}"),
+        }).await;
+
+        let verification = verify(app_state, USDC, CHAIN_ID).await.unwrap();
+        assert_eq!(verification.address, USDC);
+        assert!(verification.on_chain.is_contract);
+        assert_eq!(verification.on_chain.name.as_deref(), Some("USD Coin"));
+        assert_eq!(verification.on_chain.symbol.as_deref(), Some("USDC"));
+        assert_eq!(verification.on_chain.decimals, Some(6));
+        assert_eq!(verification.listed.unwrap().symbol, "USDC");
+        assert!(verification.matches_listing);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn contract_reporting_other_decimals_does_not_match(pool: PgPool) {
+        let app_state = app_state(pool, "0x6080", |selector| match selector {
+            "0x06fdde03" => Ok(abi_string("USD Coin")),
+            "0x95d89b41" => Ok(abi_string("USDC")),
+            "0x313ce567" => Ok(abi_uint(18)),
+            _ => unreachable!("{selector}"),
+        }).await;
+
+        let verification = verify(app_state, USDC, CHAIN_ID).await.unwrap();
+        assert!(verification.listed.is_some());
+        assert!(!verification.matches_listing);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn reverting_getters_are_left_out(pool: PgPool) {
+        let app_state = app_state(pool, "0x6080", |selector| match selector {
+            "0x313ce567" => Ok(abi_uint(6)),
+            _ => Err(json!({ "code": 3, "message": "execution reverted" })),
+        }).await;
+
+        let verification = verify(app_state, USDC, CHAIN_ID).await.unwrap();
+        assert!(verification.on_chain.is_contract);
+        assert_eq!(verification.on_chain.name, None);
+        assert_eq!(verification.on_chain.symbol, None);
+        assert_eq!(verification.on_chain.decimals, Some(6));
+        assert!(!verification.matches_listing);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn addresses_without_code_are_not_contracts(pool: PgPool) {
+        let app_state = app_state(pool, "0x", |selector| unreachable!("{selector}")).await;
+
+        let verification = verify(app_state, "0x0000000000000000000000000000000000000001", CHAIN_ID).await.unwrap();
+        assert!(!verification.on_chain.is_contract);
+        assert_eq!(verification.on_chain.symbol, None);
+        assert!(verification.listed.is_none());
+        assert!(!verification.matches_listing);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn other_chains_and_invalid_addresses_are_rejected(pool: PgPool) {
+        let app_state = app_state(pool, "0x6080", |selector| unreachable!("{selector}")).await;
+
+        let result = verify(app_state.clone(), USDC, 1).await;
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+        let result = verify(app_state, "0x1234", CHAIN_ID).await;
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+    }
+}
@@ -0,0 +1,148 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::HeaderMap,
+};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{admin_user::AdminUser, auth_user::AuthUser, json::Json},
+    models::{
+        security_events::{record_event, EventType, REVOKED_FOR_SECURITY},
+        sessions::Session,
+        users::{User, UserPage, UserSummary},
+    },
+    utils::server_utils::extract_client_info,
+    AppState,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct SetVerificationRequest {
+    pub verified: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeSessionsResponse {
+    pub revoked_sessions: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserSearchQuery {
+    pub q: Option<String>,
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Searches users by partial address, email or username, e.g. `?q=alice&limit=20`
+///
+/// Without `q` every user is listed. Page with `offset` while `has_more` is true.
+pub async fn search_users(
+    State(app_state): State<Arc<AppState>>,
+    _admin: AdminUser,
+    Query(params): Query<UserSearchQuery>,
+) -> Result<Json<UserPage>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}", MAX_PAGE_SIZE
+        )));
+    }
+
+    let offset = params.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(AppError::ValidationError("offset cannot be negative".to_string()));
+    }
+
+    let term = params.q.as_deref().map(str::trim).unwrap_or_default();
+    let page = User::search(&app_state.pool, term, offset, limit).await?;
+
+    Ok(Json(page))
+}
+
+/// Profile of the caller, including whether the account is verified
+pub async fn get_me(
+    State(app_state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+) -> Result<Json<UserSummary>, AppError> {
+    let user = User::get_user_by_id(&app_state.pool, auth_user.user_id())
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+    Ok(Json(user.into()))
+}
+
+/// Grants or withdraws a user's verified mark, recorded on the user's event log
+pub async fn set_user_verification(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+    Json(payload): Json<SetVerificationRequest>,
+) -> Result<Json<UserSummary>, AppError> {
+    let user = User::set_verified(&app_state.pool, app_state.clock.as_ref(), user_id, payload.verified)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError(format!("User {} not found", user_id)))?;
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::VerificationChanged,
+        user.id,
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "verified": user.is_verified,
+            "changed_by": admin.user_id(),
+        }),
+    ).await?;
+
+    Ok(Json(user))
+}
+
+/// Signs a user out everywhere, e.g. when their account is compromised
+///
+/// Every active session is revoked and its refresh token blacklisted, so the
+/// user's next refresh fails, and their token epoch is bumped so access
+/// tokens already issued stop working at once. Recorded as a
+/// `SessionsRevoked` event on the user's log.
+pub async fn revoke_user_sessions(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    admin: AdminUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<RevokeSessionsResponse>, AppError> {
+    let user = User::get_user_by_id(&app_state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError(format!("User {} not found", user_id)))?;
+
+    let revoked_sessions = Session::revoke_all_for_user(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        user.id,
+        REVOKED_FOR_SECURITY,
+    ).await?;
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::SessionsRevoked,
+        user.id,
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "revoked_sessions": revoked_sessions,
+            "revoked_by": admin.user_id(),
+        }),
+    ).await?;
+
+    Ok(Json(RevokeSessionsResponse { revoked_sessions }))
+}
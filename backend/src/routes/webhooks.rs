@@ -0,0 +1,186 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::HeaderMap,
+};
+use serde::Serialize;
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::{
+    app_error::app_error::AppError,
+    extractors::{admin_user::AdminUser, json::Json},
+    models::{
+        outbox::{OutboxDeliveryAttempt, OutboxMessage},
+        security_events::{record_event, EventType},
+    },
+    services::outbox::replay_message,
+    utils::server_utils::extract_client_info,
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct ReplayWebhookResponse {
+    /// The message after the replay
+    pub message: OutboxMessage,
+    /// The call just made, `error` tells whether the webhook accepted it
+    pub attempt: OutboxDeliveryAttempt,
+}
+
+/// Re-sends an outbox message to its webhook, dead-lettered ones included
+///
+/// Answers 200 whether or not the webhook accepted the message; a failed
+/// replay is reported in `attempt.error`.
+pub async fn replay_webhook(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    admin: AdminUser,
+    Path(delivery_id): Path<i64>,
+) -> Result<Json<ReplayWebhookResponse>, AppError> {
+    let (message, attempt) = replay_message(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        &app_state.config.outbox,
+        delivery_id,
+        admin.user_id(),
+    ).await?;
+
+    let (client_ip, user_agent) = extract_client_info(&headers, addr);
+    record_event(
+        &app_state.pool,
+        app_state.clock.as_ref(),
+        EventType::WebhookReplayed,
+        admin.user_id(),
+        client_ip,
+        &user_agent,
+        serde_json::json!({
+            "message_id": message.id,
+            "event_type": message.event_type,
+            "aggregate_type": message.aggregate_type,
+            "aggregate_id": message.aggregate_id,
+            "webhook_url": attempt.webhook_url,
+            "delivered": attempt.error.is_none(),
+        }),
+    ).await?;
+
+    Ok(Json(ReplayWebhookResponse { message, attempt }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::users::User,
+        test_support,
+        utils::clock::{Clock, MockClock},
+    };
+    use axum::{http::StatusCode, routing::post, Router};
+    use chrono::{Duration, NaiveDate};
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    const ADMIN: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    /// URL of a webhook answering every delivery with `status`
+    async fn webhook(status: StatusCode) -> String {
+        let app = Router::new().route("/", post(move || async move { status }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    /// A message the relay gave up on after the webhook refused it
+    async fn dead_letter(pool: &PgPool, clock: &MockClock, webhook_url: &str) -> i64 {
+        let mut conn = pool.acquire().await.unwrap();
+        OutboxMessage::enqueue(&mut conn, clock.now(), "invoice", Uuid::new_v4(), "invoice.paid", serde_json::json!({ "amount": "12.5" }))
+            .await
+            .unwrap();
+        let id = sqlx::query_scalar!("SELECT MAX(id) as \"id!\" FROM outbox_messages").fetch_one(pool).await.unwrap();
+        OutboxDeliveryAttempt::record(pool, id, webhook_url, clock.now(), Some("webhook answered 410 Gone"), None).await.unwrap();
+        OutboxMessage::mark_dead(pool, id, "webhook answered 410 Gone", clock.now()).await.unwrap();
+        clock.advance(Duration::hours(1));
+        id
+    }
+
+    async fn setup(pool: &PgPool, status: StatusCode) -> (Arc<AppState>, Arc<MockClock>, User, i64) {
+        test_support::init_schema(pool).await;
+        let clock = Arc::new(MockClock::new(NaiveDate::from_ymd_opt(2026, 5, 4).unwrap().and_hms_opt(8, 0, 0).unwrap()));
+        let mut config = test_support::config();
+        config.outbox.enabled = true;
+        config.outbox.webhook_url = webhook(status).await;
+        let id = dead_letter(pool, &clock, &config.outbox.webhook_url).await;
+        let admin = test_support::create_user(pool, clock.as_ref(), ADMIN).await;
+        (test_support::app_state_with(pool.clone(), clock.clone(), config), clock, admin, id)
+    }
+
+    async fn replay(app_state: &Arc<AppState>, admin: &User, id: i64) -> Result<ReplayWebhookResponse, AppError> {
+        let addr = SocketAddr::new(test_support::client_ip().ip(), 443);
+        replay_webhook(State(app_state.clone()), ConnectInfo(addr), HeaderMap::new(), test_support::admin(admin), Path(id))
+            .await
+            .map(|Json(response)| response)
+    }
+
+    async fn attempts(pool: &PgPool, id: i64) -> Vec<(Option<String>, Option<Uuid>)> {
+        sqlx::query!("SELECT error, replayed_by FROM outbox_delivery_attempts WHERE message_id = $1 ORDER BY id", id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.error, row.replayed_by))
+            .collect()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn replaying_a_dead_letter_records_a_fresh_attempt(pool: PgPool) {
+        let (app_state, clock, admin, id) = setup(&pool, StatusCode::NO_CONTENT).await;
+
+        let response = replay(&app_state, &admin, id).await.unwrap();
+        assert_eq!(response.attempt.message_id, id);
+        assert_eq!(response.attempt.error, None);
+        assert_eq!(response.attempt.replayed_by, Some(admin.id));
+        assert_eq!(response.attempt.attempted_at, clock.now());
+        assert_eq!(response.message.attempts, 2);
+        assert_eq!(response.message.sent_at, Some(clock.now()));
+        // The dead-lettering stays as history
+        assert!(response.message.dead_at.is_some());
+
+        assert_eq!(attempts(&pool, id).await, [
+            (Some("webhook answered 410 Gone".to_string()), None),
+            (None, Some(admin.id)),
+        ]);
+
+        let metadata = sqlx::query_scalar!(
+            r#"SELECT metadata as "metadata!" FROM security_events WHERE user_id = $1 AND event_type = 'webhookreplayed'"#,
+            admin.id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(metadata["message_id"], id);
+        assert_eq!(metadata["delivered"], true);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn failed_replays_are_reported_in_the_attempt(pool: PgPool) {
+        let (app_state, _clock, admin, id) = setup(&pool, StatusCode::SERVICE_UNAVAILABLE).await;
+
+        let response = replay(&app_state, &admin, id).await.unwrap();
+        assert_eq!(response.attempt.error.as_deref(), Some("webhook answered 503 Service Unavailable"));
+        assert_eq!(response.message.sent_at, None);
+        assert_eq!(response.message.last_error, response.attempt.error);
+        assert_eq!(attempts(&pool, id).await.len(), 2);
+
+        // Each replay is one more attempt
+        replay(&app_state, &admin, id).await.unwrap();
+        assert_eq!(attempts(&pool, id).await.len(), 3);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn unknown_messages_are_not_found(pool: PgPool) {
+        let (app_state, _clock, admin, id) = setup(&pool, StatusCode::OK).await;
+
+        let result = replay(&app_state, &admin, id + 1).await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))));
+        assert_eq!(attempts(&pool, id).await.len(), 1);
+    }
+}
@@ -0,0 +1,449 @@
+use chrono::{Duration, NaiveDateTime};
+use sqlx::{types::ipnetwork::IpNetwork, PgPool};
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::AnomalyDetection,
+    models::{
+        security_events::{
+            anomaly_recorded_since, count_logins_in_range, failed_logins_per_user_since,
+            login_ips_since, record_event, EventType,
+        },
+        users::User,
+    },
+    services::{geoip::GeoLocator, notifier::Notifier},
+    utils::clock::Clock,
+};
+
+/// Events are recorded by the detector itself rather than for a request,
+/// so they carry the unspecified address; observed IPs are in the metadata
+const DETECTOR_IP: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DETECTOR_USER_AGENT: &str = "anomaly-detector";
+
+/// Kind of suspicious pattern, stored as `metadata.kind` of `AnomalyDetected`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// One user signed in from too many countries within the window
+    ImpossibleTravel,
+    /// Failed logins across many addresses spiked within the window
+    FailedLoginSpike,
+    /// A user signed in from an IP range not seen in their history
+    NewIpRange,
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::ImpossibleTravel => "impossible_travel",
+            AnomalyKind::FailedLoginSpike => "failed_login_spike",
+            AnomalyKind::NewIpRange => "new_ip_range",
+        }
+    }
+
+    fn subject(&self) -> &'static str {
+        match self {
+            AnomalyKind::ImpossibleTravel => "Sign-ins from several countries",
+            AnomalyKind::FailedLoginSpike => "Spike of failed sign-ins",
+            AnomalyKind::NewIpRange => "Sign-in from a new network",
+        }
+    }
+}
+
+/// A pattern flagged for one user
+#[derive(Debug)]
+pub struct Anomaly {
+    pub kind: AnomalyKind,
+    pub user_id: Uuid,
+    pub details: serde_json::Value,
+}
+
+/// Periodically scans recent security events for anomalies
+///
+/// Each anomaly is recorded as a high-severity `AnomalyDetected` event on the
+/// affected user, at most once per kind and user within `window_secs`, and
+/// sent to the account owner and `alert_recipient` when they have an address.
+pub fn spawn_anomaly_detection_task(
+    pool: PgPool,
+    clock: Arc<dyn Clock>,
+    notifier: Arc<dyn Notifier>,
+    geo_locator: Arc<GeoLocator>,
+    config: AnomalyDetection,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(config.interval_secs));
+        let mut last_run = clock.now() - Duration::seconds(config.interval_secs as i64);
+
+        loop {
+            interval.tick().await;
+
+            let now = clock.now();
+            match detect_anomalies(&pool, &geo_locator, &config, last_run, now).await {
+                Ok(anomalies) => {
+                    for anomaly in anomalies {
+                        if let Err(e) = report_anomaly(&pool, clock.as_ref(), notifier.as_ref(), &config, now, anomaly).await {
+                            eprintln!("Failed to record anomaly: {}", e);
+                        }
+                    }
+                    last_run = now;
+                }
+                Err(e) => eprintln!("Failed to scan security events for anomalies: {}", e),
+            }
+        }
+    })
+}
+
+/// Anomalies visible at `now`; logins are only checked for new IP ranges
+/// once, in the scan following the one before `since`
+pub async fn detect_anomalies(
+    pool: &PgPool,
+    geo_locator: &GeoLocator,
+    config: &AnomalyDetection,
+    since: NaiveDateTime,
+    now: NaiveDateTime,
+) -> Result<Vec<Anomaly>, AppError> {
+    let window_start = now - Duration::seconds(config.window_secs as i64);
+    let recent_logins = login_ips_since(pool, window_start.min(since)).await?;
+
+    let window_logins: Vec<(Uuid, IpAddr)> = recent_logins.iter()
+        .map(|(user_id, ip)| (*user_id, ip.ip()))
+        .collect();
+    let mut anomalies = detect_impossible_travel(&window_logins, config.max_countries_per_user, |ip| {
+        geo_locator.locate(ip).and_then(|location| location.country)
+    });
+
+    let failed_logins = failed_logins_per_user_since(pool, window_start).await?;
+    anomalies.extend(detect_failed_login_spike(&failed_logins, config));
+
+    let lookback_start = since - Duration::days(config.known_range_lookback_days);
+    let mut checked = BTreeSet::new();
+    for (user_id, ip) in recent_logins {
+        let range = ip_range(ip.ip(), config);
+        if !checked.insert((user_id, range)) {
+            continue;
+        }
+
+        let (known_logins, logins_in_range) = count_logins_in_range(pool, user_id, range, lookback_start, since).await?;
+        // Users without earlier logins have no history to compare against
+        if known_logins > 0 && logins_in_range == 0 {
+            anomalies.push(Anomaly {
+                kind: AnomalyKind::NewIpRange,
+                user_id,
+                details: serde_json::json!({
+                    "ip": ip.ip().to_string(),
+                    "range": range.to_string(),
+                    "known_logins": known_logins,
+                    "lookback_days": config.known_range_lookback_days,
+                }),
+            });
+        }
+    }
+
+    Ok(anomalies)
+}
+
+/// Users whose logins come from at least `max_countries` countries
+///
+/// IPs `locate` has no country for are ignored.
+pub fn detect_impossible_travel(
+    logins: &[(Uuid, IpAddr)],
+    max_countries: usize,
+    locate: impl Fn(IpAddr) -> Option<String>,
+) -> Vec<Anomaly> {
+    let mut countries: HashMap<Uuid, BTreeSet<String>> = HashMap::new();
+    for (user_id, ip) in logins {
+        if let Some(country) = locate(*ip) {
+            countries.entry(*user_id).or_default().insert(country);
+        }
+    }
+
+    countries.into_iter()
+        .filter(|(_, countries)| countries.len() >= max_countries)
+        .map(|(user_id, countries)| Anomaly {
+            kind: AnomalyKind::ImpossibleTravel,
+            user_id,
+            details: serde_json::json!({ "countries": countries }),
+        })
+        .collect()
+}
+
+/// One anomaly per targeted user when failed logins across addresses spike
+pub fn detect_failed_login_spike(
+    failed_logins: &[(Uuid, i64)],
+    config: &AnomalyDetection,
+) -> Vec<Anomaly> {
+    let total: i64 = failed_logins.iter().map(|(_, count)| count).sum();
+    if total < config.failed_login_spike_threshold
+        || (failed_logins.len() as i64) < config.failed_login_spike_min_addresses
+    {
+        return Vec::new();
+    }
+
+    failed_logins.iter()
+        .map(|(user_id, count)| Anomaly {
+            kind: AnomalyKind::FailedLoginSpike,
+            user_id: *user_id,
+            details: serde_json::json!({
+                "failed_logins": count,
+                "total_failed_logins": total,
+                "targeted_addresses": failed_logins.len(),
+                "window_secs": config.window_secs,
+            }),
+        })
+        .collect()
+}
+
+/// Network of `ip` at the configured prefix length, e.g. `203.0.113.0/24`
+pub fn ip_range(ip: IpAddr, config: &AnomalyDetection) -> IpNetwork {
+    let prefix = match ip {
+        IpAddr::V4(_) => config.ipv4_range_prefix,
+        IpAddr::V6(_) => config.ipv6_range_prefix,
+    };
+    IpNetwork::new(ip, prefix)
+        .and_then(|network| IpNetwork::new(network.network(), prefix))
+        .unwrap_or_else(|_| IpNetwork::from(ip))
+}
+
+async fn report_anomaly(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    notifier: &dyn Notifier,
+    config: &AnomalyDetection,
+    now: NaiveDateTime,
+    anomaly: Anomaly,
+) -> Result<(), AppError> {
+    let window_start = now - Duration::seconds(config.window_secs as i64);
+    if anomaly_recorded_since(pool, anomaly.user_id, anomaly.kind.as_str(), window_start).await? {
+        return Ok(());
+    }
+
+    record_event(
+        pool,
+        clock,
+        EventType::AnomalyDetected,
+        anomaly.user_id,
+        IpNetwork::from(DETECTOR_IP),
+        DETECTOR_USER_AGENT,
+        serde_json::json!({
+            "kind": anomaly.kind.as_str(),
+            "severity": "high",
+            "details": anomaly.details,
+        }),
+    )
+    .await?;
+
+    let user = User::get_user_by_id(pool, anomaly.user_id).await?;
+    let address = user.as_ref().map_or("an unknown account", |user| user.ethereum_address.as_str());
+    let body = format!(
+        "{} was detected on {} at {} UTC.\n{}\n\
+         If this activity was not yours, review your recent sessions and sign out of the others.",
+        anomaly.kind.subject(), address, now.format("%Y-%m-%d %H:%M:%S"), anomaly.details
+    );
+
+    let owner_email = user.as_ref().map(|user| user.email.trim()).filter(|email| !email.is_empty());
+    for recipient in owner_email.into_iter().chain(config.alert_recipient.as_deref()) {
+        if let Err(e) = notifier.send(recipient, anomaly.kind.subject(), &body) {
+            eprintln!("Failed to send anomaly notification for user {}: {}", anomaly.user_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support, utils::clock::MockClock};
+    use chrono::NaiveDate;
+    use std::sync::Mutex;
+
+    fn config() -> AnomalyDetection {
+        AnomalyDetection {
+            failed_login_spike_threshold: 6,
+            failed_login_spike_min_addresses: 3,
+            alert_recipient: Some("security@example.com".to_string()),
+            ..test_support::config().anomaly_detection
+        }
+    }
+
+    fn start() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 3, 1).unwrap().and_hms_opt(12, 0, 0).unwrap()
+    }
+
+    fn address(n: u32) -> String {
+        format!("0x{:040x}", n)
+    }
+
+    async fn login(pool: &PgPool, clock: &MockClock, event_type: EventType, user: &User, ip: &str) {
+        record_event(pool, clock, event_type, user.id, ip.parse().unwrap(), "agent/1.0", serde_json::json!({}))
+            .await
+            .unwrap();
+    }
+
+    async fn scan(pool: &PgPool, clock: &MockClock, since: NaiveDateTime) -> Vec<Anomaly> {
+        // The development configuration has no GeoIP database
+        let geo_locator = GeoLocator::new(&test_support::config().geoip);
+        detect_anomalies(pool, &geo_locator, &config(), since, clock.now()).await.unwrap()
+    }
+
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sent: Mutex<Vec<String>>,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn send(&self, recipient: &str, _subject: &str, _body: &str) -> Result<(), AppError> {
+            self.sent.lock().unwrap().push(recipient.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn logins_from_too_many_countries_are_impossible_travel() {
+        let traveler = Uuid::new_v4();
+        let commuter = Uuid::new_v4();
+        let logins = [
+            (traveler, "203.0.113.1".parse().unwrap()),
+            (traveler, "198.51.100.1".parse().unwrap()),
+            (traveler, "192.0.2.1".parse().unwrap()),
+            (commuter, "203.0.113.2".parse().unwrap()),
+            (commuter, "198.51.100.2".parse().unwrap()),
+            // Not located, so not a third country
+            (commuter, "10.0.0.1".parse().unwrap()),
+        ];
+        let locate = |ip: IpAddr| match ip.to_string().split('.').next().unwrap() {
+            "203" => Some("FR".to_string()),
+            "198" => Some("BR".to_string()),
+            "192" => Some("JP".to_string()),
+            _ => None,
+        };
+
+        let anomalies = detect_impossible_travel(&logins, 3, locate);
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].kind, AnomalyKind::ImpossibleTravel);
+        assert_eq!(anomalies[0].user_id, traveler);
+        assert_eq!(anomalies[0].details["countries"], serde_json::json!(["BR", "FR", "JP"]));
+    }
+
+    #[test]
+    fn failed_login_spike_needs_enough_failures_and_addresses() {
+        let users: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        let spike: Vec<(Uuid, i64)> = users.iter().map(|user| (*user, 2)).collect();
+        let anomalies = detect_failed_login_spike(&spike, &config());
+        assert_eq!(anomalies.len(), 3);
+        assert!(anomalies.iter().all(|anomaly| anomaly.kind == AnomalyKind::FailedLoginSpike));
+        assert_eq!(anomalies[0].details["total_failed_logins"], 6);
+
+        // One mistyping user, however many failures
+        assert!(detect_failed_login_spike(&[(users[0], 50)], &config()).is_empty());
+        // Enough addresses, too few failures
+        let quiet: Vec<(Uuid, i64)> = users.iter().map(|user| (*user, 1)).collect();
+        assert!(detect_failed_login_spike(&quiet, &config()).is_empty());
+    }
+
+    #[test]
+    fn ip_ranges_use_the_configured_prefixes() {
+        assert_eq!(ip_range("203.0.113.77".parse().unwrap(), &config()).to_string(), "203.0.113.0/24");
+        assert_eq!(ip_range("2001:db8:1:2::1".parse().unwrap(), &config()).to_string(), "2001:db8:1::/48");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn logins_from_a_new_range_are_flagged(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(start());
+        let regular = test_support::create_user(&pool, &clock, &address(1)).await;
+        let newcomer = test_support::create_user(&pool, &clock, &address(2)).await;
+
+        login(&pool, &clock, EventType::Login, &regular, "203.0.113.7").await;
+        clock.advance(Duration::days(2));
+        let since = clock.now();
+        clock.advance(Duration::minutes(5));
+        // Same /24 as before, then another network
+        login(&pool, &clock, EventType::Login, &regular, "203.0.113.99").await;
+        login(&pool, &clock, EventType::Login, &regular, "198.51.100.4").await;
+        // No history to compare against
+        login(&pool, &clock, EventType::Login, &newcomer, "192.0.2.1").await;
+
+        let anomalies = scan(&pool, &clock, since).await;
+        assert_eq!(anomalies.len(), 1, "{anomalies:?}");
+        assert_eq!(anomalies[0].kind, AnomalyKind::NewIpRange);
+        assert_eq!(anomalies[0].user_id, regular.id);
+        assert_eq!(anomalies[0].details["range"], "198.51.100.0/24");
+        assert_eq!(anomalies[0].details["known_logins"], 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn failed_logins_across_addresses_are_a_spike(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(start());
+        let since = clock.now();
+        let mut targeted = Vec::new();
+        for n in 1..=3 {
+            let user = test_support::create_user(&pool, &clock, &address(n)).await;
+            for _ in 0..2 {
+                login(&pool, &clock, EventType::FailedLogin, &user, "192.0.2.66").await;
+            }
+            targeted.push(user.id);
+        }
+        clock.advance(Duration::minutes(1));
+
+        let mut flagged: Vec<Uuid> = scan(&pool, &clock, since).await
+            .into_iter()
+            .filter(|anomaly| anomaly.kind == AnomalyKind::FailedLoginSpike)
+            .map(|anomaly| anomaly.user_id)
+            .collect();
+        flagged.sort();
+        targeted.sort();
+        assert_eq!(flagged, targeted);
+
+        // Out of the window, the failures no longer count
+        clock.advance(Duration::seconds(config().window_secs as i64));
+        assert!(scan(&pool, &clock, clock.now()).await.is_empty());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn anomalies_are_reported_once_per_window(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(start());
+        let user = test_support::create_user(&pool, &clock, &address(1)).await;
+        sqlx::query!("UPDATE users SET email = 'owner@example.com' WHERE id = $1", user.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let notifier = RecordingNotifier::default();
+        let anomaly = || Anomaly {
+            kind: AnomalyKind::NewIpRange,
+            user_id: user.id,
+            details: serde_json::json!({ "range": "198.51.100.0/24" }),
+        };
+
+        for _ in 0..2 {
+            report_anomaly(&pool, &clock, &notifier, &config(), clock.now(), anomaly()).await.unwrap();
+            clock.advance(Duration::minutes(5));
+        }
+
+        let recorded = sqlx::query_scalar!(
+            r#"SELECT metadata as "metadata!" FROM security_events WHERE user_id = $1 AND event_type = 'anomalydetected'"#,
+            user.id
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0]["kind"], "new_ip_range");
+        assert_eq!(recorded[0]["severity"], "high");
+        assert_eq!(*notifier.sent.lock().unwrap(), ["owner@example.com", "security@example.com"]);
+
+        // A new window reports it again
+        clock.advance(Duration::seconds(config().window_secs as i64));
+        report_anomaly(&pool, &clock, &notifier, &config(), clock.now(), anomaly()).await.unwrap();
+        assert_eq!(notifier.sent.lock().unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,86 @@
+//! Tamper-evident export of the security event log
+//!
+//! The export is a JSON Lines document: one `SecurityEvent` per line, in
+//! chronological order, followed by a single trailer line
+//!
+//! ```text
+//! {"signature":{"algorithm":"HMAC-SHA256","count":<events>,"hmac":"<hex>"}}
+//! ```
+//!
+//! `hmac` is the HMAC-SHA256, keyed with `audit.signing_key`, of every byte
+//! preceding the trailer, newlines included. To verify an export, strip the
+//! last line, recompute the HMAC over the remaining bytes exactly as received
+//! and compare it with the trailer; also check that `count` matches the
+//! number of event lines. Any edited, reordered, removed or truncated line
+//! changes the HMAC, and a missing trailer means the export was cut short.
+
+use axum::body::Bytes;
+use futures::{stream, Stream, StreamExt};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+
+use crate::{
+    app_error::app_error::AppError,
+    models::security_events::stream_all_events,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Streams the signed export, see the module documentation for the format
+///
+/// Events are read from the database as the client consumes the body. A
+/// database error ends the stream before the trailer, so a failed export can
+/// never verify.
+pub fn export_signed_events(
+    pool: PgPool,
+    signing_key: &str,
+) -> Result<impl Stream<Item = Result<Bytes, std::io::Error>> + use<>, AppError> {
+    let mut mac = HmacSha256::new_from_slice(signing_key.as_bytes())
+        .map_err(|e| AppError::ConfigError(format!("Invalid audit signing key: {}", e)))?;
+
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(64);
+
+    tokio::spawn(async move {
+        let mut events = stream_all_events(&pool);
+        let mut count: u64 = 0;
+
+        while let Some(event) = events.next().await {
+            let line = event.and_then(|event| {
+                serde_json::to_vec(&event)
+                    .map_err(|e| AppError::OtherError(format!("Failed to serialize event: {}", e)))
+            });
+            let mut line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("Audit export aborted: {}", e);
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    return;
+                }
+            };
+            line.push(b'\n');
+
+            mac.update(&line);
+            count += 1;
+
+            // The receiver is gone when the client disconnected
+            if tx.send(Ok(Bytes::from(line))).await.is_err() {
+                return;
+            }
+        }
+
+        let trailer = serde_json::json!({
+            "signature": {
+                "algorithm": "HMAC-SHA256",
+                "count": count,
+                "hmac": hex::encode(mac.finalize().into_bytes()),
+            }
+        });
+        let _ = tx.send(Ok(Bytes::from(format!("{}\n", trailer)))).await;
+    });
+
+    Ok(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }))
+}
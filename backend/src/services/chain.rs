@@ -0,0 +1,791 @@
+//! Minimal Ethereum JSON-RPC client for reading the `InvoicePayment` contract
+
+use bigdecimal::num_bigint::BigInt;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use sha3::{Digest, Keccak256};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::{Ethereum, RpcSaturation},
+    models::invoices::InvoiceStatus,
+    utils::ethereum::EthRpcError,
+};
+
+/// Seconds before an RPC request is abandoned
+const RPC_TIMEOUT_SECS: u64 = 10;
+/// Seconds before a chain head lookup is abandoned, kept short for `GET /status`
+const HEAD_TIMEOUT_SECS: u64 = 2;
+/// Seconds a chain head, or a failed lookup, is served from cache
+const HEAD_CACHE_SECS: u64 = 5;
+
+/// Seconds fee market data is served from cache
+const FEE_CACHE_SECS: u64 = 15;
+/// Seconds fee market data is still served while the RPC endpoint fails
+const FEE_STALE_SECS: u64 = 300;
+/// Tip suggested on EIP-1559 chains whose node does not suggest one, 1.5 gwei
+const DEFAULT_PRIORITY_FEE_WEI: u128 = 1_500_000_000;
+
+/// Seconds token contract metadata is served from cache
+const TOKEN_CACHE_SECS: u64 = 60;
+/// Contracts kept in the token metadata cache, bounding what arbitrary
+/// lookups can make it hold
+const TOKEN_CACHE_MAX_ENTRIES: usize = 1024;
+
+/// What `isValidSignature` returns for a valid signature, its own selector
+const EIP1271_MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// `Status` enum of `contracts/InvoicePayment.sol`, in declaration order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnChainStatus {
+    Pending,
+    Paid,
+    Disputed,
+    Released,
+    Validated,
+}
+
+impl OnChainStatus {
+    fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(OnChainStatus::Pending),
+            1 => Some(OnChainStatus::Paid),
+            2 => Some(OnChainStatus::Disputed),
+            3 => Some(OnChainStatus::Released),
+            4 => Some(OnChainStatus::Validated),
+            _ => None,
+        }
+    }
+
+    /// Status stored in the database, where released funds count as paid
+    pub fn invoice_status(self) -> InvoiceStatus {
+        match self {
+            OnChainStatus::Pending => InvoiceStatus::Pending,
+            OnChainStatus::Paid | OnChainStatus::Released | OnChainStatus::Validated => InvoiceStatus::Paid,
+            OnChainStatus::Disputed => InvoiceStatus::Disputed,
+        }
+    }
+}
+
+/// Latest block of a chain as seen by its RPC endpoint
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ChainHead {
+    pub chain_id: u32,
+    /// Absent when the RPC endpoint could not be reached
+    pub head_block: Option<u64>,
+    /// Seconds since the head was last seen advancing
+    pub head_age_secs: Option<u64>,
+    /// The head has not advanced for longer than `max_head_stall_secs`
+    pub stalled: bool,
+    pub healthy: bool,
+}
+
+/// How transactions pay for gas on a chain, in wei per gas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeMarket {
+    /// EIP-1559: a base fee burnt by the protocol plus a tip to the validator
+    Eip1559 { base_fee_per_gas: u128, max_priority_fee_per_gas: u128 },
+    Legacy { gas_price: u128 },
+}
+
+/// Outbound RPC requests of a chain, for `/metrics`
+#[derive(Debug, Clone, Copy)]
+pub struct RpcStatus {
+    pub max_concurrent: usize,
+    pub in_flight: usize,
+    pub saturation: RpcSaturation,
+    /// Requests failed since startup because `max_concurrent` were in flight
+    pub rejected: u64,
+}
+
+/// ERC-20 metadata as reported by a contract
+///
+/// A getter that reverts, is missing or returns garbage is `None`; all are
+/// `None` when the address holds no code.
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenMetadata {
+    pub is_contract: bool,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+#[derive(Clone)]
+pub struct ChainClient {
+    http: reqwest::Client,
+    rpc_url: String,
+    contract_address: String,
+    chain_id: u32,
+    max_head_stall: Option<Duration>,
+    head_cache: Arc<Mutex<Option<(Instant, ChainHead)>>>,
+    /// Highest block seen and when it was first seen
+    head_progress: Arc<Mutex<Option<(u64, Instant)>>>,
+    fee_cache: Arc<Mutex<Option<(Instant, FeeMarket)>>>,
+    token_cache: Arc<Mutex<HashMap<String, (Instant, TokenMetadata)>>>,
+    /// One permit per request allowed in flight, see `max_concurrent_rpc`
+    rpc_permits: Arc<Semaphore>,
+    max_concurrent_rpc: usize,
+    rpc_saturation: RpcSaturation,
+    rpc_queue_timeout: Duration,
+    rpc_rejected: Arc<AtomicU64>,
+}
+
+impl ChainClient {
+    pub fn new(ethereum: &Ethereum) -> Result<Self, AppError> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(RPC_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| AppError::ConfigError(format!("Failed to build RPC client: {}", e)))?;
+
+        Ok(ChainClient {
+            http,
+            rpc_url: ethereum.rpc_url.clone(),
+            contract_address: ethereum.contract_address.clone(),
+            chain_id: ethereum.chain_id,
+            max_head_stall: (ethereum.max_head_stall_secs > 0)
+                .then(|| Duration::from_secs(ethereum.max_head_stall_secs)),
+            head_cache: Arc::new(Mutex::new(None)),
+            head_progress: Arc::new(Mutex::new(None)),
+            fee_cache: Arc::new(Mutex::new(None)),
+            token_cache: Arc::new(Mutex::new(HashMap::new())),
+            rpc_permits: Arc::new(Semaphore::new(ethereum.max_concurrent_rpc)),
+            max_concurrent_rpc: ethereum.max_concurrent_rpc,
+            rpc_saturation: ethereum.rpc_saturation,
+            rpc_queue_timeout: Duration::from_secs(ethereum.rpc_queue_timeout_secs),
+            rpc_rejected: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn chain_id(&self) -> u32 {
+        self.chain_id
+    }
+
+    /// Sends a JSON-RPC request and returns its `result`
+    ///
+    /// See `EthRpcError::into_app_error` for how failures are reported.
+    pub async fn request(&self, method: &str, params: JsonValue) -> Result<JsonValue, AppError> {
+        self.rpc(method, params, Duration::from_secs(RPC_TIMEOUT_SECS))
+            .await
+            .map_err(|e| e.into_app_error(method))
+    }
+
+    pub fn rpc_status(&self) -> RpcStatus {
+        RpcStatus {
+            max_concurrent: self.max_concurrent_rpc,
+            in_flight: self.max_concurrent_rpc.saturating_sub(self.rpc_permits.available_permits()),
+            saturation: self.rpc_saturation,
+            rejected: self.rpc_rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn rpc(&self, method: &str, params: JsonValue, timeout: Duration) -> Result<JsonValue, EthRpcError> {
+        let _permit = self.acquire_rpc_permit().await?;
+
+        let response = self.http
+            .post(&self.rpc_url)
+            .timeout(timeout)
+            .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+            .send()
+            .await
+            .map_err(|e| EthRpcError::Transport(e.to_string()))?;
+        if let Some(error) = EthRpcError::from_http_response(&response) {
+            return Err(error);
+        }
+
+        let response: JsonValue = response.json()
+            .await
+            .map_err(|e| EthRpcError::Transport(e.to_string()))?;
+        EthRpcError::parse_response(&response)
+    }
+
+    /// Waits for a slot among the `max_concurrent_rpc` requests allowed in
+    /// flight, or fails at once when shedding
+    ///
+    /// The wait is bounded by `rpc_queue_timeout_secs`, so a slow endpoint
+    /// makes callers fail with 503 rather than pile up behind it.
+    async fn acquire_rpc_permit(&self) -> Result<SemaphorePermit<'_>, EthRpcError> {
+        let permit = match self.rpc_saturation {
+            RpcSaturation::Shed => self.rpc_permits.try_acquire().ok(),
+            RpcSaturation::Queue => tokio::time::timeout(self.rpc_queue_timeout, self.rpc_permits.acquire())
+                .await
+                .ok()
+                .and_then(Result::ok),
+        };
+        permit.ok_or_else(|| {
+            self.rpc_rejected.fetch_add(1, Ordering::Relaxed);
+            EthRpcError::Saturated { max_concurrent: self.max_concurrent_rpc }
+        })
+    }
+
+    /// Latest block number, cached for a few seconds
+    ///
+    /// Never fails: an unreachable or misbehaving endpoint is reported as an
+    /// unhealthy head, and that outcome is cached too so a down RPC is not
+    /// hammered by status polling. A head that stopped advancing for longer
+    /// than `max_head_stall_secs` is unhealthy too: the node is stuck, or
+    /// serving a stale view of the chain.
+    ///
+    /// While our own RPC concurrency limit is reached the last head is
+    /// served past its cache lifetime: the endpoint is busy, not down.
+    pub async fn head(&self) -> ChainHead {
+        let cached = *self.head_cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((fetched_at, head)) = cached
+            && fetched_at.elapsed() < Duration::from_secs(HEAD_CACHE_SECS)
+        {
+            return head;
+        }
+
+        let result = self.rpc("eth_blockNumber", json!([]), Duration::from_secs(HEAD_TIMEOUT_SECS)).await;
+        if let (Err(EthRpcError::Saturated { .. }), Some((_, head))) = (&result, cached) {
+            return head;
+        }
+        let head_block = result
+            .ok()
+            .and_then(|result| {
+                let digits = result.as_str()?.strip_prefix("0x")?;
+                u64::from_str_radix(digits, 16).ok()
+            });
+        let head_age = head_block.map(|block| self.head_age(block));
+        let stalled = head_age.zip(self.max_head_stall).is_some_and(|(age, max)| age > max);
+        let head = ChainHead {
+            chain_id: self.chain_id,
+            head_block,
+            head_age_secs: head_age.map(|age| age.as_secs()),
+            stalled,
+            healthy: head_block.is_some() && !stalled,
+        };
+
+        *self.head_cache.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), head));
+        head
+    }
+
+    /// Time since `block`, or a higher one, was first seen as the head
+    ///
+    /// A head going backwards, e.g. on a reorg or a lagging node behind a
+    /// load balancer, does not count as progress.
+    fn head_age(&self, block: u64) -> Duration {
+        let mut progress = self.head_progress.lock().unwrap_or_else(|e| e.into_inner());
+        match *progress {
+            Some((highest, seen_at)) if block <= highest => seen_at.elapsed(),
+            _ => {
+                *progress = Some((block, Instant::now()));
+                Duration::ZERO
+            }
+        }
+    }
+
+    /// Refuses to act on the chain while its head is stalled, so a stuck
+    /// node cannot drive invoice status changes
+    async fn ensure_head_advancing(&self) -> Result<(), AppError> {
+        let head = self.head().await;
+        if head.stalled {
+            return Err(AppError::ServiceUnavailableError(format!(
+                "Chain {} head has not advanced for {}s, payment confirmations are paused",
+                self.chain_id,
+                head.head_age_secs.unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+
+    /// On-chain ids of the invoices a transaction paid, from its `PaymentMade` logs
+    ///
+    /// Returns `None` while the transaction has no receipt. A reverted
+    /// transaction, or one to another contract, paid nothing. Fails while
+    /// the chain head is stalled.
+    pub async fn paid_invoice_ids(&self, tx_hash: &str) -> Result<Option<Vec<BigInt>>, AppError> {
+        self.ensure_head_advancing().await?;
+
+        let receipt = self.request("eth_getTransactionReceipt", json!([tx_hash])).await?;
+        if receipt.is_null() {
+            return Ok(None);
+        }
+        if receipt.get("status").and_then(JsonValue::as_str) != Some("0x1") {
+            return Ok(Some(Vec::new()));
+        }
+
+        let payment_made = format!("0x{}", hex::encode(Keccak256::digest(b"PaymentMade(uint256,uint256)")));
+        let invoice_ids = receipt.get("logs")
+            .and_then(JsonValue::as_array)
+            .into_iter()
+            .flatten()
+            .filter(|log| {
+                log.get("address")
+                    .and_then(JsonValue::as_str)
+                    .is_some_and(|address| address.eq_ignore_ascii_case(&self.contract_address))
+            })
+            .filter_map(|log| {
+                // PaymentMade(uint256 indexed invoiceId, uint256 amount)
+                let topics = log.get("topics")?.as_array()?;
+                if !topics.first()?.as_str()?.eq_ignore_ascii_case(&payment_made) {
+                    return None;
+                }
+                let invoice_id = hex::decode(topics.get(1)?.as_str()?.strip_prefix("0x")?).ok()?;
+                Some(BigInt::from_bytes_be(bigdecimal::num_bigint::Sign::Plus, &invoice_id))
+            })
+            .collect();
+
+        Ok(Some(invoice_ids))
+    }
+
+    /// Reads an invoice through the contract's public `invoices(uint256)` getter
+    ///
+    /// Returns `None` when the contract has no invoice with this id. Fails
+    /// while the chain head is stalled.
+    pub async fn invoice_status(&self, on_chain_id: &str) -> Result<Option<OnChainStatus>, AppError> {
+        let invoice_id = invoice_id_word(on_chain_id)?;
+        self.ensure_head_advancing().await?;
+
+        let Some(words) = self.read_invoice(&invoice_id).await? else {
+            return Ok(None);
+        };
+        OnChainStatus::from_index(words[6 * 32 - 1])
+            .map(Some)
+            .ok_or_else(|| AppError::ServiceUnavailableError("Unknown on-chain invoice status".to_string()))
+    }
+
+    /// Amount in wei `payInvoice` must be sent for an invoice, `None` when
+    /// the contract has no invoice with this id
+    pub async fn invoice_amount(&self, on_chain_id: &str) -> Result<Option<BigInt>, AppError> {
+        let invoice_id = invoice_id_word(on_chain_id)?;
+        let amount = self.read_invoice(&invoice_id).await?
+            .map(|words| BigInt::from_bytes_be(bigdecimal::num_bigint::Sign::Plus, &words[3 * 32..4 * 32]));
+        Ok(amount)
+    }
+
+    /// Words returned by `invoices(uint256)`:
+    /// (invoiceId, client, emitter, amount, paymentTimeStamp, status)
+    async fn read_invoice(&self, invoice_id: &[u8; 32]) -> Result<Option<Vec<u8>>, AppError> {
+        let mut data = function_selector("invoices(uint256)").to_vec();
+        data.extend(invoice_id);
+
+        let result = self.request(
+            "eth_call",
+            json!([{ "to": self.contract_address, "data": format!("0x{}", hex::encode(data)) }, "latest"]),
+        ).await?;
+
+        let words = result.as_str()
+            .and_then(|result| result.strip_prefix("0x"))
+            .and_then(|result| hex::decode(result).ok())
+            .filter(|words| words.len() >= 6 * 32)
+            .ok_or_else(|| AppError::ServiceUnavailableError("Unexpected invoices() return data".to_string()))?;
+
+        // A zero id means no invoice
+        if words[..32].iter().all(|byte| *byte == 0) {
+            return Ok(None);
+        }
+        Ok(Some(words))
+    }
+
+    /// Calldata paying an invoice through `payInvoice(uint256)`
+    pub fn pay_invoice_calldata(&self, on_chain_id: &str) -> Result<String, AppError> {
+        let mut data = function_selector("payInvoice(uint256)").to_vec();
+        data.extend(invoice_id_word(on_chain_id)?);
+        Ok(format!("0x{}", hex::encode(data)))
+    }
+
+    pub fn contract_address(&self) -> &str {
+        &self.contract_address
+    }
+
+    /// Current fees, cached for a few seconds
+    ///
+    /// Chains whose latest block has a base fee are EIP-1559 chains. When
+    /// the endpoint fails, a market read within the last few minutes is
+    /// returned instead, flagged as stale.
+    pub async fn fee_market(&self) -> Result<(FeeMarket, bool), AppError> {
+        let cached = *self.fee_cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((fetched_at, market)) = cached
+            && fetched_at.elapsed() < Duration::from_secs(FEE_CACHE_SECS)
+        {
+            return Ok((market, false));
+        }
+
+        match self.fetch_fee_market().await {
+            Ok(market) => {
+                *self.fee_cache.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), market));
+                Ok((market, false))
+            }
+            Err(e) => match cached {
+                Some((fetched_at, market)) if fetched_at.elapsed() < Duration::from_secs(FEE_STALE_SECS) => {
+                    eprintln!("Serving a stale fee market: {}", e);
+                    Ok((market, true))
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    async fn fetch_fee_market(&self) -> Result<FeeMarket, AppError> {
+        let block = self.request("eth_getBlockByNumber", json!(["latest", false])).await?;
+        let Some(base_fee_per_gas) = block.get("baseFeePerGas").and_then(parse_quantity) else {
+            let gas_price = self.request("eth_gasPrice", json!([])).await?;
+            let gas_price = parse_quantity(&gas_price)
+                .ok_or_else(|| AppError::ServiceUnavailableError("Unexpected eth_gasPrice result".to_string()))?;
+            return Ok(FeeMarket::Legacy { gas_price });
+        };
+
+        // Not every EIP-1559 node serves the tip suggestion
+        let max_priority_fee_per_gas = match self.rpc("eth_maxPriorityFeePerGas", json!([]), Duration::from_secs(RPC_TIMEOUT_SECS)).await {
+            Ok(tip) => parse_quantity(&tip).unwrap_or(DEFAULT_PRIORITY_FEE_WEI),
+            Err(EthRpcError::MethodNotFound(_)) => DEFAULT_PRIORITY_FEE_WEI,
+            Err(e) => return Err(e.into_app_error("eth_maxPriorityFeePerGas")),
+        };
+
+        Ok(FeeMarket::Eip1559 { base_fee_per_gas, max_priority_fee_per_gas })
+    }
+
+    /// Gas `transaction` would use, `None` when the node cannot simulate it,
+    /// e.g. because it would revert for this sender
+    pub async fn estimate_gas(&self, transaction: JsonValue) -> Option<u64> {
+        let result = self.rpc("eth_estimateGas", json!([transaction]), Duration::from_secs(RPC_TIMEOUT_SECS)).await;
+        match result {
+            Ok(gas) => parse_quantity(&gas).and_then(|gas| u64::try_from(gas).ok()),
+            Err(e) => {
+                eprintln!("Gas estimation failed: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Reads `name()`, `symbol()` and `decimals()` of a token contract, cached
+    /// for a minute
+    ///
+    /// `address` must be normalized. Failures to reach the RPC endpoint are
+    /// errors and are not cached, unlike what the contract answered.
+    pub async fn token_metadata(&self, address: &str) -> Result<TokenMetadata, AppError> {
+        if let Some((fetched_at, metadata)) = self.token_cache.lock().unwrap_or_else(|e| e.into_inner()).get(address)
+            && fetched_at.elapsed() < Duration::from_secs(TOKEN_CACHE_SECS)
+        {
+            return Ok(metadata.clone());
+        }
+
+        let code = self.request("eth_getCode", json!([address, "latest"])).await?;
+        let is_contract = code.as_str().is_some_and(|code| code.len() > "0x".len());
+
+        let metadata = if is_contract {
+            TokenMetadata {
+                is_contract,
+                name: self.call_view(address, "name()").await?.and_then(|data| decode_abi_string(&data)),
+                symbol: self.call_view(address, "symbol()").await?.and_then(|data| decode_abi_string(&data)),
+                decimals: self.call_view(address, "decimals()").await?.and_then(|data| decode_abi_u8(&data)),
+            }
+        } else {
+            TokenMetadata { is_contract, name: None, symbol: None, decimals: None }
+        };
+
+        let mut cache = self.token_cache.lock().unwrap_or_else(|e| e.into_inner());
+        if cache.len() >= TOKEN_CACHE_MAX_ENTRIES {
+            cache.retain(|_, (fetched_at, _)| fetched_at.elapsed() < Duration::from_secs(TOKEN_CACHE_SECS));
+            if cache.len() >= TOKEN_CACHE_MAX_ENTRIES {
+                cache.clear();
+            }
+        }
+        cache.insert(address.to_string(), (Instant::now(), metadata.clone()));
+
+        Ok(metadata)
+    }
+
+    /// Calls an argument-less view function, `None` when it reverts
+    async fn call_view(&self, address: &str, signature: &str) -> Result<Option<Vec<u8>>, AppError> {
+        let data = format!("0x{}", hex::encode(function_selector(signature)));
+
+        match self.rpc("eth_call", json!([{ "to": address, "data": data }, "latest"]), Duration::from_secs(RPC_TIMEOUT_SECS)).await {
+            Ok(result) => Ok(result.as_str()
+                .and_then(|result| result.strip_prefix("0x"))
+                .and_then(|result| hex::decode(result).ok())),
+            Err(EthRpcError::Reverted(_)) => Ok(None),
+            Err(e) => Err(e.into_app_error("eth_call")),
+        }
+    }
+
+    /// Asks a contract wallet whether it accepts `signature` over `hash` (EIP-1271)
+    ///
+    /// Addresses without code, externally owned accounts included, accept
+    /// nothing. Contract wallets may use signatures of any length.
+    pub async fn is_valid_signature(
+        &self,
+        wallet: &str,
+        hash: &[u8; 32],
+        signature: &[u8],
+    ) -> Result<bool, AppError> {
+        // isValidSignature(bytes32 hash, bytes signature)
+        let mut data = function_selector("isValidSignature(bytes32,bytes)").to_vec();
+        data.extend(hash);
+        data.extend(abi_word(64));
+        data.extend(abi_word(signature.len() as u64));
+        data.extend(signature);
+        data.extend(std::iter::repeat_n(0u8, signature.len().next_multiple_of(32) - signature.len()));
+
+        let result = self.rpc(
+            "eth_call",
+            json!([{ "to": wallet, "data": format!("0x{}", hex::encode(data)) }, "latest"]),
+            Duration::from_secs(RPC_TIMEOUT_SECS),
+        ).await;
+
+        // A contract without the method, or one rejecting the signature, reverts
+        let result = match result {
+            Ok(result) => result,
+            Err(EthRpcError::Reverted(_)) => return Ok(false),
+            Err(e) => return Err(e.into_app_error("eth_call")),
+        };
+
+        let returned = result.as_str()
+            .and_then(|result| result.strip_prefix("0x"))
+            .and_then(|result| hex::decode(result).ok())
+            .unwrap_or_default();
+
+        Ok(returned.len() >= 32 && returned[..4] == EIP1271_MAGIC_VALUE)
+    }
+}
+
+/// An on-chain invoice id as a `uint256` ABI word
+fn invoice_id_word(on_chain_id: &str) -> Result<[u8; 32], AppError> {
+    let invoice_id = on_chain_id.parse::<BigInt>()
+        .ok()
+        .filter(|id| id.sign() != bigdecimal::num_bigint::Sign::Minus)
+        .map(|id| id.to_bytes_be().1)
+        .filter(|bytes| bytes.len() <= 32)
+        .ok_or_else(|| AppError::ValidationError(format!("Invalid on-chain invoice id: {}", on_chain_id)))?;
+
+    let mut word = [0u8; 32];
+    word[32 - invoice_id.len()..].copy_from_slice(&invoice_id);
+    Ok(word)
+}
+
+/// Calldata of an ERC-20 `transfer(address,uint256)`
+pub fn erc20_transfer_calldata(to: &str, amount: &BigInt) -> Result<String, AppError> {
+    let to = to.strip_prefix("0x")
+        .and_then(|to| hex::decode(to).ok())
+        .filter(|to| to.len() == 20)
+        .ok_or_else(|| AppError::ValidationError(format!("Invalid address: {}", to)))?;
+    let (sign, amount) = amount.to_bytes_be();
+    if sign == bigdecimal::num_bigint::Sign::Minus || amount.len() > 32 {
+        return Err(AppError::ValidationError("Transfer amount does not fit a uint256".to_string()));
+    }
+
+    let mut data = function_selector("transfer(address,uint256)").to_vec();
+    data.extend([0u8; 12]);
+    data.extend(to);
+    data.extend(std::iter::repeat_n(0u8, 32 - amount.len()));
+    data.extend(amount);
+    Ok(format!("0x{}", hex::encode(data)))
+}
+
+/// Parses a JSON-RPC quantity such as `"0x3b9aca00"`
+fn parse_quantity(value: &JsonValue) -> Option<u128> {
+    u128::from_str_radix(value.as_str()?.strip_prefix("0x")?, 16).ok()
+}
+
+/// `value` as a 32-byte big-endian ABI word
+fn abi_word(value: u64) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// Decodes an ABI `string`, or the `bytes32` some older tokens return instead
+fn decode_abi_string(data: &[u8]) -> Option<String> {
+    let bytes = if data.len() == 32 {
+        let end = data.iter().position(|byte| *byte == 0).unwrap_or(32);
+        &data[..end]
+    } else {
+        // offset word, length word, then the bytes
+        let offset = usize::try_from(decode_abi_u64(data.get(..32)?)?).ok()?;
+        let length = usize::try_from(decode_abi_u64(data.get(offset..offset.checked_add(32)?)?)?).ok()?;
+        data.get(offset + 32..(offset + 32).checked_add(length)?)?
+    };
+
+    String::from_utf8(bytes.to_vec()).ok().filter(|value| !value.is_empty())
+}
+
+/// Decodes a `uint8` word, refusing values that do not fit
+fn decode_abi_u8(data: &[u8]) -> Option<u8> {
+    u8::try_from(decode_abi_u64(data.get(..32)?)?).ok()
+}
+
+fn decode_abi_u64(word: &[u8]) -> Option<u64> {
+    if word.len() != 32 || word[..24].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+    Some(u64::from_be_bytes(word[24..].try_into().ok()?))
+}
+
+fn function_selector(signature: &str) -> [u8; 4] {
+    let hash = Keccak256::digest(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use axum::{extract::State, routing::post, Json, Router};
+    use std::sync::atomic::AtomicUsize;
+    use tokio::task::JoinSet;
+
+    /// Requests the stub endpoint is serving, and the most it served at once
+    #[derive(Default)]
+    struct Load {
+        current: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    async fn slow_block_number(State(load): State<Arc<Load>>) -> Json<JsonValue> {
+        let current = load.current.fetch_add(1, Ordering::SeqCst) + 1;
+        load.peak.fetch_max(current, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        load.current.fetch_sub(1, Ordering::SeqCst);
+        Json(json!({ "jsonrpc": "2.0", "id": 1, "result": "0x10" }))
+    }
+
+    /// A client of a stub endpoint taking 100ms per request
+    async fn client(max_concurrent_rpc: usize, rpc_saturation: RpcSaturation) -> (ChainClient, Arc<Load>) {
+        let load = Arc::new(Load::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().route("/", post(slow_block_number)).with_state(load.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let ethereum = Ethereum {
+            rpc_url,
+            max_concurrent_rpc,
+            rpc_saturation,
+            ..test_support::config().ethereum
+        };
+        (ChainClient::new(&ethereum).unwrap(), load)
+    }
+
+    async fn request_concurrently(client: &ChainClient, requests: usize) -> Vec<Result<JsonValue, AppError>> {
+        let mut calls = JoinSet::new();
+        for _ in 0..requests {
+            let client = client.clone();
+            calls.spawn(async move { client.request("eth_blockNumber", json!([])).await });
+        }
+        calls.join_all().await
+    }
+
+    #[tokio::test]
+    async fn queued_requests_never_exceed_the_limit() {
+        let (client, load) = client(2, RpcSaturation::Queue).await;
+
+        let results = request_concurrently(&client, 8).await;
+        assert!(results.iter().all(|result| matches!(result, Ok(block) if block == "0x10")));
+        assert_eq!(load.peak.load(Ordering::SeqCst), 2);
+
+        let status = client.rpc_status();
+        assert_eq!((status.max_concurrent, status.in_flight, status.rejected), (2, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn shed_requests_fail_with_a_503() {
+        let (client, load) = client(1, RpcSaturation::Shed).await;
+
+        let results = request_concurrently(&client, 3).await;
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        for error in results.into_iter().filter_map(Result::err) {
+            assert_eq!(error.status_code(), hyper::http::StatusCode::SERVICE_UNAVAILABLE);
+            assert!(matches!(error, AppError::OverloadedError(_, 1)), "{error:?}");
+        }
+        assert_eq!(load.peak.load(Ordering::SeqCst), 1);
+        assert_eq!(client.rpc_status().rejected, 2);
+    }
+
+    /// A client of an endpoint whose head is the block in `head`
+    async fn head_client(max_head_stall_secs: u64, head: Arc<AtomicU64>) -> ChainClient {
+        let rpc_url = test_support::stub_rpc(move |method, _| match method {
+            "eth_blockNumber" => Ok(json!(format!("0x{:x}", head.load(Ordering::SeqCst)))),
+            _ => Ok(JsonValue::Null),
+        }).await;
+        ChainClient::new(&Ethereum { rpc_url, max_head_stall_secs, ..test_support::config().ethereum }).unwrap()
+    }
+
+    /// Makes the client last see the head move to `block` `secs` ago, and
+    /// drops the cached head
+    fn seen_advancing(client: &ChainClient, block: u64, secs: u64) {
+        let seen_at = Instant::now().checked_sub(Duration::from_secs(secs)).unwrap();
+        *client.head_progress.lock().unwrap() = Some((block, seen_at));
+        *client.head_cache.lock().unwrap() = None;
+    }
+
+    #[tokio::test]
+    async fn head_that_stops_advancing_is_stalled() {
+        let head = Arc::new(AtomicU64::new(16));
+        let client = head_client(60, head.clone()).await;
+
+        let fresh = client.head().await;
+        assert_eq!(fresh.head_block, Some(16));
+        assert_eq!(fresh.head_age_secs, Some(0));
+        assert!(fresh.healthy && !fresh.stalled);
+
+        seen_advancing(&client, 16, 120);
+        let stalled = client.head().await;
+        assert_eq!(stalled.head_block, Some(16));
+        assert!(stalled.head_age_secs.unwrap() >= 120);
+        assert!(stalled.stalled && !stalled.healthy);
+
+        // A new block is progress again
+        head.store(17, Ordering::SeqCst);
+        *client.head_cache.lock().unwrap() = None;
+        let resumed = client.head().await;
+        assert_eq!(resumed.head_age_secs, Some(0));
+        assert!(resumed.healthy && !resumed.stalled);
+    }
+
+    #[tokio::test]
+    async fn head_going_backwards_is_not_progress() {
+        let client = head_client(60, Arc::new(AtomicU64::new(16))).await;
+
+        // E.g. a lagging node behind a load balancer
+        seen_advancing(&client, 20, 120);
+        let head = client.head().await;
+        assert_eq!(head.head_block, Some(16));
+        assert!(head.stalled);
+    }
+
+    #[tokio::test]
+    async fn chain_reads_are_refused_while_the_head_is_stalled() {
+        let client = head_client(60, Arc::new(AtomicU64::new(16))).await;
+        let tx_hash = format!("0x{}", "ab".repeat(32));
+        // The stub has no receipt for it yet
+        assert_eq!(client.paid_invoice_ids(&tx_hash).await.unwrap(), None);
+
+        seen_advancing(&client, 16, 120);
+        let result = client.paid_invoice_ids(&tx_hash).await;
+        assert!(matches!(result, Err(AppError::ServiceUnavailableError(_))), "{result:?}");
+        let result = client.invoice_status("1").await;
+        assert!(matches!(result, Err(AppError::ServiceUnavailableError(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn stall_detection_is_off_without_a_limit() {
+        let client = head_client(0, Arc::new(AtomicU64::new(16))).await;
+        client.head().await;
+
+        seen_advancing(&client, 16, 86_400);
+        let head = client.head().await;
+        assert!(head.head_age_secs.unwrap() >= 86_400);
+        assert!(head.healthy && !head.stalled);
+    }
+
+    #[tokio::test]
+    async fn unreachable_endpoint_is_unhealthy_but_not_stalled() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let client = ChainClient::new(&Ethereum { rpc_url, ..test_support::config().ethereum }).unwrap();
+
+        let head = client.head().await;
+        assert_eq!((head.head_block, head.head_age_secs), (None, None));
+        assert!(!head.healthy && !head.stalled);
+    }
+}
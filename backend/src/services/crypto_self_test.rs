@@ -0,0 +1,72 @@
+use secp256k1::{Message, Secp256k1, SecretKey};
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::CryptoSelfTest,
+    models::auth_challenges::verify_signature,
+};
+
+/// Well-known test key from the web3.js documentation, never used for funds
+const TEST_PRIVATE_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+/// Address of `TEST_PRIVATE_KEY`
+const TEST_ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+const TEST_MESSAGE: &str = "Some data";
+/// `personal_sign` of `TEST_MESSAGE` by `TEST_PRIVATE_KEY`, as published by web3.js (v = 28)
+const TEST_SIGNATURE: &str = "0xb91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a0291c";
+
+/// Checks the Keccak and secp256k1 glue against known vectors before serving
+///
+/// The published signature is verified with both encodings of `v` (27/28
+/// and 0/1), a fresh signature by the test key must recover its address,
+/// and a signature over another message must not. Any failure aborts startup.
+pub fn run_crypto_self_test(self_test: &CryptoSelfTest) -> Result<(), AppError> {
+    if !self_test.enabled {
+        return Ok(());
+    }
+
+    let fresh_signature = sign_with_test_key(TEST_MESSAGE)?;
+    let mut zero_based_v = TEST_SIGNATURE.to_string();
+    zero_based_v.replace_range(zero_based_v.len() - 2.., "01");
+
+    let checks = [
+        ("published signature", TEST_SIGNATURE, TEST_MESSAGE, true),
+        ("published signature with v as 0/1", zero_based_v.as_str(), TEST_MESSAGE, true),
+        ("freshly signed message", fresh_signature.as_str(), TEST_MESSAGE, true),
+        ("signature over another message", TEST_SIGNATURE, "Other data", false),
+    ];
+    for (name, signature, message, expected) in checks {
+        let matches = verify_signature(signature, message, TEST_ADDRESS).unwrap_or(false);
+        if matches != expected {
+            return Err(AppError::ConfigError(format!(
+                "Crypto self-test failed: {} {} the test address",
+                name,
+                if expected { "did not recover" } else { "recovered" }
+            )));
+        }
+    }
+
+    println!("Crypto self-test passed ({} signature checks)", checks.len());
+    Ok(())
+}
+
+/// Signs `message` as `personal_sign` with the test key, `v` as 27/28
+fn sign_with_test_key(message: &str) -> Result<String, AppError> {
+    let failed = |e: String| AppError::ConfigError(format!("Crypto self-test failed to sign: {}", e));
+
+    let key_bytes: [u8; 32] = hex::decode(TEST_PRIVATE_KEY)
+        .map_err(|e| failed(e.to_string()))?
+        .try_into()
+        .map_err(|_| failed("test key is not 32 bytes".to_string()))?;
+    let secret_key = SecretKey::from_byte_array(key_bytes).map_err(|e| failed(e.to_string()))?;
+
+    let prefixed_message = format!("\x19Ethereum Signed Message:\n{}", message.len()) + message;
+    let digest: [u8; 32] = Keccak256::digest(prefixed_message.as_bytes()).into();
+
+    let (recovery_id, compact) = Secp256k1::new()
+        .sign_ecdsa_recoverable(Message::from_digest(digest), &secret_key)
+        .serialize_compact();
+    let v = 27 + i32::from(recovery_id) as u8;
+
+    Ok(format!("0x{}{:02x}", hex::encode(compact), v))
+}
@@ -0,0 +1,167 @@
+//! `Link: rel=preload` header of the home page
+//!
+//! hyper cannot send informational responses, so the header is set on the
+//! page itself; proxies and CDNs supporting Early Hints, e.g. Cloudflare or
+//! nginx with `early_hints`, replay it as a 103 ahead of later responses.
+
+use axum::http::HeaderValue;
+use std::{fs, path::Path};
+
+use crate::{app_error::app_error::AppError, config::app_config::EarlyHints};
+
+/// Resolves `early_hints.preload` against the frontend build into a `Link`
+/// header value, `None` when disabled
+///
+/// Entries are paths relative to the dist directory, whose file name may
+/// use `*` wildcards since bundlers hash asset names, e.g. `assets/index-*.js`.
+/// Each entry must match at least one file, so a renamed bundle is caught
+/// at startup rather than preloading a 404.
+pub fn preload_link_header(dist_path: &str, config: &EarlyHints) -> Result<Option<HeaderValue>, AppError> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let mut links = Vec::new();
+    for pattern in &config.preload {
+        let matches = resolve_pattern(dist_path, pattern)?;
+        if matches.is_empty() {
+            return Err(AppError::ConfigError(format!(
+                "frontend.early_hints.preload entry '{}' matches no file in {}", pattern, dist_path
+            )));
+        }
+        for path in matches {
+            links.push(link_for(&path)?);
+        }
+    }
+    if links.is_empty() {
+        return Ok(None);
+    }
+
+    HeaderValue::from_str(&links.join(", "))
+        .map(Some)
+        .map_err(|e| AppError::ConfigError(format!("Invalid frontend.early_hints.preload entry: {}", e)))
+}
+
+/// Files matching `pattern`, as URL paths such as `/assets/index-3f2a.js`
+fn resolve_pattern(dist_path: &str, pattern: &str) -> Result<Vec<String>, AppError> {
+    let (dir, file_pattern) = pattern.rsplit_once('/').unwrap_or(("", pattern));
+    if dir.contains('*') {
+        return Err(AppError::ConfigError(format!(
+            "frontend.early_hints.preload entry '{}' may only use wildcards in the file name", pattern
+        )));
+    }
+
+    let entries = match fs::read_dir(Path::new(dist_path).join(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut matches: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| matches_wildcard(file_pattern, name))
+        .map(|name| if dir.is_empty() { format!("/{}", name) } else { format!("/{}/{}", dir, name) })
+        .collect();
+    matches.sort();
+
+    Ok(matches)
+}
+
+/// Glob-style match where `*` stands for any run of characters
+fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Link to one asset, with the request destination browsers need to use
+/// the preloaded response
+fn link_for(path: &str) -> Result<String, AppError> {
+    let extension = path.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+    let attributes = match extension {
+        // Bundlers emit ES modules, which a plain script preload cannot serve
+        "js" | "mjs" => "rel=modulepreload",
+        "css" => "rel=preload; as=style",
+        "woff" | "woff2" => "rel=preload; as=font; crossorigin",
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "avif" => "rel=preload; as=image",
+        _ => {
+            return Err(AppError::ConfigError(format!(
+                "frontend.early_hints.preload cannot preload '{}': unknown asset type", path
+            )));
+        }
+    };
+    Ok(format!("<{}>; {}", path, attributes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A dist directory holding a hashed Vite build
+    fn dist() -> String {
+        let dist = std::env::temp_dir().join(format!("early-hints-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dist.join("assets")).unwrap();
+        for file in ["index.html", "assets/index-3f2a.js", "assets/index-9c1b.css", "assets/inter-4e1d.woff2", "assets/vendor-77aa.js"] {
+            fs::write(dist.join(file), "").unwrap();
+        }
+        dist.to_string_lossy().into_owned()
+    }
+
+    fn early_hints(preload: &[&str]) -> EarlyHints {
+        EarlyHints { enabled: true, preload: preload.iter().map(|entry| entry.to_string()).collect() }
+    }
+
+    #[test]
+    fn preload_entries_resolve_to_the_built_assets() {
+        let header = preload_link_header(&dist(), &early_hints(&["assets/index-*.js", "assets/*.css", "assets/inter-*.woff2"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            header.to_str().unwrap(),
+            "</assets/index-3f2a.js>; rel=modulepreload, \
+             </assets/index-9c1b.css>; rel=preload; as=style, \
+             </assets/inter-4e1d.woff2>; rel=preload; as=font; crossorigin"
+        );
+    }
+
+    #[test]
+    fn no_header_when_disabled() {
+        let config = EarlyHints { enabled: false, ..early_hints(&["assets/missing-*.js"]) };
+        assert_eq!(preload_link_header(&dist(), &config).unwrap(), None);
+        assert_eq!(preload_link_header(&dist(), &early_hints(&[])).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_entries_fail_at_startup() {
+        let dist = dist();
+        for preload in ["assets/missing-*.js", "*/index-*.js", "index.html"] {
+            let result = preload_link_header(&dist, &early_hints(&[preload]));
+            assert!(matches!(result, Err(AppError::ConfigError(_))), "{preload}");
+        }
+    }
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        assert!(matches_wildcard("index-*.js", "index-3f2a.js"));
+        assert!(matches_wildcard("*.js", "index-3f2a.js"));
+        assert!(matches_wildcard("index.js", "index.js"));
+        assert!(matches_wildcard("a*b*c", "a-b-b-c"));
+        assert!(!matches_wildcard("index-*.js", "index-3f2a.js.map"));
+        assert!(!matches_wildcard("index-*.js", "vendor-77aa.js"));
+        assert!(!matches_wildcard("index.js", "index.jsx"));
+    }
+}
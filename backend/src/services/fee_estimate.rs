@@ -0,0 +1,262 @@
+//! Gas cost of paying an invoice, for payers to fund their wallet
+
+use bigdecimal::num_bigint::BigInt;
+use serde::Serialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    app_error::app_error::AppError,
+    models::{invoices::Invoice, users::User},
+    services::chain::{erc20_transfer_calldata, FeeMarket},
+    AppState,
+};
+
+/// Gas used by `payInvoice` when the node cannot simulate the payment
+const PAY_INVOICE_GAS: u64 = 80_000;
+/// Gas used by an ERC-20 transfer when the node cannot simulate it
+const ERC20_TRANSFER_GAS: u64 = 65_000;
+/// Headroom added to simulated gas, which varies with the chain state
+const GAS_MARGIN_PERCENT: u64 = 20;
+
+/// What paying an invoice is expected to cost, wei amounts as decimal strings
+///
+/// `max_fee_wei` bounds what the payer is charged for gas: on EIP-1559
+/// chains the max fee per gas allows the base fee to double before the
+/// transaction is mined, and only the actual base fee plus tip is charged.
+#[derive(Debug, Serialize)]
+pub struct FeeEstimate {
+    pub invoice_id: Uuid,
+    pub chain_id: u32,
+    /// `eip1559` or `legacy`
+    pub fee_model: &'static str,
+    pub gas_limit: u64,
+    /// False when the node could not simulate the payment, e.g. for a sender
+    /// other than the invoice's client, and a typical gas limit is used
+    pub gas_limit_estimated: bool,
+    pub base_fee_per_gas: Option<String>,
+    pub max_priority_fee_per_gas: Option<String>,
+    pub max_fee_per_gas: Option<String>,
+    pub gas_price: Option<String>,
+    pub expected_fee_wei: String,
+    pub max_fee_wei: String,
+    /// Native currency sent with the payment, 0 for token invoices
+    pub payment_value_wei: String,
+    /// Token the invoice is paid in, with the base units transferred
+    pub token_address: Option<String>,
+    pub token_amount: Option<String>,
+    /// Native currency the payer needs at most: the payment value plus `max_fee_wei`
+    pub suggested_total_wei: String,
+    /// Fees were read a few minutes ago because the RPC endpoint is unavailable
+    pub stale: bool,
+}
+
+/// Estimates the cost of `payer` paying `invoice`
+///
+/// Native invoices are paid through the contract's `payInvoice`, with the
+/// amount the contract holds for the invoice; token invoices by an ERC-20
+/// transfer of the invoice amount to the issuer.
+pub async fn estimate_payment_fee(
+    app_state: &AppState,
+    invoice: &Invoice,
+    payer: &str,
+) -> Result<FeeEstimate, AppError> {
+    let chain = &app_state.chain;
+
+    let (transaction, fallback_gas, payment_value, token_amount) = match invoice.token_address.as_deref() {
+        None => {
+            let value = chain.invoice_amount(&invoice.on_chain_id)
+                .await?
+                .ok_or_else(|| AppError::NotFoundError(format!(
+                    "Invoice {} is not on chain yet", invoice.id
+                )))?;
+            let transaction = json!({
+                "from": payer,
+                "to": chain.contract_address(),
+                "data": chain.pay_invoice_calldata(&invoice.on_chain_id)?,
+                "value": format!("0x{}", value.to_str_radix(16)),
+            });
+            (transaction, PAY_INVOICE_GAS, value, None)
+        }
+        Some(token_address) => {
+            let token = app_state.config.ethereum.find_token(chain.chain_id(), token_address)
+                .ok_or_else(|| AppError::ConflictError(format!(
+                    "Token {} is no longer accepted on chain {}", token_address, chain.chain_id()
+                )))?;
+            let amount = token.amount_to_base_units(&invoice.amount)?;
+            let issuer = User::get_user_by_id(&app_state.pool, invoice.created_by)
+                .await?
+                .ok_or_else(|| AppError::NotFoundError("Invoice issuer not found".to_string()))?;
+            let transaction = json!({
+                "from": payer,
+                "to": token_address,
+                "data": erc20_transfer_calldata(&issuer.ethereum_address, &amount)?,
+            });
+            (transaction, ERC20_TRANSFER_GAS, BigInt::from(0), Some(amount))
+        }
+    };
+
+    let (market, stale) = chain.fee_market().await?;
+    let estimated_gas = chain.estimate_gas(transaction).await
+        .map(|gas| gas.saturating_add(gas.saturating_mul(GAS_MARGIN_PERCENT) / 100));
+    let gas_limit = estimated_gas.unwrap_or(fallback_gas);
+
+    let (fee_model, expected_per_gas, max_per_gas) = match market {
+        FeeMarket::Eip1559 { base_fee_per_gas, max_priority_fee_per_gas } => (
+            "eip1559",
+            base_fee_per_gas.saturating_add(max_priority_fee_per_gas),
+            base_fee_per_gas.saturating_mul(2).saturating_add(max_priority_fee_per_gas),
+        ),
+        FeeMarket::Legacy { gas_price } => ("legacy", gas_price, gas_price),
+    };
+    let expected_fee = BigInt::from(gas_limit) * BigInt::from(expected_per_gas);
+    let max_fee = BigInt::from(gas_limit) * BigInt::from(max_per_gas);
+    let suggested_total = &payment_value + &max_fee;
+
+    let (base_fee_per_gas, max_priority_fee_per_gas, max_fee_per_gas, gas_price) = match market {
+        FeeMarket::Eip1559 { base_fee_per_gas, max_priority_fee_per_gas } => (
+            Some(base_fee_per_gas.to_string()),
+            Some(max_priority_fee_per_gas.to_string()),
+            Some(max_per_gas.to_string()),
+            None,
+        ),
+        FeeMarket::Legacy { gas_price } => (None, None, None, Some(gas_price.to_string())),
+    };
+
+    Ok(FeeEstimate {
+        invoice_id: invoice.id,
+        chain_id: chain.chain_id(),
+        fee_model,
+        gas_limit,
+        gas_limit_estimated: estimated_gas.is_some(),
+        base_fee_per_gas,
+        max_priority_fee_per_gas,
+        max_fee_per_gas,
+        gas_price,
+        expected_fee_wei: expected_fee.to_string(),
+        max_fee_wei: max_fee.to_string(),
+        payment_value_wei: payment_value.to_string(),
+        token_address: invoice.token_address.clone(),
+        token_amount: token_amount.map(|amount| amount.to_string()),
+        suggested_total_wei: suggested_total.to_string(),
+        stale,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::invoices, test_support, utils::clock::SystemClock};
+    use serde_json::Value as JsonValue;
+    use sqlx::PgPool;
+    use std::sync::Arc;
+
+    const GWEI: u128 = 1_000_000_000;
+    const PAYER: &str = "0x0000000000000000000000000000000000000001";
+    const USDC: &str = "0x1c7d4b196cb0c7b01d743fbc6116a902379c7238";
+
+    fn quantity(value: u128) -> JsonValue {
+        json!(format!("0x{:x}", value))
+    }
+
+    /// `invoices(uint256)` words of on-chain invoice 1, holding one ether
+    fn on_chain_invoice() -> JsonValue {
+        let words = [1, 0, 0, 10u128.pow(18), 0, 0].map(|word| format!("{:064x}", word));
+        json!(format!("0x{}", words.concat()))
+    }
+
+    /// State whose RPC endpoint answers `method` with `respond(method)`
+    async fn app_state<F>(pool: PgPool, respond: F) -> Arc<AppState>
+    where
+        F: Fn(&str) -> Result<JsonValue, JsonValue> + Send + Sync + 'static,
+    {
+        let mut config = test_support::config();
+        config.ethereum.rpc_url = test_support::stub_rpc(move |method, _| respond(method)).await;
+        test_support::app_state_with(pool, Arc::new(SystemClock), config)
+    }
+
+    fn not_served() -> Result<JsonValue, JsonValue> {
+        Err(json!({ "code": -32601, "message": "the method does not exist/is not available" }))
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn eip1559_fees_allow_the_base_fee_to_double(pool: PgPool) {
+        let app_state = app_state(pool, |method| match method {
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": quantity(20 * GWEI) })),
+            "eth_maxPriorityFeePerGas" => Ok(quantity(2 * GWEI)),
+            "eth_estimateGas" => Ok(quantity(50_000)),
+            "eth_call" => Ok(on_chain_invoice()),
+            _ => not_served(),
+        }).await;
+        let invoice = invoices::tests::invoice(uuid::Uuid::new_v4());
+
+        let estimate = estimate_payment_fee(&app_state, &invoice, PAYER).await.unwrap();
+        assert_eq!(estimate.fee_model, "eip1559");
+        // 20% over the simulated gas
+        assert_eq!(estimate.gas_limit, 60_000);
+        assert!(estimate.gas_limit_estimated);
+        assert_eq!(estimate.base_fee_per_gas, Some((20 * GWEI).to_string()));
+        assert_eq!(estimate.max_priority_fee_per_gas, Some((2 * GWEI).to_string()));
+        assert_eq!(estimate.max_fee_per_gas, Some((42 * GWEI).to_string()));
+        assert_eq!(estimate.gas_price, None);
+        assert_eq!(estimate.expected_fee_wei, (60_000 * 22 * GWEI).to_string());
+        assert_eq!(estimate.max_fee_wei, (60_000 * 42 * GWEI).to_string());
+        assert_eq!(estimate.payment_value_wei, 10u128.pow(18).to_string());
+        assert_eq!(estimate.suggested_total_wei, (10u128.pow(18) + 60_000 * 42 * GWEI).to_string());
+        assert!(!estimate.stale);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn eip1559_nodes_without_tip_suggestions_use_the_default_tip(pool: PgPool) {
+        let app_state = app_state(pool, |method| match method {
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10", "baseFeePerGas": quantity(20 * GWEI) })),
+            "eth_estimateGas" => Ok(quantity(50_000)),
+            "eth_call" => Ok(on_chain_invoice()),
+            _ => not_served(),
+        }).await;
+        let invoice = invoices::tests::invoice(uuid::Uuid::new_v4());
+
+        let estimate = estimate_payment_fee(&app_state, &invoice, PAYER).await.unwrap();
+        assert_eq!(estimate.fee_model, "eip1559");
+        assert_eq!(estimate.max_priority_fee_per_gas, Some(1_500_000_000.to_string()));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn legacy_chains_pay_the_gas_price(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let app_state = app_state(pool.clone(), |method| match method {
+            // No base fee in pre-London blocks
+            "eth_getBlockByNumber" => Ok(json!({ "number": "0x10" })),
+            "eth_gasPrice" => Ok(quantity(5 * GWEI)),
+            "eth_estimateGas" => Err(json!({ "code": 3, "message": "execution reverted: ERC20: transfer amount exceeds balance" })),
+            _ => not_served(),
+        }).await;
+        let issuer = test_support::create_user(&pool, &SystemClock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+        let invoice = Invoice { token_address: Some(USDC.to_string()), ..invoices::tests::invoice(issuer.id) };
+
+        let estimate = estimate_payment_fee(&app_state, &invoice, PAYER).await.unwrap();
+        assert_eq!(estimate.fee_model, "legacy");
+        assert_eq!(estimate.gas_price, Some((5 * GWEI).to_string()));
+        assert_eq!((estimate.base_fee_per_gas, estimate.max_fee_per_gas), (None, None));
+        // The transfer could not be simulated for this payer
+        assert_eq!(estimate.gas_limit, ERC20_TRANSFER_GAS);
+        assert!(!estimate.gas_limit_estimated);
+        assert_eq!(estimate.expected_fee_wei, estimate.max_fee_wei);
+        assert_eq!(estimate.max_fee_wei, (65_000 * 5 * GWEI).to_string());
+        assert_eq!(estimate.payment_value_wei, "0");
+        assert_eq!(estimate.token_amount.as_deref(), Some("1500000000"));
+        assert_eq!(estimate.suggested_total_wei, estimate.max_fee_wei);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn invoices_not_on_chain_cannot_be_estimated(pool: PgPool) {
+        let app_state = app_state(pool, |method| match method {
+            "eth_call" => Ok(json!(format!("0x{}", "0".repeat(6 * 64)))),
+            _ => not_served(),
+        }).await;
+        let invoice = invoices::tests::invoice(uuid::Uuid::new_v4());
+
+        let result = estimate_payment_fee(&app_state, &invoice, PAYER).await;
+        assert!(matches!(result, Err(AppError::NotFoundError(_))), "{result:?}");
+    }
+}
@@ -0,0 +1,118 @@
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+};
+
+use crate::config::app_config::GeoIp;
+
+/// Coarse location of a client IP, deliberately no finer than a region
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct GeoLocation {
+    /// ISO 3166-1 country code, e.g. `FR`
+    pub country: Option<String>,
+    /// ISO 3166-2 subdivision code without the country, e.g. `IDF`
+    pub region: Option<String>,
+}
+
+/// Offline IP geolocation backed by a MaxMind GeoIP2/GeoLite2 City or Country database
+///
+/// Without a configured or readable database every lookup returns `None`, so
+/// callers never depend on geo data being present. Results, including misses,
+/// are cached per IP; the cache is cleared when it reaches `cache_size`.
+pub struct GeoLocator {
+    reader: Option<Reader<Vec<u8>>>,
+    cache: Mutex<HashMap<IpAddr, Option<GeoLocation>>>,
+    cache_size: usize,
+}
+
+impl GeoLocator {
+    pub fn new(config: &GeoIp) -> Self {
+        let reader = config.database_path.as_deref().and_then(|path| {
+            Reader::open_readfile(path)
+                .inspect(|_| println!("GeoIP database loaded from {}", path))
+                .map_err(|e| eprintln!("GeoIP lookups disabled, failed to open {}: {}", path, e))
+                .ok()
+        });
+
+        GeoLocator {
+            reader,
+            cache: Mutex::new(HashMap::new()),
+            cache_size: config.cache_size,
+        }
+    }
+
+    /// Country and region of `ip`, `None` when unknown or lookups are disabled
+    pub fn locate(&self, ip: IpAddr) -> Option<GeoLocation> {
+        let reader = self.reader.as_ref()?;
+
+        if let Some(cached) = self.cache.lock().unwrap().get(&ip) {
+            return cached.clone();
+        }
+
+        let location = match reader.lookup::<geoip2::City>(ip) {
+            Ok(city) => Some(GeoLocation {
+                country: city.country.and_then(|country| country.iso_code).map(str::to_string),
+                region: city
+                    .subdivisions
+                    .and_then(|subdivisions| subdivisions.into_iter().next())
+                    .and_then(|subdivision| subdivision.iso_code)
+                    .map(str::to_string),
+            }),
+            Err(MaxMindDBError::AddressNotFoundError(_)) => None,
+            Err(e) => {
+                eprintln!("GeoIP lookup failed for {}: {}", ip, e);
+                None
+            }
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= self.cache_size {
+            cache.clear();
+        }
+        cache.insert(ip, location.clone());
+
+        location
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// City database mapping 203.0.113.0/24 to FR-IDF and 198.51.100.0/24 to
+    /// JP without a region, nothing else
+    pub(crate) fn test_locator() -> GeoLocator {
+        GeoLocator::new(&GeoIp {
+            database_path: Some(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/geoip-test.mmdb").to_string()),
+            cache_size: 2,
+        })
+    }
+
+    fn location(country: &str, region: Option<&str>) -> Option<GeoLocation> {
+        Some(GeoLocation { country: Some(country.to_string()), region: region.map(str::to_string) })
+    }
+
+    #[test]
+    fn locates_known_networks_to_country_and_region() {
+        let locator = test_locator();
+        assert_eq!(locator.locate("203.0.113.7".parse().unwrap()), location("FR", Some("IDF")));
+        assert_eq!(locator.locate("198.51.100.1".parse().unwrap()), location("JP", None));
+        assert_eq!(locator.locate("192.0.2.1".parse().unwrap()), None);
+
+        // Served from the cache, which was cleared once full
+        assert_eq!(locator.locate("192.0.2.1".parse().unwrap()), None);
+        assert_eq!(locator.locate("203.0.113.7".parse().unwrap()), location("FR", Some("IDF")));
+        assert!(locator.cache.lock().unwrap().len() <= 2);
+    }
+
+    #[test]
+    fn lookups_are_disabled_without_a_readable_database() {
+        for database_path in [None, Some("/nonexistent/GeoLite2-City.mmdb".to_string())] {
+            let locator = GeoLocator::new(&GeoIp { database_path, cache_size: 10 });
+            assert_eq!(locator.locate("203.0.113.7".parse().unwrap()), None);
+        }
+    }
+}
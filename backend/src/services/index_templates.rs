@@ -0,0 +1,204 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::{FrontendConfig, IndexVariant},
+};
+
+/// The index.html templates the home page is served from
+///
+/// A request gets the variant named by `frontend.index_variant_header` when
+/// it names a configured one, else the variant named like `RUN_ENV` if any,
+/// else the default `index.html`. Each variant is read and has its static
+/// placeholders replaced once, then is cached; in `server.dev_mode` templates
+/// are read on every request so frontend rebuilds show up at once.
+pub struct IndexTemplates {
+    dist_path: String,
+    variants: HashMap<String, IndexVariant>,
+    env_variant: Option<String>,
+    header: Option<HeaderName>,
+    cache_enabled: bool,
+    cache: Mutex<HashMap<Option<String>, Arc<str>>>,
+    preload_links: Option<HeaderValue>,
+}
+
+impl IndexTemplates {
+    pub fn new(dist_path: &str, frontend: &FrontendConfig, run_env: &str, dev_mode: bool) -> Self {
+        IndexTemplates {
+            dist_path: dist_path.to_string(),
+            variants: frontend.index_variants.clone(),
+            env_variant: frontend.index_variants.contains_key(run_env).then(|| run_env.to_string()),
+            header: frontend.index_variant_header.as_deref()
+                .and_then(|header| HeaderName::from_bytes(header.as_bytes()).ok()),
+            cache_enabled: !dev_mode,
+            cache: Mutex::new(HashMap::new()),
+            preload_links: None,
+        }
+    }
+
+    /// Sets the `Link` header sent with every variant, see `services::early_hints`
+    pub fn with_preload_links(mut self, preload_links: Option<HeaderValue>) -> Self {
+        self.preload_links = preload_links;
+        self
+    }
+
+    /// Fails when a configured variant's template is missing, so a typo is
+    /// caught at startup rather than by the first visitor
+    pub fn validate_templates(&self) -> Result<(), AppError> {
+        for name in self.variants.keys() {
+            let path = self.template_path(Some(name));
+            if !Path::new(&path).is_file() {
+                return Err(AppError::ConfigError(format!(
+                    "Index template of frontend variant '{}' not found at {}", name, path
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// `Link` header preloading the critical assets, when early hints are enabled
+    pub fn preload_links(&self) -> Option<&HeaderValue> {
+        self.preload_links.as_ref()
+    }
+
+    /// Header the served variant depends on, for `Vary`
+    pub fn variant_header(&self) -> Option<&HeaderName> {
+        self.header.as_ref()
+    }
+
+    /// Name of the variant to serve for a request, `None` for the default
+    pub fn select(&self, headers: &HeaderMap) -> Option<&str> {
+        let requested = self.header.as_ref()
+            .and_then(|header| headers.get(header))
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .and_then(|name| self.variants.get_key_value(name))
+            .map(|(name, _)| name.as_str());
+
+        requested.or(self.env_variant.as_deref())
+    }
+
+    /// Path of the template a variant is read from
+    pub fn template_path(&self, variant: Option<&str>) -> String {
+        let template = variant
+            .and_then(|name| self.variants.get(name))
+            .map_or("index.html", |variant| variant.template.as_str());
+        format!("{}/{}", self.dist_path, template)
+    }
+
+    /// Template of `variant` with its placeholders replaced
+    pub fn load(&self, variant: Option<&str>) -> io::Result<Arc<str>> {
+        let key = variant.map(str::to_string);
+        if let Some(cached) = self.cache.lock().unwrap().get(&key) {
+            return Ok(cached.clone());
+        }
+
+        let mut html = fs::read_to_string(self.template_path(variant))?;
+        let placeholders = variant.and_then(|name| self.variants.get(name)).map(|config| &config.placeholders);
+        for (placeholder, value) in placeholders.into_iter().flatten() {
+            html = html.replace(placeholder.as_str(), value);
+        }
+
+        let html: Arc<str> = html.into();
+        if self.cache_enabled {
+            self.cache.lock().unwrap().insert(key, html.clone());
+        }
+        Ok(html)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    /// A dist directory with the default template and a staging variant
+    fn dist() -> String {
+        let dist = std::env::temp_dir().join(format!("index-templates-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("index.html"), "<h1>default</h1>").unwrap();
+        fs::write(dist.join("index.staging.html"), "<h1>staging</h1><!-- ENV_BANNER -->").unwrap();
+        dist.to_string_lossy().into_owned()
+    }
+
+    fn frontend(header: Option<&str>) -> FrontendConfig {
+        let staging = IndexVariant {
+            template: "index.staging.html".to_string(),
+            placeholders: HashMap::from([("<!-- ENV_BANNER -->".to_string(), "<div>Staging</div>".to_string())]),
+        };
+        FrontendConfig {
+            index_variants: HashMap::from([("staging".to_string(), staging)]),
+            index_variant_header: header.map(str::to_string),
+            ..test_support::config().frontend
+        }
+    }
+
+    fn served(templates: &IndexTemplates, headers: &HeaderMap) -> Arc<str> {
+        templates.load(templates.select(headers)).unwrap()
+    }
+
+    #[test]
+    fn environments_get_their_variant_or_the_default() {
+        let dist = dist();
+
+        let staging = IndexTemplates::new(&dist, &frontend(None), "staging", false);
+        assert_eq!(staging.select(&HeaderMap::new()), Some("staging"));
+        assert_eq!(&*served(&staging, &HeaderMap::new()), "<h1>staging</h1><div>Staging</div>");
+
+        for run_env in ["production", "development"] {
+            let templates = IndexTemplates::new(&dist, &frontend(None), run_env, false);
+            assert_eq!(templates.select(&HeaderMap::new()), None);
+            assert_eq!(&*served(&templates, &HeaderMap::new()), "<h1>default</h1>");
+        }
+    }
+
+    #[test]
+    fn variant_header_selects_configured_variants_only() {
+        let dist = dist();
+        let templates = IndexTemplates::new(&dist, &frontend(Some("x-index-variant")), "production", false);
+        let request = |variant: &str| HeaderMap::from_iter([(
+            HeaderName::from_static("x-index-variant"),
+            HeaderValue::from_str(variant).unwrap(),
+        )]);
+
+        assert_eq!(templates.variant_header().map(HeaderName::as_str), Some("x-index-variant"));
+        assert_eq!(templates.select(&request(" staging ")), Some("staging"));
+        assert_eq!(&*served(&templates, &request("staging")), "<h1>staging</h1><div>Staging</div>");
+        // Unknown names cannot read other files
+        assert_eq!(templates.select(&request("../secrets")), None);
+        assert_eq!(&*served(&templates, &request("../secrets")), "<h1>default</h1>");
+
+        // Without the header configured, the request cannot pick
+        let templates = IndexTemplates::new(&dist, &frontend(None), "production", false);
+        assert_eq!(templates.variant_header(), None);
+        assert_eq!(templates.select(&request("staging")), None);
+    }
+
+    #[test]
+    fn templates_are_cached_outside_dev_mode() {
+        let dist = dist();
+        let cached = IndexTemplates::new(&dist, &frontend(None), "production", false);
+        let reread = IndexTemplates::new(&dist, &frontend(None), "production", true);
+        assert_eq!(&*cached.load(None).unwrap(), "<h1>default</h1>");
+
+        fs::write(format!("{}/index.html", dist), "<h1>rebuilt</h1>").unwrap();
+        assert_eq!(&*cached.load(None).unwrap(), "<h1>default</h1>");
+        assert_eq!(&*reread.load(None).unwrap(), "<h1>rebuilt</h1>");
+    }
+
+    #[test]
+    fn missing_variant_templates_fail_validation() {
+        let dist = dist();
+        assert!(IndexTemplates::new(&dist, &frontend(None), "production", false).validate_templates().is_ok());
+
+        fs::remove_file(format!("{}/index.staging.html", dist)).unwrap();
+        let result = IndexTemplates::new(&dist, &frontend(None), "production", false).validate_templates();
+        assert!(matches!(result, Err(AppError::ConfigError(_))));
+    }
+}
@@ -0,0 +1,208 @@
+//! CSV export of an issuer's invoices
+//!
+//! Rows are read from a database cursor as the client consumes the body, so
+//! memory use does not grow with the number of invoices.
+
+use axum::body::Bytes;
+use futures::{stream, Stream, StreamExt};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::models::invoices::{Invoice, CUSTOMER_REF, ORDER_ID};
+
+/// Rows are flushed to the client in chunks of about this many bytes
+const CHUNK_BYTES: usize = 16 * 1024;
+
+const HEADER: &str = "id,display_number,on_chain_id,title,description,amount,currency,status,\
+    due_date,created_at,accepted_at,recipient_address,token_address,external_ref,order_id,customer_ref\n";
+
+/// Streams the CSV export of the invoices issued by `created_by`
+///
+/// The response has already started when a row fails to load, so the error
+/// is logged and the body is aborted instead of being closed normally: the
+/// client sees a truncated transfer rather than a complete-looking file.
+pub fn export_invoices_csv(
+    pool: PgPool,
+    created_by: Uuid,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> + use<> {
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(16);
+
+    tokio::spawn(async move {
+        let mut invoices = Invoice::stream_issued_by(&pool, created_by);
+        let mut chunk = String::from(HEADER);
+
+        while let Some(invoice) = invoices.next().await {
+            let invoice = match invoice {
+                Ok(invoice) => invoice,
+                Err(e) => {
+                    eprintln!("Invoice export for {} aborted: {}", created_by, e);
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    return;
+                }
+            };
+            push_row(&mut chunk, &invoice);
+
+            // The receiver is gone when the client disconnected
+            if chunk.len() >= CHUNK_BYTES && tx.send(Ok(Bytes::from(std::mem::take(&mut chunk)))).await.is_err() {
+                return;
+            }
+        }
+
+        let _ = tx.send(Ok(Bytes::from(chunk))).await;
+    });
+
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
+
+fn push_row(out: &mut String, invoice: &Invoice) {
+    let status = match serde_json::to_value(invoice.status) {
+        Ok(serde_json::Value::String(status)) => status,
+        _ => String::new(),
+    };
+    let fields = [
+        invoice.id.to_string(),
+        invoice.display_number.clone(),
+        invoice.on_chain_id.clone(),
+        invoice.title.clone(),
+        invoice.description.clone().unwrap_or_default(),
+        invoice.amount.to_plain_string(),
+        invoice.currency.clone(),
+        status,
+        invoice.due_date.to_string(),
+        invoice.created_at.to_string(),
+        invoice.accepted_at.map(|at| at.to_string()).unwrap_or_default(),
+        invoice.recipient_address.clone().unwrap_or_default(),
+        invoice.token_address.clone().unwrap_or_default(),
+        invoice.external_ref.clone().unwrap_or_default(),
+        ORDER_ID.get(&invoice.metadata).unwrap_or_default(),
+        CUSTOMER_REF.get(&invoice.metadata).unwrap_or_default(),
+    ];
+
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        push_field(out, field);
+    }
+    out.push('\n');
+}
+
+/// Quotes a field when needed and defuses values a spreadsheet would run as a formula
+fn push_field(out: &mut String, field: &str) {
+    let is_formula = field.starts_with(['=', '+', '-', '@', '\t', '\r']);
+    if !is_formula && !field.contains([',', '"', '\n', '\r']) {
+        out.push_str(field);
+        return;
+    }
+
+    out.push('"');
+    if is_formula {
+        out.push('\'');
+    }
+    out.push_str(&field.replace('"', "\"\""));
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::invoices::tests::invoice, test_support, utils::clock::SystemClock};
+
+    fn csv_row(invoice: &Invoice) -> Vec<String> {
+        let mut out = String::new();
+        push_row(&mut out, invoice);
+        out.trim_end_matches('\n').split(',').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn rows_follow_the_header() {
+        let columns: Vec<&str> = HEADER.trim_end().split(',').collect();
+        let invoice = invoice(Uuid::new_v4());
+        let row = csv_row(&invoice);
+
+        assert_eq!(row.len(), columns.len());
+        let column = |name: &str| row[columns.iter().position(|column| *column == name).unwrap()].clone();
+        assert_eq!(column("id"), invoice.id.to_string());
+        assert_eq!(column("amount"), "1500");
+        assert_eq!(column("status"), "Pending");
+        assert_eq!(column("external_ref"), "order-42");
+    }
+
+    #[test]
+    fn well_known_metadata_keys_get_their_own_columns() {
+        let mut invoice = invoice(Uuid::new_v4());
+        invoice.metadata = serde_json::json!({ "order_id": "A-42", "customer_ref": "ACME", "note": "x" });
+        let row = csv_row(&invoice);
+        assert_eq!(row[row.len() - 2..], ["A-42", "ACME"]);
+
+        // Values of another type are left out rather than exported as JSON
+        invoice.metadata = serde_json::json!({ "order_id": 42, "customer_ref": { "id": 1 } });
+        let row = csv_row(&invoice);
+        assert_eq!(row[row.len() - 2..], ["", ""]);
+    }
+
+    #[test]
+    fn formulas_are_defused() {
+        let mut out = String::new();
+        for field in ["=HYPERLINK(\"x\")", "plain", "a,b"] {
+            push_field(&mut out, field);
+            out.push('|');
+        }
+        assert_eq!(out, "\"'=HYPERLINK(\"\"x\"\")\"|plain|\"a,b\"|");
+    }
+
+    /// Inserts `count` invoices issued by `created_by`, numbered from 1
+    async fn insert_invoices(pool: &PgPool, created_by: Uuid, prefix: &str, count: i64) {
+        sqlx::query!(
+            r#"
+            INSERT INTO invoices (id, on_chain_id, title, description, amount, currency, due_date,
+                                  created_by, sequence_number, display_number, metadata)
+            SELECT gen_random_uuid(), $2 || n, 'Widgets, large', 'Says "hi"', n, 'ETH', '2026-12-31',
+                   $1, n, 'INV-' || n, jsonb_build_object('order_id', 'A-' || n)
+            FROM generate_series(1, $3::bigint) AS n
+            "#,
+            created_by,
+            prefix,
+            count
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn large_exports_stream_in_bounded_chunks(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let issuer = test_support::create_user(&pool, &SystemClock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+        let other = test_support::create_user(&pool, &SystemClock, "0x0000000000000000000000000000000000000001").await;
+        const COUNT: i64 = 20_000;
+        insert_invoices(&pool, issuer.id, "issuer-", COUNT).await;
+        insert_invoices(&pool, other.id, "other-", 10).await;
+
+        let mut export = std::pin::pin!(export_invoices_csv(pool, issuer.id));
+        let (mut csv, mut chunks) = (String::new(), 0);
+        while let Some(chunk) = export.next().await {
+            let chunk = chunk.unwrap();
+            // A chunk is flushed once it reaches the threshold, a row past it at most
+            assert!(chunk.len() < CHUNK_BYTES + 512, "{} bytes", chunk.len());
+            csv.push_str(std::str::from_utf8(&chunk).unwrap());
+            chunks += 1;
+        }
+        assert!(chunks > 100, "{chunks} chunks");
+
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(HEADER.trim_end()));
+        let rows: Vec<&str> = lines.collect();
+        assert_eq!(rows.len() as i64, COUNT);
+        for (n, row) in (1..).zip(&rows) {
+            let fields: Vec<&str> = row.splitn(4, ',').collect();
+            assert_eq!(fields[1], format!("INV-{n}"));
+            assert_eq!(fields[2], format!("issuer-{n}"));
+            assert!(row.ends_with(&format!(",A-{n},")), "{row}");
+        }
+        assert!(rows[0].contains(r#","Widgets, large","Says ""hi""","#), "{}", rows[0]);
+    }
+}
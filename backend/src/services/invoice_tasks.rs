@@ -0,0 +1,69 @@
+//! Cancellation of background work tied to a single invoice
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Cancellation tokens of the invoices that have background work in flight
+///
+/// Tasks hold an `InvoiceTask` while they run; cancelling the invoice
+/// cancels every such task, which stops at its next check of `token()`.
+#[derive(Debug, Default)]
+pub struct InvoiceTasks {
+    running: Mutex<HashMap<Uuid, (CancellationToken, usize)>>,
+}
+
+impl InvoiceTasks {
+    /// Registers a task working on `invoice_id`
+    pub fn start(self: &Arc<Self>, invoice_id: Uuid) -> InvoiceTask {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        let (token, count) = running.entry(invoice_id).or_default();
+        *count += 1;
+
+        InvoiceTask {
+            invoice_id,
+            token: token.child_token(),
+            tasks: self.clone(),
+        }
+    }
+
+    /// Cancels every task registered for `invoice_id`
+    pub fn cancel(&self, invoice_id: Uuid) {
+        let cancelled = self.running.lock().unwrap_or_else(|e| e.into_inner()).remove(&invoice_id);
+        if let Some((token, _)) = cancelled {
+            token.cancel();
+        }
+    }
+}
+
+/// Registration of a running task, released when dropped
+#[derive(Debug)]
+pub struct InvoiceTask {
+    invoice_id: Uuid,
+    token: CancellationToken,
+    tasks: Arc<InvoiceTasks>,
+}
+
+impl InvoiceTask {
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Drop for InvoiceTask {
+    fn drop(&mut self) {
+        let mut running = self.tasks.running.lock().unwrap_or_else(|e| e.into_inner());
+        // A cancelled task's entry is gone already, possibly replaced by newer work
+        if !self.token.is_cancelled()
+            && let Some((_, count)) = running.get_mut(&self.invoice_id)
+        {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&self.invoice_id);
+            }
+        }
+    }
+}
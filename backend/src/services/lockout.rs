@@ -0,0 +1,306 @@
+use chrono::{Duration, NaiveDateTime};
+use sqlx::{types::ipnetwork::IpNetwork, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::Lockout,
+    models::{
+        security_events::{count_user_events_since, record_event, EventType},
+        users::User,
+    },
+    services::notifier::Notifier,
+    utils::clock::Clock,
+};
+
+/// Length of a lockout following `prior_lockouts` recent ones
+///
+/// The first lockout lasts `base_duration_secs` and each recent one doubles
+/// it, up to `max_duration_secs`.
+pub fn lockout_duration(lockout: &Lockout, prior_lockouts: i64) -> Duration {
+    let doublings = prior_lockouts.clamp(0, 63) as u32;
+    let duration_secs = lockout.base_duration_secs
+        .saturating_mul(1u64 << doublings)
+        .min(lockout.max_duration_secs);
+
+    Duration::seconds(duration_secs as i64)
+}
+
+/// When an account locked now should unlock
+///
+/// `AccountLocked` events of the user within `escalation_window_secs` count
+/// as prior lockouts, so after a quiet period the duration is back to the base.
+pub async fn escalated_locked_until(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    lockout: &Lockout,
+    user_id: Uuid,
+) -> Result<NaiveDateTime, AppError> {
+    let now = clock.now();
+    let since = now - Duration::seconds(lockout.escalation_window_secs as i64);
+    let prior_lockouts = count_user_events_since(pool, user_id, EventType::AccountLocked, since).await?;
+
+    Ok(now + lockout_duration(lockout, prior_lockouts))
+}
+
+/// Refuses sign-ins to an address whose account is locked
+pub async fn check_account_lock(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    address: &str,
+) -> Result<(), AppError> {
+    match User::get_locked_until(pool, address).await? {
+        Some(locked_until) if locked_until > clock.now() => Err(AppError::ForbiddenError(format!(
+            "This account is locked after repeated failed sign-ins until {} UTC",
+            locked_until.format("%Y-%m-%d %H:%M:%S")
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Locks the account once its recent failed logins reach `max_failed_logins`
+///
+/// Call after recording the `FailedLogin` event. Only failures since the
+/// previous lock ended count, so an account is not locked again by the
+/// failures that locked it before. The lock is recorded and the owner told,
+/// see `record_account_locked`. Returns when the account unlocks if this
+/// failure locked it.
+pub async fn lock_after_failed_login(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    lockout: &Lockout,
+    notifier: Option<&dyn Notifier>,
+    user: &User,
+    client_ip: IpNetwork,
+    user_agent: &str,
+) -> Result<Option<NaiveDateTime>, AppError> {
+    if lockout.max_failed_logins == 0 {
+        return Ok(None);
+    }
+
+    let window_start = clock.now() - Duration::seconds(lockout.failure_window_secs as i64);
+    let since = match User::get_locked_until(pool, &user.ethereum_address).await? {
+        Some(previous_unlock) => previous_unlock.max(window_start),
+        None => window_start,
+    };
+    let failures = count_user_events_since(pool, user.id, EventType::FailedLogin, since).await?;
+    if failures < i64::from(lockout.max_failed_logins) {
+        return Ok(None);
+    }
+
+    let locked_until = escalated_locked_until(pool, clock, lockout, user.id).await?;
+    if !User::lock_until(pool, clock, user.id, locked_until).await? {
+        return Ok(None);
+    }
+
+    record_account_locked(pool, clock, notifier, user, client_ip, user_agent, locked_until).await?;
+
+    Ok(Some(locked_until))
+}
+
+/// Records an `AccountLocked` transition and informs the account owner
+///
+/// The event keeps the IP and user agent that triggered the lock. When a
+/// notifier is given (see `auth.notify_on_lockout`) and the user has an email
+/// address, the owner is told when the account unlocks; otherwise the lock is
+/// only logged. A failed notification never undoes the lock.
+pub async fn record_account_locked(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    notifier: Option<&dyn Notifier>,
+    user: &User,
+    client_ip: IpNetwork,
+    user_agent: &str,
+    locked_until: NaiveDateTime,
+) -> Result<(), AppError> {
+    record_event(
+        pool,
+        clock,
+        EventType::AccountLocked,
+        user.id,
+        client_ip,
+        user_agent,
+        serde_json::json!({
+            "ip": client_ip.to_string(),
+            "user_agent": user_agent,
+            "locked_until": locked_until,
+        }),
+    )
+    .await?;
+
+    let email = user.email.trim();
+    let Some(notifier) = notifier.filter(|_| !email.is_empty()) else {
+        println!(
+            "Account {} locked until {} after failed logins from {}",
+            user.ethereum_address, locked_until, client_ip
+        );
+        return Ok(());
+    };
+
+    let body = format!(
+        "Your account {} was locked after repeated failed sign-in attempts from {}.\n\
+         It will be unlocked automatically at {} UTC.\n\
+         If these attempts were not yours, your wallet is still safe, but consider \
+         reviewing your recent activity.",
+        user.ethereum_address, client_ip, locked_until.format("%Y-%m-%d %H:%M:%S")
+    );
+    if let Err(e) = notifier.send(email, "Your account has been locked", &body) {
+        eprintln!("Failed to send lockout notification for user {}: {}", user.id, e);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support, utils::clock::MockClock};
+    use chrono::NaiveDate;
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    fn lockout() -> Lockout {
+        Lockout {
+            max_failed_logins: 3,
+            failure_window_secs: 900,
+            base_duration_secs: 300,
+            max_duration_secs: 86400,
+            escalation_window_secs: 86400,
+        }
+    }
+
+    fn start() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn lockout_doubles_with_each_prior_lockout() {
+        for (prior_lockouts, secs) in [(0, 300), (1, 600), (2, 1200), (3, 2400), (8, 76800)] {
+            assert_eq!(lockout_duration(&lockout(), prior_lockouts), Duration::seconds(secs));
+        }
+    }
+
+    #[test]
+    fn lockout_is_capped_at_the_max_duration() {
+        for prior_lockouts in [9, 10, 63, 64, i64::MAX] {
+            assert_eq!(lockout_duration(&lockout(), prior_lockouts), Duration::seconds(86400));
+        }
+    }
+
+    async fn fail_login(pool: &PgPool, clock: &MockClock, user: &User) -> Option<NaiveDateTime> {
+        record_event(pool, clock, EventType::FailedLogin, user.id, test_support::client_ip(), "test", serde_json::json!({}))
+            .await
+            .unwrap();
+        lock_after_failed_login(pool, clock, &lockout(), None, user, test_support::client_ip(), "test")
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn repeated_lockouts_escalate_until_a_quiet_period(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(start());
+        let user = test_support::create_user(&pool, &clock, ADDRESS).await;
+
+        let mut durations = Vec::new();
+        for _ in 0..3 {
+            assert_eq!(fail_login(&pool, &clock, &user).await, None);
+            assert_eq!(fail_login(&pool, &clock, &user).await, None);
+            let locked_until = fail_login(&pool, &clock, &user).await.expect("third failure locks");
+            durations.push((locked_until - clock.now()).num_seconds());
+
+            assert!(check_account_lock(&pool, &clock, ADDRESS).await.is_err());
+            clock.set(locked_until);
+            check_account_lock(&pool, &clock, ADDRESS).await.unwrap();
+        }
+        assert_eq!(durations, [300, 600, 1200]);
+
+        clock.advance(Duration::seconds(lockout().escalation_window_secs as i64));
+        for _ in 0..2 {
+            fail_login(&pool, &clock, &user).await;
+        }
+        let locked_until = fail_login(&pool, &clock, &user).await.unwrap();
+        assert_eq!((locked_until - clock.now()).num_seconds(), 300);
+    }
+
+    /// Keeps the notifications instead of sending them
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sent: std::sync::Mutex<Vec<(String, String)>>,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn send(&self, recipient: &str, _subject: &str, body: &str) -> Result<(), AppError> {
+            self.sent.lock().unwrap().push((recipient.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+
+    async fn lock_with_notifier(pool: &PgPool, clock: &MockClock, user: &User, notifier: &RecordingNotifier) -> NaiveDateTime {
+        let mut locked_until = None;
+        for _ in 0..lockout().max_failed_logins {
+            record_event(pool, clock, EventType::FailedLogin, user.id, test_support::client_ip(), "test", serde_json::json!({}))
+                .await
+                .unwrap();
+            locked_until = lock_after_failed_login(pool, clock, &lockout(), Some(notifier), user, test_support::client_ip(), "agent/1.0")
+                .await
+                .unwrap();
+        }
+        locked_until.expect("the last failure locks")
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn locking_records_the_event_and_notifies_the_owner(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(start());
+        let user = test_support::create_user(&pool, &clock, ADDRESS).await;
+        sqlx::query!("UPDATE users SET email = 'owner@example.com' WHERE id = $1", user.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let user = User::get_user_by_id(&pool, user.id).await.unwrap().unwrap();
+
+        let notifier = RecordingNotifier::default();
+        let locked_until = lock_with_notifier(&pool, &clock, &user, &notifier).await;
+
+        let sent = notifier.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "owner@example.com");
+        assert!(sent[0].1.contains(&locked_until.format("%Y-%m-%d %H:%M:%S").to_string()));
+
+        let event = sqlx::query!(
+            r#"SELECT client_ip, user_agent, metadata FROM security_events WHERE event_type = 'accountlocked'"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(event.client_ip, Some(test_support::client_ip()));
+        assert_eq!(event.user_agent.as_deref(), Some("agent/1.0"));
+        assert_eq!(event.metadata.unwrap()["locked_until"], serde_json::json!(locked_until));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn locking_an_account_without_email_only_logs_it(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(start());
+        let user = test_support::create_user(&pool, &clock, ADDRESS).await;
+
+        let notifier = RecordingNotifier::default();
+        lock_with_notifier(&pool, &clock, &user, &notifier).await;
+        assert!(notifier.sent.lock().unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn failures_outside_the_window_do_not_lock(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = MockClock::new(start());
+        let user = test_support::create_user(&pool, &clock, ADDRESS).await;
+
+        for _ in 0..2 {
+            fail_login(&pool, &clock, &user).await;
+        }
+        clock.advance(Duration::seconds(lockout().failure_window_secs as i64 + 1));
+        assert_eq!(fail_login(&pool, &clock, &user).await, None);
+        check_account_lock(&pool, &clock, ADDRESS).await.unwrap();
+    }
+}
@@ -0,0 +1,22 @@
+pub mod anomaly;
+pub mod audit_export;
+pub mod chain;
+pub mod crypto_self_test;
+pub mod early_hints;
+pub mod fee_estimate;
+pub mod geoip;
+pub mod index_templates;
+pub mod invoice_export;
+pub mod invoice_tasks;
+pub mod lockout;
+pub mod notifier;
+pub mod outbox;
+pub mod payments;
+pub mod pool_monitor;
+pub mod readiness;
+pub mod registration;
+pub mod retention;
+pub mod signature_pool;
+pub mod tarpit;
+pub mod time_check;
+pub mod tokens;
@@ -0,0 +1,16 @@
+use crate::app_error::app_error::AppError;
+
+/// Delivers user-facing notifications, e.g. by email
+pub trait Notifier: Send + Sync {
+    fn send(&self, recipient: &str, subject: &str, body: &str) -> Result<(), AppError>;
+}
+
+/// Notifier that only writes the notification to the server log
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn send(&self, recipient: &str, subject: &str, body: &str) -> Result<(), AppError> {
+        println!("Notification to {}: {}\n{}", recipient, subject, body);
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use std::{sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+
+use uuid::Uuid;
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::Outbox,
+    models::outbox::{OutboxDeliveryAttempt, OutboxMessage},
+    utils::clock::Clock,
+};
+
+/// Why a delivery attempt failed
+enum DeliveryError {
+    /// Worth retrying: network errors, timeouts, 5xx, 408 and 429
+    Transient(String),
+    /// Retrying cannot help, e.g. the webhook rejected the payload
+    Permanent(String),
+}
+
+/// Periodically delivers pending outbox messages to `outbox.webhook_url`,
+/// or to the `aggregate_webhooks` entry of their aggregate type
+///
+/// Failed deliveries are retried with exponential backoff; a message is
+/// dead-lettered after `max_attempts` or on a permanent failure. Delivery is
+/// at least once: receivers should deduplicate on the `X-Outbox-Message-Id`
+/// header.
+pub fn spawn_outbox_relay(
+    pool: PgPool,
+    clock: Arc<dyn Clock>,
+    outbox: Outbox,
+) -> Option<JoinHandle<()>> {
+    if !outbox.enabled {
+        return None;
+    }
+
+    Some(tokio::spawn(async move {
+        let http = webhook_client(&outbox);
+        let mut interval = tokio::time::interval(Duration::from_secs(outbox.poll_interval_secs));
+
+        loop {
+            interval.tick().await;
+
+            let messages = match OutboxMessage::fetch_due(&pool, clock.now(), outbox.batch_size).await {
+                Ok(messages) => messages,
+                Err(e) => {
+                    eprintln!("Failed to read the outbox: {}", e);
+                    continue;
+                }
+            };
+
+            for message in messages {
+                let webhook_url = outbox.webhook_url_for(&message.aggregate_type);
+                let result = deliver(&http, webhook_url, &message).await;
+                if let Err(e) = record_attempt(&pool, clock.as_ref(), &outbox, webhook_url, &message, result).await {
+                    eprintln!("Failed to update outbox message {}: {}", message.id, e);
+                }
+            }
+        }
+    }))
+}
+
+/// Delivers a message again now, whatever its state, e.g. after the
+/// receiving endpoint was down long enough for it to be dead-lettered
+///
+/// The message goes to the endpoint currently configured for its aggregate
+/// type. The call is recorded as a delivery attempt made by `admin_id`; see
+/// `OutboxMessage::record_replay` for how the message is updated.
+pub async fn replay_message(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    outbox: &Outbox,
+    message_id: i64,
+    admin_id: Uuid,
+) -> Result<(OutboxMessage, OutboxDeliveryAttempt), AppError> {
+    if !outbox.enabled {
+        return Err(AppError::ConflictError("Outbox delivery is disabled".to_string()));
+    }
+    let message = OutboxMessage::get(pool, message_id)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError(format!("No outbox message {}", message_id)))?;
+
+    let webhook_url = outbox.webhook_url_for(&message.aggregate_type);
+    let error = match deliver(&webhook_client(outbox), webhook_url, &message).await {
+        Ok(()) => None,
+        Err(DeliveryError::Transient(error) | DeliveryError::Permanent(error)) => Some(error),
+    };
+
+    let now = clock.now();
+    let attempt = OutboxDeliveryAttempt::record(pool, message.id, webhook_url, now, error.as_deref(), Some(admin_id)).await?;
+    let message = OutboxMessage::record_replay(pool, message.id, error.as_deref(), now).await?;
+
+    Ok((message, attempt))
+}
+
+fn webhook_client(outbox: &Outbox) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(outbox.timeout_secs))
+        .build()
+        .unwrap_or_default()
+}
+
+async fn deliver(
+    http: &reqwest::Client,
+    webhook_url: &str,
+    message: &OutboxMessage,
+) -> Result<(), DeliveryError> {
+    let response = http
+        .post(webhook_url)
+        .header("X-Outbox-Message-Id", message.id.to_string())
+        .json(&serde_json::json!({
+            "id": message.id,
+            "type": message.event_type,
+            "aggregate_type": message.aggregate_type,
+            "aggregate_id": message.aggregate_id,
+            "created_at": message.created_at,
+            "data": message.payload,
+        }))
+        .send()
+        .await
+        .map_err(|e| DeliveryError::Transient(e.to_string()))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else if status.is_client_error()
+        && status != StatusCode::REQUEST_TIMEOUT
+        && status != StatusCode::TOO_MANY_REQUESTS {
+        Err(DeliveryError::Permanent(format!("webhook answered {}", status)))
+    } else {
+        Err(DeliveryError::Transient(format!("webhook answered {}", status)))
+    }
+}
+
+async fn record_attempt(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    outbox: &Outbox,
+    webhook_url: &str,
+    message: &OutboxMessage,
+    result: Result<(), DeliveryError>,
+) -> Result<(), AppError> {
+    let now = clock.now();
+    let attempts = message.attempts as u32 + 1;
+
+    let error = match &result {
+        Ok(()) => None,
+        Err(DeliveryError::Transient(error) | DeliveryError::Permanent(error)) => Some(error.as_str()),
+    };
+    OutboxDeliveryAttempt::record(pool, message.id, webhook_url, now, error, None).await?;
+
+    match result {
+        Ok(()) => OutboxMessage::mark_sent(pool, message.id, now).await,
+        Err(DeliveryError::Permanent(error)) => {
+            eprintln!("Outbox message {} dead-lettered: {}", message.id, error);
+            OutboxMessage::mark_dead(pool, message.id, &error, now).await
+        }
+        Err(DeliveryError::Transient(error)) if attempts >= outbox.max_attempts => {
+            eprintln!("Outbox message {} dead-lettered after {} attempts: {}", message.id, attempts, error);
+            OutboxMessage::mark_dead(pool, message.id, &error, now).await
+        }
+        Err(DeliveryError::Transient(error)) => {
+            let backoff = outbox.base_backoff_secs.saturating_mul(1 << (attempts - 1).min(16));
+            let next_attempt_at = now + chrono::Duration::seconds(backoff as i64);
+            OutboxMessage::mark_failed(pool, message.id, &error, next_attempt_at).await
+        }
+    }
+}
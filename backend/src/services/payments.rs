@@ -0,0 +1,264 @@
+//! Settlement of invoices from the on-chain transactions that paid them
+
+use bigdecimal::num_bigint::BigInt;
+use sqlx::types::ipnetwork::IpNetwork;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    app_error::app_error::AppError,
+    models::{
+        invoices::{Invoice, InvoiceStatus, PaymentClaim},
+        security_events::{record_event, EventType},
+    },
+    AppState,
+};
+
+/// Caller a settlement is made for, attributed in the events it records
+#[derive(Debug, Clone)]
+pub struct Requester {
+    pub user_id: Uuid,
+    pub client_ip: IpNetwork,
+    pub user_agent: String,
+}
+
+/// Records `tx_hash` as paying `invoice` once the chain confirms it did
+///
+/// Returns `Ok(false)` while the transaction is not mined. A hash already
+/// settling another invoice fails with a conflict and is recorded as a
+/// `PaymentReplayed` event; it is refused before the chain is asked.
+pub async fn settle_payment(
+    app_state: &AppState,
+    invoice: &Invoice,
+    tx_hash: &str,
+    requester: &Requester,
+) -> Result<bool, AppError> {
+    let chain_id = app_state.config.ethereum.chain_id;
+
+    let claim = match Invoice::find_by_payment(&app_state.pool, chain_id, tx_hash).await? {
+        Some(paid_invoice_id) if paid_invoice_id != invoice.id => PaymentClaim::Replayed { paid_invoice_id },
+        _ => {
+            let Some(paid_ids) = app_state.chain.paid_invoice_ids(tx_hash).await? else {
+                return Ok(false);
+            };
+            let pays_invoice = invoice.on_chain_id.parse::<BigInt>()
+                .is_ok_and(|on_chain_id| paid_ids.contains(&on_chain_id));
+            if !pays_invoice {
+                return Err(AppError::ValidationError(format!("Transaction {} did not pay this invoice", tx_hash)));
+            }
+
+            Invoice::confirm_payment(&app_state.pool, app_state.clock.as_ref(), invoice.id, chain_id, tx_hash).await?
+        }
+    };
+
+    match claim {
+        PaymentClaim::Replayed { paid_invoice_id } => {
+            record_event(
+                &app_state.pool,
+                app_state.clock.as_ref(),
+                EventType::PaymentReplayed,
+                requester.user_id,
+                requester.client_ip,
+                &requester.user_agent,
+                serde_json::json!({
+                    "invoice_id": invoice.id,
+                    "paid_invoice_id": paid_invoice_id,
+                    "chain_id": chain_id,
+                    "tx_hash": tx_hash,
+                }),
+            ).await?;
+
+            Err(AppError::ConflictError(format!("Transaction {} already settled another invoice", tx_hash)))
+        }
+        PaymentClaim::Recorded { status_changed: true } => {
+            record_event(
+                &app_state.pool,
+                app_state.clock.as_ref(),
+                EventType::InvoiceStatusChanged,
+                invoice.created_by,
+                requester.client_ip,
+                &requester.user_agent,
+                serde_json::json!({
+                    "invoice_id": invoice.id,
+                    "display_number": invoice.display_number,
+                    "from": invoice.status,
+                    "to": InvoiceStatus::Paid,
+                    "source": "confirm",
+                    "tx_hash": tx_hash,
+                }),
+            ).await?;
+
+            Ok(true)
+        }
+        PaymentClaim::Recorded { status_changed: false } => Ok(true),
+    }
+}
+
+/// Settles an invoice from a submitted transaction once it gets mined
+///
+/// The receipt is polled every `payment_watch.interval_secs` until
+/// `payment_watch.timeout_secs` have passed. Cancelling the invoice stops the
+/// watcher before its next poll; a cancellation racing with the final poll
+/// is harmless since only pending invoices can be marked paid.
+pub fn spawn_payment_watcher(
+    app_state: Arc<AppState>,
+    invoice: Invoice,
+    tx_hash: String,
+    requester: Requester,
+) -> JoinHandle<()> {
+    let task = app_state.invoice_tasks.start(invoice.id);
+
+    tokio::spawn(async move {
+        let watch = &app_state.config.payment_watch;
+        let deadline = Instant::now() + Duration::from_secs(watch.timeout_secs);
+        let mut delay_secs = watch.interval_secs;
+
+        loop {
+            tokio::select! {
+                _ = task.token().cancelled() => {
+                    println!("Stopped watching {} for invoice {}: invoice cancelled", tx_hash, invoice.id);
+                    return;
+                }
+                _ = tokio::time::sleep(Duration::from_secs(delay_secs)) => {}
+            }
+            delay_secs = watch.interval_secs;
+
+            match settle_payment(&app_state, &invoice, &tx_hash, &requester).await {
+                Ok(true) => return,
+                Ok(false) if Instant::now() < deadline => {}
+                Ok(false) => {
+                    eprintln!("Gave up watching {} for invoice {}: not mined in time", tx_hash, invoice.id);
+                    return;
+                }
+                // RPC outages are retried until the deadline, anything else is final
+                Err(AppError::ServiceUnavailableError(e)) if Instant::now() < deadline => {
+                    eprintln!("Watching {} for invoice {}: {}", tx_hash, invoice.id, e);
+                }
+                // A throttled provider told us nothing about the payment, back off
+                Err(AppError::OverloadedError(e, retry_after)) if Instant::now() < deadline => {
+                    eprintln!("Watching {} for invoice {}: {}", tx_hash, invoice.id, e);
+                    delay_secs = delay_secs.max(retry_after);
+                }
+                Err(e) => {
+                    eprintln!("Stopped watching {} for invoice {}: {}", tx_hash, invoice.id, e);
+                    return;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use sha3::{Digest, Keccak256};
+    use sqlx::PgPool;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use crate::{
+        models::invoices::{tests::{create, input}, InvoiceInput},
+        test_support,
+        utils::clock::MockClock,
+    };
+
+    const TX_HASH: &str = "0x8f3c0a5e7d2b4c6f9a1e3d5b7c9f2a4e6b8d0c2f4a6e8b0d2c4f6a8e0b2d4c6f";
+
+    #[sqlx::test(migrations = false)]
+    async fn a_settled_transaction_cannot_pay_another_invoice(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = Arc::new(MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap()));
+        let app_state = test_support::app_state(pool.clone(), clock.clone());
+        let config = &app_state.config;
+        let user = test_support::create_user(&pool, clock.as_ref(), "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+
+        let first = create(&pool, &clock, config, user.id, &input()).await.unwrap();
+        let second = create(&pool, &clock, config, user.id, &InvoiceInput { on_chain_id: "2".to_string(), ..input() })
+            .await
+            .unwrap();
+        let claim = Invoice::confirm_payment(&pool, clock.as_ref(), first.id, config.ethereum.chain_id, TX_HASH).await.unwrap();
+        assert!(matches!(claim, PaymentClaim::Recorded { status_changed: true }));
+
+        let requester = Requester {
+            user_id: user.id,
+            client_ip: test_support::client_ip(),
+            user_agent: "test".to_string(),
+        };
+        // Refused from the recorded payment, the stub chain is never asked
+        let result = settle_payment(&app_state, &second, TX_HASH, &requester).await;
+        assert!(matches!(result, Err(AppError::ConflictError(_))));
+
+        let second = Invoice::get_invoice_by_id(&pool, second.id).await.unwrap().unwrap();
+        assert_eq!(second.status, InvoiceStatus::Pending);
+        let replayed = sqlx::query_scalar!(
+            "SELECT metadata AS \"metadata!\" FROM security_events WHERE event_type = 'paymentreplayed' AND user_id = $1",
+            user.id
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(replayed[0]["invoice_id"], second.id.to_string());
+        assert_eq!(replayed[0]["paid_invoice_id"], first.id.to_string());
+
+        // The same race lost inside `confirm_payment` reports the paying invoice too
+        let claim = Invoice::confirm_payment(&pool, clock.as_ref(), second.id, config.ethereum.chain_id, TX_HASH).await.unwrap();
+        assert!(matches!(claim, PaymentClaim::Replayed { paid_invoice_id } if paid_invoice_id == first.id));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn cancelling_the_invoice_stops_its_watcher(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let clock = Arc::new(MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap()));
+        let mut config = test_support::config();
+
+        // Not mined until `mined` is set, then paying invoice 1
+        let (mined, polls) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)));
+        let contract_address = config.ethereum.contract_address.clone();
+        let (stub_mined, stub_polls) = (mined.clone(), polls.clone());
+        config.ethereum.rpc_url = test_support::stub_rpc(move |method, _| match method {
+            "eth_blockNumber" => Ok(serde_json::json!("0x10")),
+            _ => {
+                stub_polls.fetch_add(1, Ordering::SeqCst);
+                if !stub_mined.load(Ordering::SeqCst) {
+                    return Ok(serde_json::Value::Null);
+                }
+                let payment_made = format!("0x{}", hex::encode(Keccak256::digest(b"PaymentMade(uint256,uint256)")));
+                Ok(serde_json::json!({
+                    "status": "0x1",
+                    "logs": [{ "address": contract_address, "topics": [payment_made, format!("0x{:064x}", 1)] }],
+                }))
+            }
+        }).await;
+        config.payment_watch.interval_secs = 1;
+        config.payment_watch.timeout_secs = 60;
+
+        let app_state = test_support::app_state_with(pool.clone(), clock.clone(), config);
+        let user = test_support::create_user(&pool, clock.as_ref(), "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+        let invoice = create(&pool, &clock, &app_state.config, user.id, &input()).await.unwrap();
+        let requester = Requester {
+            user_id: user.id,
+            client_ip: test_support::client_ip(),
+            user_agent: "test".to_string(),
+        };
+
+        let watcher = spawn_payment_watcher(app_state.clone(), invoice.clone(), TX_HASH.to_string(), requester);
+        while polls.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // Cancelled while waiting for its next poll, which would now settle it
+        app_state.invoice_tasks.cancel(invoice.id);
+        mined.store(true, Ordering::SeqCst);
+        tokio::time::timeout(Duration::from_secs(5), watcher).await.unwrap().unwrap();
+
+        assert_eq!(polls.load(Ordering::SeqCst), 1);
+        let invoice = Invoice::get_invoice_by_id(&pool, invoice.id).await.unwrap().unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Pending);
+        assert_eq!(Invoice::find_by_payment(&pool, app_state.config.ethereum.chain_id, TX_HASH).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,236 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::{Database, PoolSaturation},
+    AppState,
+};
+
+/// Interval between two measurements of the connection wait
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Acquires that gave up after the pool timeout, counted as sqlx errors
+/// are converted into `AppError`
+static ACQUIRE_TIMEOUTS: AtomicU64 = AtomicU64::new(0);
+
+/// Retry-After of 503s caused by a saturated pool, set once at startup
+static RETRY_AFTER_SECS: AtomicU64 = AtomicU64::new(1);
+
+pub fn set_retry_after_secs(secs: u64) {
+    RETRY_AFTER_SECS.store(secs, Ordering::Relaxed);
+}
+
+pub fn retry_after_secs() -> u64 {
+    RETRY_AFTER_SECS.load(Ordering::Relaxed)
+}
+
+pub fn record_acquire_timeout() {
+    ACQUIRE_TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Snapshot of the database pool, served by `/ready` and `/metrics`
+#[derive(Debug, Serialize)]
+pub struct PoolStatus {
+    pub saturation_mode: PoolSaturation,
+    pub max_connections: u32,
+    pub open_connections: u32,
+    pub idle_connections: usize,
+    /// Share of `max_connections` checked out, 1.0 once every one is busy
+    pub utilization: f64,
+    pub saturated: bool,
+    /// Time the last probe waited for a connection
+    pub acquire_wait_secs: f64,
+    pub acquire_timeouts: u64,
+    pub shed_requests: u64,
+}
+
+/// Watches how busy the database pool is
+///
+/// sqlx does not report how long callers wait for a connection, so a probe
+/// acquires and releases one every second and records the wait. Under
+/// `saturation = "shed"` the monitor also tells the API middleware when to
+/// turn requests away instead of letting them queue.
+pub struct PoolMonitor {
+    pool: PgPool,
+    max_connections: u32,
+    saturation: PoolSaturation,
+    acquire_wait_micros: AtomicU64,
+    shed_requests: AtomicU64,
+}
+
+impl PoolMonitor {
+    pub fn new(pool: PgPool, config: &Database) -> Self {
+        PoolMonitor {
+            pool,
+            max_connections: config.max_connections,
+            saturation: config.saturation,
+            acquire_wait_micros: AtomicU64::new(0),
+            shed_requests: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether every connection the pool may open is open and checked out
+    pub fn is_saturated(&self) -> bool {
+        self.pool.size() >= self.max_connections && self.pool.num_idle() == 0
+    }
+
+    /// Whether a new request should be rejected rather than wait for a connection
+    pub fn should_shed(&self) -> bool {
+        self.saturation == PoolSaturation::Shed && self.is_saturated()
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        let open_connections = self.pool.size();
+        let idle_connections = self.pool.num_idle();
+        let in_use = (open_connections as usize).saturating_sub(idle_connections);
+
+        PoolStatus {
+            saturation_mode: self.saturation,
+            max_connections: self.max_connections,
+            open_connections,
+            idle_connections,
+            utilization: in_use as f64 / self.max_connections.max(1) as f64,
+            saturated: self.is_saturated(),
+            acquire_wait_secs: self.acquire_wait_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+            acquire_timeouts: ACQUIRE_TIMEOUTS.load(Ordering::Relaxed),
+            shed_requests: self.shed_requests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Measures the connection wait every `PROBE_INTERVAL` until the pool closes
+pub fn spawn_pool_probe(monitor: Arc<PoolMonitor>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PROBE_INTERVAL);
+
+        while !monitor.pool.is_closed() {
+            interval.tick().await;
+
+            let started = Instant::now();
+            // Released right away: the probe only measures the wait
+            let connection = monitor.pool.acquire().await;
+            let waited = started.elapsed();
+            drop(connection);
+
+            monitor.acquire_wait_micros.store(waited.as_micros() as u64, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Rejects API requests with 503 and Retry-After while the pool is
+/// saturated, when configured to shed load
+pub async fn shed_when_pool_saturated(
+    State(app_state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if app_state.pool_monitor.should_shed() {
+        app_state.pool_monitor.shed_requests.fetch_add(1, Ordering::Relaxed);
+        return AppError::OverloadedError(
+            "Server is busy, please retry later".to_string(),
+            retry_after_secs(),
+        ).into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support, utils::clock::SystemClock};
+    use axum::{body::Body, http::StatusCode, middleware::from_fn_with_state, routing::get, Router};
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+
+    /// A two-connection pool on the database of `pool`
+    async fn small_pool(pool: &PgPool) -> PgPool {
+        PgPoolOptions::new()
+            .max_connections(2)
+            .acquire_timeout(Duration::from_millis(300))
+            .connect_with(pool.connect_options().as_ref().clone())
+            .await
+            .unwrap()
+    }
+
+    fn app_state(pool: PgPool, saturation: PoolSaturation) -> Arc<AppState> {
+        let mut config = test_support::config();
+        config.database.max_connections = 2;
+        config.database.saturation = saturation;
+        test_support::app_state_with(pool, Arc::new(SystemClock), config)
+    }
+
+    async fn get_status(app_state: &Arc<AppState>) -> (StatusCode, Option<String>) {
+        let app = Router::new()
+            .route("/api/invoices", get(|| async { "ok" }))
+            .layer(from_fn_with_state(app_state.clone(), shed_when_pool_saturated));
+        let response = app.oneshot(Request::builder().uri("/api/invoices").body(Body::empty()).unwrap()).await.unwrap();
+        let retry_after = response.headers()
+            .get(axum::http::header::RETRY_AFTER)
+            .map(|value| value.to_str().unwrap().to_string());
+        (response.status(), retry_after)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn requests_are_shed_while_every_connection_is_busy(pool: PgPool) {
+        let app_state = app_state(small_pool(&pool).await, PoolSaturation::Shed);
+        let monitor = &app_state.pool_monitor;
+        assert_eq!(get_status(&app_state).await.0, StatusCode::OK);
+
+        let held = [app_state.pool.acquire().await.unwrap(), app_state.pool.acquire().await.unwrap()];
+        assert!(monitor.is_saturated());
+        assert_eq!(monitor.status().utilization, 1.0);
+        assert_eq!(get_status(&app_state).await, (StatusCode::SERVICE_UNAVAILABLE, Some(retry_after_secs().to_string())));
+        assert_eq!(monitor.status().shed_requests, 1);
+
+        // Connections go back to the pool in the background
+        drop(held);
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while monitor.is_saturated() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(get_status(&app_state).await.0, StatusCode::OK);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn queued_requests_wait_for_a_connection(pool: PgPool) {
+        let app_state = app_state(small_pool(&pool).await, PoolSaturation::Queue);
+        let monitor = &app_state.pool_monitor;
+
+        let mut held = vec![app_state.pool.acquire().await.unwrap(), app_state.pool.acquire().await.unwrap()];
+        assert!(monitor.is_saturated() && !monitor.should_shed());
+        assert_eq!(get_status(&app_state).await.0, StatusCode::OK);
+
+        // Nothing is released within the acquire timeout
+        let timeouts = monitor.status().acquire_timeouts;
+        let error = AppError::from(app_state.pool.acquire().await.unwrap_err());
+        assert!(matches!(error, AppError::OverloadedError(_, _)), "{error:?}");
+        assert!(monitor.status().acquire_timeouts > timeouts);
+
+        // A connection released while waiting is handed over
+        let waiting = tokio::spawn({
+            let pool = app_state.pool.clone();
+            async move { pool.acquire().await.map(drop) }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        held.pop();
+        waiting.await.unwrap().unwrap();
+        assert_eq!(monitor.status().shed_requests, 0);
+    }
+}
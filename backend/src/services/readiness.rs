@@ -0,0 +1,153 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::app_error::app_error::AppError;
+
+/// Whether this instance should receive new traffic
+///
+/// Draining flips `/ready` to 503 so the load balancer stops routing new
+/// requests here, while the process keeps serving in-flight ones. Shutting
+/// down also drains, and additionally refuses requests that still arrive.
+#[derive(Debug)]
+pub struct Readiness {
+    ready: AtomicBool,
+    shutting_down: AtomicBool,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Readiness {
+            ready: AtomicBool::new(true),
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Updates the readiness and logs the transition, if any
+    pub fn set_ready(&self, ready: bool, reason: &str) {
+        if self.ready.swap(ready, Ordering::SeqCst) != ready {
+            let state = if ready { "ready" } else { "draining" };
+            println!("Readiness changed to {} ({})", state, reason);
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Drains for good, `reject_during_shutdown` refuses new requests from now on
+    ///
+    /// Unlike draining, shutdown cannot be undone by SIGUSR2.
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.set_ready(false, "shutdown");
+    }
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Readiness::new()
+    }
+}
+
+/// Drains on SIGUSR1 and resumes on SIGUSR2
+#[cfg(unix)]
+pub fn spawn_readiness_signal_handler(readiness: Arc<Readiness>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let (mut drain, mut resume) = match (
+            signal(SignalKind::user_defined1()),
+            signal(SignalKind::user_defined2()),
+        ) {
+            (Ok(drain), Ok(resume)) => (drain, resume),
+            _ => {
+                eprintln!("Failed to install readiness signal handlers");
+                return;
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = drain.recv() => readiness.set_ready(false, "SIGUSR1"),
+                _ = resume.recv(), if !readiness.is_shutting_down() => readiness.set_ready(true, "SIGUSR2"),
+            }
+        }
+    });
+}
+
+/// Answers 503 with `Connection: close` once shutdown began
+///
+/// Graceful shutdown stops accepting connections but keeps serving open
+/// ones, so requests still arriving on keep-alive connections would start
+/// work the process may be killed before finishing. In-flight requests
+/// passed this check before shutdown and run to completion.
+pub async fn reject_during_shutdown(
+    State(readiness): State<Arc<Readiness>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if readiness.is_shutting_down() {
+        let mut response = AppError::ServiceUnavailableError("Server is shutting down".to_string()).into_response();
+        response.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("close"));
+        return response;
+    }
+
+    next.run(request).await
+}
+
+#[cfg(not(unix))]
+pub fn spawn_readiness_signal_handler(_readiness: Arc<Readiness>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, middleware::from_fn_with_state, routing::get, Router};
+    use tokio::sync::{mpsc, Notify};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn in_flight_requests_finish_while_new_ones_are_refused() {
+        let readiness = Arc::new(Readiness::new());
+        let (entered_tx, mut entered) = mpsc::channel::<()>(1);
+        let release = Arc::new(Notify::new());
+        let app = Router::new()
+            .route("/slow", get({
+                let release = release.clone();
+                move || async move {
+                    entered_tx.send(()).await.unwrap();
+                    release.notified().await;
+                    "done"
+                }
+            }))
+            .route("/fast", get(|| async { "done" }))
+            .layer(from_fn_with_state(readiness.clone(), reject_during_shutdown));
+        let get = |path: &str| app.clone().oneshot(Request::builder().uri(path).body(Body::empty()).unwrap());
+
+        let in_flight = tokio::spawn(get("/slow"));
+        entered.recv().await.unwrap();
+        readiness.begin_shutdown();
+        assert!(!readiness.is_ready());
+
+        let refused = get("/fast").await.unwrap();
+        assert_eq!(refused.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(refused.headers()[header::CONNECTION], "close");
+
+        release.notify_one();
+        let finished = in_flight.await.unwrap().unwrap();
+        assert_eq!(finished.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(finished.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"done");
+    }
+}
@@ -0,0 +1,70 @@
+use sqlx::{types::ipnetwork::IpNetwork, PgPool};
+
+use crate::{
+    app_error::app_error::AppError,
+    models::{security_events::{record_event, EventType}, users::User},
+    services::geoip::GeoLocator,
+    utils::clock::Clock,
+};
+
+/// Records the `WalletConnected` event of a user created at their first login
+///
+/// Besides the IP and user agent, the event carries the country and region
+/// of the client IP when a GeoIP database is configured, to spot account
+/// creation from unexpected places. Nothing finer than a region is stored.
+pub async fn record_account_created(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    geo_locator: &GeoLocator,
+    user: &User,
+    client_ip: IpNetwork,
+    user_agent: &str,
+) -> Result<(), AppError> {
+    let location = geo_locator.locate(client_ip.ip()).unwrap_or_default();
+
+    record_event(
+        pool,
+        clock,
+        EventType::WalletConnected,
+        user.id,
+        client_ip,
+        user_agent,
+        serde_json::json!({
+            "new_account": true,
+            "ethereum_address": user.ethereum_address,
+            "ip": client_ip.to_string(),
+            "user_agent": user_agent,
+            "country": location.country,
+            "region": location.region,
+        }),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{services::geoip::tests::test_locator, test_support, utils::clock::SystemClock};
+
+    #[sqlx::test(migrations = false)]
+    async fn account_creation_event_carries_the_client_location(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let user = test_support::create_user(&pool, &SystemClock, "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23").await;
+
+        record_account_created(&pool, &SystemClock, &test_locator(), &user, test_support::client_ip(), "test")
+            .await
+            .unwrap();
+
+        let metadata = sqlx::query_scalar!(
+            "SELECT metadata FROM security_events WHERE event_type = 'walletconnected' AND user_id = $1",
+            user.id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(metadata["new_account"], true);
+        assert_eq!(metadata["country"], "FR");
+        assert_eq!(metadata["region"], "IDF");
+    }
+}
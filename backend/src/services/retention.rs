@@ -0,0 +1,39 @@
+use sqlx::PgPool;
+use std::{sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::app_config::Retention,
+    models::{security_events::delete_events_older_than, wallet_diagnostics::delete_reports_older_than},
+    utils::clock::Clock,
+};
+
+/// Periodically prunes security events older than the retention period
+///
+/// Event types listed in `preserved_event_types` are kept regardless of age.
+/// Wallet diagnostics reports are pruned after `diagnostics_retention_days`.
+pub fn spawn_event_retention_task(
+    pool: PgPool,
+    clock: Arc<dyn Clock>,
+    retention: Retention,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(retention.purge_interval_secs));
+
+        loop {
+            interval.tick().await;
+
+            let cutoff = clock.now() - chrono::Duration::days(retention.retention_days);
+            match delete_events_older_than(&pool, cutoff, &retention.preserved_event_types).await {
+                Ok(pruned) => println!("Pruned {} security events older than {}", pruned, cutoff),
+                Err(e) => eprintln!("Failed to prune security events: {}", e),
+            }
+
+            let cutoff = clock.now() - chrono::Duration::days(retention.diagnostics_retention_days);
+            match delete_reports_older_than(&pool, cutoff).await {
+                Ok(pruned) => println!("Pruned {} wallet diagnostics reports older than {}", pruned, cutoff),
+                Err(e) => eprintln!("Failed to prune wallet diagnostics: {}", e),
+            }
+        }
+    })
+}
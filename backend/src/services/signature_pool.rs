@@ -0,0 +1,41 @@
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::SignatureWorkers,
+};
+
+/// Runs CPU-bound signature recovery off the async runtime threads
+///
+/// A burst of sign-ins would otherwise keep the runtime busy with secp256k1
+/// math and stall I/O. Jobs run on tokio's blocking pool, at most
+/// `signature_workers.max_concurrency` at a time; later jobs wait for a slot.
+#[derive(Clone)]
+pub struct SignatureVerifier {
+    permits: Arc<Semaphore>,
+}
+
+impl SignatureVerifier {
+    pub fn new(config: &SignatureWorkers) -> Self {
+        SignatureVerifier {
+            permits: Arc::new(Semaphore::new(config.max_concurrency)),
+        }
+    }
+
+    /// Runs `job` on the blocking pool once a worker slot is free
+    ///
+    /// Errors of `job` are returned as is; a panicking job is a `ServerError`.
+    pub async fn run<T, F>(&self, job: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, AppError> + Send + 'static,
+    {
+        let _permit = self.permits.acquire().await
+            .map_err(|e| AppError::ServerError(format!("Signature worker pool closed: {}", e)))?;
+
+        tokio::task::spawn_blocking(job)
+            .await
+            .map_err(|e| AppError::ServerError(format!("Signature verification task failed: {}", e)))?
+    }
+}
@@ -0,0 +1,88 @@
+use sqlx::{types::ipnetwork::IpNetwork, PgPool};
+use std::time::Duration;
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::Tarpit,
+    models::security_events::count_failed_logins_since,
+    utils::clock::Clock,
+};
+
+/// Delay owed after `failures` recent failed logins
+///
+/// Starts at `base_delay_ms` for the first failure and doubles with each one,
+/// up to `max_delay_ms`.
+pub fn failed_login_delay(tarpit: &Tarpit, failures: i64) -> Duration {
+    if !tarpit.enabled || failures <= 0 {
+        return Duration::ZERO;
+    }
+
+    let doublings = u32::try_from(failures - 1).unwrap_or(u32::MAX).min(63);
+    let delay_ms = tarpit.base_delay_ms
+        .saturating_mul(1u64 << doublings)
+        .min(tarpit.max_delay_ms);
+
+    Duration::from_millis(delay_ms)
+}
+
+/// Holds a failed login response back according to recent failures
+///
+/// Call only on the failure path, after the failure has been recorded, so
+/// successful logins are never slowed down. Failures are counted both for the
+/// address and for the client IP.
+pub async fn delay_failed_login(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    tarpit: &Tarpit,
+    address: &str,
+    client_ip: IpNetwork,
+) -> Result<(), AppError> {
+    if !tarpit.enabled {
+        return Ok(());
+    }
+
+    let since = clock.now() - chrono::Duration::seconds(tarpit.window_secs as i64);
+    let failures = count_failed_logins_since(pool, address, client_ip, since).await?;
+
+    let delay = failed_login_delay(tarpit, failures);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tarpit() -> Tarpit {
+        Tarpit { enabled: true, base_delay_ms: 250, max_delay_ms: 5000, window_secs: 900 }
+    }
+
+    #[test]
+    fn delay_doubles_with_each_failure() {
+        let delays: Vec<u64> = (0..=5)
+            .map(|failures| failed_login_delay(&tarpit(), failures).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [0, 250, 500, 1000, 2000, 4000]);
+    }
+
+    #[test]
+    fn delay_never_decreases_and_stops_at_the_cap() {
+        let mut previous = Duration::ZERO;
+        for failures in 0..200 {
+            let delay = failed_login_delay(&tarpit(), failures);
+            assert!(delay >= previous, "delay dropped at {failures} failures");
+            assert!(delay <= Duration::from_millis(5000));
+            previous = delay;
+        }
+        assert_eq!(failed_login_delay(&tarpit(), i64::MAX), Duration::from_millis(5000));
+    }
+
+    #[test]
+    fn disabled_tarpit_never_delays() {
+        let tarpit = Tarpit { enabled: false, ..tarpit() };
+        assert_eq!(failed_login_delay(&tarpit, 10), Duration::ZERO);
+    }
+}
@@ -0,0 +1,90 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+
+use crate::{app_error::app_error::AppError, config::app_config::TimeCheck};
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_UNIX_OFFSET_SECS: f64 = 2_208_988_800.0;
+
+/// Compares the local clock with an NTP server before serving requests
+///
+/// The measured offset is always logged. When it exceeds `max_offset_secs`
+/// startup is refused if `fail_on_drift` is set, otherwise a warning is
+/// printed. An unreachable NTP server never blocks startup.
+pub async fn check_clock_drift(time_check: &TimeCheck) -> Result<(), AppError> {
+    if !time_check.enabled {
+        return Ok(());
+    }
+
+    let offset = match measure_clock_offset(&time_check.ntp_server, Duration::from_secs(time_check.timeout_secs)).await {
+        Ok(offset) => offset,
+        Err(e) => {
+            eprintln!("Clock check skipped, could not query {}: {}", time_check.ntp_server, e);
+            return Ok(());
+        }
+    };
+
+    println!("Local clock offset from {}: {:+.3}s", time_check.ntp_server, offset);
+
+    if offset.abs() > time_check.max_offset_secs as f64 {
+        let message = format!(
+            "Local clock is off by {:+.3}s, more than the allowed {}s",
+            offset, time_check.max_offset_secs
+        );
+        if time_check.fail_on_drift {
+            return Err(AppError::ConfigError(message));
+        }
+        eprintln!("WARNING: {}; challenge and token expiry may be unreliable", message);
+    }
+
+    Ok(())
+}
+
+/// Queries an SNTP server and returns its offset from the local clock, in
+/// seconds (positive when the local clock is behind)
+async fn measure_clock_offset(server: &str, timeout: Duration) -> Result<f64, AppError> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|e| AppError::ServerError(format!("Failed to open UDP socket: {}", e)))?;
+    socket.connect(server)
+        .await
+        .map_err(|e| AppError::ServerError(format!("Failed to resolve NTP server: {}", e)))?;
+
+    // LI = 0, version 3, mode 3 (client)
+    let mut request = [0u8; 48];
+    request[0] = 0x1b;
+
+    let sent_at = unix_now();
+    socket.send(&request)
+        .await
+        .map_err(|e| AppError::ServerError(format!("Failed to send NTP request: {}", e)))?;
+
+    let mut response = [0u8; 48];
+    let received = tokio::time::timeout(timeout, socket.recv(&mut response))
+        .await
+        .map_err(|_| AppError::ServerError("NTP request timed out".to_string()))?
+        .map_err(|e| AppError::ServerError(format!("Failed to read NTP response: {}", e)))?;
+    let received_at = unix_now();
+
+    if received < response.len() {
+        return Err(AppError::ServerError("Truncated NTP response".to_string()));
+    }
+
+    let server_received = ntp_timestamp(&response[32..40]);
+    let server_sent = ntp_timestamp(&response[40..48]);
+
+    Ok(((server_received - sent_at) + (server_sent - received_at)) / 2.0)
+}
+
+fn ntp_timestamp(bytes: &[u8]) -> f64 {
+    let seconds = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64;
+    let fraction = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as f64 / 4_294_967_296.0;
+    seconds + fraction - NTP_UNIX_OFFSET_SECS
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or_default()
+}
@@ -0,0 +1,529 @@
+use chrono::NaiveDateTime;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{types::ipnetwork::IpNetwork, PgPool};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    OnceLock,
+};
+use uuid::Uuid;
+
+use crate::{
+    app_error::app_error::AppError,
+    config::app_config::Auth,
+    models::{
+        invoice_shares::InvoiceShare,
+        outbox::OutboxMessage,
+        security_events::{blacklist_reason, record_event, revocation_message, EventType},
+        sessions::Session,
+        users::User,
+    },
+    utils::clock::Clock,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JwtClaims {
+    pub sub: Uuid,
+    pub address: String,
+    pub is_admin: bool,
+    pub jti: String,
+    pub token_type: TokenType,
+    /// User token epoch at mint time, see `User::bump_token_epoch`
+    pub epoch: i32,
+    pub iat: i64,
+    pub exp: i64,
+    /// Deployment-specific claims such as a tenant id, see `set_extra_claims_hook`
+    ///
+    /// Signed like the other claims, but never read by this crate.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Claims registered by RFC 7519 or minted by this crate, which extra
+/// claims may not use
+pub const RESERVED_CLAIMS: [&str; 11] = [
+    "iss", "sub", "aud", "exp", "nbf", "iat", "jti",
+    "address", "is_admin", "token_type", "epoch",
+];
+
+/// Computes the extra claims of a user's tokens at mint time
+pub type ExtraClaimsHook = Box<dyn Fn(&User) -> Map<String, Value> + Send + Sync>;
+
+/// Source of extra claims, set once at startup; none are minted without it
+static EXTRA_CLAIMS_HOOK: OnceLock<ExtraClaimsHook> = OnceLock::new();
+
+pub fn set_extra_claims_hook(hook: ExtraClaimsHook) {
+    if EXTRA_CLAIMS_HOOK.set(hook).is_err() {
+        eprintln!("Extra claims hook already set, keeping the first one");
+    }
+}
+
+/// Extra claims for a user's tokens, leaving out any reserved claim the
+/// hook returned so it cannot override a registered one
+pub fn extra_claims(user: &User) -> Map<String, Value> {
+    let Some(hook) = EXTRA_CLAIMS_HOOK.get() else {
+        return Map::new();
+    };
+
+    let mut claims = hook(user);
+    claims.retain(|name, _| {
+        let reserved = RESERVED_CLAIMS.contains(&name.as_str());
+        if reserved {
+            eprintln!("Ignoring extra claim \"{}\": it collides with a registered claim", name);
+        }
+        !reserved
+    });
+    claims
+}
+
+/// Scope carried by invoice share tokens
+pub const SHARE_TOKEN_SCOPE: &str = "invoice:read";
+
+/// Claims of a read-only token granting access to a single invoice
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShareClaims {
+    /// Invoice the token grants read access to
+    pub sub: Uuid,
+    /// Id of the `InvoiceShare` backing the token, used for revocation
+    pub jti: Uuid,
+    pub scope: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub access_expires_at: NaiveDateTime,
+    pub refresh_expires_at: NaiveDateTime,
+}
+
+const OUTBOX_AGGREGATE: &str = "token";
+const OUTBOX_ISSUED: &str = "token.issued";
+
+/// Whether minted tokens are queued to the outbox, set once at startup
+static ISSUANCE_AUDIT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_issuance_audit(enabled: bool) {
+    ISSUANCE_AUDIT.store(enabled, Ordering::Relaxed);
+}
+
+/// Mints an access/refresh token pair and records the refresh token's session
+///
+/// Enforces `auth.max_sessions_per_user`: depending on the policy, either
+/// the sign-in is refused or the oldest sessions are revoked, each recorded
+/// as a `SessionEvicted` event. Access tokens of an evicted session stay
+/// valid until they expire. With `audit.token_issuance` on, each token is
+/// also queued as a `token.issued` outbox message.
+pub async fn generate_token_pair(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    auth: &Auth,
+    user: &User,
+    client_ip: IpNetwork,
+    user_agent: &str,
+) -> Result<TokenPair, AppError> {
+    let now = clock.now();
+    let access_expires_at = now + chrono::Duration::seconds(auth.access_token_expires_in as i64);
+    let refresh_expires_at = now + chrono::Duration::seconds(auth.token_expires_in as i64);
+
+    let access_claims = build_claims(user, TokenType::Access, now, access_expires_at);
+    let access_token = encode_claims(auth, &access_claims)?;
+
+    let refresh_claims = build_claims(user, TokenType::Refresh, now, refresh_expires_at);
+    let refresh_token = encode_claims(auth, &refresh_claims)?;
+
+    let (session, evicted) = Session::create_within_limit(
+        pool,
+        clock,
+        user.id,
+        &refresh_claims.jti,
+        refresh_expires_at,
+        auth.max_sessions_per_user,
+        auth.session_limit_policy,
+    ).await?;
+
+    if ISSUANCE_AUDIT.load(Ordering::Relaxed) {
+        let mut conn = pool.acquire().await?;
+        for claims in [&access_claims, &refresh_claims] {
+            OutboxMessage::enqueue(
+                &mut conn,
+                now,
+                OUTBOX_AGGREGATE,
+                user.id,
+                OUTBOX_ISSUED,
+                issuance_payload(claims, session.id, client_ip, user_agent),
+            ).await?;
+        }
+    }
+
+    for evicted_session in evicted {
+        record_event(
+            pool,
+            clock,
+            EventType::SessionEvicted,
+            user.id,
+            client_ip,
+            user_agent,
+            serde_json::json!({
+                "session_id": evicted_session.id,
+                "issued_at": evicted_session.issued_at,
+                "replaced_by": session.id,
+                "max_sessions": auth.max_sessions_per_user,
+            }),
+        ).await?;
+    }
+
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+        access_expires_at,
+        refresh_expires_at,
+    })
+}
+
+/// Outbox payload describing a minted token, without the token itself
+fn issuance_payload(
+    claims: &JwtClaims,
+    session_id: Uuid,
+    client_ip: IpNetwork,
+    user_agent: &str,
+) -> serde_json::Value {
+    serde_json::json!({
+        "user_id": claims.sub,
+        "address": claims.address,
+        "jti": claims.jti,
+        "token_type": claims.token_type,
+        "session_id": session_id,
+        "iat": claims.iat,
+        "exp": claims.exp,
+        "client_ip": client_ip.ip().to_string(),
+        "user_agent": user_agent,
+    })
+}
+
+/// Validates an access token
+///
+/// Besides the signature and expiry, the token must not claim to be issued more
+/// than `max_token_iat_skew_secs` in the future, must not be blacklisted and its
+/// epoch must not be older than the user's current token epoch.
+pub async fn validate_access_token(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    auth: &Auth,
+    token: &str,
+) -> Result<JwtClaims, AppError> {
+    validate_token(pool, clock, auth, token, TokenType::Access).await
+}
+
+/// Validates a refresh token, with the same checks as access tokens
+pub async fn validate_refresh_token(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    auth: &Auth,
+    token: &str,
+) -> Result<JwtClaims, AppError> {
+    validate_token(pool, clock, auth, token, TokenType::Refresh).await
+}
+
+async fn validate_token(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    auth: &Auth,
+    token: &str,
+    expected_type: TokenType,
+) -> Result<JwtClaims, AppError> {
+    let mut claims = decode::<JwtClaims>(
+        token,
+        &DecodingKey::from_secret(auth.jwt_secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))?
+    .claims;
+
+    // Registered claims we do not mint, e.g. `aud`, would land among the
+    // extra ones; they carry no meaning here
+    claims.extra.retain(|name, _| !RESERVED_CLAIMS.contains(&name.as_str()));
+
+    if claims.token_type != expected_type {
+        return Err(AppError::UnauthorizedError("Invalid token type".to_string()));
+    }
+
+    // We never mint tokens ahead of our own clock, such a token was forged
+    // or signed by a host whose clock is off
+    let latest_iat = clock.now().and_utc().timestamp() + auth.max_token_iat_skew_secs as i64;
+    if claims.iat > latest_iat {
+        return Err(AppError::UnauthorizedError("Token issued in the future".to_string()));
+    }
+
+    if let Some(reason) = blacklist_reason(pool, &claims.jti).await? {
+        return Err(AppError::UnauthorizedError(revocation_message(&reason)));
+    }
+
+    let current_epoch = User::get_token_epoch(pool, claims.sub)
+        .await?
+        .ok_or_else(|| AppError::UnauthorizedError("Unknown user".to_string()))?;
+    if claims.epoch < current_epoch {
+        return Err(AppError::UnauthorizedError("Token has been revoked".to_string()));
+    }
+
+    Ok(claims)
+}
+
+/// Signs the share token for an invoice share
+pub fn mint_share_token(auth: &Auth, share: &InvoiceShare) -> Result<String, AppError> {
+    let claims = ShareClaims {
+        sub: share.invoice_id,
+        jti: share.id,
+        scope: SHARE_TOKEN_SCOPE.to_string(),
+        iat: share.created_at.and_utc().timestamp(),
+        exp: share.expires_at.and_utc().timestamp(),
+    };
+
+    encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(auth.jwt_secret.as_bytes()),
+    )
+    .map_err(|e| AppError::ServerError(format!("Failed to sign token: {}", e)))
+}
+
+/// Checks a share token's signature and scope
+///
+/// Expiry, revocation and the view cap are checked against `InvoiceShare`,
+/// so an expired link can be told apart from a forged one.
+pub fn decode_share_token(auth: &Auth, token: &str) -> Result<ShareClaims, AppError> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.validate_exp = false;
+
+    let claims = decode::<ShareClaims>(
+        token,
+        &DecodingKey::from_secret(auth.jwt_secret.as_bytes()),
+        &validation,
+    )
+    .map_err(|_| AppError::UnauthorizedError("Invalid share token".to_string()))?
+    .claims;
+
+    if claims.scope != SHARE_TOKEN_SCOPE {
+        return Err(AppError::UnauthorizedError("Invalid share token".to_string()));
+    }
+
+    Ok(claims)
+}
+
+fn build_claims(
+    user: &User,
+    token_type: TokenType,
+    issued_at: NaiveDateTime,
+    expires_at: NaiveDateTime,
+) -> JwtClaims {
+    JwtClaims {
+        sub: user.id,
+        address: user.ethereum_address.clone(),
+        is_admin: user.is_admin(),
+        jti: Uuid::new_v4().to_string(),
+        token_type,
+        epoch: user.token_epoch,
+        iat: issued_at.and_utc().timestamp(),
+        exp: expires_at.and_utc().timestamp(),
+        extra: extra_claims(user),
+    }
+}
+
+fn encode_claims(auth: &Auth, claims: &JwtClaims) -> Result<String, AppError> {
+    encode(
+        &Header::new(Algorithm::HS256),
+        claims,
+        &EncodingKey::from_secret(auth.jwt_secret.as_bytes()),
+    )
+    .map_err(|e| AppError::ServerError(format!("Failed to sign token: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::security_events::{add_token_to_blacklist, REVOKED_BY_LOGOUT},
+        test_support,
+        utils::clock::SystemClock,
+    };
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    const ADDRESS: &str = "0x52908400098527886e0f7030069857d2e4169ee7";
+
+    async fn render(error: AppError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn the_blacklist_reason_is_shown_in_the_401(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let auth = test_support::config().auth;
+        let user = test_support::create_user(&pool, &SystemClock, ADDRESS).await;
+
+        for (reason, message) in [
+            (REVOKED_BY_LOGOUT, "Token has been revoked: you signed out of this session"),
+            // Free-form reasons may be internal notes, they are not shown
+            ("flagged in incident 42", "Token has been revoked"),
+        ] {
+            let tokens = generate_token_pair(&pool, &SystemClock, &auth, &user, test_support::client_ip(), "test")
+                .await
+                .unwrap();
+            let claims = validate_access_token(&pool, &SystemClock, &auth, &tokens.access_token).await.unwrap();
+            let issued_at = chrono::DateTime::from_timestamp(claims.iat, 0).unwrap().naive_utc();
+            let expires_at = chrono::DateTime::from_timestamp(claims.exp, 0).unwrap().naive_utc();
+            add_token_to_blacklist(&pool, &SystemClock, user.id, &claims.jti, issued_at, expires_at, reason)
+                .await
+                .unwrap();
+
+            let error = validate_access_token(&pool, &SystemClock, &auth, &tokens.access_token).await.unwrap_err();
+            let (status, body) = render(error).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(body["error"]["message"], message);
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn token_minted_before_an_epoch_bump_is_revoked(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let auth = test_support::config().auth;
+        let user = test_support::create_user(&pool, &SystemClock, ADDRESS).await;
+
+        let tokens = generate_token_pair(&pool, &SystemClock, &auth, &user, test_support::client_ip(), "test")
+            .await
+            .unwrap();
+        let claims = validate_access_token(&pool, &SystemClock, &auth, &tokens.access_token)
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, user.id);
+
+        let mut conn = pool.acquire().await.unwrap();
+        User::bump_token_epoch(&mut conn, user.id).await.unwrap();
+
+        for (token, validated) in [
+            (&tokens.access_token, validate_access_token(&pool, &SystemClock, &auth, &tokens.access_token).await),
+            (&tokens.refresh_token, validate_refresh_token(&pool, &SystemClock, &auth, &tokens.refresh_token).await),
+        ] {
+            match validated {
+                Err(AppError::UnauthorizedError(message)) => assert_eq!(message, "Token has been revoked"),
+                other => panic!("{token} was not revoked: {other:?}"),
+            }
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn tokens_issued_in_the_future_are_rejected(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let auth = test_support::config().auth;
+        let user = test_support::create_user(&pool, &SystemClock, ADDRESS).await;
+        let now = SystemClock.now().and_utc().timestamp();
+
+        let sign = |iat: i64| {
+            let claims = JwtClaims {
+                sub: user.id,
+                address: user.ethereum_address.clone(),
+                is_admin: false,
+                jti: Uuid::new_v4().to_string(),
+                token_type: TokenType::Access,
+                epoch: user.token_epoch,
+                iat,
+                exp: iat + 3600,
+                extra: Map::new(),
+            };
+            encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(auth.jwt_secret.as_bytes())).unwrap()
+        };
+
+        match validate_access_token(&pool, &SystemClock, &auth, &sign(now + 600)).await {
+            Err(AppError::UnauthorizedError(message)) => assert_eq!(message, "Token issued in the future"),
+            other => panic!("a token issued in 10 minutes was accepted: {other:?}"),
+        }
+        // Small clock differences between hosts are tolerated
+        let within_skew = now + auth.max_token_iat_skew_secs as i64 / 2;
+        assert_eq!(validate_access_token(&pool, &SystemClock, &auth, &sign(within_skew)).await.unwrap().sub, user.id);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn minted_tokens_carry_the_extra_claims(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let auth = test_support::config().auth;
+        let user = test_support::create_user(&pool, &SystemClock, ADDRESS).await;
+
+        // The hook is process wide, no other test may set it
+        set_extra_claims_hook(Box::new(|user| {
+            let mut claims = Map::new();
+            claims.insert("tenant".to_string(), Value::from("acme"));
+            claims.insert("wallet".to_string(), Value::from(user.ethereum_address.clone()));
+            claims.insert("sub".to_string(), Value::from("someone-else"));
+            claims
+        }));
+
+        let tokens = generate_token_pair(&pool, &SystemClock, &auth, &user, test_support::client_ip(), "test")
+            .await
+            .unwrap();
+        for token in [&tokens.access_token, &tokens.refresh_token] {
+            let claims = decode::<Map<String, Value>>(
+                token,
+                &DecodingKey::from_secret(auth.jwt_secret.as_bytes()),
+                &Validation::new(Algorithm::HS256),
+            )
+            .unwrap()
+            .claims;
+            assert_eq!(claims["tenant"], "acme");
+            assert_eq!(claims["wallet"], ADDRESS);
+            assert_eq!(claims["sub"], user.id.to_string());
+        }
+
+        let validated = validate_access_token(&pool, &SystemClock, &auth, &tokens.access_token)
+            .await
+            .unwrap();
+        assert_eq!(validated.extra.get("tenant"), Some(&Value::from("acme")));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn minting_with_issuance_audit_queues_each_token(pool: PgPool) {
+        test_support::init_schema(&pool).await;
+        let auth = test_support::config().auth;
+        let user = test_support::create_user(&pool, &SystemClock, ADDRESS).await;
+
+        set_issuance_audit(true);
+        let minted = generate_token_pair(&pool, &SystemClock, &auth, &user, test_support::client_ip(), "test").await;
+        set_issuance_audit(false);
+        let tokens = minted.unwrap();
+
+        let queued = sqlx::query!(
+            "SELECT aggregate_type, aggregate_id, event_type, payload FROM outbox_messages ORDER BY id"
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(queued.len(), 2);
+
+        for (message, token) in queued.iter().zip([&tokens.access_token, &tokens.refresh_token]) {
+            let claims = decode::<JwtClaims>(
+                token,
+                &DecodingKey::from_secret(auth.jwt_secret.as_bytes()),
+                &Validation::new(Algorithm::HS256),
+            )
+            .unwrap()
+            .claims;
+            assert_eq!(message.aggregate_type, OUTBOX_AGGREGATE);
+            assert_eq!(message.aggregate_id, user.id);
+            assert_eq!(message.event_type, OUTBOX_ISSUED);
+            assert_eq!(message.payload["jti"], claims.jti);
+            assert_eq!(message.payload["client_ip"], "203.0.113.7");
+            assert!(!message.payload.to_string().contains(token.as_str()));
+        }
+    }
+}
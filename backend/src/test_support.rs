@@ -0,0 +1,114 @@
+//! Shared fixtures of the unit tests
+//!
+//! Database tests run with `#[sqlx::test(migrations = false)]`, each on a
+//! fresh database that `init_schema` loads `db/init.sql` into.
+
+use axum::{routing::post, Json, Router};
+use serde_json::{json, Value as JsonValue};
+use sqlx::{types::ipnetwork::IpNetwork, PgPool};
+use std::sync::Arc;
+
+use crate::{
+    config::app_config::AppConfig,
+    extractors::{admin_user::AdminUser, auth_user::AuthUser},
+    models::{challenge_store::PgChallengeStore, users::User},
+    services::{
+        chain::ChainClient, geoip::GeoLocator, index_templates::IndexTemplates,
+        invoice_tasks::InvoiceTasks, notifier::LogNotifier, pool_monitor::PoolMonitor,
+        readiness::Readiness, signature_pool::SignatureVerifier,
+        tokens::{JwtClaims, TokenType},
+    },
+    utils::clock::Clock,
+    AppState,
+};
+
+pub async fn init_schema(pool: &PgPool) {
+    sqlx::raw_sql(include_str!("../../db/init.sql"))
+        .execute(pool)
+        .await
+        .expect("db/init.sql should apply to an empty database");
+}
+
+/// The development configuration, as the server loads it
+pub fn config() -> AppConfig {
+    AppConfig::new().expect("config/ should deserialize")
+}
+
+/// State of the development configuration, as `create_router` builds it,
+/// on `pool` and `clock`
+pub fn app_state(pool: PgPool, clock: Arc<dyn Clock>) -> Arc<AppState> {
+    app_state_with(pool, clock, config())
+}
+
+/// State as `app_state` builds it, with an adjusted `config`
+pub fn app_state_with(pool: PgPool, clock: Arc<dyn Clock>, config: AppConfig) -> Arc<AppState> {
+    Arc::new(AppState {
+        vue_dist_path: String::new(),
+        pool: pool.clone(),
+        clock,
+        notifier: Arc::new(LogNotifier),
+        readiness: Arc::new(Readiness::new()),
+        chain: ChainClient::new(&config.ethereum).unwrap(),
+        invoice_tasks: Arc::new(InvoiceTasks::default()),
+        geo_locator: Arc::new(GeoLocator::new(&config.geoip)),
+        signature_verifier: SignatureVerifier::new(&config.signature_workers),
+        pool_monitor: Arc::new(PoolMonitor::new(pool.clone(), &config.database)),
+        challenge_store: Arc::new(PgChallengeStore::new(pool, &config.challenge_store)),
+        index_templates: Arc::new(IndexTemplates::new("", &config.frontend, "test", true)),
+        config,
+    })
+}
+
+pub async fn create_user(pool: &PgPool, clock: &dyn Clock, address: &str) -> User {
+    let (user, created) = User::find_or_create_by_address(pool, clock, address)
+        .await
+        .expect("user should be created");
+    assert!(created, "{address} was already registered");
+    user
+}
+
+/// `user` as the admin extractor yields it, for calling admin handlers
+pub fn admin(user: &User) -> AdminUser {
+    let now = chrono::Utc::now().timestamp();
+    AdminUser {
+        auth_user: AuthUser {
+            claims: JwtClaims {
+                sub: user.id,
+                address: user.ethereum_address.clone(),
+                is_admin: true,
+                jti: uuid::Uuid::new_v4().to_string(),
+                token_type: TokenType::Access,
+                epoch: user.token_epoch,
+                iat: now,
+                exp: now + 900,
+                extra: Default::default(),
+            },
+            api_key_id: None,
+        },
+    }
+}
+
+pub fn client_ip() -> IpNetwork {
+    "203.0.113.7".parse().unwrap()
+}
+
+/// URL of a JSON-RPC endpoint answering each call with `respond(method, params)`,
+/// as its `result` or, on `Err`, its `error` object
+pub async fn stub_rpc<F>(respond: F) -> String
+where
+    F: Fn(&str, &JsonValue) -> Result<JsonValue, JsonValue> + Send + Sync + 'static,
+{
+    let respond = Arc::new(respond);
+    let app = Router::new().route("/", post(move |Json(call): Json<JsonValue>| async move {
+        let method = call["method"].as_str().unwrap_or_default();
+        Json(match respond(method, &call["params"]) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": call["id"], "result": result }),
+            Err(error) => json!({ "jsonrpc": "2.0", "id": call["id"], "error": error }),
+        })
+    }));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    rpc_url
+}
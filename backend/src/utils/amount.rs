@@ -0,0 +1,68 @@
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+/// Serde helper for amount fields, used as `#[serde(with = "crate::utils::amount")]`
+///
+/// Amounts travel as decimal strings: wei and base-unit values routinely
+/// exceed 2^53, which JavaScript clients cannot hold in a JSON number.
+/// JSON numbers, exponent notation and negative values are rejected.
+pub fn serialize<S: Serializer>(amount: &BigDecimal, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&amount.to_plain_string())
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigDecimal, D::Error> {
+    let raw = String::deserialize(deserializer)?;
+    parse_amount(&raw).map_err(de::Error::custom)
+}
+
+/// Parses a non-negative plain decimal string such as `"1500"` or `"0.25"`
+pub fn parse_amount(raw: &str) -> Result<BigDecimal, String> {
+    if raw.starts_with('-') {
+        return Err(format!("amount must not be negative, got \"{}\"", raw));
+    }
+
+    let (integer, fraction) = raw.split_once('.').unwrap_or((raw, "0"));
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(integer) || !is_digits(fraction) {
+        return Err(format!("amount must be a decimal string, got \"{}\"", raw));
+    }
+
+    BigDecimal::from_str(raw).map_err(|e| format!("invalid amount \"{}\": {}", raw, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Priced {
+        #[serde(with = "crate::utils::amount")]
+        amount: BigDecimal,
+    }
+
+    #[test]
+    fn wei_amounts_round_trip_without_precision_loss() {
+        for raw in ["123456789012345678901234567890", "0", "0.25", "1500.000000000000000001"] {
+            let priced: Priced = serde_json::from_str(&format!(r#"{{"amount":"{raw}"}}"#)).unwrap();
+            assert_eq!(priced.amount, BigDecimal::from_str(raw).unwrap());
+            assert_eq!(serde_json::to_value(&priced).unwrap()["amount"], raw);
+        }
+    }
+
+    #[test]
+    fn non_decimal_or_negative_amounts_are_rejected() {
+        for raw in ["-1", "-0.5", "1e18", "0x10", "", ".5", "1.", "1.2.3", " 1", "1,5", "NaN", "١٢"] {
+            assert!(parse_amount(raw).is_err(), "{raw:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn json_numbers_are_rejected() {
+        for body in [r#"{"amount":1500}"#, r#"{"amount":1.5}"#, r#"{"amount":null}"#] {
+            assert!(serde_json::from_str::<Priced>(body).is_err(), "{body} was accepted");
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use axum::{
+    extract::{ConnectInfo, OriginalUri, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::{app_error::app_error::AppError, config::app_config::BotFilter, AppState};
+
+impl BotFilter {
+    /// Whether a request with this `User-Agent` header should be rejected
+    ///
+    /// A missing or empty header is only blocked with `block_missing_user_agent`,
+    /// never by the substring list.
+    pub fn is_blocked(&self, user_agent: Option<&str>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        match user_agent.map(str::trim).filter(|agent| !agent.is_empty()) {
+            None => self.block_missing_user_agent,
+            Some(agent) => {
+                let agent = agent.to_lowercase();
+                self.blocked_user_agents
+                    .iter()
+                    .filter(|pattern| !pattern.is_empty())
+                    .any(|pattern| agent.contains(&pattern.to_lowercase()))
+            }
+        }
+    }
+}
+
+/// Rejects requests from blocked user agents with 403, see `[bot_filter]`
+pub async fn reject_blocked_user_agents(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    OriginalUri(uri): OriginalUri,
+    request: Request,
+    next: Next,
+) -> Response {
+    let user_agent = request.headers()
+        .get(header::USER_AGENT)
+        .map(|value| value.to_str().unwrap_or_default());
+
+    if app_state.config.bot_filter.is_blocked(user_agent) {
+        // Blocked requests are anonymous, so they are logged rather than
+        // stored as security events, which always belong to a user
+        eprintln!(
+            "Blocked user agent {:?} from {} on {}",
+            user_agent.unwrap_or_default(), addr.ip(), uri.path()
+        );
+        return AppError::ForbiddenError("Forbidden".to_string()).into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> BotFilter {
+        BotFilter { enabled: true, ..crate::test_support::config().bot_filter }
+    }
+
+    #[test]
+    fn listed_user_agents_are_blocked() {
+        let filter = enabled();
+        for agent in ["python-requests/2.31.0", "curl/8.4.0", "Mozilla/5.0 HeadlessChrome/120.0", "Scrapy/2.11"] {
+            assert!(filter.is_blocked(Some(agent)), "{agent}");
+        }
+        for agent in ["Mozilla/5.0 (X11; Linux x86_64) Firefox/128.0", "MetaMask/11.0", "curl"] {
+            assert!(!filter.is_blocked(Some(agent)), "{agent}");
+        }
+    }
+
+    #[test]
+    fn missing_user_agents_are_only_blocked_when_configured() {
+        let mut filter = enabled();
+        assert!(!filter.block_missing_user_agent);
+        for agent in [None, Some(""), Some("   ")] {
+            assert!(!filter.is_blocked(agent), "{agent:?}");
+        }
+
+        filter.block_missing_user_agent = true;
+        for agent in [None, Some(""), Some("   ")] {
+            assert!(filter.is_blocked(agent), "{agent:?}");
+        }
+    }
+
+    #[test]
+    fn a_disabled_filter_blocks_nothing() {
+        let filter = BotFilter { enabled: false, block_missing_user_agent: true, ..enabled() };
+        assert!(!filter.is_blocked(Some("curl/8.4.0")));
+        assert!(!filter.is_blocked(None));
+    }
+}
@@ -0,0 +1,53 @@
+use chrono::{NaiveDateTime, Utc};
+#[cfg(test)]
+use chrono::Duration;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+/// Source of the current time for all expiry and window computations
+///
+/// Time-dependent code takes a `&dyn Clock` instead of calling `Utc::now()`
+/// so that expiry boundaries can be exercised deterministically.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// Wall clock, used by default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Utc::now().naive_utc()
+    }
+}
+
+/// Manually driven clock for deterministic tests
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    current: Arc<Mutex<NaiveDateTime>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(start: NaiveDateTime) -> Self {
+        MockClock { current: Arc::new(Mutex::new(start)) }
+    }
+
+    pub fn set(&self, time: NaiveDateTime) {
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = time;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        *current += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> NaiveDateTime {
+        *self.current.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
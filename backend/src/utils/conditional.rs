@@ -0,0 +1,118 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use chrono::{DateTime, NaiveDateTime};
+
+use crate::app_error::app_error::AppError;
+
+/// `Last-Modified` format, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// `ETag` and `Last-Modified` of a resource, with `Cache-Control` asking
+/// clients to revalidate before reusing a cached copy
+pub fn validator_headers(etag: &str, last_modified: NaiveDateTime) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&last_modified.format(HTTP_DATE_FORMAT).to_string()) {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("private, no-cache"));
+    headers
+}
+
+/// Whether a GET can be answered with 304 Not Modified
+///
+/// `If-None-Match` takes precedence over `If-Modified-Since`, which is only
+/// compared to the second since HTTP dates carry no fraction.
+pub fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: NaiveDateTime) -> bool {
+    if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+
+    header_str(headers, header::IF_MODIFIED_SINCE)
+        .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+        .is_some_and(|since| last_modified.and_utc().timestamp() <= since.timestamp())
+}
+
+/// Refuses an update with 412 when `If-Match` names another version
+///
+/// Requests without `If-Match` are let through. Weak tags never match, as
+/// required for `If-Match`.
+pub fn check_if_match(headers: &HeaderMap, etag: &str) -> Result<(), AppError> {
+    let Some(if_match) = header_str(headers, header::IF_MATCH) else {
+        return Ok(());
+    };
+
+    let matches = if_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == etag);
+    if !matches {
+        return Err(AppError::PreconditionFailedError(
+            "The resource was modified since it was read".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    const ETAG: &str = "\"v7\"";
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn modified_at() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(1994, 11, 6).unwrap().and_hms_milli_opt(8, 49, 37, 250).unwrap()
+    }
+
+    #[test]
+    fn if_none_match_compares_strong_and_weak_tags() {
+        for value in [ETAG, "W/\"v7\"", "\"v6\", \"v7\"", "*"] {
+            assert!(is_not_modified(&headers(header::IF_NONE_MATCH, value), ETAG, modified_at()), "{value}");
+        }
+        assert!(!is_not_modified(&headers(header::IF_NONE_MATCH, "\"v6\""), ETAG, modified_at()));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let mut headers = headers(header::IF_NONE_MATCH, "\"v6\"");
+        headers.insert(header::IF_MODIFIED_SINCE, HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert!(!is_not_modified(&headers, ETAG, modified_at()));
+    }
+
+    #[test]
+    fn if_modified_since_is_compared_to_the_second() {
+        // The fraction of `modified_at` is not in the HTTP date
+        let since = |at: NaiveDateTime| headers(header::IF_MODIFIED_SINCE, &at.format(HTTP_DATE_FORMAT).to_string());
+        assert!(is_not_modified(&since(modified_at()), ETAG, modified_at()));
+        assert!(is_not_modified(&since(modified_at() + Duration::seconds(1)), ETAG, modified_at()));
+        assert!(!is_not_modified(&since(modified_at() - Duration::seconds(1)), ETAG, modified_at()));
+        assert!(!is_not_modified(&headers(header::IF_MODIFIED_SINCE, "yesterday"), ETAG, modified_at()));
+        assert!(!is_not_modified(&HeaderMap::new(), ETAG, modified_at()));
+    }
+
+    #[test]
+    fn stale_if_match_is_refused() {
+        assert!(check_if_match(&HeaderMap::new(), ETAG).is_ok());
+        assert!(check_if_match(&headers(header::IF_MATCH, ETAG), ETAG).is_ok());
+        assert!(check_if_match(&headers(header::IF_MATCH, "*"), ETAG).is_ok());
+        for value in ["\"v6\"", "W/\"v7\""] {
+            let result = check_if_match(&headers(header::IF_MATCH, value), ETAG);
+            assert!(matches!(result, Err(AppError::PreconditionFailedError(_))), "{value}");
+        }
+    }
+}
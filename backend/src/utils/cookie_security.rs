@@ -0,0 +1,168 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use sqlx::types::ipnetwork::IpNetwork;
+use std::net::SocketAddr;
+
+use crate::config::app_config::Server;
+
+/// Decides whether cookies set on a response get the `Secure` attribute
+///
+/// With `server.trust_proxy_tls`, a request is secure when a proxy listed in
+/// `server.trusted_proxies` reports `X-Forwarded-Proto: https`; the header is
+/// ignored from any other peer. Otherwise the listener's own TLS state is used,
+/// so plain HTTP development keeps working while production behind a TLS
+/// terminating proxy still gets secure cookies.
+#[derive(Debug, Clone)]
+pub struct CookiePolicy {
+    trust_proxy_tls: bool,
+    trusted_proxies: Vec<IpNetwork>,
+    listener_tls: bool,
+}
+
+impl CookiePolicy {
+    /// `trusted_proxies` is validated at startup, invalid entries are skipped here
+    pub fn new(server: &Server, listener_tls: bool) -> Self {
+        CookiePolicy {
+            trust_proxy_tls: server.trust_proxy_tls,
+            trusted_proxies: server.trusted_proxy_networks().unwrap_or_default(),
+            listener_tls,
+        }
+    }
+
+    /// Whether `X-Forwarded-*` headers from `peer` describe the client request
+    pub fn trusts_forwarded_headers(&self, peer: SocketAddr) -> bool {
+        self.trust_proxy_tls && self.trusted_proxies.iter().any(|proxy| proxy.contains(peer.ip()))
+    }
+
+    pub fn is_secure(&self, headers: &HeaderMap, peer: SocketAddr) -> bool {
+        if !self.trusts_forwarded_headers(peer) {
+            return self.listener_tls;
+        }
+
+        // Only the first value is the client-facing scheme when proxies are chained
+        headers.get("x-forwarded-proto")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map_or(self.listener_tls, |proto| proto.trim().eq_ignore_ascii_case("https"))
+    }
+}
+
+/// Adds `Secure` to every cookie set on the response when the request was secure
+pub async fn secure_cookies(
+    State(policy): State<CookiePolicy>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let secure = policy.is_secure(request.headers(), addr);
+    let mut response = next.run(request).await;
+
+    if secure {
+        let cookies: Vec<HeaderValue> = response.headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .cloned()
+            .collect();
+        response.headers_mut().remove(header::SET_COOKIE);
+        for cookie in cookies {
+            response.headers_mut().append(header::SET_COOKIE, with_secure_attribute(cookie));
+        }
+    }
+
+    response
+}
+
+fn with_secure_attribute(cookie: HeaderValue) -> HeaderValue {
+    let Ok(value) = cookie.to_str() else {
+        return cookie;
+    };
+    let already_secure = value.split(';')
+        .skip(1)
+        .any(|attribute| attribute.trim().eq_ignore_ascii_case("secure"));
+    if already_secure {
+        return cookie;
+    }
+
+    HeaderValue::from_str(&format!("{}; Secure", value)).unwrap_or(cookie)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware::from_fn_with_state, response::AppendHeaders, routing::get, Router};
+    use tower::ServiceExt;
+
+    const PROXY: &str = "10.0.0.5:443";
+    const CLIENT: &str = "203.0.113.7:50000";
+
+    fn policy(trust_proxy_tls: bool, listener_tls: bool) -> CookiePolicy {
+        let server = Server {
+            trust_proxy_tls,
+            trusted_proxies: vec!["10.0.0.0/8".to_string()],
+            ..crate::test_support::config().server
+        };
+        CookiePolicy::new(&server, listener_tls)
+    }
+
+    fn forwarded(proto: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-proto", HeaderValue::from_str(proto).unwrap());
+        headers
+    }
+
+    #[test]
+    fn https_reported_by_a_trusted_proxy_is_secure() {
+        let policy = policy(true, false);
+        assert!(policy.is_secure(&forwarded("https"), PROXY.parse().unwrap()));
+        // The first hop is the one the client talked to
+        assert!(policy.is_secure(&forwarded("HTTPS, http"), PROXY.parse().unwrap()));
+        assert!(!policy.is_secure(&forwarded("http"), PROXY.parse().unwrap()));
+        assert!(!policy.is_secure(&HeaderMap::new(), PROXY.parse().unwrap()));
+    }
+
+    #[test]
+    fn forwarded_proto_is_ignored_from_other_peers() {
+        // A client talking plain HTTP directly cannot claim HTTPS
+        assert!(!policy(true, false).is_secure(&forwarded("https"), CLIENT.parse().unwrap()));
+        // Nor through a proxy when proxied TLS is not trusted
+        assert!(!policy(false, false).is_secure(&forwarded("https"), PROXY.parse().unwrap()));
+        assert!(policy(false, true).is_secure(&forwarded("http"), PROXY.parse().unwrap()));
+    }
+
+    async fn set_cookies(policy: CookiePolicy, peer: &str, proto: &str) -> Vec<String> {
+        let app = Router::new()
+            .route("/", get(|| async {
+                AppendHeaders([(header::SET_COOKIE, "session=1; HttpOnly"), (header::SET_COOKIE, "_csrf=2; Secure")])
+            }))
+            .layer(from_fn_with_state(policy, secure_cookies));
+        let mut request = Request::builder()
+            .uri("/")
+            .header("x-forwarded-proto", proto)
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+
+        let response = app.oneshot(request).await.unwrap();
+        response.headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn cookies_get_secure_only_on_secure_requests() {
+        assert_eq!(
+            set_cookies(policy(true, false), PROXY, "https").await,
+            ["session=1; HttpOnly; Secure", "_csrf=2; Secure"],
+        );
+        assert_eq!(
+            set_cookies(policy(true, false), CLIENT, "https").await,
+            ["session=1; HttpOnly", "_csrf=2; Secure"],
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tower::{Layer, ServiceExt};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::{app_error::app_error::AppError, config::app_config::Cors};
+
+const ALLOWED_METHODS: [Method; 5] = [Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS];
+
+/// Picks the CORS policy of each route group
+///
+/// The web app and its sign-in flow only answer `cors.allowed_origins`,
+/// which may send cookies. Requests under `cors.public_api.path_prefixes`
+/// answer `cors.public_api.allowed_origins` instead, `"*"` included, but are
+/// never allowed credentials; while that list is empty they get the web app
+/// policy.
+#[derive(Clone)]
+pub struct CorsPolicy {
+    web_app: CorsLayer,
+    public_api: Option<CorsLayer>,
+    public_api_prefixes: Arc<[String]>,
+}
+
+impl CorsPolicy {
+    pub fn new(config: &Cors) -> Result<Self, AppError> {
+        config.validate_cors()?;
+        let max_age = config.max_age()?;
+
+        let web_app = CorsLayer::new()
+            .allow_origin(AllowOrigin::list(parse_origins(&config.allowed_origins)?))
+            .allow_methods(ALLOWED_METHODS)
+            .allow_headers([
+                HeaderName::from_static("content-type"),
+                HeaderName::from_static("authorization"),
+                HeaderName::from_static("x-csrf-token"),
+            ])
+            .expose_headers([HeaderName::from_static("x-request-id")])
+            .max_age(max_age)
+            .allow_credentials(true);
+
+        let public_origins = &config.public_api.allowed_origins;
+        let public_api = if public_origins.is_empty() {
+            None
+        } else {
+            let allow_origin = if public_origins.iter().any(|origin| origin == "*") {
+                AllowOrigin::any()
+            } else {
+                AllowOrigin::list(parse_origins(public_origins)?)
+            };
+            Some(public_api_layer(allow_origin, max_age))
+        };
+
+        Ok(CorsPolicy {
+            web_app,
+            public_api,
+            public_api_prefixes: config.public_api.path_prefixes.clone().into(),
+        })
+    }
+
+    /// `/api/invoices` covers `/api/invoices` and `/api/invoices/...` but
+    /// not `/api/invoices-archive`
+    fn is_public_api(&self, path: &str) -> bool {
+        self.public_api_prefixes.iter().any(|prefix| {
+            let prefix = prefix.trim_end_matches('/');
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+fn public_api_layer(allow_origin: AllowOrigin, max_age: Duration) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(ALLOWED_METHODS)
+        .allow_headers([
+            HeaderName::from_static("content-type"),
+            HeaderName::from_static("authorization"),
+        ])
+        .expose_headers([HeaderName::from_static("x-request-id")])
+        .max_age(max_age)
+        .allow_credentials(false)
+}
+
+fn parse_origins(origins: &[String]) -> Result<Vec<HeaderValue>, AppError> {
+    origins.iter()
+        .map(|origin| origin.parse::<HeaderValue>().map_err(|e| {
+            AppError::ConfigError(format!("Failed to parse CORS origin {}: {}", origin, e))
+        }))
+        .collect()
+}
+
+/// Answers preflights and adds CORS headers with the policy of the
+/// request's route group
+pub async fn apply_cors(
+    State(policy): State<CorsPolicy>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let layer = match &policy.public_api {
+        Some(public_api) if policy.is_public_api(request.uri().path()) => public_api,
+        _ => &policy.web_app,
+    };
+
+    let result: Result<Response, Infallible> = layer.layer(next).oneshot(request).await;
+    match result {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use axum::{
+        body::Body,
+        http::{header, HeaderMap, StatusCode},
+        middleware::from_fn_with_state,
+        routing::get,
+        Router,
+    };
+
+    const WEB_APP: &str = "http://localhost:3000";
+    const INTEGRATOR: &str = "https://erp.example.com";
+
+    fn app(public_origins: &[&str]) -> Router {
+        let mut cors = test_support::config().cors;
+        cors.public_api.allowed_origins = public_origins.iter().map(|origin| origin.to_string()).collect();
+        let policy = CorsPolicy::new(&cors).unwrap();
+
+        Router::new()
+            .route("/api/auth/challenge", get(|| async { "challenge" }))
+            .route("/api/invoices/{id}", get(|| async { "invoice" }))
+            .route("/api/invoices-archive", get(|| async { "archive" }))
+            .route("/api/tokens", get(|| async { "tokens" }))
+            .layer(from_fn_with_state(policy, apply_cors))
+    }
+
+    async fn get_from(app: Router, path: &str, origin: &str) -> HeaderMap {
+        let request = Request::get(path).header(header::ORIGIN, origin).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.headers().clone()
+    }
+
+    async fn preflight(app: Router, path: &str, origin: &str) -> HeaderMap {
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri(path)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap().headers().clone()
+    }
+
+    fn allowed_headers(headers: &HeaderMap) -> String {
+        headers[header::ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn web_app_routes_allow_its_origin_with_credentials() {
+        let headers = get_from(app(&["*"]), "/api/auth/challenge", WEB_APP).await;
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], WEB_APP);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_EXPOSE_HEADERS], "x-request-id");
+
+        let headers = preflight(app(&["*"]), "/api/auth/challenge", WEB_APP).await;
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], WEB_APP);
+        assert!(allowed_headers(&headers).contains("x-csrf-token"));
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "7200");
+
+        // Public origins do not extend to the web app routes
+        let headers = get_from(app(&["*"]), "/api/auth/challenge", INTEGRATOR).await;
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn public_api_routes_allow_any_origin_without_credentials() {
+        for path in ["/api/invoices/42", "/api/tokens"] {
+            let headers = get_from(app(&["*"]), path, INTEGRATOR).await;
+            assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*", "{path}");
+            assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS), "{path}");
+        }
+
+        let headers = preflight(app(&["*"]), "/api/invoices/42", INTEGRATOR).await;
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!allowed_headers(&headers).contains("x-csrf-token"));
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+
+        // A prefix only covers whole path segments
+        let headers = get_from(app(&["*"]), "/api/invoices-archive", INTEGRATOR).await;
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn listed_public_origins_are_the_only_ones_allowed() {
+        let headers = get_from(app(&[INTEGRATOR]), "/api/tokens", INTEGRATOR).await;
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], INTEGRATOR);
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+
+        let headers = get_from(app(&[INTEGRATOR]), "/api/tokens", "https://evil.example").await;
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn public_api_falls_back_to_the_web_app_policy() {
+        let headers = get_from(app(&[]), "/api/invoices/42", WEB_APP).await;
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], WEB_APP);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+        let headers = get_from(app(&[]), "/api/invoices/42", INTEGRATOR).await;
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
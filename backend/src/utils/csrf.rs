@@ -0,0 +1,166 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_csrf::CsrfToken;
+use std::sync::Arc;
+
+use crate::{app_error::app_error::AppError, config::app_config::Csrf};
+
+/// Header the frontend echoes the token of `BACKEND_CONFIG.csrf_token` in
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Decides which requests must carry a valid CSRF token
+///
+/// Only state-changing methods are checked. Requests authenticated with
+/// `Authorization: Bearer` under one of `csrf.bearer_exempt_prefixes` are
+/// not: a browser never attaches that header by itself, so a cross-site page
+/// cannot forge them, and API clients have no CSRF cookie to pair with.
+#[derive(Debug, Clone)]
+pub struct CsrfPolicy {
+    enabled: bool,
+    bearer_exempt_prefixes: Arc<[String]>,
+}
+
+impl CsrfPolicy {
+    pub fn new(config: &Csrf) -> Self {
+        CsrfPolicy {
+            enabled: config.enabled,
+            bearer_exempt_prefixes: config.bearer_exempt_prefixes.clone().into(),
+        }
+    }
+
+    pub fn requires_token(&self, method: &Method, path: &str, headers: &HeaderMap) -> bool {
+        if !self.enabled || matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+            return false;
+        }
+
+        !(has_bearer_token(headers) && self.is_bearer_exempt(path))
+    }
+
+    /// `/api` covers `/api` and `/api/...` but not `/apis`
+    fn is_bearer_exempt(&self, path: &str) -> bool {
+        self.bearer_exempt_prefixes.iter().any(|prefix| {
+            let prefix = prefix.trim_end_matches('/');
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+fn has_bearer_token(headers: &HeaderMap) -> bool {
+    headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("Bearer "))
+}
+
+/// Rejects with 403 state-changing requests whose `X-CSRF-Token` does not
+/// match the CSRF cookie, unless `CsrfPolicy` exempts them
+pub async fn verify_csrf(
+    State(policy): State<CsrfPolicy>,
+    token: CsrfToken,
+    request: Request,
+    next: Next,
+) -> Response {
+    if policy.requires_token(request.method(), request.uri().path(), request.headers()) {
+        let valid = request.headers()
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| token.verify(value).is_ok());
+        if !valid {
+            return AppError::ForbiddenError("Missing or invalid CSRF token".to_string()).into_response();
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::StatusCode,
+        middleware::from_fn_with_state,
+        routing::{get, post},
+        Router,
+    };
+    use axum_csrf::{CsrfConfig, CsrfLayer};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let policy = CsrfPolicy::new(&Csrf { enabled: true, bearer_exempt_prefixes: vec!["/api".to_string()] });
+        Router::new()
+            .route("/token", get(|token: CsrfToken| async move {
+                let authenticity_token = token.authenticity_token().unwrap();
+                (token, authenticity_token)
+            }))
+            .route("/form", post(|| async { "ok" }))
+            .route("/api/invoices", post(|| async { "ok" }))
+            .layer(from_fn_with_state(policy, verify_csrf))
+            .layer(CsrfLayer::new(CsrfConfig::default()))
+    }
+
+    async fn post_to(app: &Router, path: &str, headers: &[(&str, &str)]) -> StatusCode {
+        let mut request = Request::builder().method(Method::POST).uri(path);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap().status()
+    }
+
+    /// CSRF cookie and the token to echo with it
+    async fn issue_token(app: &Router) -> (String, String) {
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/token").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = response.headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().split(';').next().unwrap().to_string())
+            .collect::<Vec<_>>()
+            .join("; ");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (cookie, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn missing_or_mismatched_tokens_are_forbidden() {
+        let app = app();
+        let (cookie, token) = issue_token(&app).await;
+
+        assert_eq!(post_to(&app, "/form", &[]).await, StatusCode::FORBIDDEN);
+        assert_eq!(post_to(&app, "/form", &[("cookie", &cookie)]).await, StatusCode::FORBIDDEN);
+        assert_eq!(post_to(&app, "/form", &[("cookie", &cookie), (CSRF_HEADER, "forged")]).await, StatusCode::FORBIDDEN);
+        // A token is only valid with the cookie it was issued with
+        assert_eq!(post_to(&app, "/form", &[(CSRF_HEADER, &token)]).await, StatusCode::FORBIDDEN);
+
+        assert_eq!(post_to(&app, "/form", &[("cookie", &cookie), (CSRF_HEADER, &token)]).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn bearer_requests_under_exempt_prefixes_pass() {
+        let app = app();
+        let bearer = ("authorization", "Bearer token");
+
+        assert_eq!(post_to(&app, "/api/invoices", &[bearer]).await, StatusCode::OK);
+        assert_eq!(post_to(&app, "/api/invoices", &[]).await, StatusCode::FORBIDDEN);
+        // Outside the exempt prefixes a bearer token is not enough
+        assert_eq!(post_to(&app, "/form", &[bearer]).await, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn exempt_prefixes_match_whole_segments() {
+        let policy = CsrfPolicy::new(&Csrf { enabled: true, bearer_exempt_prefixes: vec!["/api/".to_string()] });
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer token".parse().unwrap());
+
+        assert!(!policy.requires_token(&Method::POST, "/api", &headers));
+        assert!(!policy.requires_token(&Method::DELETE, "/api/invoices/1", &headers));
+        assert!(policy.requires_token(&Method::POST, "/apis", &headers));
+        assert!(!policy.requires_token(&Method::GET, "/form", &HeaderMap::new()));
+    }
+}
@@ -0,0 +1,246 @@
+//! Classification of Ethereum JSON-RPC failures
+//!
+//! Providers report throttling in several ways: HTTP 429, a `-32005` limit
+//! exceeded error, or a JSON-RPC error with code 429. All of them become
+//! `EthRpcError::RateLimited` so callers back off instead of reading the
+//! failure as an answer, e.g. a missing receipt.
+
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use serde_json::Value as JsonValue;
+use std::fmt;
+
+use crate::app_error::app_error::AppError;
+
+/// Seconds to wait when a throttling provider does not say how long
+const DEFAULT_RETRY_AFTER_SECS: u64 = 10;
+
+/// Seconds to wait when our own RPC concurrency limit is reached
+const SATURATED_RETRY_AFTER_SECS: u64 = 1;
+
+/// `-32005`, used by Infura and geth-based nodes for request limits
+const LIMIT_EXCEEDED: i64 = -32005;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// geth's code for a reverted `eth_call`, which also carries the revert data
+const EXECUTION_REVERTED: i64 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EthRpcError {
+    /// The provider throttled us, retry after the given seconds
+    RateLimited { message: String, retry_after_secs: u64 },
+    /// The node does not serve this method
+    MethodNotFound(String),
+    /// The node refused the request parameters
+    InvalidParams(String),
+    /// The called contract reverted
+    Reverted(String),
+    /// Any other JSON-RPC error object
+    Rpc { code: i64, message: String },
+    /// The node could not be reached or did not answer JSON-RPC
+    Transport(String),
+    /// `max_concurrent_rpc` requests were already in flight, nothing was sent
+    Saturated { max_concurrent: usize },
+}
+
+impl fmt::Display for EthRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EthRpcError::RateLimited { message, retry_after_secs } => {
+                write!(f, "rate limited for {}s: {}", retry_after_secs, message)
+            }
+            EthRpcError::MethodNotFound(message) => write!(f, "method not found: {}", message),
+            EthRpcError::InvalidParams(message) => write!(f, "invalid params: {}", message),
+            EthRpcError::Reverted(message) => write!(f, "execution reverted: {}", message),
+            EthRpcError::Rpc { code, message } => write!(f, "error {}: {}", code, message),
+            EthRpcError::Transport(message) => write!(f, "{}", message),
+            EthRpcError::Saturated { max_concurrent } => {
+                write!(f, "{} requests already in flight", max_concurrent)
+            }
+        }
+    }
+}
+
+impl EthRpcError {
+    /// Classifies the `error` object of a JSON-RPC response
+    pub fn from_error_object(error: &JsonValue) -> Self {
+        let code = error.get("code").and_then(JsonValue::as_i64);
+        let message = error.get("message")
+            .and_then(JsonValue::as_str)
+            .map_or_else(|| error.to_string(), str::to_string);
+
+        match code {
+            Some(LIMIT_EXCEEDED) | Some(429) => EthRpcError::RateLimited {
+                retry_after_secs: backoff_secs(error.get("data")).unwrap_or(DEFAULT_RETRY_AFTER_SECS),
+                message,
+            },
+            Some(METHOD_NOT_FOUND) => EthRpcError::MethodNotFound(message),
+            Some(INVALID_PARAMS) => EthRpcError::InvalidParams(message),
+            Some(EXECUTION_REVERTED) => EthRpcError::Reverted(message),
+            // Nodes other than geth revert with a generic server error
+            _ if message.contains("revert") => EthRpcError::Reverted(message),
+            Some(code) => EthRpcError::Rpc { code, message },
+            None => EthRpcError::Transport(format!("malformed error object: {}", message)),
+        }
+    }
+
+    /// Error for an HTTP response the provider failed, `None` for a success
+    pub fn from_http_response(response: &Response) -> Option<Self> {
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after_secs = response.headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .unwrap_or(DEFAULT_RETRY_AFTER_SECS);
+            return Some(EthRpcError::RateLimited {
+                message: "HTTP 429 Too Many Requests".to_string(),
+                retry_after_secs,
+            });
+        }
+        (!status.is_success()).then(|| EthRpcError::Transport(format!("HTTP {}", status)))
+    }
+
+    /// `result` of a JSON-RPC response body, or its error
+    pub fn parse_response(response: &JsonValue) -> Result<JsonValue, Self> {
+        if let Some(error) = response.get("error") {
+            return Err(EthRpcError::from_error_object(error));
+        }
+        response.get("result")
+            .cloned()
+            .ok_or_else(|| EthRpcError::Transport("response has no result".to_string()))
+    }
+
+    /// `AppError` for a failed call to `method`
+    ///
+    /// Throttling is an overload with `Retry-After`, so it is never mistaken
+    /// for what the chain answered. Parameters we built that the node refused
+    /// are our own bug; everything else means the chain cannot be read now.
+    pub fn into_app_error(self, method: &str) -> AppError {
+        match self {
+            EthRpcError::RateLimited { retry_after_secs, .. } => AppError::OverloadedError(
+                format!("Ethereum RPC provider is rate limiting {}: {}", method, self),
+                retry_after_secs,
+            ),
+            EthRpcError::Saturated { .. } => AppError::OverloadedError(
+                format!("Too many concurrent Ethereum RPC requests, {} not sent: {}", method, self),
+                SATURATED_RETRY_AFTER_SECS,
+            ),
+            EthRpcError::InvalidParams(_) => {
+                AppError::ServerError(format!("Ethereum RPC {} failed: {}", method, self))
+            }
+            _ => AppError::ServiceUnavailableError(format!("Ethereum RPC {} failed: {}", method, self)),
+        }
+    }
+}
+
+/// Backoff a provider suggests in the error `data`, e.g. Infura's
+/// `{ "rate": { "backoff_seconds": 30 } }`
+fn backoff_secs(data: Option<&JsonValue>) -> Option<u64> {
+    let data = data?;
+    data.pointer("/rate/backoff_seconds")
+        .or_else(|| data.get("backoff_seconds"))
+        .or_else(|| data.get("retry_after"))
+        .and_then(JsonValue::as_f64)
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(|secs| (secs.ceil() as u64).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn http_response(status: u16, retry_after: Option<&str>) -> Response {
+        let mut response = axum::http::Response::builder().status(status);
+        if let Some(retry_after) = retry_after {
+            response = response.header(RETRY_AFTER, retry_after);
+        }
+        Response::from(response.body("").unwrap())
+    }
+
+    #[test]
+    fn results_are_returned_as_is() {
+        let response = json!({ "jsonrpc": "2.0", "id": 1, "result": "0x10" });
+        assert_eq!(EthRpcError::parse_response(&response), Ok(json!("0x10")));
+        // A null result, e.g. a pending receipt, is still an answer
+        let response = json!({ "jsonrpc": "2.0", "id": 1, "result": null });
+        assert_eq!(EthRpcError::parse_response(&response), Ok(JsonValue::Null));
+
+        let response = json!({ "jsonrpc": "2.0", "id": 1 });
+        assert!(matches!(EthRpcError::parse_response(&response), Err(EthRpcError::Transport(_))));
+    }
+
+    #[test]
+    fn throttling_errors_are_rate_limits() {
+        // Infura
+        let response = json!({ "jsonrpc": "2.0", "id": 1, "error": {
+            "code": -32005,
+            "message": "daily request count exceeded, request rate limited",
+            "data": { "rate": { "allowed_rps": 1, "backoff_seconds": 30, "current_rps": 1.4 } },
+        }});
+        assert_eq!(EthRpcError::parse_response(&response), Err(EthRpcError::RateLimited {
+            message: "daily request count exceeded, request rate limited".to_string(),
+            retry_after_secs: 30,
+        }));
+
+        // Fractional backoff rounds up, a missing one uses the default
+        let error = json!({ "code": 429, "message": "Too many requests", "data": { "retry_after": 0.2 } });
+        assert!(matches!(EthRpcError::from_error_object(&error), EthRpcError::RateLimited { retry_after_secs: 1, .. }));
+        let error = json!({ "code": -32005, "message": "limit exceeded" });
+        assert!(matches!(
+            EthRpcError::from_error_object(&error),
+            EthRpcError::RateLimited { retry_after_secs: DEFAULT_RETRY_AFTER_SECS, .. }
+        ));
+    }
+
+    #[test]
+    fn reverts_are_recognized_from_any_node() {
+        // geth, with the revert data
+        let error = json!({ "code": 3, "message": "execution reverted: ERC20: paused", "data": "0x08c379a0" });
+        assert_eq!(EthRpcError::from_error_object(&error), EthRpcError::Reverted("execution reverted: ERC20: paused".to_string()));
+        // Nethermind, Erigon and others use a generic server error
+        let error = json!({ "code": -32000, "message": "VM execution error: revert" });
+        assert!(matches!(EthRpcError::from_error_object(&error), EthRpcError::Reverted(_)));
+    }
+
+    #[test]
+    fn other_error_objects_keep_their_code() {
+        let error = json!({ "code": -32601, "message": "the method eth_foo does not exist/is not available" });
+        assert!(matches!(EthRpcError::from_error_object(&error), EthRpcError::MethodNotFound(_)));
+
+        let error = json!({ "code": -32602, "message": "invalid argument 0: hex string has length 3" });
+        assert!(matches!(EthRpcError::from_error_object(&error), EthRpcError::InvalidParams(_)));
+
+        let error = json!({ "code": -32000, "message": "header not found" });
+        assert_eq!(EthRpcError::from_error_object(&error), EthRpcError::Rpc { code: -32000, message: "header not found".to_string() });
+
+        // Without a code the object is not JSON-RPC
+        let error = json!("upstream connect error");
+        assert!(matches!(EthRpcError::from_error_object(&error), EthRpcError::Transport(_)));
+    }
+
+    #[test]
+    fn http_failures_are_classified_before_the_body() {
+        assert_eq!(EthRpcError::from_http_response(&http_response(200, None)), None);
+        assert_eq!(
+            EthRpcError::from_http_response(&http_response(429, Some("45"))),
+            Some(EthRpcError::RateLimited { message: "HTTP 429 Too Many Requests".to_string(), retry_after_secs: 45 })
+        );
+        // An HTTP date is not understood, the default applies
+        assert!(matches!(
+            EthRpcError::from_http_response(&http_response(429, Some("Wed, 21 Oct 2026 07:28:00 GMT"))),
+            Some(EthRpcError::RateLimited { retry_after_secs: DEFAULT_RETRY_AFTER_SECS, .. })
+        ));
+        assert!(matches!(EthRpcError::from_http_response(&http_response(502, None)), Some(EthRpcError::Transport(_))));
+    }
+
+    #[test]
+    fn app_errors_tell_throttling_from_outages() {
+        let throttled = EthRpcError::RateLimited { message: "limit".to_string(), retry_after_secs: 30 };
+        assert!(matches!(throttled.into_app_error("eth_call"), AppError::OverloadedError(_, 30)));
+        let refused = EthRpcError::InvalidParams("bad".to_string());
+        assert!(matches!(refused.into_app_error("eth_call"), AppError::ServerError(_)));
+        let down = EthRpcError::Transport("HTTP 502 Bad Gateway".to_string());
+        assert!(matches!(down.into_app_error("eth_call"), AppError::ServiceUnavailableError(_)));
+    }
+}
@@ -0,0 +1,157 @@
+use chrono::{FixedOffset, NaiveDateTime};
+use std::collections::HashMap;
+
+use crate::config::app_config::Auth;
+
+/// Statement used when no template is configured for the default locale
+pub const DEFAULT_STATEMENT: &str = "Sign in to verify ownership of this address. This request expires at {expires_at}.";
+/// Appended to templates that do not place `{expires_at}` themselves
+const EXPIRY_SENTENCE: &str = "This request expires at {expires_at}.";
+
+/// Sign-in statement template picked for a request, with the locale it was picked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalizedStatement {
+    pub locale: String,
+    pub template: String,
+}
+
+impl LocalizedStatement {
+    /// Picks the best configured statement for an `Accept-Language` header
+    ///
+    /// Languages are tried by decreasing quality, first by exact tag then by
+    /// primary subtag (`fr-CA` matches `fr`), before falling back to
+    /// `auth.default_locale`.
+    pub fn negotiate(auth: &Auth, accept_language: Option<&str>) -> Self {
+        let locale = accept_language
+            .and_then(|header| best_match(header, &auth.statements))
+            .unwrap_or_else(|| auth.default_locale.clone());
+
+        let template = auth.statements.get(&locale)
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_STATEMENT.to_string());
+
+        LocalizedStatement { locale, template }
+    }
+
+    /// Renders the statement, substituting the `{domain}`, `{address}` and
+    /// `{expires_at}` placeholders
+    ///
+    /// Wallets show the statement to the user, so the expiry is always stated:
+    /// templates without `{expires_at}` get an English sentence appended.
+    /// EIP-4361 statements are a single line, so line breaks are flattened.
+    pub fn render(&self, domain: &str, address: &str, expires_at: &str) -> String {
+        let template = if self.template.contains("{expires_at}") {
+            self.template.clone()
+        } else {
+            format!("{} {}", self.template.trim_end(), EXPIRY_SENTENCE)
+        };
+
+        template
+            .replace("{domain}", domain)
+            .replace("{address}", address)
+            .replace("{expires_at}", expires_at)
+            .replace(['\r', '\n'], " ")
+    }
+}
+
+/// How challenge expiry times are shown to users, see `auth.expiry_time_format`
+#[derive(Debug, Clone)]
+pub struct ExpiryDisplay {
+    pub format: String,
+    pub offset: FixedOffset,
+}
+
+impl ExpiryDisplay {
+    /// Falls back to UTC when the offset is invalid; it is checked at startup
+    pub fn from_config(auth: &Auth) -> Self {
+        ExpiryDisplay {
+            format: auth.expiry_time_format.clone(),
+            offset: auth.expiry_offset().unwrap_or(FixedOffset::east_opt(0).expect("zero offset is valid")),
+        }
+    }
+
+    pub fn render(&self, expires_at: &NaiveDateTime) -> String {
+        expires_at.and_utc()
+            .with_timezone(&self.offset)
+            .format(&self.format)
+            .to_string()
+    }
+}
+
+fn best_match(header: &str, statements: &HashMap<String, String>) -> Option<String> {
+    let mut languages: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // Stable sort keeps the header order between equal qualities
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    languages.into_iter().find_map(|(tag, _)| {
+        let primary = tag.split('-').next().unwrap_or(tag);
+        statements.keys()
+            .find(|locale| locale.eq_ignore_ascii_case(tag))
+            .or_else(|| statements.keys().find(|locale| locale.eq_ignore_ascii_case(primary)))
+            .cloned()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::auth_challenges::{AuthChallenge, ChallengeScope},
+        test_support,
+        utils::clock::MockClock,
+    };
+    use chrono::NaiveDate;
+
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    fn auth(expiry_utc_offset: &str) -> Auth {
+        Auth {
+            expiry_utc_offset: expiry_utc_offset.to_string(),
+            expiry_time_format: "%Y-%m-%d %H:%M:%S UTC%:z".to_string(),
+            ..test_support::config().auth
+        }
+    }
+
+    #[test]
+    fn the_expiry_is_shown_in_the_configured_offset() {
+        let auth = auth("+02:00");
+        let expires_at = NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(23, 30, 0).unwrap();
+        let expiry = ExpiryDisplay::from_config(&auth).render(&expires_at);
+        // Past midnight in the offset
+        assert_eq!(expiry, "2026-03-03 01:30:00 UTC+02:00");
+
+        let statement = LocalizedStatement::negotiate(&auth, Some("fr-CA, en;q=0.5"));
+        assert!(statement.render("example.com", ADDRESS, &expiry).ends_with("Cette demande expire le 2026-03-03 01:30:00 UTC+02:00."));
+
+        let custom = LocalizedStatement { locale: "en".to_string(), template: "Sign in to {domain}.\n".to_string() };
+        assert_eq!(
+            custom.render("example.com", ADDRESS, &expiry),
+            "Sign in to example.com. This request expires at 2026-03-03 01:30:00 UTC+02:00.",
+        );
+    }
+
+    #[test]
+    fn challenge_messages_state_their_own_expiry() {
+        let mut config = test_support::config();
+        config.auth = auth("-05:00");
+        let clock = MockClock::new(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap().and_hms_opt(9, 0, 0).unwrap());
+        let statement = LocalizedStatement::negotiate(&config.auth, None);
+
+        let preview = AuthChallenge::preview_for_addr(&clock, ADDRESS, &ChallengeScope::from_config(&config), &statement).unwrap();
+        let expiry = preview.expires_at.and_utc()
+            .with_timezone(&FixedOffset::west_opt(5 * 3600).unwrap())
+            .format("%Y-%m-%d %H:%M:%S UTC-05:00")
+            .to_string();
+        assert!(preview.message.contains(&format!("This request expires at {expiry}.")), "{}", preview.message);
+    }
+}
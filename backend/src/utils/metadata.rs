@@ -0,0 +1,265 @@
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value as JsonValue};
+use std::marker::PhantomData;
+use validator::ValidationError;
+
+use crate::config::app_config::{EventMetadataLimits, OversizedMetadata};
+
+/// Largest metadata object accepted, in bytes of serialized JSON
+pub const MAX_METADATA_BYTES: usize = 16 * 1024;
+/// Largest number of top-level keys accepted in a metadata object
+pub const MAX_METADATA_KEYS: usize = 64;
+
+/// Validator for user and invoice `metadata` fields
+///
+/// Metadata must be a JSON object within the size and key-count caps above;
+/// `null` is accepted and stored as an empty object.
+pub fn validate_metadata(metadata: &JsonValue) -> Result<(), ValidationError> {
+    if metadata.is_null() {
+        return Ok(());
+    }
+
+    let Some(fields) = metadata.as_object() else {
+        return Err(ValidationError::new("metadata_not_object")
+            .with_message("metadata must be a JSON object".into()));
+    };
+
+    if fields.len() > MAX_METADATA_KEYS {
+        return Err(ValidationError::new("metadata_too_many_keys")
+            .with_message(format!("metadata cannot have more than {} keys", MAX_METADATA_KEYS).into()));
+    }
+
+    let size = serde_json::to_vec(metadata).map(|bytes| bytes.len()).unwrap_or(usize::MAX);
+    if size > MAX_METADATA_BYTES {
+        return Err(ValidationError::new("metadata_too_large")
+            .with_message(format!("metadata cannot exceed {} bytes", MAX_METADATA_BYTES).into()));
+    }
+
+    Ok(())
+}
+
+/// A well-known metadata key with the type of its value
+///
+/// Reading returns `None` when the key is absent or holds a value of another
+/// type, so callers never have to handle malformed integrator data.
+pub struct MetadataKey<T> {
+    pub name: &'static str,
+    value_type: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> MetadataKey<T> {
+    pub const fn new(name: &'static str) -> Self {
+        MetadataKey { name, value_type: PhantomData }
+    }
+
+    pub fn get(&self, metadata: &JsonValue) -> Option<T> {
+        metadata.get(self.name)
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+    }
+}
+
+/// Room kept for the `_truncated` marker when truncating event metadata
+const TRUNCATION_MARKER_BYTES: usize = 96;
+
+/// Shortest a string is cut to; one that would end up shorter is dropped
+const MIN_CUT_STRING_CHARS: usize = 16;
+
+/// Brings security event metadata within `limits`
+///
+/// Metadata within the limits is returned as is. Otherwise, under
+/// `truncate`, the leading entries are kept while they fit: a string that
+/// overflows is cut and marked with `…`, any other entry that does not fit
+/// is dropped. A `_truncated` key then records the original size and key
+/// count. Under `reject`, or when the metadata is not an object, only a
+/// `_metadata_rejected` marker with that size and count is kept.
+pub fn cap_event_metadata(metadata: JsonValue, limits: &EventMetadataLimits) -> JsonValue {
+    let bytes = json_len(&metadata);
+    let keys = count_keys(&metadata);
+    if bytes <= limits.max_bytes && keys <= limits.max_keys {
+        return metadata;
+    }
+
+    let original = serde_json::json!({ "bytes": bytes, "keys": keys });
+    let fields = match (limits.oversized, metadata) {
+        (OversizedMetadata::Truncate, JsonValue::Object(fields)) => fields,
+        _ => return serde_json::json!({ "_metadata_rejected": original }),
+    };
+
+    let budget = limits.max_bytes.saturating_sub(TRUNCATION_MARKER_BYTES);
+    // The marker takes one key
+    let mut keys_left = limits.max_keys - 1;
+    // Braces of the object
+    let mut used = 2;
+    let mut kept = Map::new();
+
+    for (key, value) in fields {
+        let value_keys = 1 + count_keys(&value);
+        if value_keys > keys_left {
+            continue;
+        }
+
+        // Quoted key, colon and separating comma
+        let overhead = json_len(&JsonValue::String(key.clone())) + 2;
+        let value = if used + overhead + json_len(&value) <= budget {
+            value
+        } else if let JsonValue::String(text) = value {
+            match cut_string(&text, budget.saturating_sub(used + overhead)) {
+                Some(cut) => JsonValue::String(cut),
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+
+        used += overhead + json_len(&value);
+        keys_left -= value_keys;
+        kept.insert(key, value);
+    }
+
+    kept.insert("_truncated".to_string(), original);
+    JsonValue::Object(kept)
+}
+
+/// Leading part of `text` whose JSON string, `…` included, fits in `room` bytes
+fn cut_string(text: &str, room: usize) -> Option<String> {
+    // Quotes and the three bytes of `…`
+    let mut left = room.checked_sub(5)?;
+    let mut cut = String::new();
+
+    for c in text.chars() {
+        let escaped_len = json_len(&JsonValue::String(c.to_string())) - 2;
+        if escaped_len > left {
+            break;
+        }
+        left -= escaped_len;
+        cut.push(c);
+    }
+
+    if cut.chars().count() < MIN_CUT_STRING_CHARS {
+        return None;
+    }
+    cut.push('…');
+
+    Some(cut)
+}
+
+/// Keys of every object in `value`, nested ones included
+fn count_keys(value: &JsonValue) -> usize {
+    match value {
+        JsonValue::Object(fields) => fields.values().map(|value| 1 + count_keys(value)).sum(),
+        JsonValue::Array(items) => items.iter().map(count_keys).sum(),
+        _ => 0,
+    }
+}
+
+/// Bytes of `value` serialized as compact JSON
+fn json_len(value: &JsonValue) -> usize {
+    // Serializing a `Value` cannot fail, its map keys are always strings
+    serde_json::to_vec(value).map(|bytes| bytes.len()).unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    fn limits(oversized: OversizedMetadata) -> EventMetadataLimits {
+        EventMetadataLimits { max_bytes: 512, max_keys: 8, oversized }
+    }
+
+    #[test]
+    fn metadata_within_limits_is_kept_as_is() {
+        let metadata = json!({ "ip": "203.0.113.7", "user_agent": "Mozilla/5.0", "nested": { "a": 1 } });
+        assert_eq!(cap_event_metadata(metadata.clone(), &limits(OversizedMetadata::Truncate)), metadata);
+    }
+
+    #[test]
+    fn oversized_string_is_cut_and_marked() {
+        let user_agent = "A".repeat(10_000);
+        let metadata = json!({ "ip": "203.0.113.7", "user_agent": user_agent });
+        let original_bytes = json_len(&metadata);
+
+        let capped = cap_event_metadata(metadata, &limits(OversizedMetadata::Truncate));
+        assert_eq!(capped["ip"], "203.0.113.7");
+        let cut = capped["user_agent"].as_str().unwrap();
+        assert!(cut.ends_with('…') && user_agent.starts_with(cut.trim_end_matches('…')));
+        assert_eq!(capped["_truncated"], json!({ "bytes": original_bytes, "keys": 2 }));
+        assert!(json_len(&capped) <= 512);
+    }
+
+    #[test]
+    fn keys_over_the_limit_are_dropped() {
+        let metadata: Map<String, JsonValue> = (0..20).map(|i| (format!("k{i:02}"), json!(i))).collect();
+
+        let capped = cap_event_metadata(JsonValue::Object(metadata), &limits(OversizedMetadata::Truncate));
+        let kept: Vec<&String> = capped.as_object().unwrap().keys().collect();
+        assert_eq!(kept, ["_truncated", "k00", "k01", "k02", "k03", "k04", "k05", "k06"]);
+        assert_eq!(capped["_truncated"]["keys"], 20);
+    }
+
+    #[test]
+    fn reject_policy_and_non_objects_keep_only_a_marker() {
+        let oversized = json!({ "user_agent": "A".repeat(1000) });
+        let original_bytes = json_len(&oversized);
+        assert_eq!(
+            cap_event_metadata(oversized, &limits(OversizedMetadata::Reject)),
+            json!({ "_metadata_rejected": { "bytes": original_bytes, "keys": 1 } })
+        );
+
+        let array = JsonValue::Array(vec![json!("A".repeat(1000))]);
+        let original_bytes = json_len(&array);
+        assert_eq!(
+            cap_event_metadata(array, &limits(OversizedMetadata::Truncate)),
+            json!({ "_metadata_rejected": { "bytes": original_bytes, "keys": 0 } })
+        );
+    }
+
+    #[test]
+    fn typed_keys_read_values_of_their_type() {
+        const ORDER: MetadataKey<String> = MetadataKey::new("order");
+        const COUNT: MetadataKey<u32> = MetadataKey::new("count");
+
+        let metadata = json!({ "order": "PO-1", "count": 3 });
+        assert_eq!(ORDER.get(&metadata).as_deref(), Some("PO-1"));
+        assert_eq!(COUNT.get(&metadata), Some(3));
+
+        // Absent, or another type under the key, reads as absent
+        assert_eq!(ORDER.get(&json!({})), None);
+        assert_eq!(ORDER.get(&JsonValue::Null), None);
+        assert_eq!(COUNT.get(&json!({ "count": "three" })), None);
+        assert_eq!(COUNT.get(&json!({ "count": -1 })), None);
+    }
+
+    fn metadata_value() -> impl Strategy<Value = JsonValue> {
+        let leaf = prop_oneof![
+            any::<i64>().prop_map(JsonValue::from),
+            "\\PC{0,300}".prop_map(JsonValue::from),
+            Just(JsonValue::Null),
+        ];
+        leaf.prop_recursive(3, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8).prop_map(JsonValue::from),
+                prop::collection::btree_map("[a-z\"\\\\]{1,12}", inner, 0..8)
+                    .prop_map(|fields| JsonValue::Object(fields.into_iter().collect())),
+            ]
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn capped_metadata_is_within_the_limits(
+            fields in prop::collection::btree_map("\\PC{1,20}", metadata_value(), 0..40),
+            max_bytes in 256usize..2048,
+            max_keys in 2usize..32,
+        ) {
+            let limits = EventMetadataLimits { max_bytes, max_keys, oversized: OversizedMetadata::Truncate };
+            let capped = cap_event_metadata(JsonValue::Object(fields.into_iter().collect()), &limits);
+            prop_assert!(json_len(&capped) <= max_bytes, "{} bytes", json_len(&capped));
+            // The marker counts as one key, its two nested ones come on top
+            prop_assert!(count_keys(&capped) <= max_keys + 2, "{} keys", count_keys(&capped));
+        }
+    }
+}
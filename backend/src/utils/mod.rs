@@ -1 +1,13 @@
-pub mod server_utils;
\ No newline at end of file
+pub mod amount;
+pub mod bot_filter;
+pub mod clock;
+pub mod conditional;
+pub mod cookie_security;
+pub mod cors;
+pub mod csrf;
+pub mod eip712;
+pub mod ethereum;
+pub mod i18n;
+pub mod metadata;
+pub mod server_utils;
+pub mod siwe;
@@ -1,17 +1,20 @@
 use tokio;
 use tokio::signal;
 use axum::{
-    http::HeaderMap, 
+    http::{header, HeaderMap}, 
     middleware::Next, 
     response::Response, 
     extract::Request
 };
+use sqlx::{postgres::PgConnectOptions, types::ipnetwork::IpNetwork};
+use std::{net::SocketAddr, sync::Arc};
 
 use crate::config::app_config::AppConfig;
 use crate::app_error::app_error::AppError;
+use crate::services::readiness::Readiness;
 
 
-pub async fn shutdown_signal(config: AppConfig) {
+pub async fn shutdown_signal(config: AppConfig, readiness: Arc<Readiness>) {
     // Wait for the signal to be received
     let _ = signal::ctrl_c()
         .await
@@ -19,9 +22,56 @@ pub async fn shutdown_signal(config: AppConfig) {
             AppError::SignalError(format!("Failed to receive CTRL+C signal: {}", e))
         ));
     println!("Received CTRL+C, shutting down...");
+    readiness.begin_shutdown();
     config.drop_config();
 }
 
+/// Extracts the client IP and user agent used to enrich security events
+/// Prints a summary of the effective configuration once the listener is bound
+///
+/// Only non-sensitive values are shown: the database appears as host and name
+/// without credentials, and keys or secrets are never printed.
+pub fn print_startup_summary(config: &AppConfig, addr: &str, maintenance_mode: bool) {
+    let run_env = std::env::var("RUN_ENV").unwrap_or_else(|_| "development".to_string());
+    let database = config.database.url
+        .parse::<PgConnectOptions>()
+        .map(|options| format!(
+            "{}:{}/{}",
+            options.get_host(),
+            options.get_port(),
+            options.get_database().unwrap_or("-"),
+        ))
+        .unwrap_or_else(|_| "<unparseable url>".to_string());
+    let tokens = config.ethereum.tokens
+        .iter()
+        .map(|token| format!("{}@{}", token.symbol, token.chain_id))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    println!("Starting crypto_invoice backend");
+    println!("  listening on:      {}", addr);
+    println!("  environment:       {}", run_env);
+    println!("  chain id:          {}", config.ethereum.chain_id);
+    println!("  tokens:            {}", if tokens.is_empty() { "none" } else { &tokens });
+    println!("  database:          {}", database);
+    println!("  tls:               off (trust_proxy_tls = {})", config.server.trust_proxy_tls);
+    println!("  migrations:        off (schema from db/init.sql)");
+    println!("  maintenance mode:  {}", if maintenance_mode { "on" } else { "off" });
+}
+
+pub fn extract_client_info(
+    headers: &HeaderMap,
+    addr: SocketAddr,
+) -> (IpNetwork, String) {
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown")
+        .to_string();
+
+    (IpNetwork::from(addr.ip()), user_agent)
+}
+
 // pub async fn restrict_origin(
 //     headers: HeaderMap, 
 //     request: Request, 
@@ -0,0 +1,183 @@
+use chrono::NaiveDateTime;
+use std::fmt;
+
+use crate::app_error::app_error::AppError;
+
+// https://eips.ethereum.org/EIPS/eip-4361
+
+const HEADER_SUFFIX: &str = " wants you to sign in with your Ethereum account:";
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Structured EIP-4361 (Sign-In with Ethereum) message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: String,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: NaiveDateTime,
+    pub expiration_time: Option<NaiveDateTime>,
+}
+
+impl SiweMessage {
+    /// Parses a message in the format rendered by `Display`
+    ///
+    /// Never panics: any malformed input is reported as a validation error.
+    pub fn parse(message: &str) -> Result<Self, AppError> {
+        let mut lines = message.lines();
+
+        let domain = lines.next()
+            .and_then(|line| line.strip_suffix(HEADER_SUFFIX))
+            .filter(|domain| !domain.is_empty())
+            .ok_or_else(|| invalid("missing header"))?
+            .to_string();
+
+        let address = lines.next()
+            .filter(|address| !address.is_empty())
+            .ok_or_else(|| invalid("missing address"))?
+            .to_string();
+
+        if lines.next() != Some("") {
+            return Err(invalid("missing blank line after address"));
+        }
+
+        // The statement is optional and followed by a blank line when present
+        let mut line = lines.next().ok_or_else(|| invalid("missing URI"))?;
+        let mut statement = None;
+        if !line.starts_with("URI: ") {
+            statement = Some(line.to_string());
+            if lines.next() != Some("") {
+                return Err(invalid("missing blank line after statement"));
+            }
+            line = lines.next().ok_or_else(|| invalid("missing URI"))?;
+        }
+
+        let uri = field(Some(line), "URI: ")?.to_string();
+        let version = field(lines.next(), "Version: ")?.to_string();
+        let chain_id = field(lines.next(), "Chain ID: ")?
+            .parse::<u64>()
+            .map_err(|_| invalid("invalid Chain ID"))?;
+        let nonce = field(lines.next(), "Nonce: ")?.to_string();
+        let issued_at = NaiveDateTime::parse_from_str(field(lines.next(), "Issued At: ")?, TIMESTAMP_FORMAT)
+            .map_err(|_| invalid("invalid Issued At"))?;
+
+        let mut line = lines.next();
+        let mut expiration_time = None;
+        if let Some(value) = line.and_then(|line| line.strip_prefix("Expiration Time: ")) {
+            expiration_time = Some(
+                NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT)
+                    .map_err(|_| invalid("invalid Expiration Time"))?,
+            );
+            line = lines.next();
+        }
+
+        if line.is_some() {
+            return Err(invalid("unexpected trailing content"));
+        }
+
+        Ok(SiweMessage {
+            domain,
+            address,
+            statement,
+            uri,
+            version,
+            chain_id,
+            nonce,
+            issued_at,
+            expiration_time,
+        })
+    }
+}
+
+impl fmt::Display for SiweMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}{}", self.domain, HEADER_SUFFIX)?;
+        writeln!(f, "{}", self.address)?;
+        writeln!(f)?;
+        if let Some(statement) = &self.statement {
+            writeln!(f, "{}", statement)?;
+            writeln!(f)?;
+        }
+        writeln!(f, "URI: {}", self.uri)?;
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(f, "Chain ID: {}", self.chain_id)?;
+        writeln!(f, "Nonce: {}", self.nonce)?;
+        write!(f, "Issued At: {}", self.issued_at.format(TIMESTAMP_FORMAT))?;
+        if let Some(expiration_time) = &self.expiration_time {
+            write!(f, "\nExpiration Time: {}", expiration_time.format(TIMESTAMP_FORMAT))?;
+        }
+        Ok(())
+    }
+}
+
+fn field<'a>(line: Option<&'a str>, prefix: &str) -> Result<&'a str, AppError> {
+    line.and_then(|line| line.strip_prefix(prefix))
+        .ok_or_else(|| invalid(&format!("missing {}", prefix.trim_end_matches(": "))))
+}
+
+fn invalid(reason: &str) -> AppError {
+    AppError::ValidationError(format!("Invalid SIWE message: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, NaiveDate};
+    use proptest::prelude::*;
+
+    fn message() -> SiweMessage {
+        let issued_at = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        SiweMessage {
+            domain: "localhost:8080".to_string(),
+            address: "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23".to_string(),
+            statement: Some("[LOGIN] Sign in to verify ownership of this address.".to_string()),
+            uri: "http://localhost:8080".to_string(),
+            version: "1".to_string(),
+            chain_id: 11155111,
+            nonce: "f5c8353696c861a5dcf82ee5a876e1b0".to_string(),
+            issued_at,
+            expiration_time: Some(issued_at + chrono::Duration::minutes(5)),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn parse_never_panics(input in any::<String>()) {
+            let _ = SiweMessage::parse(&input);
+        }
+
+        #[test]
+        fn parse_never_panics_on_damaged_messages(
+            cut in any::<prop::sample::Index>(),
+            insert in "[\\PC\\n:]{0,12}",
+        ) {
+            let rendered = message().to_string();
+            let boundaries: Vec<usize> = rendered.char_indices().map(|(i, _)| i).collect();
+            let at = boundaries[cut.index(boundaries.len())];
+
+            let _ = SiweMessage::parse(&rendered[..at]);
+            let _ = SiweMessage::parse(&format!("{}{}{}", &rendered[..at], insert, &rendered[at..]));
+        }
+
+        #[test]
+        fn rendered_messages_parse_back(
+            nonce in "[a-zA-Z0-9]{8,32}",
+            chain_id in any::<u64>(),
+            issued_at in 0i64..4_102_444_800,
+            expires in proptest::option::of(0i64..86_400),
+        ) {
+            let issued_at = DateTime::from_timestamp(issued_at, 0).unwrap().naive_utc();
+            let expected = SiweMessage {
+                nonce,
+                chain_id,
+                issued_at,
+                expiration_time: expires.map(|secs| issued_at + chrono::Duration::seconds(secs)),
+                ..message()
+            };
+            prop_assert_eq!(SiweMessage::parse(&expected.to_string()).unwrap(), expected);
+        }
+    }
+}
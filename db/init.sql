@@ -1,4 +1,5 @@
 CREATE EXTENSION IF NOT EXISTS "uuid-ossp"; 
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
 
 CREATE TYPE user_role AS ENUM (
     'emitter',
@@ -9,7 +10,8 @@ CREATE TYPE user_role AS ENUM (
 CREATE TYPE invoice_status AS ENUM (
     'pending',
     'paid',
-    'disputed'
+    'disputed',
+    'cancelled'
 );
 
 CREATE TYPE event_type AS ENUM (
@@ -19,7 +21,32 @@ CREATE TYPE event_type AS ENUM (
     'walletdisconnected',
     'passwordchanged',
     'accountlocked',
-    'accountunlocked'
+    'accountunlocked',
+    'multisigapproval',
+    'invoiceaccepted',
+    'sessionsrevoked',
+    'invoicestatuschanged',
+    'ratelimitcleared',
+    'paymentreplayed',
+    'verificationchanged',
+    'invoicequotaexceeded',
+    'apikeysrotated',
+    'sharedinvoiceviewed',
+    'sessionkeyauthorized',
+    'sessionkeyrevoked',
+    'expiredchallengesigned',
+    'sessionevicted',
+    'anomalydetected',
+    'webhookreplayed'
+);
+
+CREATE TYPE failure_category AS ENUM (
+    'user_rejected',
+    'invalid_signature',
+    'wrong_chain',
+    'wallet_error',
+    'timeout',
+    'other'
 );
 
 -- CREATE TYPE dispute_decision AS ENUM (
@@ -43,42 +70,85 @@ CREATE TYPE event_type AS ENUM (
 CREATE TABLE IF NOT EXISTS users (
     id UUID PRIMARY KEY,
     ethereum_address VARCHAR(42) UNIQUE NOT NULL,
-    email VARCHAR(255) UNIQUE NOT NULL,
+    -- Empty for wallet users who gave none, see idx_users_email
+    email VARCHAR(255) NOT NULL DEFAULT '',
     username VARCHAR(50) UNIQUE NOT NULL,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     is_active BOOLEAN NOT NULL DEFAULT TRUE,
     is_admin BOOLEAN NOT NULL DEFAULT FALSE, 
     is_verified BOOLEAN NOT NULL DEFAULT FALSE,
-    metadata JSONB NOT NULL DEFAULT '{}'::JSONB
+    metadata JSONB NOT NULL DEFAULT '{}'::JSONB,
+    token_epoch INTEGER NOT NULL DEFAULT 0,
+    -- Sign-ins are refused until then, after too many failed logins
+    locked_until TIMESTAMP
 );
 
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email ON users (email) WHERE email <> '';
+
+-- Trigram indexes serve the admin user search's case-insensitive partial matches
+CREATE INDEX IF NOT EXISTS idx_users_ethereum_address_trgm ON users USING GIN (ethereum_address gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_users_email_trgm ON users USING GIN (email gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_users_username_trgm ON users USING GIN (username gin_trgm_ops);
+
 CREATE TABLE IF NOT EXISTS invoices (
     id UUID PRIMARY KEY,
     on_chain_id VARCHAR(255) UNIQUE NOT NULL,
     title VARCHAR(255) NOT NULL,
     description TEXT,
-    amount NUMERIC(20, 8) NOT NULL,
+    -- Wide enough for any uint256 base-unit amount at 18 decimals
+    amount NUMERIC(96, 18) NOT NULL,
     currency VARCHAR(3) NOT NULL,
     due_date TIMESTAMP NOT NULL,
     created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     status invoice_status DEFAULT 'pending',
-    created_by UUID REFERENCES users(id)
+    created_by UUID REFERENCES users(id),
+    sequence_number BIGINT NOT NULL,
+    display_number VARCHAR(64) NOT NULL,
+    recipient_address VARCHAR(42),
+    accepted_at TIMESTAMP,
+    token_address VARCHAR(42),
+    metadata JSONB NOT NULL DEFAULT '{}'::JSONB,
+    -- Issuer's own identifier of the invoice, e.g. an order number
+    external_ref VARCHAR(64),
+    UNIQUE (created_by, sequence_number),
+    UNIQUE (created_by, external_ref)
+);
+
+CREATE INDEX IF NOT EXISTS idx_invoices_created_by_created_at ON invoices (created_by, created_at);
+CREATE INDEX IF NOT EXISTS idx_invoices_recipient_address ON invoices (recipient_address);
+CREATE INDEX IF NOT EXISTS idx_invoices_metadata ON invoices USING GIN (metadata jsonb_path_ops);
+
+CREATE TABLE IF NOT EXISTS invoice_counters (
+    user_id UUID PRIMARY KEY REFERENCES users(id),
+    last_number BIGINT NOT NULL DEFAULT 0
 );
 
 CREATE TABLE IF NOT EXISTS auth_challenges (
     id UUID PRIMARY KEY,
     ethereum_address VARCHAR(42) NOT NULL,
     nonce VARCHAR(255) NOT NULL,
-    challenge_message VARCHAR(255) NOT NULL,
+    challenge_message TEXT NOT NULL,
     expires_at TIMESTAMP NOT NULL,
     used BOOLEAN NOT NULL DEFAULT FALSE,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     domain VARCHAR(255) NOT NULL,
-    chal_timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+    chal_timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    chain_id BIGINT NOT NULL,
+    uri VARCHAR(255) NOT NULL,
+    locale VARCHAR(35)
+);
+
+-- Challenge nonces issued within challenge_store.nonce_reuse_window_secs,
+-- outliving the challenges themselves so a repeated nonce is caught
+CREATE TABLE IF NOT EXISTS challenge_nonces (
+    nonce VARCHAR(255) PRIMARY KEY,
+    issued_at TIMESTAMP NOT NULL
 );
 
+CREATE INDEX IF NOT EXISTS idx_challenge_nonces_issued_at ON challenge_nonces(issued_at);
+
 CREATE TABLE IF NOT EXISTS security_events (
     id UUID PRIMARY KEY,
     user_id UUID NOT NULL REFERENCES users(id),
@@ -89,6 +159,32 @@ CREATE TABLE IF NOT EXISTS security_events (
     metadata JSONB DEFAULT '{}'::JSONB
 );
 
+CREATE INDEX IF NOT EXISTS idx_security_events_timestamp ON security_events (timestamp);
+CREATE INDEX IF NOT EXISTS idx_security_events_timestamp_id ON security_events (timestamp, id);
+
+-- Legal-hold log of security event erasures, append-only. The erased
+-- address is kept as a SHA-256 digest only.
+CREATE TABLE IF NOT EXISTS event_erasures (
+    id UUID PRIMARY KEY,
+    address_hash CHAR(64) NOT NULL,
+    user_id UUID,
+    requested_by UUID NOT NULL REFERENCES users(id),
+    reason TEXT NOT NULL,
+    deleted_events BIGINT NOT NULL,
+    anonymized_events BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE OR REPLACE FUNCTION reject_event_erasure_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'event_erasures is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER event_erasures_append_only
+    BEFORE UPDATE OR DELETE ON event_erasures
+    FOR EACH ROW EXECUTE FUNCTION reject_event_erasure_changes();
+
 CREATE TABLE IF NOT EXISTS token_blacklist (
     id UUID PRIMARY KEY,
     user_id UUID REFERENCES users(id),
@@ -98,3 +194,160 @@ CREATE TABLE IF NOT EXISTS token_blacklist (
     blacklisted_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     reason VARCHAR(255) NOT NULL
 );
+
+CREATE INDEX IF NOT EXISTS idx_token_blacklist_blacklisted_at ON token_blacklist (blacklisted_at);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id),
+    refresh_jti VARCHAR(255) UNIQUE NOT NULL,
+    issued_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions (user_id);
+
+-- Only a sha256 of each key is stored, the plaintext is shown once at creation
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id),
+    name VARCHAR(64) NOT NULL,
+    key_prefix VARCHAR(16) NOT NULL,
+    key_hash CHAR(64) UNIQUE NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys (user_id);
+
+CREATE TABLE IF NOT EXISTS invoice_shares (
+    id UUID PRIMARY KEY,
+    invoice_id UUID NOT NULL REFERENCES invoices(id),
+    created_by UUID NOT NULL REFERENCES users(id),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP,
+    -- Views allowed before the link is used up, unlimited when NULL
+    max_views INTEGER CHECK (max_views > 0),
+    view_count INTEGER NOT NULL DEFAULT 0,
+    last_viewed_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_invoice_shares_invoice_id ON invoice_shares (invoice_id);
+
+-- Keys a payer authorizes, with one wallet signature, to accept invoices
+-- addressed to it within a spending limit
+CREATE TABLE IF NOT EXISTS session_keys (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id),
+    owner_address VARCHAR(42) NOT NULL,
+    session_address VARCHAR(42) NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    max_amount_per_invoice NUMERIC(96, 18) NOT NULL,
+    spend_limit NUMERIC(96, 18) NOT NULL,
+    spent NUMERIC(96, 18) NOT NULL DEFAULT 0 CHECK (spent <= spend_limit),
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    -- Set once the owner signed the authorization message
+    authorized_at TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_session_keys_user_id ON session_keys (user_id);
+
+-- One on-chain payment settles at most one invoice
+CREATE TABLE IF NOT EXISTS invoice_payments (
+    chain_id BIGINT NOT NULL,
+    tx_hash VARCHAR(66) NOT NULL,
+    invoice_id UUID NOT NULL REFERENCES invoices(id),
+    confirmed_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (chain_id, tx_hash)
+);
+
+-- Opt-in, anonymous reports of failed wallet sign-ins
+CREATE TABLE IF NOT EXISTS wallet_diagnostics (
+    id UUID PRIMARY KEY,
+    wallet_name VARCHAR(64) NOT NULL,
+    wallet_version VARCHAR(32),
+    chain_id BIGINT,
+    failure_category failure_category NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_wallet_diagnostics_created_at ON wallet_diagnostics (created_at);
+
+CREATE TABLE IF NOT EXISTS rate_limits (
+    identifier VARCHAR(255) NOT NULL,
+    action VARCHAR(64) NOT NULL,
+    attempt_count INTEGER NOT NULL DEFAULT 0,
+    window_start TIMESTAMP NOT NULL,
+    last_attempt TIMESTAMP NOT NULL,
+    PRIMARY KEY (identifier, action)
+);
+
+-- One row per window in which an identifier exceeded a rate limit. Kept
+-- apart from security_events: the limited endpoints are anonymous, while
+-- security events always belong to a user.
+CREATE TABLE IF NOT EXISTS rate_limit_violations (
+    identifier VARCHAR(255) NOT NULL,
+    action VARCHAR(64) NOT NULL,
+    window_start TIMESTAMP NOT NULL,
+    attempt_count INTEGER NOT NULL,
+    max_attempts INTEGER NOT NULL,
+    first_violation_at TIMESTAMP NOT NULL,
+    last_violation_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (identifier, action, window_start)
+);
+
+CREATE INDEX IF NOT EXISTS idx_rate_limit_violations_last
+    ON rate_limit_violations (last_violation_at);
+
+-- Identifiers denied every rate-limited action, until blocked_until or,
+-- when it is NULL, until an admin clears their rate limits
+CREATE TABLE IF NOT EXISTS rate_limit_blocks (
+    identifier VARCHAR(255) PRIMARY KEY,
+    violations BIGINT NOT NULL,
+    blocked_at TIMESTAMP NOT NULL,
+    blocked_until TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name VARCHAR(64) PRIMARY KEY,
+    enabled BOOLEAN NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS outbox_messages (
+    id BIGSERIAL PRIMARY KEY,
+    aggregate_type VARCHAR(64) NOT NULL,
+    aggregate_id UUID NOT NULL,
+    event_type VARCHAR(64) NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL,
+    last_error TEXT,
+    sent_at TIMESTAMP,
+    dead_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_outbox_messages_pending
+    ON outbox_messages (aggregate_type, aggregate_id, id)
+    WHERE sent_at IS NULL AND dead_at IS NULL;
+
+-- One row per webhook call made for an outbox message, by the relay or
+-- replayed by an admin
+CREATE TABLE IF NOT EXISTS outbox_delivery_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES outbox_messages(id) ON DELETE CASCADE,
+    webhook_url TEXT NOT NULL,
+    attempted_at TIMESTAMP NOT NULL,
+    -- NULL when the webhook accepted the message
+    error TEXT,
+    replayed_by UUID REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_outbox_delivery_attempts_message
+    ON outbox_delivery_attempts (message_id, id);